@@ -0,0 +1,105 @@
+//! Compares the reply path of `Connection` with the previous implementation,
+//! which awaited a `write_u8`/`write_all` on a `BufWriter` for every piece of
+//! a frame, and compares copying with zero-copy bulk parsing.
+//!
+//! Run with `cargo bench -p mini-redis --bench connection`.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_redis::{Connection, Frame};
+use std::io::Cursor;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 3] = [16, 4 * 1024, 1024 * 1024];
+
+/// Returns a connected socket whose peer discards everything it receives.
+async fn sink() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 256 * 1024];
+        while socket.read(&mut buf).await.unwrap_or(0) > 0 {}
+    });
+    TcpStream::connect(addr).await.unwrap()
+}
+
+fn reply(size: usize) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from_static(b"message"));
+    frame.push_bulk(Bytes::from_static(b"channel"));
+    frame.push_bulk(Bytes::from(vec![b'x'; size]));
+    frame
+}
+
+/// The encoding used before frames were encoded into a reusable buffer.
+async fn legacy_write_frame(stream: &mut BufWriter<TcpStream>, frame: &Frame) {
+    async fn decimal(stream: &mut BufWriter<TcpStream>, val: u64) {
+        stream.write_all(val.to_string().as_bytes()).await.unwrap();
+        stream.write_all(b"\r\n").await.unwrap();
+    }
+
+    let Frame::Array(entries) = frame else {
+        unreachable!()
+    };
+    stream.write_u8(b'*').await.unwrap();
+    decimal(stream, entries.len() as u64).await;
+    for entry in entries {
+        let Frame::Bulk(val) = entry else {
+            unreachable!()
+        };
+        stream.write_u8(b'$').await.unwrap();
+        decimal(stream, val.len() as u64).await;
+        stream.write_all(val).await.unwrap();
+        stream.write_all(b"\r\n").await.unwrap();
+    }
+    stream.flush().await.unwrap();
+}
+
+fn write_frame(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("write_frame");
+
+    for size in SIZES {
+        let frame = reply(size);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let mut connection = Connection::new(rt.block_on(sink()));
+        group.bench_with_input(BenchmarkId::new("connection", size), &frame, |b, frame| {
+            b.iter(|| rt.block_on(connection.write_frame(frame)).unwrap())
+        });
+
+        let mut legacy = BufWriter::new(rt.block_on(sink()));
+        group.bench_with_input(BenchmarkId::new("legacy", size), &frame, |b, frame| {
+            b.iter(|| rt.block_on(legacy_write_frame(&mut legacy, frame)))
+        });
+    }
+
+    group.finish();
+}
+
+fn parse_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_frame");
+
+    for size in SIZES {
+        let mut encoded = BytesMut::new();
+        reply(size).encode(&mut encoded);
+        let encoded = encoded.freeze();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("copy", size), &encoded, |b, src| {
+            b.iter(|| Frame::parse(&mut Cursor::new(&src[..])).unwrap())
+        });
+
+        group.bench_with_input(BenchmarkId::new("zero_copy", size), &encoded, |b, src| {
+            b.iter(|| Frame::parse_bytes(src).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, write_frame, parse_frame);
+criterion_main!(benches);
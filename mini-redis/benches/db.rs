@@ -0,0 +1,71 @@
+//! Compares the throughput of the keyspace split into shards with the
+//! previous design, where every command waited on a single lock, when many
+//! threads run commands at once.
+//!
+//! Run with `cargo bench -p mini-redis --bench db`.
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_redis::Db;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+const KEYS: usize = 10_000;
+
+/// Threads running commands concurrently, at least a few even on small
+/// machines so there is contention to measure.
+fn threads() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get().max(4))
+}
+
+/// Runs `ops` commands spread over the threads, one `SET` for every four
+/// `GET`s, and returns the time taken.
+fn run(db: &Db, ops: u64) -> Duration {
+    let threads = threads();
+    let per_thread = ops.div_ceil(threads as u64);
+    let value = Bytes::from_static(b"value");
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for t in 0..threads {
+            let (db, value) = (db.clone(), value.clone());
+            scope.spawn(move || {
+                let mut key = t * 7919;
+                for op in 0..per_thread {
+                    key = (key + 104_729) % KEYS;
+                    let key = format!("key:{}", key);
+                    if op % 5 == 0 {
+                        db.set(key, value.clone(), None);
+                    } else {
+                        db.get(&key);
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn contended(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    // `Db` spawns its expiration task on the runtime.
+    let _guard = rt.enter();
+    let mut group = c.benchmark_group("contended");
+    group.throughput(Throughput::Elements(1));
+
+    // A single shard behaves as the previous, globally locked, keyspace.
+    for shards in [1, 16, 64] {
+        let db = Db::with_shards(shards);
+        for i in 0..KEYS {
+            db.set(format!("key:{}", i), Bytes::from_static(b"value"), None);
+        }
+        group.bench_with_input(BenchmarkId::new("shards", shards), &db, |b, db| {
+            b.iter_custom(|ops| run(db, ops))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, contended);
+criterion_main!(benches);
@@ -0,0 +1,741 @@
+//! Access control lists: users, their passwords and what they may access.
+//!
+//! Every connection runs as a user. A user is allowed a set of commands,
+//! granted individually or by category (`@read`, `@write`, ...), and a set of
+//! key and channel glob patterns. `server::Handler` checks each command
+//! against the connection's user before applying it. Denied attempts are
+//! recorded in a log inspected with `ACL LOG`.
+//!
+//! Users are described by the same rules Redis uses for `ACL SETUSER` and ACL
+//! files, e.g. `on >secret ~cache:* &* +@read -@dangerous`.
+
+use crate::cmd::COMMAND_TABLE;
+use crate::config::write_atomically;
+use crate::pattern::glob_match;
+use crate::Command;
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The user every connection starts as.
+pub const DEFAULT_USER: &str = "default";
+
+/// Maximum number of entries kept by the ACL log.
+const LOG_MAX_LEN: usize = 128;
+
+/// Denials of the same kind within this many milliseconds are folded into a
+/// single log entry.
+const LOG_GROUPING_MS: u64 = 60_000;
+
+/// A class of commands that can be granted or revoked as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Keyspace,
+    Read,
+    Write,
+    String,
+    Pubsub,
+    Admin,
+    Fast,
+    Slow,
+    Dangerous,
+    Connection,
+}
+
+impl Category {
+    pub const ALL: &'static [Category] = &[
+        Category::Keyspace,
+        Category::Read,
+        Category::Write,
+        Category::String,
+        Category::Pubsub,
+        Category::Admin,
+        Category::Fast,
+        Category::Slow,
+        Category::Dangerous,
+        Category::Connection,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Keyspace => "keyspace",
+            Category::Read => "read",
+            Category::Write => "write",
+            Category::String => "string",
+            Category::Pubsub => "pubsub",
+            Category::Admin => "admin",
+            Category::Fast => "fast",
+            Category::Slow => "slow",
+            Category::Dangerous => "dangerous",
+            Category::Connection => "connection",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL
+            .iter()
+            .copied()
+            .find(|category| category.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Names of the commands belonging to the category.
+    pub fn commands(self) -> impl Iterator<Item = &'static str> {
+        COMMAND_TABLE
+            .iter()
+            .filter(move |(_, categories)| categories.contains(&self))
+            .map(|(name, _)| *name)
+    }
+}
+
+/// A user known to the server.
+#[derive(Clone, Debug)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    nopass: bool,
+    /// Hex encoded SHA-256 digests of the accepted passwords.
+    passwords: BTreeSet<String>,
+    /// Names of the commands the user may run.
+    commands: HashSet<&'static str>,
+    /// `+`/`-` rules the command set was built from, used to describe it.
+    command_rules: Vec<String>,
+    keys: Vec<String>,
+    channels: Vec<String>,
+}
+
+/// Why a command was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denial {
+    Command,
+    Key,
+    Channel,
+    /// The user was deleted or disabled after the connection authenticated.
+    User,
+}
+
+/// Why authentication failed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// `AUTH password` was used while the default user needs no password.
+    NoPasswordConfigured,
+    WrongPass,
+}
+
+/// Shared handle to the users and the ACL log. Cloning is shallow.
+#[derive(Clone, Debug)]
+pub struct AccessControl {
+    shared: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    users: BTreeMap<String, User>,
+    log: VecDeque<LogEntry>,
+    next_entry_id: u64,
+    /// File used by `ACL LOAD` and `ACL SAVE`.
+    file: Option<PathBuf>,
+}
+
+/// A denied command or failed authentication, as reported by `ACL LOG`.
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub count: u64,
+    pub reason: &'static str,
+    pub context: &'static str,
+    pub object: String,
+    pub username: String,
+    pub client_info: String,
+    pub entry_id: u64,
+    pub created_ms: u64,
+    pub updated_ms: u64,
+}
+
+impl User {
+    /// A new user as created by `ACL SETUSER`: disabled, without passwords
+    /// and without any permission.
+    pub fn new(name: impl ToString) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            commands: HashSet::new(),
+            command_rules: Vec::new(),
+            keys: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// The default user of a fresh server: allowed everything, protected by
+    /// `requirepass` if given.
+    fn default_user(requirepass: Option<&str>) -> User {
+        let mut user = User::new(DEFAULT_USER);
+        let password = requirepass.map(|password| format!(">{}", password));
+        let rules = ["on", "~*", "&*", "+@all"];
+        let rules = rules
+            .iter()
+            .copied()
+            .chain(Some(password.as_deref().unwrap_or("nopass")));
+        for rule in rules {
+            user.apply_rule(rule).expect("default rules are valid");
+        }
+        user
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Apply a single rule, e.g. `on`, `>password`, `~key:*` or `+@read`.
+    pub fn apply_rule(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_ascii_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.channels = vec!["*".to_string()],
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.apply_rule("+@all")?,
+            "nocommands" => self.apply_rule("-@all")?,
+            "reset" => *self = User::new(&self.name),
+            _ => return self.apply_argument_rule(rule),
+        }
+        Ok(())
+    }
+
+    fn apply_argument_rule(&mut self, rule: &str) -> Result<(), String> {
+        let (op, arg) = match rule.char_indices().nth(1) {
+            Some((i, _)) => rule.split_at(i),
+            None => return Err("Syntax error".to_string()),
+        };
+        match op {
+            ">" => {
+                self.passwords.insert(hash_password(arg));
+                self.nopass = false;
+            }
+            "<" => {
+                if !self.passwords.remove(&hash_password(arg)) {
+                    return Err("no such password".to_string());
+                }
+            }
+            "#" => {
+                let hash = arg.to_ascii_lowercase();
+                if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+                }
+                self.passwords.insert(hash);
+                self.nopass = false;
+            }
+            "!" => {
+                if !self.passwords.remove(&arg.to_ascii_lowercase()) {
+                    return Err("no such password".to_string());
+                }
+            }
+            "~" => push_pattern(&mut self.keys, arg),
+            "&" => push_pattern(&mut self.channels, arg),
+            "+" | "-" => self.apply_command_rule(op == "+", arg)?,
+            _ => return Err("Syntax error".to_string()),
+        }
+        Ok(())
+    }
+
+    fn apply_command_rule(&mut self, allow: bool, arg: &str) -> Result<(), String> {
+        let arg = arg.to_ascii_lowercase();
+        let names: Vec<&'static str> = match arg.strip_prefix('@') {
+            Some("all") => COMMAND_TABLE.iter().map(|(name, _)| *name).collect(),
+            Some(category) => match Category::from_name(category) {
+                Some(category) => category.commands().collect(),
+                None => return Err("Unknown command or category name in ACL".to_string()),
+            },
+            None => match COMMAND_TABLE.iter().find(|(name, _)| *name == arg) {
+                Some((name, _)) => vec![*name],
+                None => return Err("Unknown command or category name in ACL".to_string()),
+            },
+        };
+
+        for name in names {
+            if allow {
+                self.commands.insert(name);
+            } else {
+                self.commands.remove(name);
+            }
+        }
+
+        let rule = format!("{}{}", if allow { '+' } else { '-' }, arg);
+        // A rule on every command supersedes all earlier ones.
+        if arg == "@all" {
+            self.command_rules.clear();
+        }
+        self.command_rules
+            .retain(|existing| existing[1..] != arg[..]);
+        self.command_rules.push(rule);
+        Ok(())
+    }
+
+    /// Returns `true` if `password` is accepted for this user.
+    pub fn check_password(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&hash_password(password))
+    }
+
+    pub fn can_run(&self, command: &str) -> bool {
+        self.commands.contains(command)
+    }
+
+    pub fn can_access_key(&self, key: &str) -> bool {
+        self.keys.iter().any(|pattern| glob_match(pattern, key))
+    }
+
+    pub fn can_access_channel(&self, channel: &str) -> bool {
+        self.channels
+            .iter()
+            .any(|pattern| glob_match(pattern, channel))
+    }
+
+    /// Checks every permission `cmd` needs. Returns the denial reason and the
+    /// offending object on failure.
+    fn check(&self, cmd: &Command) -> Result<(), (Denial, String)> {
+        if !self.enabled {
+            return Err((Denial::User, self.name.clone()));
+        }
+        let name = cmd.get_name();
+        if !self.can_run(name) {
+            return Err((Denial::Command, name.to_string()));
+        }
+        if let Some(key) = cmd.keys().into_iter().find(|key| !self.can_access_key(key)) {
+            return Err((Denial::Key, key.to_string()));
+        }
+        if let Some(channel) = cmd
+            .channels()
+            .into_iter()
+            .find(|channel| !self.can_access_channel(channel))
+        {
+            return Err((Denial::Channel, channel.to_string()));
+        }
+        Ok(())
+    }
+
+    /// The flags reported by `ACL GETUSER`.
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    /// Hex encoded SHA-256 digests of the user's passwords.
+    pub fn password_hashes(&self) -> impl Iterator<Item = &str> {
+        self.passwords.iter().map(String::as_str)
+    }
+
+    /// The command rules, e.g. `+@all -set`.
+    pub fn describe_commands(&self) -> String {
+        if self.command_rules.is_empty() {
+            "-@all".to_string()
+        } else {
+            self.command_rules.join(" ")
+        }
+    }
+
+    /// The key patterns, e.g. `~cache:* ~session:*`.
+    pub fn describe_keys(&self) -> String {
+        describe_patterns('~', &self.keys)
+    }
+
+    /// The channel patterns, e.g. `&news.*`.
+    pub fn describe_channels(&self) -> String {
+        describe_patterns('&', &self.channels)
+    }
+}
+
+/// Formats the user as a line of an ACL file, e.g.
+/// `user default on nopass ~* &* +@all`.
+impl fmt::Display for User {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "user {}", self.name)?;
+        for flag in self.flags() {
+            write!(fmt, " {}", flag)?;
+        }
+        for hash in &self.passwords {
+            write!(fmt, " #{}", hash)?;
+        }
+        for part in [
+            self.describe_keys(),
+            self.describe_channels(),
+            self.describe_commands(),
+        ] {
+            if !part.is_empty() {
+                write!(fmt, " {}", part)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AccessControl {
+    /// Create the access control state of a fresh server. It knows only the
+    /// default user, allowed everything and protected by `requirepass` if
+    /// given.
+    pub fn new(requirepass: Option<&str>) -> AccessControl {
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), User::default_user(requirepass));
+        AccessControl {
+            shared: Arc::new(Mutex::new(State {
+                users,
+                log: VecDeque::new(),
+                next_entry_id: 0,
+                file: None,
+            })),
+        }
+    }
+
+    /// Load the users from the ACL file at `path`. The file is remembered for
+    /// `ACL LOAD` and `ACL SAVE`.
+    pub fn with_file(path: impl Into<PathBuf>) -> crate::Result<AccessControl> {
+        let access = AccessControl::new(None);
+        access.shared.lock().unwrap().file = Some(path.into());
+        access.load()?;
+        Ok(access)
+    }
+
+    /// Returns the user a new connection is authenticated as, if the default
+    /// user is enabled and needs no password.
+    pub fn default_login(&self) -> Option<String> {
+        let state = self.shared.lock().unwrap();
+        state
+            .users
+            .get(DEFAULT_USER)
+            .filter(|user| user.enabled && user.nopass)
+            .map(|user| user.name.clone())
+    }
+
+    /// Checks the credentials of `AUTH` or `HELLO`. Returns the name of the
+    /// authenticated user. Failures are recorded in the log.
+    pub fn authenticate(
+        &self,
+        username: Option<&str>,
+        password: &str,
+        client_info: &str,
+    ) -> Result<String, AuthError> {
+        let mut state = self.shared.lock().unwrap();
+        let name = username.unwrap_or(DEFAULT_USER);
+
+        if let Some(user) = state.users.get(name) {
+            if username.is_none() && user.nopass {
+                return Err(AuthError::NoPasswordConfigured);
+            }
+            if user.enabled && user.check_password(password) {
+                return Ok(user.name.clone());
+            }
+        }
+
+        state.log("auth", "toplevel", "AUTH", name, client_info);
+        Err(AuthError::WrongPass)
+    }
+
+    /// Checks that `username` may run `cmd`. Denials are recorded in the log.
+    pub fn check(&self, username: &str, cmd: &Command, client_info: &str) -> Result<(), Denial> {
+        let mut state = self.shared.lock().unwrap();
+        let res = match state.users.get(username) {
+            Some(user) => user.check(cmd),
+            None => Err((Denial::User, username.to_string())),
+        };
+        match res {
+            Ok(()) => Ok(()),
+            Err((Denial::User, _)) => Err(Denial::User),
+            Err((denial, object)) => {
+                let reason = match denial {
+                    Denial::Command => "command",
+                    Denial::Key => "key",
+                    _ => "channel",
+                };
+                state.log(reason, "toplevel", &object, username, client_info);
+                Err(denial)
+            }
+        }
+    }
+
+    /// Checks that `username` may subscribe to `channel`. Denials are recorded
+    /// in the log.
+    pub fn check_channel(&self, username: &str, channel: &str, client_info: &str) -> bool {
+        let mut state = self.shared.lock().unwrap();
+        let allowed = state
+            .users
+            .get(username)
+            .is_some_and(|user| user.enabled && user.can_access_channel(channel));
+        if !allowed {
+            state.log("channel", "toplevel", channel, username, client_info);
+        }
+        allowed
+    }
+
+    /// Apply `rules` to the user `name`, creating it if needed. Either every
+    /// rule applies or the user is left unchanged.
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut state = self.shared.lock().unwrap();
+        let mut user = state
+            .users
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply_rule(rule)
+                .map_err(|err| format!("Error in ACL SETUSER modifier '{}': {}", rule, err))?;
+        }
+        state.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Returns a copy of the user `name`.
+    pub fn get_user(&self, name: &str) -> Option<User> {
+        self.shared.lock().unwrap().users.get(name).cloned()
+    }
+
+    /// Delete users, returning how many existed. The default user cannot be
+    /// deleted.
+    pub fn del_users(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+        let mut state = self.shared.lock().unwrap();
+        Ok(names
+            .iter()
+            .filter(|name| state.users.remove(name.as_str()).is_some())
+            .count())
+    }
+
+    /// Returns every user, ordered by name.
+    pub fn users(&self) -> Vec<User> {
+        self.shared
+            .lock()
+            .unwrap()
+            .users
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the `count` most recent log entries, newest first.
+    pub fn log_entries(&self, count: usize) -> Vec<LogEntry> {
+        let state = self.shared.lock().unwrap();
+        state.log.iter().take(count).cloned().collect()
+    }
+
+    pub fn reset_log(&self) {
+        self.shared.lock().unwrap().log.clear();
+    }
+
+    /// Replace every user with the content of the ACL file. On error the
+    /// current users are kept.
+    pub fn load(&self) -> crate::Result<()> {
+        let path = self.file()?;
+        let content = fs::read_to_string(&path)?;
+        let users = parse_file(&content)?;
+        self.shared.lock().unwrap().users = users;
+        Ok(())
+    }
+
+    /// Write every user to the ACL file.
+    pub fn save(&self) -> crate::Result<()> {
+        let path = self.file()?;
+        let mut content = String::new();
+        for user in self.users() {
+            let _ = writeln!(content, "{}", user);
+        }
+        write_atomically(&path, &content)?;
+        Ok(())
+    }
+
+    fn file(&self) -> crate::Result<PathBuf> {
+        self.shared.lock().unwrap().file.clone().ok_or_else(|| {
+            "This Redis instance is not configured to use an ACL file. You may want to specify \
+             users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you \
+             have a Redis configuration file set) in order to store users in the Redis \
+             configuration."
+                .into()
+        })
+    }
+}
+
+impl Default for AccessControl {
+    fn default() -> AccessControl {
+        AccessControl::new(None)
+    }
+}
+
+impl State {
+    fn log(
+        &mut self,
+        reason: &'static str,
+        context: &'static str,
+        object: &str,
+        username: &str,
+        client_info: &str,
+    ) {
+        let now = now_ms();
+
+        // Repeated denials of the same kind update the existing entry rather
+        // than flooding the log.
+        if let Some(entry) = self.log.iter_mut().find(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+                && now.saturating_sub(entry.updated_ms) < LOG_GROUPING_MS
+        }) {
+            entry.count += 1;
+            entry.updated_ms = now;
+            entry.client_info = client_info.to_string();
+            return;
+        }
+
+        self.log.push_front(LogEntry {
+            count: 1,
+            reason,
+            context,
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info: client_info.to_string(),
+            entry_id: self.next_entry_id,
+            created_ms: now,
+            updated_ms: now,
+        });
+        self.next_entry_id += 1;
+        self.log.truncate(LOG_MAX_LEN);
+    }
+}
+
+/// Parses an ACL file. Every non-empty line not starting with `#` reads
+/// `user <name> <rules>...`. A default user is created if the file does not
+/// define one.
+fn parse_file(content: &str) -> crate::Result<BTreeMap<String, User>> {
+    let mut users = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let name = match (parts.next(), parts.next()) {
+            (Some("user"), Some(name)) => name,
+            _ => {
+                return Err(
+                    format!("ACL file line {}: should start with user keyword", i + 1).into(),
+                )
+            }
+        };
+        if users.contains_key(name) {
+            return Err(format!("ACL file line {}: duplicate user '{}'", i + 1, name).into());
+        }
+        let mut user = User::new(name);
+        for rule in parts {
+            user.apply_rule(rule)
+                .map_err(|err| format!("ACL file line {}: '{}': {}", i + 1, rule, err))?;
+        }
+        users.insert(name.to_string(), user);
+    }
+    users
+        .entry(DEFAULT_USER.to_string())
+        .or_insert_with(|| User::default_user(None));
+    Ok(users)
+}
+
+fn push_pattern(patterns: &mut Vec<String>, pattern: &str) {
+    if !patterns.iter().any(|existing| existing == pattern) {
+        patterns.push(pattern.to_string());
+    }
+}
+
+fn describe_patterns(prefix: char, patterns: &[String]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| format!("{}{}", prefix, pattern))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Hex encoded SHA-256 digest of `password`, the form passwords are stored in.
+fn hash_password(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, b| {
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        })
+}
+
+/// Milliseconds since the Unix epoch, as the ACL log reports times.
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cmd::{Get, Publish, Set};
+    use bytes::Bytes;
+
+    #[test]
+    fn user_rules() {
+        let mut user = User::new("alice");
+        for rule in ["on", ">secret", "~cache:*", "&news.*", "+@read", "+publish"] {
+            user.apply_rule(rule).unwrap();
+        }
+
+        assert!(user.check_password("secret"));
+        assert!(!user.check_password("guess"));
+        assert!(user.check(&Command::Get(Get::new("cache:1"))).is_ok());
+        assert_eq!(
+            user.check(&Command::Get(Get::new("session:1")))
+                .unwrap_err()
+                .0,
+            Denial::Key
+        );
+        assert_eq!(
+            user.check(&Command::Set(Set::new("cache:1", Bytes::new(), None)))
+                .unwrap_err()
+                .0,
+            Denial::Command
+        );
+        assert!(user
+            .check(&Command::Publish(Publish::new("news.sport", Bytes::new())))
+            .is_ok());
+        assert_eq!(
+            user.to_string(),
+            format!(
+                "user alice on #{} ~cache:* &news.* +@read +publish",
+                hash_password("secret")
+            )
+        );
+    }
+
+    #[test]
+    fn parse_acl_file() {
+        let users = parse_file("# users\nuser alice on nopass ~* +get\n").unwrap();
+        assert_eq!(users.len(), 2);
+        assert!(users["alice"].can_run("get"));
+        assert!(users[DEFAULT_USER].nopass);
+        assert!(parse_file("alice on\n").is_err());
+        assert!(parse_file("user alice +bogus\n").is_err());
+    }
+}
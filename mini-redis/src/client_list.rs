@@ -0,0 +1,256 @@
+//! Registry of the connections served, inspected and managed with the
+//! `CLIENT` command.
+
+use crate::acl::Category;
+use crate::cmd::COMMAND_TABLE;
+use crate::Command;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Shared handle to the registry. Cloning is shallow.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientList {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    /// Set by `CLIENT PAUSE`, cleared by `CLIENT UNPAUSE`. Paused clients
+    /// wait for it to change.
+    pause: watch::Sender<Option<Pause>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    clients: BTreeMap<u64, ClientInfo>,
+    next_id: u64,
+}
+
+/// A connection, as reported by `CLIENT LIST`.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientInfo {
+    pub(crate) id: u64,
+    pub(crate) addr: String,
+    /// Set by `CLIENT SETNAME`, empty otherwise.
+    pub(crate) name: String,
+    /// The user the connection runs as, if authenticated.
+    pub(crate) user: Option<String>,
+    created: Instant,
+    /// When the last command was received.
+    last_interaction: Instant,
+    /// Channels the client is subscribed to.
+    pub(crate) subscriptions: usize,
+    /// Name of the last command run, empty until then.
+    last_cmd: String,
+    kill: CancellationToken,
+}
+
+/// Commands delayed by `CLIENT PAUSE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PauseMode {
+    /// Commands that may change the dataset.
+    Write,
+    /// Every command but `CLIENT`.
+    All,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pause {
+    mode: PauseMode,
+    until: Instant,
+}
+
+/// Registration of a connection, removed from the registry when dropped.
+#[derive(Debug)]
+pub(crate) struct ClientHandle {
+    id: u64,
+    kill: CancellationToken,
+    list: ClientList,
+}
+
+impl ClientList {
+    pub(crate) fn new() -> ClientList {
+        ClientList {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    clients: BTreeMap::new(),
+                    next_id: 1,
+                }),
+                pause: watch::channel(None).0,
+            }),
+        }
+    }
+
+    /// Adds a connection from `addr` to the registry.
+    pub(crate) fn register(&self, addr: &str, user: Option<String>) -> ClientHandle {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let now = Instant::now();
+        let kill = CancellationToken::new();
+        state.clients.insert(
+            id,
+            ClientInfo {
+                id,
+                addr: addr.to_string(),
+                name: String::new(),
+                user,
+                created: now,
+                last_interaction: now,
+                subscriptions: 0,
+                last_cmd: String::new(),
+                kill: kill.clone(),
+            },
+        );
+        ClientHandle {
+            id,
+            kill,
+            list: self.clone(),
+        }
+    }
+
+    /// Changes the entry of the connection `id`, if still registered.
+    pub(crate) fn update(&self, id: u64, f: impl FnOnce(&mut ClientInfo)) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(client) = state.clients.get_mut(&id) {
+            f(client);
+        }
+    }
+
+    /// Records that the connection `id` received the command `name`.
+    pub(crate) fn interaction(&self, id: u64, name: &str) {
+        self.update(id, |client| {
+            client.last_interaction = Instant::now();
+            client.last_cmd.clear();
+            client.last_cmd.push_str(name);
+        });
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<ClientInfo> {
+        let state = self.shared.state.lock().unwrap();
+        state.clients.get(&id).cloned()
+    }
+
+    /// Returns the connections not killed yet, oldest first.
+    pub(crate) fn list(&self) -> Vec<ClientInfo> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .clients
+            .values()
+            .filter(|client| !client.kill.is_cancelled())
+            .cloned()
+            .collect()
+    }
+
+    /// Closes the connections matching `filter`, returning their number.
+    pub(crate) fn kill(&self, filter: impl Fn(&ClientInfo) -> bool) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        let mut killed = 0;
+        for client in state.clients.values() {
+            if !client.kill.is_cancelled() && filter(client) {
+                client.kill.cancel();
+                killed += 1;
+            }
+        }
+        killed
+    }
+
+    /// Delays the commands selected by `mode` for `timeout`. A pause
+    /// replaces the previous one, as in Redis. Returns `false`, leaving the
+    /// current pause as it is, if `timeout` is too far in the future for the
+    /// clock.
+    pub(crate) fn pause(&self, timeout: Duration, mode: PauseMode) -> bool {
+        let until = match Instant::now().checked_add(timeout) {
+            Some(until) => until,
+            None => return false,
+        };
+        self.shared.pause.send_replace(Some(Pause { mode, until }));
+        true
+    }
+
+    pub(crate) fn unpause(&self) {
+        self.shared.pause.send_replace(None);
+    }
+
+    /// Waits until `cmd` is no longer paused.
+    pub(crate) async fn wait_unpaused(&self, cmd: &Command) {
+        let mut pause = self.shared.pause.subscribe();
+        loop {
+            let until = match *pause.borrow_and_update() {
+                Some(Pause { mode, until }) if until > Instant::now() && is_paused(cmd, mode) => {
+                    until
+                }
+                _ => return,
+            };
+            tokio::select! {
+                _ = time::sleep_until(until) => {}
+                _ = pause.changed() => {}
+            }
+        }
+    }
+}
+
+/// Whether `cmd` waits while clients are paused with `mode`.
+fn is_paused(cmd: &Command, mode: PauseMode) -> bool {
+    match cmd {
+        // Pausing must not prevent from unpausing.
+        Command::Client(_) => false,
+        Command::Unknown(_) => mode == PauseMode::All,
+        // Publishing does not change the dataset, but is paused as well so
+        // that a replica taking over sees every message.
+        Command::Publish(_) => true,
+        cmd => {
+            mode == PauseMode::All
+                || COMMAND_TABLE.iter().any(|(name, categories)| {
+                    *name == cmd.get_name() && categories.contains(&Category::Write)
+                })
+        }
+    }
+}
+
+impl ClientHandle {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Cancelled once the connection is killed by `CLIENT KILL`.
+    pub(crate) fn kill_token(&self) -> CancellationToken {
+        self.kill.clone()
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        let mut state = self.list.shared.state.lock().unwrap();
+        state.clients.remove(&self.id);
+    }
+}
+
+/// Formats the client the way `CLIENT LIST` and `CLIENT INFO` do.
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = Instant::now();
+        write!(
+            f,
+            "id={} addr={} name={} age={} idle={} db=0 sub={} psub=0 user={} cmd={}",
+            self.id,
+            self.addr,
+            self.name,
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(self.last_interaction).as_secs(),
+            self.subscriptions,
+            self.user.as_deref().unwrap_or(""),
+            if self.last_cmd.is_empty() {
+                "NULL"
+            } else {
+                &self.last_cmd
+            },
+        )
+    }
+}
@@ -1,13 +1,50 @@
-use crate::cmd::{Get, Ping, Publish, Set, Subscribe, Unsubscribe};
+use crate::clients::url::{Addr, ConnectionInfo};
+use crate::cmd::{
+    Auth, Get, Ping, Publish, Role, Sentinel as SentinelCmd, Set, Subscribe, Unsubscribe,
+};
+use crate::tls::{
+    self,
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+};
 use crate::{Connection, Frame};
 use async_stream::try_stream;
 use bytes::Bytes;
 use std::io::{Error, ErrorKind};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_rustls::TlsConnector;
 use tokio_stream::Stream;
 use tracing::{debug, instrument};
 
+/// How `Client::connect_sentinel_with` reaches the sentinels and the master
+/// they point to.
+#[derive(Clone, Debug)]
+pub struct SentinelOptions {
+    /// Credentials sent with `AUTH` to the master before asking its role,
+    /// as the default user unless `username` is given.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Time given to each sentinel, and to the master, to accept the
+    /// connection before moving on.
+    pub connect_timeout: Duration,
+}
+
+impl Default for SentinelOptions {
+    fn default() -> SentinelOptions {
+        SentinelOptions {
+            username: None,
+            password: None,
+            connect_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
 pub struct Client {
     connection: Connection,
 }
@@ -15,8 +52,156 @@ pub struct Client {
 impl Client {
     pub async fn connect<T: ToSocketAddrs>(addr: T) -> crate::Result<Client> {
         let socket = TcpStream::connect(addr).await?;
-        let connection = Connection::new(socket);
-        Ok(Client { connection })
+        Ok(Client::from_stream(socket))
+    }
+
+    /// Connect to the server described by `url` and authenticate if the URL
+    /// holds credentials.
+    ///
+    /// Supported forms are `redis://[[username]:password@]host[:port]` and,
+    /// on Unix platforms, `unix:///path/to/socket?password=password`.
+    pub async fn connect_url(url: &str) -> crate::Result<Client> {
+        let info: ConnectionInfo = url.parse()?;
+        let mut client = match info.addr {
+            Addr::Tcp(addr) => Client::connect(addr).await?,
+            #[cfg(unix)]
+            Addr::Unix(path) => Client::connect_unix(path).await?,
+        };
+        if let Some(password) = info.password {
+            client.auth(info.username.as_deref(), &password).await?;
+        }
+        Ok(client)
+    }
+
+    /// Connect to the current master `name` as known by the sentinels at
+    /// `sentinels`, with the default `SentinelOptions`.
+    pub async fn connect_sentinel<T: ToSocketAddrs>(
+        sentinels: &[T],
+        name: &str,
+    ) -> crate::Result<Client> {
+        Client::connect_sentinel_with(sentinels, name, &SentinelOptions::default()).await
+    }
+
+    /// Connect to the current master `name` as known by the sentinels at
+    /// `sentinels`. The sentinels are asked in turn until one gives the
+    /// address of a server confirming it is a master, which may not be the
+    /// case right after a failover.
+    pub async fn connect_sentinel_with<T: ToSocketAddrs>(
+        sentinels: &[T],
+        name: &str,
+        options: &SentinelOptions,
+    ) -> crate::Result<Client> {
+        let mut last_err = "no sentinel to ask".into();
+        for sentinel in sentinels {
+            match Client::discover_master(sentinel, name, options).await {
+                Ok(client) => return Ok(client),
+                Err(err) => {
+                    debug!(case = %err, "failed to discover master");
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    async fn discover_master(
+        sentinel: impl ToSocketAddrs,
+        name: &str,
+        options: &SentinelOptions,
+    ) -> crate::Result<Client> {
+        let mut client = Client::connect_within(sentinel, options.connect_timeout).await?;
+        let frame = SentinelCmd::get_master_addr_by_name(name).into_frame();
+        client.connection.write_frame(&frame).await?;
+        let addr = match client.read_response().await? {
+            Frame::Array(addr) => match &addr[..] {
+                [Frame::Bulk(ip), Frame::Bulk(port)] => {
+                    let ip = String::from_utf8_lossy(ip).into_owned();
+                    let port: u16 = std::str::from_utf8(port)?.parse()?;
+                    (ip, port)
+                }
+                _ => return Err("unexpected reply from sentinel".into()),
+            },
+            Frame::Null => return Err(format!("sentinel doesn't know master '{}'", name).into()),
+            frame => return Err(frame.to_error()),
+        };
+
+        let mut master =
+            Client::connect_within((&addr.0[..], addr.1), options.connect_timeout).await?;
+        if let Some(password) = &options.password {
+            master.auth(options.username.as_deref(), password).await?;
+        }
+        master
+            .connection
+            .write_frame(&Role::new().into_frame())
+            .await?;
+        match master.read_response().await? {
+            Frame::Array(role) if role.first().is_some_and(|role| *role == "master") => Ok(master),
+            _ => Err(format!("{}:{} is not a master", addr.0, addr.1).into()),
+        }
+    }
+
+    /// Like `connect`, failing if the connection takes longer than `timeout`.
+    async fn connect_within(addr: impl ToSocketAddrs, timeout: Duration) -> crate::Result<Client> {
+        match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(socket) => Ok(Client::from_stream(socket?)),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "connection timed out").into()),
+        }
+    }
+
+    /// Connect to a server listening on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<Path>) -> crate::Result<Client> {
+        let socket = UnixStream::connect(path).await?;
+        Ok(Client::from_stream(socket))
+    }
+
+    /// Connect to a TLS server at `addr`, verifying its certificate for
+    /// `domain` against `roots`.
+    pub async fn connect_tls<T: ToSocketAddrs>(
+        addr: T,
+        domain: &str,
+        roots: RootCertStore,
+    ) -> crate::Result<Client> {
+        Client::connect_tls_with_config(addr, domain, tls::client_config(roots)).await
+    }
+
+    /// Connect to a TLS server at `addr` using a custom `rustls`
+    /// configuration, e.g. one presenting a client certificate built with
+    /// `tls::client_config_with_auth`.
+    pub async fn connect_tls_with_config<T: ToSocketAddrs>(
+        addr: T,
+        domain: &str,
+        config: Arc<ClientConfig>,
+    ) -> crate::Result<Client> {
+        let socket = TcpStream::connect(addr).await?;
+        let domain = ServerName::try_from(domain.to_string())?;
+        let stream = TlsConnector::from(config).connect(domain, socket).await?;
+        Ok(Client::from_stream(stream))
+    }
+
+    /// Create a client talking to a server over an already established
+    /// stream, such as a `UnixStream`, a TLS stream or a `tokio::io::duplex`
+    /// pipe.
+    pub fn from_stream<S>(stream: S) -> Client
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        Client {
+            connection: Connection::new(stream),
+        }
+    }
+
+    /// Authenticate the connection, as the default user unless `username`
+    /// is given.
+    #[instrument(skip(self, password))]
+    pub async fn auth(&mut self, username: Option<&str>, password: &str) -> crate::Result<()> {
+        // The frame is not logged, it holds the password.
+        let frame = Auth::new(username, password).into_frame();
+        self.connection.write_frame(&frame).await?;
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
     }
 
     #[instrument(skip(self))]
@@ -1,7 +1,8 @@
 mod blocking_client;
 mod buffered_client;
 mod client;
+mod url;
 
 pub use blocking_client::BlockingClient;
 pub use buffered_client::BufferedClient;
-pub use client::{Client, Message, Subscriber};
+pub use client::{Client, Message, SentinelOptions, Subscriber};
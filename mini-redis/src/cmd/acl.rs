@@ -0,0 +1,236 @@
+use super::{ok, optional};
+use crate::acl::{now_ms, AccessControl, Category, User};
+use crate::{Connection, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Number of entries `ACL LOG` returns without an explicit count.
+const DEFAULT_LOG_COUNT: usize = 10;
+
+/// Inspect and change the users of the server.
+///
+/// Supports the `SETUSER`, `GETUSER`, `DELUSER`, `LIST`, `USERS`, `WHOAMI`,
+/// `CAT`, `LOG`, `LOAD` and `SAVE` subcommands.
+#[derive(Debug)]
+pub struct Acl {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    SetUser {
+        username: String,
+        rules: Vec<String>,
+    },
+    GetUser(String),
+    DelUser(Vec<String>),
+    List,
+    Users,
+    WhoAmI,
+    Cat(Option<String>),
+    Log(Option<usize>),
+    LogReset,
+    Load,
+    Save,
+}
+
+impl Acl {
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Acl> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "setuser" => Subcommand::SetUser {
+                username: parse.next_string()?,
+                rules: rest(parse)?,
+            },
+            "getuser" => Subcommand::GetUser(parse.next_string()?),
+            "deluser" => {
+                let mut usernames = vec![parse.next_string()?];
+                usernames.extend(rest(parse)?);
+                Subcommand::DelUser(usernames)
+            }
+            "list" => Subcommand::List,
+            "users" => Subcommand::Users,
+            "whoami" => Subcommand::WhoAmI,
+            "cat" => Subcommand::Cat(optional(parse)?),
+            "log" => match optional(parse)? {
+                None => Subcommand::Log(None),
+                Some(arg) if arg.eq_ignore_ascii_case("reset") => Subcommand::LogReset,
+                Some(arg) => match arg.parse() {
+                    Ok(count) => Subcommand::Log(Some(count)),
+                    Err(_) => return Err("ERR value is out of range, must be positive".into()),
+                },
+            },
+            "load" => Subcommand::Load,
+            "save" => Subcommand::Save,
+            _ => return Err(format!("ERR unknown subcommand '{}'. Try ACL HELP.", name).into()),
+        };
+        Ok(Acl { subcommand })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("acl".as_bytes()));
+        let mut push = |s: &str| frame.push_bulk(Bytes::from(s.to_string()));
+        match self.subcommand {
+            Subcommand::SetUser { username, rules } => {
+                push("setuser");
+                push(&username);
+                rules.iter().for_each(|rule| push(rule));
+            }
+            Subcommand::GetUser(username) => {
+                push("getuser");
+                push(&username);
+            }
+            Subcommand::DelUser(usernames) => {
+                push("deluser");
+                usernames.iter().for_each(|username| push(username));
+            }
+            Subcommand::List => push("list"),
+            Subcommand::Users => push("users"),
+            Subcommand::WhoAmI => push("whoami"),
+            Subcommand::Cat(category) => {
+                push("cat");
+                if let Some(category) = category {
+                    push(&category);
+                }
+            }
+            Subcommand::Log(count) => {
+                push("log");
+                if let Some(count) = count {
+                    push(&count.to_string());
+                }
+            }
+            Subcommand::LogReset => {
+                push("log");
+                push("reset");
+            }
+            Subcommand::Load => push("load"),
+            Subcommand::Save => push("save"),
+        }
+        frame
+    }
+
+    /// Runs the subcommand against `acl` on behalf of the connection's
+    /// `username` and writes the reply.
+    pub async fn apply(
+        self,
+        acl: &AccessControl,
+        username: &str,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let frame = match self.subcommand {
+            Subcommand::SetUser { username, rules } => match acl.set_user(&username, &rules) {
+                Ok(()) => ok(),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            Subcommand::GetUser(username) => match acl.get_user(&username) {
+                Some(user) => describe_user(&user),
+                None => Frame::Null,
+            },
+            Subcommand::DelUser(usernames) => match acl.del_users(&usernames) {
+                Ok(deleted) => Frame::Integer(deleted as u64),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            Subcommand::List => bulk_array(acl.users().iter().map(User::to_string)),
+            Subcommand::Users => bulk_array(acl.users().iter().map(|user| user.name().to_string())),
+            Subcommand::WhoAmI => Frame::Bulk(Bytes::from(username.to_string())),
+            Subcommand::Cat(None) => bulk_array(
+                Category::ALL
+                    .iter()
+                    .map(|category| category.name().to_string()),
+            ),
+            Subcommand::Cat(Some(name)) => match Category::from_name(&name) {
+                Some(category) => bulk_array(category.commands().map(str::to_string)),
+                None => Frame::Error(format!("ERR Unknown category '{}'", name)),
+            },
+            Subcommand::Log(count) => {
+                let now = now_ms();
+                let mut frame = Frame::array();
+                for entry in acl.log_entries(count.unwrap_or(DEFAULT_LOG_COUNT)) {
+                    let mut item = Frame::array();
+                    let age = now.saturating_sub(entry.created_ms) as f64 / 1000.0;
+                    push_field(&mut item, "count", Frame::Integer(entry.count));
+                    push_field(&mut item, "reason", bulk(entry.reason));
+                    push_field(&mut item, "context", bulk(entry.context));
+                    push_field(&mut item, "object", bulk(&entry.object));
+                    push_field(&mut item, "username", bulk(&entry.username));
+                    push_field(&mut item, "age-seconds", bulk(&format!("{:.3}", age)));
+                    push_field(&mut item, "client-info", bulk(&entry.client_info));
+                    push_field(&mut item, "entry-id", Frame::Integer(entry.entry_id));
+                    push_field(
+                        &mut item,
+                        "timestamp-created",
+                        Frame::Integer(entry.created_ms),
+                    );
+                    push_field(
+                        &mut item,
+                        "timestamp-last-updated",
+                        Frame::Integer(entry.updated_ms),
+                    );
+                    frame.push_frame(item);
+                }
+                frame
+            }
+            Subcommand::LogReset => {
+                acl.reset_log();
+                ok()
+            }
+            Subcommand::Load => match acl.load() {
+                Ok(()) => ok(),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            Subcommand::Save => match acl.save() {
+                Ok(()) => ok(),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+/// The `ACL GETUSER` reply.
+fn describe_user(user: &User) -> Frame {
+    let mut frame = Frame::array();
+    push_field(
+        &mut frame,
+        "flags",
+        bulk_array(user.flags().into_iter().map(str::to_string)),
+    );
+    push_field(
+        &mut frame,
+        "passwords",
+        bulk_array(user.password_hashes().map(str::to_string)),
+    );
+    push_field(&mut frame, "commands", bulk(&user.describe_commands()));
+    push_field(&mut frame, "keys", bulk(&user.describe_keys()));
+    push_field(&mut frame, "channels", bulk(&user.describe_channels()));
+    push_field(&mut frame, "selectors", Frame::array());
+    frame
+}
+
+fn rest(parse: &mut Parse) -> crate::Result<Vec<String>> {
+    let mut args = vec![];
+    while let Some(arg) = optional(parse)? {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn push_field(frame: &mut Frame, name: &'static str, value: Frame) {
+    frame.push_bulk(Bytes::from_static(name.as_bytes()));
+    frame.push_frame(value);
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::from(s.to_string()))
+}
+
+fn bulk_array(items: impl Iterator<Item = String>) -> Frame {
+    let mut frame = Frame::array();
+    for item in items {
+        frame.push_bulk(Bytes::from(item));
+    }
+    frame
+}
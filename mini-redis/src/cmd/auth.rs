@@ -0,0 +1,205 @@
+use crate::acl::{AccessControl, AuthError};
+use crate::parse::ParseError;
+use crate::{Connection, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Authenticate the connection.
+///
+/// `AUTH password` authenticates as the default user, `AUTH username
+/// password` names the user explicitly.
+#[derive(Debug)]
+pub struct Auth {
+    username: Option<String>,
+    password: String,
+}
+
+impl Auth {
+    pub fn new(username: Option<&str>, password: impl ToString) -> Auth {
+        Auth {
+            username: username.map(str::to_string),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Auth> {
+        let first = parse.next_string()?;
+        match parse.next_string() {
+            Ok(password) => Ok(Auth {
+                username: Some(first),
+                password,
+            }),
+            Err(ParseError::EndOfStream) => Ok(Auth {
+                username: None,
+                password: first,
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("auth".as_bytes()));
+        if let Some(username) = self.username {
+            frame.push_bulk(Bytes::from(username.into_bytes()));
+        }
+        frame.push_bulk(Bytes::from(self.password.into_bytes()));
+        frame
+    }
+
+    /// Checks the credentials against the users in `acl` and writes the
+    /// reply. Returns the name of the user the connection is now
+    /// authenticated as, if the credentials were accepted.
+    pub async fn apply(
+        self,
+        acl: &AccessControl,
+        client_info: &str,
+        connection: &mut Connection,
+    ) -> crate::Result<Option<String>> {
+        let (frame, user) = match acl.authenticate(self.username(), &self.password, client_info) {
+            Ok(user) => (Frame::Simple("OK".to_string()), Some(user)),
+            Err(AuthError::NoPasswordConfigured) => (
+                Frame::Error(
+                    "ERR AUTH <password> called without any password configured for the default user"
+                        .to_string(),
+                ),
+                None,
+            ),
+            Err(AuthError::WrongPass) => (wrong_pass(), None),
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(user)
+    }
+}
+
+/// Handshake with the server, optionally authenticating at the same time.
+///
+/// Only protocol version 2 is supported, as `Frame` has no RESP3 types. The
+/// reply is the usual list of server properties.
+#[derive(Debug, Default)]
+pub struct Hello {
+    protover: Option<u64>,
+    auth: Option<(String, String)>,
+}
+
+impl Hello {
+    pub fn new(protover: Option<u64>, auth: Option<(&str, &str)>) -> Hello {
+        Hello {
+            protover,
+            auth: auth.map(|(username, password)| (username.to_string(), password.to_string())),
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Hello> {
+        let protover = match parse.next_int() {
+            Ok(protover) => protover,
+            Err(ParseError::EndOfStream) => return Ok(Hello::default()),
+            Err(_) => return Err("ERR Protocol version is not an integer or out of range".into()),
+        };
+        let mut auth = None;
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.to_uppercase() == "AUTH" => {
+                    match (parse.next_string(), parse.next_string()) {
+                        (Ok(username), Ok(password)) => auth = Some((username, password)),
+                        _ => return Err(format!("ERR Syntax error in HELLO option '{}'", s).into()),
+                    }
+                }
+                Ok(s) => return Err(format!("ERR Syntax error in HELLO option '{}'", s).into()),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Hello {
+            protover: Some(protover),
+            auth,
+        })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("hello".as_bytes()));
+        if let Some(protover) = self.protover {
+            frame.push_bulk(Bytes::from(protover.to_string()));
+        }
+        if let Some((username, password)) = self.auth {
+            frame.push_bulk(Bytes::from("auth".as_bytes()));
+            frame.push_bulk(Bytes::from(username.into_bytes()));
+            frame.push_bulk(Bytes::from(password.into_bytes()));
+        }
+        frame
+    }
+
+    /// Writes the handshake reply. `user` is the user the connection is
+    /// currently authenticated as. Returns the user it is authenticated as
+    /// after the command.
+    pub async fn apply(
+        self,
+        acl: &AccessControl,
+        user: Option<String>,
+        client_info: &str,
+        connection: &mut Connection,
+    ) -> crate::Result<Option<String>> {
+        if let Some(protover) = self.protover {
+            if protover != 2 {
+                let frame = Frame::Error("NOPROTO unsupported protocol version".to_string());
+                connection.write_frame(&frame).await?;
+                return Ok(user);
+            }
+        }
+
+        let user = match &self.auth {
+            Some((username, password)) => {
+                match acl.authenticate(Some(username), password, client_info) {
+                    Ok(user) => Some(user),
+                    Err(_) => {
+                        connection.write_frame(&wrong_pass()).await?;
+                        return Ok(user);
+                    }
+                }
+            }
+            None => user,
+        };
+
+        if user.is_none() {
+            let frame = Frame::Error(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise \
+                 the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the \
+                 client and select the RESP protocol version at the same time"
+                    .to_string(),
+            );
+            connection.write_frame(&frame).await?;
+            return Ok(None);
+        }
+
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from_static(b"server"));
+        frame.push_bulk(Bytes::from_static(b"redis"));
+        frame.push_bulk(Bytes::from_static(b"version"));
+        frame.push_bulk(Bytes::from_static(env!("CARGO_PKG_VERSION").as_bytes()));
+        frame.push_bulk(Bytes::from_static(b"proto"));
+        frame.push_int(2);
+        frame.push_bulk(Bytes::from_static(b"mode"));
+        frame.push_bulk(Bytes::from_static(b"standalone"));
+        frame.push_bulk(Bytes::from_static(b"role"));
+        frame.push_bulk(Bytes::from_static(b"master"));
+        frame.push_bulk(Bytes::from_static(b"modules"));
+        frame.push_frame(Frame::array());
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(user)
+    }
+}
+
+fn wrong_pass() -> Frame {
+    Frame::Error("WRONGPASS invalid username-password pair or user is disabled.".to_string())
+}
@@ -0,0 +1,255 @@
+use super::optional;
+use crate::client_list::{ClientInfo, ClientList, PauseMode};
+use crate::{Connection, Frame, Parse};
+use bytes::Bytes;
+use std::time::Duration;
+use tracing::debug;
+
+/// Inspect and manage the connections to the server.
+///
+/// Supports the `ID`, `GETNAME`, `SETNAME`, `INFO`, `LIST`, `KILL`, `PAUSE`
+/// and `UNPAUSE` subcommands.
+#[derive(Debug)]
+pub struct Client {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    Id,
+    GetName,
+    SetName(String),
+    Info,
+    List(Vec<u64>),
+    Kill(Kill),
+    Pause(u64, PauseMode),
+    Unpause,
+}
+
+/// Connections selected by `CLIENT KILL`.
+#[derive(Debug, Default)]
+struct Kill {
+    /// Given in the old `CLIENT KILL addr` form, which replies `OK` rather
+    /// than the number of connections killed.
+    legacy: bool,
+    id: Option<u64>,
+    addr: Option<String>,
+    user: Option<String>,
+    /// Whether the calling connection is spared. Only in the new form.
+    skip_me: bool,
+}
+
+impl Client {
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Client> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "id" => Subcommand::Id,
+            "getname" => Subcommand::GetName,
+            "setname" => Subcommand::SetName(parse.next_string()?),
+            "info" => Subcommand::Info,
+            "list" => {
+                let mut ids = vec![];
+                if let Some(filter) = optional(parse)? {
+                    if !filter.eq_ignore_ascii_case("id") {
+                        return Err("ERR syntax error".into());
+                    }
+                    ids.push(parse_id(&parse.next_string()?)?);
+                    while let Some(id) = optional(parse)? {
+                        ids.push(parse_id(&id)?);
+                    }
+                }
+                Subcommand::List(ids)
+            }
+            "kill" => {
+                let first = parse.next_string()?;
+                let mut kill = Kill {
+                    skip_me: true,
+                    ..Kill::default()
+                };
+                match optional(parse)? {
+                    None => {
+                        kill.legacy = true;
+                        kill.skip_me = false;
+                        kill.addr = Some(first);
+                    }
+                    Some(value) => {
+                        let mut filter = Some((first, value));
+                        while let Some((name, value)) = filter {
+                            match &name.to_lowercase()[..] {
+                                "id" => kill.id = Some(parse_id(&value)?),
+                                "addr" => kill.addr = Some(value),
+                                "user" => kill.user = Some(value),
+                                "skipme" => {
+                                    kill.skip_me = match &value.to_lowercase()[..] {
+                                        "yes" => true,
+                                        "no" => false,
+                                        _ => return Err("ERR syntax error".into()),
+                                    }
+                                }
+                                _ => return Err("ERR syntax error".into()),
+                            }
+                            filter = match optional(parse)? {
+                                Some(name) => Some((name, parse.next_string()?)),
+                                None => None,
+                            };
+                        }
+                    }
+                }
+                Subcommand::Kill(kill)
+            }
+            "pause" => {
+                let timeout = parse
+                    .next_int()
+                    .map_err(|_| "ERR timeout is not an integer or out of range")?;
+                let mode = match optional(parse)?.map(|mode| mode.to_lowercase()) {
+                    None => PauseMode::All,
+                    Some(mode) if mode == "all" => PauseMode::All,
+                    Some(mode) if mode == "write" => PauseMode::Write,
+                    Some(_) => return Err("ERR syntax error".into()),
+                };
+                Subcommand::Pause(timeout, mode)
+            }
+            "unpause" => Subcommand::Unpause,
+            _ => return Err(format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", name).into()),
+        };
+        Ok(Client { subcommand })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("client".as_bytes()));
+        let mut push = |arg: String| frame.push_bulk(Bytes::from(arg.into_bytes()));
+        match self.subcommand {
+            Subcommand::Id => push("id".to_string()),
+            Subcommand::GetName => push("getname".to_string()),
+            Subcommand::SetName(name) => {
+                push("setname".to_string());
+                push(name);
+            }
+            Subcommand::Info => push("info".to_string()),
+            Subcommand::List(ids) => {
+                push("list".to_string());
+                if !ids.is_empty() {
+                    push("id".to_string());
+                    for id in ids {
+                        push(id.to_string());
+                    }
+                }
+            }
+            Subcommand::Kill(kill) => {
+                push("kill".to_string());
+                if kill.legacy {
+                    push(kill.addr.unwrap_or_default());
+                } else {
+                    if let Some(id) = kill.id {
+                        push("id".to_string());
+                        push(id.to_string());
+                    }
+                    if let Some(addr) = kill.addr {
+                        push("addr".to_string());
+                        push(addr);
+                    }
+                    if let Some(user) = kill.user {
+                        push("user".to_string());
+                        push(user);
+                    }
+                    push("skipme".to_string());
+                    push(if kill.skip_me { "yes" } else { "no" }.to_string());
+                }
+            }
+            Subcommand::Pause(timeout, mode) => {
+                push("pause".to_string());
+                push(timeout.to_string());
+                push(
+                    match mode {
+                        PauseMode::Write => "write",
+                        PauseMode::All => "all",
+                    }
+                    .to_string(),
+                );
+            }
+            Subcommand::Unpause => push("unpause".to_string()),
+        }
+        frame
+    }
+
+    /// Applies the command on behalf of the connection `id`.
+    pub(crate) async fn apply(
+        self,
+        clients: &ClientList,
+        id: u64,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let frame = match self.subcommand {
+            Subcommand::Id => Frame::Integer(id),
+            Subcommand::GetName => match clients.get(id) {
+                Some(client) if !client.name.is_empty() => Frame::Bulk(Bytes::from(client.name)),
+                _ => Frame::Null,
+            },
+            Subcommand::SetName(name) => {
+                if name.chars().all(|c| c.is_ascii_graphic()) {
+                    clients.update(id, |client| client.name = name);
+                    Frame::Simple("OK".to_string())
+                } else {
+                    Frame::Error(
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                            .to_string(),
+                    )
+                }
+            }
+            Subcommand::Info => {
+                let info = clients.get(id).map(|client| client.to_string());
+                Frame::Bulk(Bytes::from(format!("{}\n", info.unwrap_or_default())))
+            }
+            Subcommand::List(ids) => {
+                let list: String = clients
+                    .list()
+                    .iter()
+                    .filter(|client| ids.is_empty() || ids.contains(&client.id))
+                    .map(|client| format!("{}\n", client))
+                    .collect();
+                Frame::Bulk(Bytes::from(list))
+            }
+            Subcommand::Kill(kill) => {
+                let killed = clients.kill(|client| kill.matches(client, id));
+                match (kill.legacy, killed) {
+                    (true, 0) => Frame::Error("ERR No such client".to_string()),
+                    (true, _) => Frame::Simple("OK".to_string()),
+                    (false, killed) => Frame::Integer(killed as u64),
+                }
+            }
+            Subcommand::Pause(timeout, mode) => {
+                if clients.pause(Duration::from_millis(timeout), mode) {
+                    Frame::Simple("OK".to_string())
+                } else {
+                    Frame::Error("ERR timeout is out of range".to_string())
+                }
+            }
+            Subcommand::Unpause => {
+                clients.unpause();
+                Frame::Simple("OK".to_string())
+            }
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+impl Kill {
+    /// Whether `client` is to be killed on behalf of the connection `me`.
+    fn matches(&self, client: &ClientInfo, me: u64) -> bool {
+        !(self.skip_me && client.id == me)
+            && self.id.is_none_or(|id| client.id == id)
+            && self.addr.as_ref().is_none_or(|addr| client.addr == *addr)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| client.user.as_ref() == Some(user))
+    }
+}
+
+fn parse_id(id: &str) -> crate::Result<u64> {
+    id.parse()
+        .map_err(|_| format!("ERR Invalid client ID '{}'", id).into())
+}
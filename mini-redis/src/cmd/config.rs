@@ -0,0 +1,153 @@
+use super::{ok, optional};
+use crate::acl::{AccessControl, User, DEFAULT_USER};
+use crate::config::LiveConfig;
+use crate::eviction::Eviction;
+use crate::stats::Stats;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Inspect and change the configuration of the running server.
+///
+/// Supports the `GET`, `SET`, `RESETSTAT` and `REWRITE` subcommands.
+#[derive(Debug)]
+pub struct Config {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    ResetStat,
+    Rewrite,
+}
+
+impl Config {
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Config> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "get" => {
+                let mut patterns = vec![parse.next_string()?];
+                while let Some(pattern) = optional(parse)? {
+                    patterns.push(pattern);
+                }
+                Subcommand::Get(patterns)
+            }
+            "set" => {
+                let mut pairs = vec![(parse.next_string()?, parse.next_string()?)];
+                while let Some(name) = optional(parse)? {
+                    pairs.push((name, parse.next_string()?));
+                }
+                Subcommand::Set(pairs)
+            }
+            "resetstat" => Subcommand::ResetStat,
+            "rewrite" => Subcommand::Rewrite,
+            _ => return Err(format!("ERR unknown subcommand '{}'. Try CONFIG HELP.", name).into()),
+        };
+        Ok(Config { subcommand })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("config".as_bytes()));
+        match self.subcommand {
+            Subcommand::Get(patterns) => {
+                frame.push_bulk(Bytes::from("get".as_bytes()));
+                for pattern in patterns {
+                    frame.push_bulk(Bytes::from(pattern.into_bytes()));
+                }
+            }
+            Subcommand::Set(pairs) => {
+                frame.push_bulk(Bytes::from("set".as_bytes()));
+                for (name, value) in pairs {
+                    frame.push_bulk(Bytes::from(name.into_bytes()));
+                    frame.push_bulk(Bytes::from(value.into_bytes()));
+                }
+            }
+            Subcommand::ResetStat => frame.push_bulk(Bytes::from("resetstat".as_bytes())),
+            Subcommand::Rewrite => frame.push_bulk(Bytes::from("rewrite".as_bytes())),
+        }
+        frame
+    }
+
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        config: &LiveConfig,
+        acl: &AccessControl,
+        stats: &Stats,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let frame = match self.subcommand {
+            Subcommand::Get(patterns) => {
+                let snapshot = config.snapshot();
+                let mut params: Vec<_> = patterns
+                    .iter()
+                    .flat_map(|pattern| snapshot.get(pattern))
+                    .collect();
+                params.sort_by_key(|(name, _)| *name);
+                params.dedup_by_key(|(name, _)| *name);
+
+                let mut frame = Frame::array();
+                for (name, value) in params {
+                    frame.push_bulk(Bytes::from_static(name.as_bytes()));
+                    frame.push_bulk(Bytes::from(value));
+                }
+                frame
+            }
+            Subcommand::Set(pairs) => {
+                // The password of the default user follows `requirepass`,
+                // unless the users are loaded from an ACL file. The rules
+                // are checked before the configuration changes.
+                let rules = password_rules(&pairs)
+                    .filter(|_| config.with(|config| config.aclfile.is_none()));
+                let checked = rules.as_deref().map_or(Ok(()), check_rules);
+                match checked.and_then(|()| config.set(&pairs)) {
+                    Ok(()) => {
+                        // Lowering `maxmemory` evicts keys right away.
+                        db.set_eviction(config.with(|config| Eviction::from(config)));
+                        db.perform_evictions();
+                        db.set_lazy_free(config.with(|config| config.lazyfree));
+                        match rules.map(|rules| acl.set_user(DEFAULT_USER, &rules)) {
+                            Some(Err(err)) => Frame::Error(format!("ERR {}", err)),
+                            _ => ok(),
+                        }
+                    }
+                    Err(err) => Frame::Error(format!("ERR {}", err)),
+                }
+            }
+            Subcommand::ResetStat => {
+                stats.reset();
+                db.reset_stats();
+                ok()
+            }
+            Subcommand::Rewrite => match config.rewrite() {
+                Ok(()) => ok(),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+/// The rules setting the password of the default user to the last
+/// `requirepass` of `pairs`, if any.
+fn password_rules(pairs: &[(String, String)]) -> Option<Vec<String>> {
+    let (_, password) = pairs
+        .iter()
+        .rev()
+        .find(|(name, _)| name.eq_ignore_ascii_case("requirepass"))?;
+    Some(match password.as_str() {
+        "" => vec!["nopass".to_string()],
+        password => vec!["resetpass".to_string(), format!(">{}", password)],
+    })
+}
+
+/// Checks that `rules` apply to a user.
+fn check_rules(rules: &[String]) -> Result<(), String> {
+    let mut user = User::new(DEFAULT_USER);
+    rules.iter().try_for_each(|rule| user.apply_rule(rule))
+}
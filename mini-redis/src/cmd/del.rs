@@ -0,0 +1,108 @@
+use super::optional;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Remove keys.
+///
+/// Replies with the number of keys that existed. Values are freed by the
+/// command, unless `lazyfree-lazy-user-del` is set.
+#[derive(Clone, Debug)]
+pub struct Del {
+    keys: Vec<String>,
+}
+
+impl Del {
+    pub fn new(keys: Vec<String>) -> Del {
+        Del { keys }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn into_keys(self) -> Vec<String> {
+        self.keys
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Del> {
+        parse_keys(parse).map(|keys| Del { keys })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        keys_frame("del", self.keys)
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        let keys: Vec<&str> = self.keys.iter().map(String::as_str).collect();
+        Frame::Integer(db.del(&keys) as u64)
+    }
+}
+
+/// Remove keys, freeing their values on a background thread.
+///
+/// Same as `DEL` otherwise.
+#[derive(Clone, Debug)]
+pub struct Unlink {
+    keys: Vec<String>,
+}
+
+impl Unlink {
+    pub fn new(keys: Vec<String>) -> Unlink {
+        Unlink { keys }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn into_keys(self) -> Vec<String> {
+        self.keys
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Unlink> {
+        parse_keys(parse).map(|keys| Unlink { keys })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        keys_frame("unlink", self.keys)
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        let keys: Vec<&str> = self.keys.iter().map(String::as_str).collect();
+        Frame::Integer(db.unlink(&keys) as u64)
+    }
+}
+
+fn parse_keys(parse: &mut Parse) -> crate::Result<Vec<String>> {
+    let mut keys = vec![parse.next_string()?];
+    while let Some(key) = optional(parse)? {
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+fn keys_frame(name: &'static str, keys: Vec<String>) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(name.as_bytes()));
+    for key in keys {
+        frame.push_bulk(Bytes::from(key.into_bytes()));
+    }
+    frame
+}
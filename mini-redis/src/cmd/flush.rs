@@ -0,0 +1,64 @@
+use super::optional;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Remove every key.
+///
+/// `ASYNC` frees the keyspace on a background thread, `SYNC` before
+/// replying. Without either, `lazyfree-lazy-user-flush` decides. There is a
+/// single database, so `FLUSHDB` and `FLUSHALL` are the same command.
+#[derive(Clone, Debug)]
+pub struct Flush {
+    /// `flushdb` or `flushall`.
+    name: &'static str,
+    lazy: Option<bool>,
+}
+
+impl Flush {
+    pub fn new(lazy: Option<bool>) -> Flush {
+        Flush {
+            name: "flushall",
+            lazy,
+        }
+    }
+
+    /// Parses the arguments of the command `name`.
+    pub fn parse_frames(name: &'static str, parse: &mut Parse) -> crate::Result<Flush> {
+        let lazy = match optional(parse)?.map(|mode| mode.to_lowercase()) {
+            None => None,
+            Some(mode) if mode == "async" => Some(true),
+            Some(mode) if mode == "sync" => Some(false),
+            Some(_) => return Err("ERR syntax error".into()),
+        };
+        Ok(Flush { name, lazy })
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(self.name.as_bytes()));
+        match self.lazy {
+            Some(true) => frame.push_bulk(Bytes::from("async".as_bytes())),
+            Some(false) => frame.push_bulk(Bytes::from("sync".as_bytes())),
+            None => {}
+        }
+        frame
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        db.flush(self.lazy);
+        Frame::Simple("OK".to_string())
+    }
+}
@@ -0,0 +1,316 @@
+use super::optional;
+use crate::config::{Backend, LiveConfig};
+use crate::replication::{LinkState, Replication};
+use crate::stats::Stats;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use std::fmt::Write;
+use tracing::debug;
+
+/// Sections returned when none, `default` or `all` is requested.
+const DEFAULT_SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "keyspace",
+];
+
+/// Report information and statistics about the server.
+///
+/// The reply is a bulk string of `# Section` headers followed by
+/// `field:value` lines, as in Redis. Sections can be selected by name.
+#[derive(Debug, Default)]
+pub struct Info {
+    sections: Vec<String>,
+}
+
+impl Info {
+    pub fn new(sections: &[&str]) -> Info {
+        Info {
+            sections: sections.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Info> {
+        let mut sections = vec![];
+        while let Some(section) = optional(parse)? {
+            sections.push(section.to_lowercase());
+        }
+        Ok(Info { sections })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("info".as_bytes()));
+        for section in self.sections {
+            frame.push_bulk(Bytes::from(section.into_bytes()));
+        }
+        frame
+    }
+
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        config: &LiveConfig,
+        stats: &Stats,
+        replication: &Replication,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let frame = Frame::Bulk(Bytes::from(self.render(db, config, stats, replication)));
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    fn render(
+        &self,
+        db: &Db,
+        config: &LiveConfig,
+        stats: &Stats,
+        replication: &Replication,
+    ) -> String {
+        let wanted = |section: &str| {
+            if self.sections.is_empty() {
+                return DEFAULT_SECTIONS.contains(&section);
+            }
+            self.sections.iter().any(|s| match s.as_str() {
+                "all" | "default" | "everything" => DEFAULT_SECTIONS.contains(&section),
+                s => s == section,
+            })
+        };
+        let config = config.snapshot();
+        let db_stats = db.stats();
+        let replication = replication.info();
+        let uptime = stats.uptime().as_secs();
+
+        // Writing to a `String` cannot fail.
+        let mut out = String::new();
+        let mut section = |name: &str, fields: &[(&str, String)]| {
+            if !wanted(&name.to_lowercase()) {
+                return;
+            }
+            if !out.is_empty() {
+                out.push_str("\r\n");
+            }
+            let _ = write!(out, "# {}\r\n", name);
+            for (field, value) in fields {
+                let _ = write!(out, "{}:{}\r\n", field, value);
+            }
+        };
+
+        section(
+            "Server",
+            &[
+                ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+                ("redis_mode", "standalone".to_string()),
+                (
+                    "os",
+                    format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+                ),
+                ("arch_bits", (usize::BITS).to_string()),
+                ("process_id", std::process::id().to_string()),
+                ("tcp_port", config.port.to_string()),
+                ("uptime_in_seconds", uptime.to_string()),
+                ("uptime_in_days", (uptime / 86400).to_string()),
+                (
+                    "executable",
+                    std::env::current_exe()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                ),
+                (
+                    "config_file",
+                    config
+                        .config_file
+                        .as_ref()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                ),
+            ],
+        );
+        section(
+            "Clients",
+            &[
+                ("connected_clients", stats.connected_clients().to_string()),
+                ("maxclients", config.max_clients.to_string()),
+            ],
+        );
+        section(
+            "Memory",
+            &[
+                ("used_memory", db_stats.used_memory.to_string()),
+                (
+                    "used_memory_human",
+                    human_bytes(db_stats.used_memory as u64),
+                ),
+                (
+                    "used_memory_overhead",
+                    (db_stats.used_memory - db_stats.dataset_memory).to_string(),
+                ),
+                ("used_memory_dataset", db_stats.dataset_memory.to_string()),
+                ("maxmemory", config.maxmemory.to_string()),
+                ("maxmemory_human", human_bytes(config.maxmemory)),
+                ("maxmemory_policy", config.maxmemory_policy.to_string()),
+                (
+                    "lazyfree_pending_objects",
+                    db_stats.lazyfree_pending_objects.to_string(),
+                ),
+            ],
+        );
+        // The write-ahead log of the lsm backend is reported as the AOF. It
+        // is replayed before the server accepts connections, so the server
+        // is never seen loading. Nothing is persisted with the memory
+        // backend, the fields are reported for the sake of tools expecting
+        // them.
+        let aof_enabled = config.backend == Backend::Lsm;
+        let write_status = match db.storage_error() {
+            Some(_) => "err",
+            None => "ok",
+        };
+        section(
+            "Persistence",
+            &[
+                ("loading", "0".to_string()),
+                ("rdb_changes_since_last_save", db_stats.changes.to_string()),
+                ("rdb_bgsave_in_progress", "0".to_string()),
+                ("aof_enabled", (aof_enabled as u8).to_string()),
+                ("aof_last_write_status", write_status.to_string()),
+            ],
+        );
+        section(
+            "Stats",
+            &[
+                (
+                    "total_connections_received",
+                    stats.connections_received().to_string(),
+                ),
+                (
+                    "total_commands_processed",
+                    stats.commands_processed().to_string(),
+                ),
+                (
+                    "rejected_connections",
+                    stats.rejected_connections().to_string(),
+                ),
+                ("expired_keys", db_stats.expired_keys.to_string()),
+                (
+                    "expired_time_cap_reached_count",
+                    db_stats.expired_time_cap_reached.to_string(),
+                ),
+                (
+                    "expire_cycle_cpu_milliseconds",
+                    db_stats.expire_cycle_time.as_millis().to_string(),
+                ),
+                ("evicted_keys", db_stats.evicted_keys.to_string()),
+                ("lazyfreed_objects", db_stats.lazyfreed_objects.to_string()),
+                ("keyspace_hits", db_stats.keyspace_hits.to_string()),
+                ("keyspace_misses", db_stats.keyspace_misses.to_string()),
+                ("pubsub_channels", db_stats.pubsub_channels.to_string()),
+                ("pubsub_patterns", "0".to_string()),
+                ("sync_full", replication.full_syncs.to_string()),
+                ("sync_partial_ok", replication.partial_syncs.to_string()),
+            ],
+        );
+        let mut fields = vec![];
+        match &replication.master {
+            Some(master) => {
+                let up = master.state == LinkState::Connected;
+                fields.extend([
+                    ("role", "slave".to_string()),
+                    ("master_host", master.host.clone()),
+                    ("master_port", master.port.to_string()),
+                    (
+                        "master_link_status",
+                        if up { "up" } else { "down" }.to_string(),
+                    ),
+                    (
+                        "master_last_io_seconds_ago",
+                        master.last_io.as_secs().to_string(),
+                    ),
+                    (
+                        "master_sync_in_progress",
+                        ((master.state == LinkState::Sync) as u8).to_string(),
+                    ),
+                    ("slave_repl_offset", replication.offset.to_string()),
+                    (
+                        "slave_read_only",
+                        (config.replica_read_only as u8).to_string(),
+                    ),
+                ]);
+            }
+            None => fields.push(("role", "master".to_string())),
+        }
+        fields.push(("connected_slaves", replication.replicas.len().to_string()));
+        let names: Vec<String> = (0..replication.replicas.len())
+            .map(|i| format!("slave{}", i))
+            .collect();
+        for (name, replica) in names.iter().zip(&replication.replicas) {
+            let value = format!(
+                "ip={},port={},state=online,offset={},lag={}",
+                replica.ip,
+                replica.port,
+                replica.ack,
+                replica.lag.as_secs()
+            );
+            fields.push((name.as_str(), value));
+        }
+        let (backlog_start, backlog_len) = replication.backlog.unwrap_or_default();
+        fields.extend([
+            ("master_replid", replication.replid),
+            ("master_replid2", replication.replid2),
+            ("master_repl_offset", replication.offset.to_string()),
+            (
+                "second_repl_offset",
+                // As in Redis, the offset of the first byte of the new
+                // stream.
+                replication
+                    .second_offset
+                    .map_or(-1, |offset| offset as i64 + 1)
+                    .to_string(),
+            ),
+            (
+                "repl_backlog_active",
+                (replication.backlog.is_some() as u8).to_string(),
+            ),
+            ("repl_backlog_size", replication.backlog_size.to_string()),
+            (
+                "repl_backlog_first_byte_offset",
+                (backlog_start + 1).to_string(),
+            ),
+            ("repl_backlog_histlen", backlog_len.to_string()),
+        ]);
+        section("Replication", &fields);
+
+        let mut keyspace = vec![];
+        // As in Redis, empty databases are not listed.
+        if db_stats.keys > 0 {
+            let db0 = format!(
+                "keys={},expires={},avg_ttl=0",
+                db_stats.keys, db_stats.expires
+            );
+            keyspace.push(("db0", db0));
+        }
+        section("Keyspace", &keyspace);
+        out
+    }
+}
+
+/// Formats a number of bytes the way Redis does, e.g. `1.50M`.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (1 << 50, "P"),
+        (1 << 40, "T"),
+        (1 << 30, "G"),
+        (1 << 20, "M"),
+        (1 << 10, "K"),
+    ];
+    for &(size, unit) in UNITS {
+        if bytes >= size {
+            return format!("{:.2}{}", bytes as f64 / size as f64, unit);
+        }
+    }
+    format!("{}B", bytes)
+}
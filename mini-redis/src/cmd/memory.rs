@@ -0,0 +1,103 @@
+use super::optional;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Inspect the memory used by the server.
+///
+/// Supports the `USAGE key [SAMPLES count]` and `STATS` subcommands. Values
+/// are plain strings, so the sample count of `USAGE` is accepted but has no
+/// effect.
+#[derive(Debug)]
+pub struct Memory {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    Usage(String),
+    Stats,
+}
+
+impl Memory {
+    /// The key inspected by `MEMORY USAGE`, if that's the subcommand.
+    pub fn key(&self) -> Option<&str> {
+        match &self.subcommand {
+            Subcommand::Usage(key) => Some(key),
+            Subcommand::Stats => None,
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Memory> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "usage" => {
+                let key = parse.next_string()?;
+                if let Some(option) = optional(parse)? {
+                    if !option.eq_ignore_ascii_case("samples") {
+                        return Err("ERR syntax error".into());
+                    }
+                    parse.next_int().map_err(|_| "ERR syntax error")?;
+                }
+                Subcommand::Usage(key)
+            }
+            "stats" => Subcommand::Stats,
+            _ => return Err(format!("ERR unknown subcommand '{}'. Try MEMORY HELP.", name).into()),
+        };
+        Ok(Memory { subcommand })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("memory".as_bytes()));
+        match self.subcommand {
+            Subcommand::Usage(key) => {
+                frame.push_bulk(Bytes::from("usage".as_bytes()));
+                frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+            Subcommand::Stats => frame.push_bulk(Bytes::from("stats".as_bytes())),
+        }
+        frame
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        match self.subcommand {
+            Subcommand::Usage(key) => match db.key_info(&key) {
+                Some(info) => Frame::Integer(info.memory as u64),
+                None => Frame::Null,
+            },
+            Subcommand::Stats => {
+                let stats = db.memory_stats();
+                let overhead = stats.entries + stats.expirations + stats.pub_sub;
+                let total = overhead + stats.dataset;
+                let fields = [
+                    ("total.allocated", total),
+                    ("overhead.entries", stats.entries),
+                    ("overhead.expirations", stats.expirations),
+                    ("overhead.pubsub", stats.pub_sub),
+                    ("overhead.total", overhead),
+                    ("keys.count", stats.keys),
+                    (
+                        "keys.bytes-per-key",
+                        total.checked_div(stats.keys).unwrap_or(0),
+                    ),
+                    ("dataset.bytes", stats.dataset),
+                ];
+                let mut frame = Frame::array();
+                for (name, value) in fields {
+                    frame.push_bulk(Bytes::from(name.as_bytes()));
+                    frame.push_int(value as u64);
+                }
+                frame
+            }
+        }
+    }
+}
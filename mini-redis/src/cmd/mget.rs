@@ -0,0 +1,64 @@
+use super::optional;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Get the values of several keys at once.
+///
+/// The reply holds the value of each key, or null if it has none, in the
+/// order of the keys.
+#[derive(Debug)]
+pub struct Mget {
+    keys: Vec<String>,
+}
+
+impl Mget {
+    pub fn new(keys: Vec<String>) -> Mget {
+        Mget { keys }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn into_keys(self) -> Vec<String> {
+        self.keys
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Mget> {
+        let mut keys = vec![parse.next_string()?];
+        while let Some(key) = optional(parse)? {
+            keys.push(key);
+        }
+        Ok(Mget { keys })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("mget".as_bytes()));
+        for key in self.keys {
+            frame.push_bulk(Bytes::from(key.into_bytes()));
+        }
+        frame
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        let keys: Vec<&str> = self.keys.iter().map(String::as_str).collect();
+        let mut frame = Frame::array();
+        for value in db.mget(&keys) {
+            match value {
+                Some(value) => frame.push_bulk(value),
+                None => frame.push_frame(Frame::Null),
+            }
+        }
+        frame
+    }
+}
@@ -1,14 +1,58 @@
+mod acl;
+pub use acl::Acl;
+
+mod auth;
+pub use auth::{Auth, Hello};
+
+mod client;
+pub use client::Client;
+
+mod config;
+pub use config::Config;
+
+mod del;
+pub use del::{Del, Unlink};
+
+mod flush;
+pub use flush::Flush;
+
+mod info;
+pub use info::Info;
+
+mod memory;
+pub use memory::Memory;
+
+mod mget;
+pub use mget::Mget;
+
+mod mset;
+pub use mset::Mset;
+
+mod object;
+pub use object::Object;
+
+mod replication;
+pub use replication::{Psync, Replconf, ReplicaOf, Role, Wait, WaitAof};
+
+mod sentinel;
+pub use sentinel::Sentinel;
+
+mod slowlog;
+pub use slowlog::Slowlog;
+
+use crate::acl::{AccessControl, Category};
 use crate::parse::ParseError;
+use crate::storage;
 use crate::Db;
 use crate::Parse;
 use crate::Shutdown;
 use crate::{Connection, Frame};
 use bytes::Bytes;
-use clap::{Parser, Subcommand};
 use std::pin::Pin;
 use std::time::Duration;
 use tokio::select;
 use tokio::sync::broadcast;
+use tokio::time::Instant;
 use tokio_stream::{Stream, StreamExt, StreamMap};
 use tracing::debug;
 
@@ -16,13 +60,70 @@ use tracing::debug;
 pub enum Command {
     Get(Get),
     Set(Set),
+    Mget(Mget),
+    Mset(Mset),
+    Del(Del),
+    Unlink(Unlink),
+    Flush(Flush),
     Publish(Publish),
     Subscribe(Subscribe),
     Unsubscribe(Unsubscribe),
     Ping(Ping),
+    Auth(Auth),
+    Hello(Hello),
+    Acl(Acl),
+    Client(Client),
+    Config(Config),
+    Info(Info),
+    Memory(Memory),
+    Object(Object),
+    Slowlog(Slowlog),
+    ReplicaOf(ReplicaOf),
+    Psync(Psync),
+    Replconf(Replconf),
+    Role(Role),
+    Wait(Wait),
+    WaitAof(WaitAof),
+    Sentinel(Sentinel),
     Unknown(Unknown),
 }
 
+/// The commands known to the server and their ACL categories.
+pub(crate) const COMMAND_TABLE: &[(&str, &[Category])] = {
+    use Category::*;
+    &[
+        ("get", &[Read, String, Fast]),
+        ("set", &[Write, String, Slow]),
+        ("mget", &[Read, String, Fast]),
+        ("mset", &[Write, String, Slow]),
+        ("del", &[Keyspace, Write, Slow]),
+        ("unlink", &[Keyspace, Write, Fast]),
+        ("flushdb", &[Keyspace, Write, Slow, Dangerous]),
+        ("flushall", &[Keyspace, Write, Slow, Dangerous]),
+        ("publish", &[Pubsub, Fast]),
+        ("subscribe", &[Pubsub, Slow]),
+        ("unsubscribe", &[Pubsub, Slow]),
+        ("ping", &[Fast, Connection]),
+        ("auth", &[Fast, Connection]),
+        ("hello", &[Fast, Connection]),
+        ("acl", &[Admin, Slow, Dangerous]),
+        ("client", &[Admin, Slow, Dangerous]),
+        ("config", &[Admin, Slow, Dangerous]),
+        ("info", &[Slow, Dangerous]),
+        ("memory", &[Read, Slow]),
+        ("object", &[Keyspace, Read, Slow]),
+        ("slowlog", &[Admin, Slow, Dangerous]),
+        ("replicaof", &[Admin, Slow, Dangerous]),
+        ("slaveof", &[Admin, Slow, Dangerous]),
+        ("psync", &[Admin, Slow, Dangerous]),
+        ("replconf", &[Admin, Slow, Dangerous]),
+        ("role", &[Admin, Fast, Dangerous]),
+        ("wait", &[Slow, Connection]),
+        ("waitaof", &[Slow, Connection]),
+        ("sentinel", &[Admin, Slow, Dangerous]),
+    ]
+};
+
 impl Command {
     pub fn from_frame(frame: Frame) -> crate::Result<Command> {
         let mut parse = Parse::new(frame)?;
@@ -31,9 +132,32 @@ impl Command {
             "get" => Command::Get(Get::parse_frames(&mut parse)?),
             "publish" => Command::Publish(Publish::parse_frames(&mut parse)?),
             "set" => Command::Set(Set::parse_frames(&mut parse)?),
+            "mget" => Command::Mget(Mget::parse_frames(&mut parse)?),
+            "mset" => Command::Mset(Mset::parse_frames(&mut parse)?),
+            "del" => Command::Del(Del::parse_frames(&mut parse)?),
+            "unlink" => Command::Unlink(Unlink::parse_frames(&mut parse)?),
+            "flushdb" => Command::Flush(Flush::parse_frames("flushdb", &mut parse)?),
+            "flushall" => Command::Flush(Flush::parse_frames("flushall", &mut parse)?),
             "subscribe" => Command::Subscribe(Subscribe::parse_frames(&mut parse)?),
             "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frames(&mut parse)?),
             "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
+            "auth" => Command::Auth(Auth::parse_frames(&mut parse)?),
+            "hello" => Command::Hello(Hello::parse_frames(&mut parse)?),
+            "acl" => Command::Acl(Acl::parse_frames(&mut parse)?),
+            "client" => Command::Client(Client::parse_frames(&mut parse)?),
+            "config" => Command::Config(Config::parse_frames(&mut parse)?),
+            "info" => Command::Info(Info::parse_frames(&mut parse)?),
+            "memory" => Command::Memory(Memory::parse_frames(&mut parse)?),
+            "object" => Command::Object(Object::parse_frames(&mut parse)?),
+            "slowlog" => Command::Slowlog(Slowlog::parse_frames(&mut parse)?),
+            "replicaof" => Command::ReplicaOf(ReplicaOf::parse_frames("replicaof", &mut parse)?),
+            "slaveof" => Command::ReplicaOf(ReplicaOf::parse_frames("slaveof", &mut parse)?),
+            "psync" => Command::Psync(Psync::parse_frames(&mut parse)?),
+            "replconf" => Command::Replconf(Replconf::parse_frames(&mut parse)?),
+            "role" => Command::Role(Role::parse_frames(&mut parse)?),
+            "wait" => Command::Wait(Wait::parse_frames(&mut parse)?),
+            "waitaof" => Command::WaitAof(WaitAof::parse_frames(&mut parse)?),
+            "sentinel" => Command::Sentinel(Sentinel::parse_frames(&mut parse)?),
             _ => return Ok(Command::Unknown(Unknown::new(command_name))),
         };
         parse.finish()?;
@@ -44,14 +168,115 @@ impl Command {
         match self {
             Command::Get(_) => "get",
             Command::Set(_) => "set",
+            Command::Mget(_) => "mget",
+            Command::Mset(_) => "mset",
+            Command::Del(_) => "del",
+            Command::Unlink(_) => "unlink",
+            Command::Flush(cmd) => cmd.get_name(),
             Command::Publish(_) => "publish",
             Command::Subscribe(_) => "subscribe",
             Command::Unsubscribe(_) => "unsubscribe",
             Command::Ping(_) => "ping",
+            Command::Auth(_) => "auth",
+            Command::Hello(_) => "hello",
+            Command::Acl(_) => "acl",
+            Command::Client(_) => "client",
+            Command::Config(_) => "config",
+            Command::Info(_) => "info",
+            Command::Memory(_) => "memory",
+            Command::Object(_) => "object",
+            Command::Slowlog(_) => "slowlog",
+            Command::ReplicaOf(cmd) => cmd.get_name(),
+            Command::Psync(_) => "psync",
+            Command::Replconf(_) => "replconf",
+            Command::Role(_) => "role",
+            Command::Wait(_) => "wait",
+            Command::WaitAof(_) => "waitaof",
+            Command::Sentinel(_) => "sentinel",
             Command::Unknown(cmd) => cmd.get_name(),
         }
     }
 
+    /// The keys the command reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Get(cmd) => vec![cmd.key()],
+            Command::Set(cmd) => vec![cmd.key()],
+            Command::Mget(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Command::Mset(cmd) => cmd.keys().collect(),
+            Command::Del(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Command::Unlink(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Command::Memory(cmd) => cmd.key().into_iter().collect(),
+            Command::Object(cmd) => vec![cmd.key()],
+            _ => vec![],
+        }
+    }
+
+    /// The channels the command publishes or subscribes to.
+    pub fn channels(&self) -> Vec<&str> {
+        match self {
+            Command::Publish(cmd) => vec![&cmd.channel[..]],
+            Command::Subscribe(cmd) => cmd.channels.iter().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether the command changes the keyspace. Read-only replicas refuse
+    /// such commands, and masters feed them to their replicas.
+    pub(crate) fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::Mset(_)
+                | Command::Del(_)
+                | Command::Unlink(_)
+                | Command::Flush(_)
+        )
+    }
+
+    /// Applies a write of the replication stream. Replicas don't evict keys
+    /// to make room for the writes of their master, which replicates its
+    /// own evictions.
+    pub(crate) fn execute_replicated(self, db: &Db) {
+        match self {
+            Command::Set(cmd) => db.set(cmd.key, cmd.value, cmd.expire),
+            Command::Mset(cmd) => cmd.execute_replicated(db),
+            cmd => {
+                cmd.execute(db);
+            }
+        }
+    }
+
+    /// Runs a command on keys, returning its reply rather than writing it.
+    /// The thread-per-core engine uses this to run commands on the core
+    /// owning their keys.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        match self {
+            Command::Get(cmd) => cmd.execute(db),
+            Command::Set(cmd) => cmd.execute(db),
+            Command::Mget(cmd) => cmd.execute(db),
+            Command::Mset(cmd) => cmd.execute(db),
+            Command::Del(cmd) => cmd.execute(db),
+            Command::Unlink(cmd) => cmd.execute(db),
+            Command::Flush(cmd) => cmd.execute(db),
+            Command::Memory(cmd) => cmd.execute(db),
+            Command::Object(cmd) => cmd.execute(db),
+            cmd => Frame::Error(format!(
+                "ERR '{}' can't run on another core",
+                cmd.get_name()
+            )),
+        }
+    }
+
+    /// Reads the values the command needs from the disk beforehand, so that
+    /// running it doesn't wait on the disk, see `Db::load`.
+    pub(crate) async fn load(&self, db: &Db) {
+        use Command::*;
+        if matches!(self, Get(_) | Mget(_) | Memory(_) | Object(_)) {
+            db.load(&self.keys()).await;
+        }
+    }
+
     pub async fn apply(
         self,
         db: &Db,
@@ -59,20 +284,81 @@ impl Command {
         shutdown: &mut Shutdown,
     ) -> crate::Result<()> {
         use Command::*;
+        self.load(db).await;
         match self {
             Get(cmd) => cmd.apply(db, connection).await,
             Set(cmd) => cmd.apply(db, connection).await,
+            Mget(cmd) => cmd.apply(db, connection).await,
+            Mset(cmd) => cmd.apply(db, connection).await,
+            Del(cmd) => cmd.apply(db, connection).await,
+            Unlink(cmd) => cmd.apply(db, connection).await,
+            Flush(cmd) => cmd.apply(db, connection).await,
             Publish(cmd) => cmd.apply(db, connection).await,
             Subscribe(cmd) => cmd.apply(db, connection, shutdown).await,
             Ping(cmd) => cmd.apply(db, connection).await,
+            Memory(cmd) => cmd.apply(db, connection).await,
+            Object(cmd) => cmd.apply(db, connection).await,
+            // Without a server there are no users besides the default one,
+            // which needs no password.
+            Auth(cmd) => {
+                let acl = AccessControl::default();
+                cmd.apply(&acl, "", connection).await.map(|_| ())
+            }
+            Hello(cmd) => {
+                let acl = AccessControl::default();
+                let user = acl.default_login();
+                cmd.apply(&acl, user, "", connection).await.map(|_| ())
+            }
             Unknown(cmd) => cmd.apply(connection).await,
             // `Unsubscribe` cannot be applied. It may only be received from the
             // context of a `Subscribe` command.
             Unsubscribe(_) => Err("`Unsubscribe` is unsupported in this context".into()),
+            // These need the state of a server, see `server::Handler`.
+            Acl(_) => Err("`Acl` is unsupported in this context".into()),
+            Client(_) => Err("`Client` is unsupported in this context".into()),
+            Config(_) => Err("`Config` is unsupported in this context".into()),
+            Info(_) => Err("`Info` is unsupported in this context".into()),
+            Slowlog(_) => Err("`Slowlog` is unsupported in this context".into()),
+            ReplicaOf(_) => Err("`ReplicaOf` is unsupported in this context".into()),
+            Psync(_) => Err("`Psync` is unsupported in this context".into()),
+            Replconf(_) => Err("`Replconf` is unsupported in this context".into()),
+            Role(_) => Err("`Role` is unsupported in this context".into()),
+            Wait(_) => Err("`Wait` is unsupported in this context".into()),
+            WaitAof(_) => Err("`WaitAof` is unsupported in this context".into()),
+            // Only served in sentinel mode, see `sentinel::run`.
+            Sentinel(_) => Err("`Sentinel` is unsupported in this context".into()),
         }
     }
 }
 
+/// The `OK` reply of commands with nothing else to report.
+fn ok() -> Frame {
+    Frame::Simple("OK".to_string())
+}
+
+/// The reply to a command that could not be parsed: the message of `err`,
+/// prefixed with `ERR` unless it starts with an error code such as
+/// `NOPROTO`.
+pub(crate) fn error_reply(err: &crate::Error) -> Frame {
+    let message = err.to_string();
+    let code = message.split(' ').next().unwrap_or_default();
+    if code.len() > 1 && code.bytes().all(|b| b.is_ascii_uppercase()) {
+        Frame::Error(message)
+    } else {
+        Frame::Error(format!("ERR {}", message))
+    }
+}
+
+/// Reads the next argument, if any. Used by commands taking optional
+/// arguments.
+fn optional(parse: &mut Parse) -> crate::Result<Option<String>> {
+    match parse.next_string() {
+        Ok(arg) => Ok(Some(arg)),
+        Err(ParseError::EndOfStream) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
 #[derive(Debug)]
 pub struct Get {
     key: String,
@@ -102,15 +388,20 @@ impl Get {
     }
 
     pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
-        let frame = if let Some(value) = db.get(&self.key) {
-            Frame::Bulk(value)
-        } else {
-            Frame::Null
-        };
+        let frame = self.execute(db);
         debug!(?frame);
         connection.write_frame(&frame).await?;
         Ok(())
     }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        if let Some(value) = db.get(&self.key) {
+            Frame::Bulk(value)
+        } else {
+            Frame::Null
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -137,7 +428,7 @@ impl Publish {
         let mut frame = Frame::array();
         frame.push_bulk(Bytes::from("publish".as_bytes()));
         frame.push_bulk(Bytes::from(self.channel.into_bytes()));
-        frame.push_bulk(Bytes::from(self.message));
+        frame.push_bulk(self.message);
         frame
     }
 
@@ -149,7 +440,7 @@ impl Publish {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Set {
     key: String,
     value: Bytes,
@@ -189,6 +480,22 @@ impl Set {
         frame
     }
 
+    /// The frame replicas receive for a key set to `value`, expiring at
+    /// `expires_at`. The expiration is sent as a Unix time, so that the key
+    /// expires at the same time on every server however late the frame is
+    /// applied.
+    pub(crate) fn replicated(key: &str, value: Bytes, expires_at: Option<Instant>) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("set".as_bytes()));
+        frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+        frame.push_bulk(value);
+        if let Some(when) = expires_at {
+            frame.push_bulk(Bytes::from("pxat".as_bytes()));
+            frame.push_int(storage::to_unix(when));
+        }
+        frame
+    }
+
     pub fn parse_frames(parse: &mut Parse) -> crate::Result<Set> {
         use ParseError::EndOfStream;
         let key = parse.next_string()?;
@@ -203,6 +510,15 @@ impl Set {
                 let ms = parse.next_int()?;
                 expire = Some(Duration::from_millis(ms));
             }
+            // Keys set to expire in the past expire right away.
+            Ok(s) if s.to_uppercase() == "EXAT" => {
+                let at = storage::from_unix(parse.next_int()?.saturating_mul(1000));
+                expire = Some(at.saturating_duration_since(Instant::now()));
+            }
+            Ok(s) if s.to_uppercase() == "PXAT" => {
+                let at = storage::from_unix(parse.next_int()?);
+                expire = Some(at.saturating_duration_since(Instant::now()));
+            }
             Ok(_) => return Err("SET only supports expire option".into()),
             Err(EndOfStream) => {}
             Err(err) => return Err(err.into()),
@@ -215,12 +531,21 @@ impl Set {
     }
 
     pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
-        db.set(self.key, self.value, self.expire);
-        let frame = Frame::Simple("OK".to_string());
+        let frame = self.execute(db);
         debug!(?frame);
         connection.write_frame(&frame).await?;
         Ok(())
     }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        if db.perform_evictions_for(&[&self.key]) {
+            db.set(self.key, self.value, self.expire);
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error("OOM command not allowed when used memory > 'maxmemory'.".to_string())
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -256,16 +581,35 @@ impl Subscribe {
     }
 
     pub async fn apply(
+        self,
+        db: &Db,
+        connection: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        self.run(db, connection, shutdown, &|_| true, &|_| ()).await
+    }
+
+    /// Same as `apply`. Channels subscribed to after entering the subscribe
+    /// mode are first checked with `allow_channel`. `subscribed` is told the
+    /// number of subscriptions whenever it changes.
+    pub(crate) async fn run(
         mut self,
         db: &Db,
         connection: &mut Connection,
         shutdown: &mut Shutdown,
+        allow_channel: &(dyn Fn(&str) -> bool + Sync),
+        subscribed: &(dyn Fn(usize) + Sync),
     ) -> crate::Result<()> {
         let mut subscriptions = StreamMap::new();
+        let mut count = 0;
         loop {
             for channel_name in self.channels.drain(..) {
                 subscribe_to_channel(channel_name, &mut subscriptions, db, connection).await?;
             }
+            if subscriptions.len() != count {
+                count = subscriptions.len();
+                subscribed(count);
+            }
             select! {
                 Some((channel_name, msg)) = subscriptions.next() => {
                    connection.write_frame(&make_message_frame(channel_name, msg)).await?;
@@ -282,6 +626,7 @@ impl Subscribe {
                         &mut self.channels,
                         &mut subscriptions,
                         connection,
+                        allow_channel,
                     ).await?;
                 }
                 _ = shutdown.recv() => {
@@ -346,19 +691,34 @@ async fn handle_command(
     subscribe_to: &mut Vec<String>,
     subscriptions: &mut StreamMap<String, Messages>,
     connection: &mut Connection,
+    allow_channel: &(dyn Fn(&str) -> bool + Sync),
 ) -> crate::Result<()> {
-    match Command::from_frame(frame)? {
+    let cmd = match Command::from_frame(frame) {
+        Ok(cmd) => cmd,
+        Err(err) => {
+            connection.write_frame(&error_reply(&err)).await?;
+            return Ok(());
+        }
+    };
+    match cmd {
         Command::Subscribe(subscribe) => {
-            subscribe_to.extend(subscribe.channels.into_iter());
+            if subscribe
+                .channels
+                .iter()
+                .all(|channel| allow_channel(channel))
+            {
+                subscribe_to.extend(subscribe.channels);
+            } else {
+                let frame = Frame::Error("NOPERM No permissions to access a channel".to_string());
+                connection.write_frame(&frame).await?;
+            }
         }
         Command::Unsubscribe(mut unsubscribe) => {
             if unsubscribe.channels.is_empty() {
-                if unsubscribe.channels.is_empty() {
-                    unsubscribe.channels = subscriptions
-                        .keys()
-                        .map(|channel_name| channel_name.to_string())
-                        .collect();
-                }
+                unsubscribe.channels = subscriptions
+                    .keys()
+                    .map(|channel_name| channel_name.to_string())
+                    .collect();
             }
             for channel_name in unsubscribe.channels {
                 subscriptions.remove(&channel_name);
@@ -436,7 +796,7 @@ impl Ping {
         frame
     }
 
-    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+    pub async fn apply(self, _db: &Db, connection: &mut Connection) -> crate::Result<()> {
         let frame = Frame::Bulk(self.get_msg());
         connection.write_frame(&frame).await?;
         Ok(())
@@ -0,0 +1,70 @@
+use super::optional;
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Set several keys at once.
+///
+/// Other clients see either none or all of the keys set.
+#[derive(Clone, Debug)]
+pub struct Mset {
+    pairs: Vec<(String, Bytes)>,
+}
+
+impl Mset {
+    pub fn new(pairs: Vec<(String, Bytes)>) -> Mset {
+        Mset { pairs }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.pairs.iter().map(|(key, _)| &key[..])
+    }
+
+    pub(crate) fn into_pairs(self) -> Vec<(String, Bytes)> {
+        self.pairs
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Mset> {
+        let mut pairs = vec![(parse.next_string()?, parse.next_bytes()?)];
+        while let Some(key) = optional(parse)? {
+            let value = parse
+                .next_bytes()
+                .map_err(|_| "ERR wrong number of arguments for 'mset' command")?;
+            pairs.push((key, value));
+        }
+        Ok(Mset { pairs })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("mset".as_bytes()));
+        for (key, value) in self.pairs {
+            frame.push_bulk(Bytes::from(key.into_bytes()));
+            frame.push_bulk(value);
+        }
+        frame
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Applies the command received from the master, see
+    /// `Command::execute_replicated`.
+    pub(crate) fn execute_replicated(self, db: &Db) {
+        db.mset(self.pairs);
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        if db.perform_evictions_for(&self.keys().collect::<Vec<_>>()) {
+            db.mset(self.pairs);
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error("OOM command not allowed when used memory > 'maxmemory'.".to_string())
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{Connection, Db, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Inspect the internals of a key.
+///
+/// Supports the `ENCODING`, `FREQ`, `IDLETIME` and `REFCOUNT` subcommands.
+/// Unlike Redis, both the idle time and the access frequency are tracked
+/// whatever the eviction policy. Inspecting a key does not count as an
+/// access.
+#[derive(Debug)]
+pub struct Object {
+    subcommand: Subcommand,
+    key: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Subcommand {
+    Encoding,
+    Freq,
+    IdleTime,
+    RefCount,
+}
+
+impl Object {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Object> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "encoding" => Subcommand::Encoding,
+            "freq" => Subcommand::Freq,
+            "idletime" => Subcommand::IdleTime,
+            "refcount" => Subcommand::RefCount,
+            _ => return Err(format!("ERR unknown subcommand '{}'. Try OBJECT HELP.", name).into()),
+        };
+        let key = parse.next_string()?;
+        Ok(Object { subcommand, key })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let name = match self.subcommand {
+            Subcommand::Encoding => "encoding",
+            Subcommand::Freq => "freq",
+            Subcommand::IdleTime => "idletime",
+            Subcommand::RefCount => "refcount",
+        };
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("object".as_bytes()));
+        frame.push_bulk(Bytes::from(name.as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+
+    pub async fn apply(self, db: &Db, connection: &mut Connection) -> crate::Result<()> {
+        let frame = self.execute(db);
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+
+    /// Runs the command, returning the reply.
+    pub(crate) fn execute(self, db: &Db) -> Frame {
+        match db.key_info(&self.key) {
+            None => Frame::Null,
+            Some(info) => match self.subcommand {
+                Subcommand::Encoding => Frame::Bulk(Bytes::from(info.encoding)),
+                Subcommand::Freq => Frame::Integer(info.frequency as u64),
+                Subcommand::IdleTime => Frame::Integer(info.idle.as_secs()),
+                // Values are never shared between keys.
+                Subcommand::RefCount => Frame::Integer(1),
+            },
+        }
+    }
+}
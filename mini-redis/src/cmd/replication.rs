@@ -0,0 +1,417 @@
+use super::optional;
+use crate::replication::Replication;
+use crate::{Connection, Db, Frame, Parse, Shutdown};
+use bytes::Bytes;
+use tokio::time::Duration;
+use tracing::debug;
+
+/// Make the server a replica of another server, or a master again with
+/// `NO ONE`.
+///
+/// `SLAVEOF` is the same command under its former name.
+#[derive(Debug)]
+pub struct ReplicaOf {
+    /// `replicaof` or `slaveof`.
+    name: &'static str,
+    master: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    /// Replicates `master`, given as a host and a port, or stops replicating
+    /// when `None`.
+    pub fn new(master: Option<(String, u16)>) -> ReplicaOf {
+        ReplicaOf {
+            name: "replicaof",
+            master,
+        }
+    }
+
+    /// Parses the arguments of the command `name`.
+    pub fn parse_frames(name: &'static str, parse: &mut Parse) -> crate::Result<ReplicaOf> {
+        let host = parse.next_string()?;
+        let port = parse.next_string()?;
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            return Ok(ReplicaOf { name, master: None });
+        }
+        let port = port.parse().map_err(|_| "ERR Invalid master port")?;
+        Ok(ReplicaOf {
+            name,
+            master: Some((host, port)),
+        })
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(self.name.as_bytes()));
+        match self.master {
+            Some((host, port)) => {
+                frame.push_bulk(Bytes::from(host.into_bytes()));
+                frame.push_bulk(Bytes::from(port.to_string()));
+            }
+            None => {
+                frame.push_bulk(Bytes::from("no".as_bytes()));
+                frame.push_bulk(Bytes::from("one".as_bytes()));
+            }
+        }
+        frame
+    }
+
+    pub(crate) async fn apply(
+        self,
+        replication: &Replication,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let reply = if replication.replica_of(self.master) {
+            "OK"
+        } else {
+            "OK Already connected to specified master"
+        };
+        let frame = Frame::Simple(reply.to_string());
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+/// Ask a master for its replication stream.
+///
+/// The replica gives the ID of the stream it last followed and its offset in
+/// it, `?` and -1 if it followed none. The master continues the stream from
+/// there if it can, or starts over with a full sync. See
+/// `crate::replication`.
+#[derive(Debug)]
+pub struct Psync {
+    replid: String,
+    offset: i64,
+}
+
+impl Psync {
+    pub fn new(replid: impl ToString, offset: i64) -> Psync {
+        Psync {
+            replid: replid.to_string(),
+            offset,
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Psync> {
+        let replid = parse.next_string()?;
+        let offset = parse
+            .next_string()?
+            .parse()
+            .map_err(|_| "ERR value is not an integer or out of range")?;
+        Ok(Psync { replid, offset })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("psync".as_bytes()));
+        frame.push_bulk(Bytes::from(self.replid.into_bytes()));
+        frame.push_bulk(Bytes::from(self.offset.to_string()));
+        frame
+    }
+
+    pub(crate) fn replid(&self) -> &str {
+        &self.replid
+    }
+
+    /// The offset the replica continues from, `None` if it knows none.
+    pub(crate) fn offset(&self) -> Option<u64> {
+        u64::try_from(self.offset).ok()
+    }
+}
+
+/// Exchange settings and progress between a replica and its master.
+///
+/// Options come in pairs. A replica announces the port it listens on with
+/// `listening-port` and acknowledges the stream it applied with `ACK
+/// <offset>`, followed by `FACK <offset>`, the part of the stream it made
+/// durable, if it keeps its data on disk. A master asks its replicas for an acknowledgment with `GETACK
+/// *`. Other options are accepted and ignored.
+#[derive(Debug)]
+pub struct Replconf {
+    options: Vec<(String, String)>,
+}
+
+impl Replconf {
+    pub fn new(option: impl ToString, value: impl ToString) -> Replconf {
+        Replconf {
+            options: vec![(option.to_string().to_lowercase(), value.to_string())],
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Replconf> {
+        let mut options = vec![];
+        let mut option = Some(parse.next_string()?);
+        while let Some(name) = option {
+            let value = optional(parse)?.ok_or("ERR syntax error")?;
+            options.push((name.to_lowercase(), value));
+            option = optional(parse)?;
+        }
+        Ok(Replconf { options })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("replconf".as_bytes()));
+        for (option, value) in self.options {
+            frame.push_bulk(Bytes::from(option.into_bytes()));
+            frame.push_bulk(Bytes::from(value.into_bytes()));
+        }
+        frame
+    }
+
+    /// Adds an option.
+    pub fn with(mut self, option: impl ToString, value: impl ToString) -> Replconf {
+        let option = option.to_string().to_lowercase();
+        self.options.push((option, value.to_string()));
+        self
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// The port the replica listens on.
+    pub(crate) fn listening_port(&self) -> Option<u16> {
+        self.option("listening-port")?.parse().ok()
+    }
+
+    /// The offset of the stream acknowledged by the replica.
+    pub(crate) fn ack(&self) -> Option<u64> {
+        self.option("ack")?.parse().ok()
+    }
+
+    /// The offset of the stream the replica made durable.
+    pub(crate) fn fack(&self) -> Option<u64> {
+        self.option("fack")?.parse().ok()
+    }
+
+    /// Whether the master asks for an acknowledgment.
+    pub(crate) fn is_getack(&self) -> bool {
+        self.option("getack").is_some()
+    }
+}
+
+/// Report the role of the server in replication.
+///
+/// A master replies with `master`, its offset in the replication stream and
+/// the address and acknowledged offset of each replica. A replica replies
+/// with `slave`, the address of its master, the state of the link to it and
+/// its offset.
+#[derive(Debug, Default)]
+pub struct Role {}
+
+impl Role {
+    pub fn new() -> Role {
+        Role {}
+    }
+
+    pub fn parse_frames(_parse: &mut Parse) -> crate::Result<Role> {
+        Ok(Role {})
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("role".as_bytes()));
+        frame
+    }
+
+    pub(crate) async fn apply(
+        self,
+        replication: &Replication,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let info = replication.info();
+        let mut frame = Frame::array();
+        match info.master {
+            Some(master) => {
+                frame.push_bulk(Bytes::from("slave".as_bytes()));
+                frame.push_bulk(Bytes::from(master.host.into_bytes()));
+                frame.push_int(master.port as u64);
+                frame.push_bulk(Bytes::from(master.state.name().as_bytes()));
+                frame.push_int(info.offset);
+            }
+            None => {
+                frame.push_bulk(Bytes::from("master".as_bytes()));
+                frame.push_int(info.offset);
+                let mut replicas = Frame::array();
+                for replica in info.replicas {
+                    let mut entry = Frame::array();
+                    entry.push_bulk(Bytes::from(replica.ip.into_bytes()));
+                    entry.push_bulk(Bytes::from(replica.port.to_string()));
+                    entry.push_bulk(Bytes::from(replica.ack.to_string()));
+                    replicas.push_frame(entry);
+                }
+                frame.push_frame(replicas);
+            }
+        }
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+/// Block until the writes of the connection reached `numreplicas` replicas,
+/// or until `timeout` milliseconds elapsed, 0 blocking forever.
+///
+/// Replies with the number of replicas that acknowledged the writes, which
+/// is less than `numreplicas` when the timeout elapsed first.
+#[derive(Debug)]
+pub struct Wait {
+    numreplicas: u64,
+    timeout: u64,
+}
+
+impl Wait {
+    pub fn new(numreplicas: u64, timeout: Duration) -> Wait {
+        Wait {
+            numreplicas,
+            timeout: timeout.as_millis() as u64,
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Wait> {
+        let numreplicas = parse
+            .next_int()
+            .map_err(|_| "ERR value is not an integer or out of range")?;
+        let timeout = parse
+            .next_int()
+            .map_err(|_| "ERR timeout is not an integer or out of range")?;
+        Ok(Wait {
+            numreplicas,
+            timeout,
+        })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("wait".as_bytes()));
+        frame.push_bulk(Bytes::from(self.numreplicas.to_string()));
+        frame.push_bulk(Bytes::from(self.timeout.to_string()));
+        frame
+    }
+
+    /// Waits for the writes up to `offset`, the end of the stream after the
+    /// last write of the connection. Returns without replying if the server
+    /// shuts down first.
+    pub(crate) async fn apply(
+        self,
+        replication: &Replication,
+        offset: u64,
+        connection: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        let frame = if replication.is_replica() {
+            Frame::Error("ERR WAIT cannot be used with replica instances.".to_string())
+        } else {
+            let numreplicas = usize::try_from(self.numreplicas).unwrap_or(usize::MAX);
+            let timeout = Some(Duration::from_millis(self.timeout)).filter(|t| !t.is_zero());
+            tokio::select! {
+                count = replication.wait(offset, numreplicas, timeout) => {
+                    Frame::Integer(count as u64)
+                }
+                _ = shutdown.recv() => return Ok(()),
+            }
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+/// Block until the writes of the connection are durable on this server if
+/// `numlocal` is 1, and on `numreplicas` replicas, or until `timeout`
+/// milliseconds elapsed, 0 blocking forever.
+///
+/// Writes are durable once in the write-ahead log of the lsm backend, the
+/// counterpart of the append-only file of Redis. Replicas report how far
+/// they made the stream durable along with their acknowledgments, those
+/// keeping everything in memory never do. Replies with the number of copies
+/// on this server, 0 or 1, and on replicas.
+#[derive(Debug)]
+pub struct WaitAof {
+    numlocal: u64,
+    numreplicas: u64,
+    timeout: u64,
+}
+
+impl WaitAof {
+    pub fn new(numlocal: u64, numreplicas: u64, timeout: Duration) -> WaitAof {
+        WaitAof {
+            numlocal,
+            numreplicas,
+            timeout: timeout.as_millis() as u64,
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<WaitAof> {
+        let numlocal = parse
+            .next_int()
+            .map_err(|_| "ERR value is not an integer or out of range")?;
+        let numreplicas = parse
+            .next_int()
+            .map_err(|_| "ERR value is not an integer or out of range")?;
+        let timeout = parse
+            .next_int()
+            .map_err(|_| "ERR timeout is not an integer or out of range")?;
+        Ok(WaitAof {
+            numlocal,
+            numreplicas,
+            timeout,
+        })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("waitaof".as_bytes()));
+        frame.push_bulk(Bytes::from(self.numlocal.to_string()));
+        frame.push_bulk(Bytes::from(self.numreplicas.to_string()));
+        frame.push_bulk(Bytes::from(self.timeout.to_string()));
+        frame
+    }
+
+    /// Waits for the writes up to `offset`, the end of the stream after the
+    /// last write of the connection. Returns without replying if the server
+    /// shuts down first.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        replication: &Replication,
+        offset: u64,
+        connection: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        let frame = if replication.is_replica() {
+            Frame::Error("ERR WAITAOF cannot be used with replica instances.".to_string())
+        } else if self.numlocal > 0 && !db.is_durable() {
+            let message =
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.";
+            Frame::Error(message.to_string())
+        } else {
+            // The writes of the connection were made durable before their
+            // replies, unless the storage failed.
+            db.written(&[]).await;
+            let local = db.is_durable() && db.storage_error().is_none();
+            let numreplicas = usize::try_from(self.numreplicas).unwrap_or(usize::MAX);
+            let timeout = Some(Duration::from_millis(self.timeout)).filter(|t| !t.is_zero());
+            let count = tokio::select! {
+                count = replication.wait_durable(offset, numreplicas, timeout) => count,
+                _ = shutdown.recv() => return Ok(()),
+            };
+            let mut frame = Frame::array();
+            frame.push_int(local as u64);
+            frame.push_int(count as u64);
+            frame
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
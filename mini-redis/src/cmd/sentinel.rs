@@ -0,0 +1,268 @@
+use crate::sentinel::{MasterReport, PeerReport, ReplicaReport};
+use crate::{Connection, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Ask a sentinel about the masters it monitors, or make it fail one over.
+///
+/// Supports the `MASTERS`, `MASTER`, `REPLICAS` (or `SLAVES`), `SENTINELS`,
+/// `GET-MASTER-ADDR-BY-NAME`, `IS-MASTER-DOWN-BY-ADDR`, `FAILOVER` and
+/// `MYID` subcommands. Only served by a server running in sentinel mode.
+#[derive(Debug)]
+pub struct Sentinel {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    Masters,
+    Master(String),
+    Replicas(String),
+    Sentinels(String),
+    GetMasterAddrByName(String),
+    /// Asked by the other sentinels, with the ID of the sentinel asking for
+    /// a vote, or `*`.
+    IsMasterDownByAddr {
+        ip: String,
+        port: u16,
+        epoch: u64,
+        runid: String,
+    },
+    Failover(String),
+    MyId,
+}
+
+impl Sentinel {
+    /// Asks for the address of the master `name`.
+    pub fn get_master_addr_by_name(name: impl ToString) -> Sentinel {
+        Sentinel {
+            subcommand: Subcommand::GetMasterAddrByName(name.to_string()),
+        }
+    }
+
+    /// Asks whether the master at `ip:port` is down, and for a vote for
+    /// `runid` in `epoch` unless it is `*`.
+    pub(crate) fn is_master_down_by_addr(
+        ip: String,
+        port: u16,
+        epoch: u64,
+        runid: impl ToString,
+    ) -> Sentinel {
+        Sentinel {
+            subcommand: Subcommand::IsMasterDownByAddr {
+                ip,
+                port,
+                epoch,
+                runid: runid.to_string(),
+            },
+        }
+    }
+
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Sentinel> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "masters" => Subcommand::Masters,
+            "master" => Subcommand::Master(parse.next_string()?),
+            "replicas" | "slaves" => Subcommand::Replicas(parse.next_string()?),
+            "sentinels" => Subcommand::Sentinels(parse.next_string()?),
+            "get-master-addr-by-name" => Subcommand::GetMasterAddrByName(parse.next_string()?),
+            "is-master-down-by-addr" => {
+                let ip = parse.next_string()?;
+                let port = parse
+                    .next_string()?
+                    .parse()
+                    .map_err(|_| "ERR invalid port")?;
+                let epoch = parse
+                    .next_int()
+                    .map_err(|_| "ERR value is not an integer or out of range")?;
+                let runid = parse.next_string()?;
+                Subcommand::IsMasterDownByAddr {
+                    ip,
+                    port,
+                    epoch,
+                    runid,
+                }
+            }
+            "failover" => Subcommand::Failover(parse.next_string()?),
+            "myid" => Subcommand::MyId,
+            _ => {
+                return Err(format!("ERR unknown subcommand '{}'. Try SENTINEL HELP.", name).into())
+            }
+        };
+        Ok(Sentinel { subcommand })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("sentinel".as_bytes()));
+        let mut push = |arg: String| frame.push_bulk(Bytes::from(arg.into_bytes()));
+        match self.subcommand {
+            Subcommand::Masters => push("masters".to_string()),
+            Subcommand::Master(name) => {
+                push("master".to_string());
+                push(name);
+            }
+            Subcommand::Replicas(name) => {
+                push("replicas".to_string());
+                push(name);
+            }
+            Subcommand::Sentinels(name) => {
+                push("sentinels".to_string());
+                push(name);
+            }
+            Subcommand::GetMasterAddrByName(name) => {
+                push("get-master-addr-by-name".to_string());
+                push(name);
+            }
+            Subcommand::IsMasterDownByAddr {
+                ip,
+                port,
+                epoch,
+                runid,
+            } => {
+                push("is-master-down-by-addr".to_string());
+                push(ip);
+                push(port.to_string());
+                push(epoch.to_string());
+                push(runid);
+            }
+            Subcommand::Failover(name) => {
+                push("failover".to_string());
+                push(name);
+            }
+            Subcommand::MyId => push("myid".to_string()),
+        }
+        frame
+    }
+
+    pub(crate) async fn apply(
+        self,
+        sentinel: &crate::sentinel::Sentinel,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let no_such_master = || Frame::Error("ERR No such master with that name".to_string());
+        let frame = match self.subcommand {
+            Subcommand::Masters => {
+                let mut frame = Frame::array();
+                for master in sentinel.masters() {
+                    frame.push_frame(master_fields(master));
+                }
+                frame
+            }
+            Subcommand::Master(name) => match sentinel.master(&name) {
+                Some(master) => master_fields(master),
+                None => no_such_master(),
+            },
+            Subcommand::Replicas(name) => match sentinel.replicas(&name) {
+                Some(replicas) => {
+                    let mut frame = Frame::array();
+                    for replica in replicas {
+                        frame.push_frame(replica_fields(replica));
+                    }
+                    frame
+                }
+                None => no_such_master(),
+            },
+            Subcommand::Sentinels(name) => match sentinel.sentinels(&name) {
+                Some(peers) => {
+                    let mut frame = Frame::array();
+                    for peer in peers {
+                        frame.push_frame(peer_fields(peer));
+                    }
+                    frame
+                }
+                None => no_such_master(),
+            },
+            Subcommand::GetMasterAddrByName(name) => match sentinel.master_addr(&name) {
+                Some((ip, port)) => {
+                    let mut frame = Frame::array();
+                    frame.push_bulk(Bytes::from(ip.into_bytes()));
+                    frame.push_bulk(Bytes::from(port.to_string().into_bytes()));
+                    frame
+                }
+                None => Frame::Null,
+            },
+            Subcommand::IsMasterDownByAddr {
+                ip,
+                port,
+                epoch,
+                runid,
+            } => {
+                let (down, leader, leader_epoch) =
+                    sentinel.is_master_down_by_addr(&ip, port, epoch, &runid);
+                let mut frame = Frame::array();
+                frame.push_int(down as u64);
+                frame.push_bulk(Bytes::from(leader.unwrap_or_else(|| "*".to_string())));
+                frame.push_int(leader_epoch);
+                frame
+            }
+            Subcommand::Failover(name) => match sentinel.failover(&name) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(err),
+            },
+            Subcommand::MyId => Frame::Bulk(Bytes::from(sentinel.myid().to_string())),
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
+
+/// Builds the flat list of field names and values the reports are made of.
+struct Fields(Frame);
+
+impl Fields {
+    fn new() -> Fields {
+        Fields(Frame::array())
+    }
+
+    fn field(mut self, name: &str, value: impl ToString) -> Fields {
+        self.0.push_bulk(Bytes::from(name.to_string()));
+        self.0.push_bulk(Bytes::from(value.to_string()));
+        self
+    }
+}
+
+fn master_fields(master: MasterReport) -> Frame {
+    Fields::new()
+        .field("name", master.name)
+        .field("ip", master.ip)
+        .field("port", master.port)
+        .field("flags", master.flags.join(","))
+        .field("num-slaves", master.replicas)
+        .field("num-other-sentinels", master.sentinels)
+        .field("quorum", master.quorum)
+        .field("config-epoch", master.config_epoch)
+        .field("down-after-milliseconds", master.down_after.as_millis())
+        .field("failover-timeout", master.failover_timeout.as_millis())
+        .0
+}
+
+fn replica_fields(replica: ReplicaReport) -> Frame {
+    let mut fields = Fields::new()
+        .field("name", format!("{}:{}", replica.ip, replica.port))
+        .field("ip", replica.ip)
+        .field("port", replica.port)
+        .field("flags", replica.flags.join(","));
+    if let Some(((host, port), link_up)) = replica.master {
+        fields = fields
+            .field("master-host", host)
+            .field("master-port", port)
+            .field("master-link-status", if link_up { "ok" } else { "err" });
+    }
+    fields.field("slave-repl-offset", replica.offset).0
+}
+
+fn peer_fields(peer: PeerReport) -> Frame {
+    let (leader, leader_epoch) = peer.vote.unwrap_or_else(|| ("*".to_string(), 0));
+    Fields::new()
+        .field("name", &peer.runid)
+        .field("ip", peer.ip)
+        .field("port", peer.port)
+        .field("runid", peer.runid)
+        .field("flags", peer.flags.join(","))
+        .field("last-hello-message", peer.last_hello.as_millis())
+        .field("leader", leader)
+        .field("leader-epoch", leader_epoch)
+        .0
+}
@@ -0,0 +1,103 @@
+use super::optional;
+use crate::slowlog::SlowLog;
+use crate::{Connection, Frame, Parse};
+use bytes::Bytes;
+use tracing::debug;
+
+/// Number of entries `SLOWLOG GET` returns without an explicit count.
+const DEFAULT_COUNT: usize = 10;
+
+/// Inspect the slow log.
+///
+/// Supports the `GET [count]`, `LEN` and `RESET` subcommands. A count of -1
+/// returns every entry.
+#[derive(Debug)]
+pub struct Slowlog {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    Get(Option<i64>),
+    Len,
+    Reset,
+}
+
+impl Slowlog {
+    pub fn parse_frames(parse: &mut Parse) -> crate::Result<Slowlog> {
+        let name = parse.next_string()?.to_lowercase();
+        let subcommand = match &name[..] {
+            "get" => match optional(parse)? {
+                None => Subcommand::Get(None),
+                Some(count) => match count.parse::<i64>() {
+                    Ok(count) if count >= -1 => Subcommand::Get(Some(count)),
+                    _ => {
+                        return Err("ERR count should be greater than or equal to -1".into());
+                    }
+                },
+            },
+            "len" => Subcommand::Len,
+            "reset" => Subcommand::Reset,
+            _ => {
+                return Err(format!("ERR unknown subcommand '{}'. Try SLOWLOG HELP.", name).into())
+            }
+        };
+        Ok(Slowlog { subcommand })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("slowlog".as_bytes()));
+        match self.subcommand {
+            Subcommand::Get(count) => {
+                frame.push_bulk(Bytes::from("get".as_bytes()));
+                if let Some(count) = count {
+                    frame.push_bulk(Bytes::from(count.to_string()));
+                }
+            }
+            Subcommand::Len => frame.push_bulk(Bytes::from("len".as_bytes())),
+            Subcommand::Reset => frame.push_bulk(Bytes::from("reset".as_bytes())),
+        }
+        frame
+    }
+
+    pub(crate) async fn apply(
+        self,
+        slowlog: &SlowLog,
+        connection: &mut Connection,
+    ) -> crate::Result<()> {
+        let frame = match self.subcommand {
+            Subcommand::Get(count) => {
+                let count = match count {
+                    Some(-1) => usize::MAX,
+                    Some(count) => count as usize,
+                    None => DEFAULT_COUNT,
+                };
+                let mut frame = Frame::array();
+                for entry in slowlog.entries(count) {
+                    let mut item = Frame::array();
+                    item.push_int(entry.id);
+                    item.push_int(entry.timestamp);
+                    item.push_int(entry.duration.as_micros() as u64);
+                    let mut args = Frame::array();
+                    for arg in entry.args {
+                        args.push_bulk(arg);
+                    }
+                    item.push_frame(args);
+                    item.push_bulk(Bytes::from(entry.client_addr));
+                    item.push_bulk(Bytes::from(entry.client_name));
+                    frame.push_frame(item);
+                }
+                frame
+            }
+            Subcommand::Len => Frame::Integer(slowlog.len() as u64),
+            Subcommand::Reset => {
+                slowlog.reset();
+                Frame::Simple("OK".to_string())
+            }
+        };
+        debug!(?frame);
+        connection.write_frame(&frame).await?;
+        Ok(())
+    }
+}
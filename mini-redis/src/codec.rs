@@ -0,0 +1,178 @@
+//! A `tokio_util::codec` implementation of the Redis protocol.
+//!
+//! `RespCodec` turns any byte stream into a stream of `Frame` values when
+//! wrapped in `tokio_util::codec::Framed`. `Connection` decodes with the same
+//! codec, so proxies built on `Framed` behave exactly like the server.
+
+use crate::frame::{self, Frame, Limits};
+
+use bytes::BytesMut;
+use std::io::{self, Cursor};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Decodes and encodes `Frame` values.
+///
+/// Decoding is bounded by `Limits`. Frames advertising lengths above the
+/// limits are rejected with `frame::Error::LimitExceeded`, as is a partial
+/// frame that grows beyond `Limits::max_query_buffer_len`.
+#[derive(Clone, Debug, Default)]
+pub struct RespCodec {
+    limits: Limits,
+}
+
+impl RespCodec {
+    /// Create a codec using the default `Limits`.
+    pub fn new() -> RespCodec {
+        RespCodec::default()
+    }
+
+    /// Create a codec applying `limits` to decoded frames.
+    pub fn with_limits(limits: Limits) -> RespCodec {
+        RespCodec { limits }
+    }
+
+    /// Returns the limits applied to decoded frames.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Frame;
+    type Error = crate::Error;
+
+    /// Tries to parse a frame from `src`. If the buffer contains enough data,
+    /// the frame is returned and the data removed from the buffer. If not
+    /// enough data has been buffered yet, `Ok(None)` is returned. If the
+    /// buffered data does not represent a valid frame, `Err` is returned.
+    fn decode(&mut self, src: &mut BytesMut) -> crate::Result<Option<Frame>> {
+        use frame::Error::Incomplete;
+
+        // Cursor is used to track the "current" location in the
+        // buffer. Cursor also implements `Buf` from the `bytes` crate
+        // which provides a number of helpful utilities for working
+        // with bytes.
+        let mut buf = Cursor::new(&src[..]);
+
+        // The first step is to check if enough data has been buffered to parse
+        // a single frame. This step is usually much faster than doing a full
+        // parse of the frame, and allows us to skip allocating data structures
+        // to hold the frame data unless we know the full frame has been
+        // received.
+        match Frame::check_with_limits(&mut buf, &self.limits) {
+            Ok(_) => {
+                // The `check` function will have advanced the cursor until the
+                // end of the frame. Since the cursor had position set to zero
+                // before `Frame::check` was called, we obtain the length of the
+                // frame by checking the cursor position.
+                let len = buf.position() as usize;
+
+                // Split the frame off the buffer. The remaining data is kept
+                // in `src` for the next call. Freezing the split part lets
+                // `Frame::parse_bytes` hand out slices of it as bulk values
+                // without copying them.
+                //
+                // When `split_to` is called on the buffer, the two halves
+                // share the same allocation. The details of how this works is
+                // left to `BytesMut`, it reclaims or reallocates the memory
+                // once the buffer needs to grow again.
+                let data = src.split_to(len).freeze();
+
+                // Parse the frame. This allocates the necessary structures to
+                // represent the frame and returns the frame value.
+                //
+                // If the encoded frame representation is invalid, an error is
+                // returned. This should terminate the **current** connection
+                // but should not impact any other connected clients.
+                let frame = Frame::parse_bytes(&data)?;
+
+                // Return the parsed frame to the caller.
+                Ok(Some(frame))
+            }
+            // There is not enough data present in the buffer to parse a
+            // single frame. The caller must read more data from the stream.
+            //
+            // We do not want to return `Err` from here as this "error" is an
+            // expected runtime condition. The exception is a peer that keeps
+            // sending an incomplete frame past the configured maximum, which
+            // could otherwise exhaust memory.
+            Err(Incomplete) => {
+                if src.len() > self.limits.max_query_buffer_len {
+                    let msg = "max query buffer length reached".to_string();
+                    return Err(frame::Error::LimitExceeded(msg).into());
+                }
+                Ok(None)
+            }
+            // An error was encountered while parsing the frame. The connection
+            // is now in an invalid state. Returning `Err` from here will result
+            // in the connection being closed.
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> crate::Result<Option<Frame>> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            // The remote closed the connection. For this to be a clean
+            // shutdown, there should be no data in the buffer. If there is,
+            // this means that the peer closed the socket while sending a
+            // frame.
+            None if src.is_empty() => Ok(None),
+            None => Err("connection reset by peer".into()),
+        }
+    }
+}
+
+impl Encoder<Frame> for RespCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&frame, dst)
+    }
+}
+
+impl Encoder<&Frame> for RespCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: &Frame, dst: &mut BytesMut) -> io::Result<()> {
+        frame.encode(dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn decode_split_frame() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$5\r\nhel"[..]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b"lo\r\n+OK\r\n");
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.to_string(), "GET hello");
+        assert_eq!(&buf[..], b"+OK\r\n");
+
+        assert!(codec.decode_eof(&mut buf).unwrap().unwrap() == "OK");
+        assert!(codec.decode_eof(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let mut codec = RespCodec::new();
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from_static(b"set"));
+        frame.push_int(42);
+
+        let mut buf = BytesMut::new();
+        codec.encode(&frame, &mut buf).unwrap();
+        assert_eq!(&buf[..], b"*2\r\n$3\r\nset\r\n:42\r\n");
+
+        let decoded = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded.to_string(), frame.to_string());
+        assert!(buf.is_empty());
+    }
+}
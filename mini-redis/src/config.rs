@@ -0,0 +1,1245 @@
+//! Server configuration: the settings, the `redis.conf` style file they are
+//! loaded from, and the live copy changed by `CONFIG SET`.
+//!
+//! Every setting is described by an entry of `PARAMS`, which knows how to
+//! parse and format it. The same table serves the configuration file,
+//! `CONFIG GET`, `CONFIG SET` and `CONFIG REWRITE`, so they always agree on
+//! names and formats.
+
+use crate::frame::Limits;
+use crate::pattern::glob_match;
+use crate::tls::TlsConfig;
+
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Default for `ServerConfig::max_clients`.
+pub const DEFAULT_MAX_CLIENTS: usize = 250;
+
+/// Port the server listens on unless configured otherwise.
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// Settings of a server started with `server::run_with_config`.
+///
+/// Fields marked as live can be changed at runtime with `CONFIG SET`.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Addresses to listen on for TCP connections.
+    pub bind: Vec<String>,
+
+    /// TCP port to listen on. 0 disables TCP.
+    pub port: u16,
+
+    /// Path of a Unix socket to listen on as well.
+    pub unixsocket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file.
+    pub unixsocketperm: u32,
+
+    /// TCP port to accept TLS connections on, on every address of `bind`.
+    /// 0 disables TLS.
+    pub tls_port: u16,
+
+    /// PEM file holding the certificate chain served on `tls_port`.
+    pub tls_cert_file: Option<PathBuf>,
+
+    /// PEM file holding the private key of `tls_cert_file`.
+    pub tls_key_file: Option<PathBuf>,
+
+    /// PEM file holding the CA certificates client certificates are
+    /// verified against.
+    pub tls_ca_cert_file: Option<PathBuf>,
+
+    /// Require TLS clients to present a certificate signed by
+    /// `tls_ca_cert_file`.
+    pub tls_auth_clients: bool,
+
+    /// Maximum number of clients served at the same time. Further
+    /// connections are refused with an error. Live.
+    pub max_clients: usize,
+
+    /// Close connections of clients idle for this long. Clients in the
+    /// subscribe mode are never closed. Live.
+    pub timeout: Option<Duration>,
+
+    /// Send TCP keepalive probes to clients once their connection has been
+    /// idle for this long, to detect dead peers. Live, applies to new
+    /// connections.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Bounds applied to frames received from clients. A client exceeding
+    /// them receives a protocol error and is disconnected. Live, applies to
+    /// new connections.
+    pub limits: Limits,
+
+    /// Password clients must `AUTH` with before running any other command.
+    /// `None` disables authentication. Ignored when `aclfile` is set. Live.
+    pub requirepass: Option<String>,
+
+    /// File the users are loaded from at startup, and by `ACL LOAD`. `ACL
+    /// SAVE` writes it back. The server does not start if it cannot be read.
+    pub aclfile: Option<PathBuf>,
+
+    /// Working directory. Relative paths are resolved against it.
+    pub dir: Option<PathBuf>,
+
+    /// Verbosity of the log.
+    pub loglevel: LogLevel,
+
+    /// Memory limit for the keyspace in bytes. 0 means no limit. Live.
+    pub maxmemory: u64,
+
+    /// How keys are evicted once `maxmemory` is reached. Live.
+    pub maxmemory_policy: MaxmemoryPolicy,
+
+    /// Keys sampled to pick each one to evict by the LRU, LFU and TTL
+    /// policies. Larger samples approximate the policy better, at the cost
+    /// of CPU. Live.
+    pub maxmemory_samples: usize,
+
+    /// Which removals of keys free their values on a background thread.
+    /// Live.
+    pub lazyfree: LazyFree,
+
+    /// Commands running longer than this many microseconds are recorded in
+    /// the slow log. 0 records every command, a negative value none. Live.
+    pub slowlog_log_slower_than: i64,
+
+    /// Number of entries kept in the slow log. Live.
+    pub slowlog_max_len: usize,
+
+    /// Address of an HTTP listener serving Prometheus metrics on
+    /// `/metrics`. `None` disables the exporter.
+    pub metrics_addr: Option<String>,
+
+    /// How commands are executed.
+    pub engine: Engine,
+
+    /// Threads run by the thread-per-core engine. 0 means one per CPU.
+    pub cores: usize,
+
+    /// Where the keys are stored.
+    pub backend: Backend,
+
+    /// Directory of the `lsm` backend, holding a directory per shard.
+    pub lsm_dir: PathBuf,
+
+    /// Bytes of values the `lsm` backend caches in memory, per shard.
+    pub lsm_cache_size: u64,
+
+    /// Bytes of writes the `lsm` backend buffers in memory, and in its
+    /// write-ahead log, before writing them to a segment, per shard.
+    pub lsm_memtable_size: u64,
+
+    /// Host and port of the master to replicate, as with `REPLICAOF`. `None`
+    /// starts the server as a master.
+    pub replicaof: Option<(String, u16)>,
+
+    /// User a replica authenticates as with its master. Live.
+    pub masteruser: Option<String>,
+
+    /// Password a replica authenticates with to its master. `None` sends no
+    /// `AUTH`. Live.
+    pub masterauth: Option<String>,
+
+    /// Whether replicas refuse writes from their clients. Live.
+    pub replica_read_only: bool,
+
+    /// Bytes of the replication stream a master keeps for replicas to
+    /// continue from after a disconnection. Live.
+    pub repl_backlog_size: u64,
+
+    /// A replica reconnects to its master after hearing nothing from it for
+    /// this long. Live.
+    pub repl_timeout: Duration,
+
+    /// File the configuration was loaded from, written by `CONFIG REWRITE`.
+    pub config_file: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind: vec!["127.0.0.1".to_string()],
+            port: DEFAULT_PORT,
+            unixsocket: None,
+            unixsocketperm: 0o700,
+            tls_port: 0,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: false,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            timeout: None,
+            tcp_keepalive: Some(Duration::from_secs(300)),
+            limits: Limits::default(),
+            requirepass: None,
+            aclfile: None,
+            dir: None,
+            loglevel: LogLevel::Notice,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lazyfree: LazyFree::default(),
+            slowlog_log_slower_than: 10_000,
+            slowlog_max_len: 128,
+            metrics_addr: None,
+            engine: Engine::Shared,
+            cores: 0,
+            backend: Backend::Memory,
+            lsm_dir: PathBuf::from("lsm"),
+            lsm_cache_size: 64 * 1024 * 1024,
+            lsm_memtable_size: 4 * 1024 * 1024,
+            replicaof: None,
+            masteruser: None,
+            masterauth: None,
+            replica_read_only: true,
+            repl_backlog_size: 1024 * 1024,
+            repl_timeout: Duration::from_secs(60),
+            config_file: None,
+        }
+    }
+}
+
+/// Log levels, named the way Redis names them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogLevel, String> {
+        match s.to_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(
+                "argument(s) must be one of the following: debug, verbose, notice, warning"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        };
+        fmt.write_str(name)
+    }
+}
+
+/// Eviction policies, named the way Redis names them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    /// Refuse writes with an `OOM` error.
+    NoEviction,
+    /// Evict the least recently used keys.
+    AllkeysLru,
+    /// Evict the least frequently used keys.
+    AllkeysLfu,
+    /// Evict random keys.
+    AllkeysRandom,
+    /// Evict the least recently used keys with an expiration.
+    VolatileLru,
+    /// Evict the least frequently used keys with an expiration.
+    VolatileLfu,
+    /// Evict random keys with an expiration.
+    VolatileRandom,
+    /// Evict the keys with an expiration closest to expire.
+    VolatileTtl,
+}
+
+const POLICIES: &[(&str, MaxmemoryPolicy)] = &[
+    ("noeviction", MaxmemoryPolicy::NoEviction),
+    ("allkeys-lru", MaxmemoryPolicy::AllkeysLru),
+    ("allkeys-lfu", MaxmemoryPolicy::AllkeysLfu),
+    ("allkeys-random", MaxmemoryPolicy::AllkeysRandom),
+    ("volatile-lru", MaxmemoryPolicy::VolatileLru),
+    ("volatile-lfu", MaxmemoryPolicy::VolatileLfu),
+    ("volatile-random", MaxmemoryPolicy::VolatileRandom),
+    ("volatile-ttl", MaxmemoryPolicy::VolatileTtl),
+];
+
+impl FromStr for MaxmemoryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MaxmemoryPolicy, String> {
+        POLICIES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, policy)| *policy)
+            .ok_or_else(|| {
+                let names: Vec<_> = POLICIES.iter().map(|(name, _)| *name).collect();
+                format!(
+                    "argument(s) must be one of the following: {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for MaxmemoryPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = POLICIES
+            .iter()
+            .find(|(_, policy)| policy == self)
+            .expect("every policy is named");
+        fmt.write_str(name)
+    }
+}
+
+/// Removals of keys whose values are freed on a background thread rather
+/// than by the command, named after the `lazyfree-lazy-*` settings of Redis.
+/// `UNLINK` and `FLUSHALL ASYNC` always free lazily.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LazyFree {
+    /// Keys evicted to honor `maxmemory`.
+    pub eviction: bool,
+    /// Keys that expired.
+    pub expire: bool,
+    /// Values replaced by a write.
+    pub server_del: bool,
+    /// Keys removed by `DEL`.
+    pub user_del: bool,
+    /// Keys removed by `FLUSHDB` and `FLUSHALL` without `ASYNC` or `SYNC`.
+    pub user_flush: bool,
+}
+
+/// Execution engines of the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// Connections are tasks of a multi-threaded runtime, sharing the
+    /// keyspace.
+    Shared,
+    /// Each core runs its own single-threaded runtime, owning a slice of
+    /// the keyspace. Commands on keys of another core are forwarded to it.
+    ThreadPerCore,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s.to_lowercase().as_str() {
+            "shared" => Ok(Engine::Shared),
+            "thread-per-core" => Ok(Engine::ThreadPerCore),
+            _ => {
+                Err("argument(s) must be one of the following: shared, thread-per-core".to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Engine::Shared => "shared",
+            Engine::ThreadPerCore => "thread-per-core",
+        };
+        fmt.write_str(name)
+    }
+}
+
+/// Where the keys are stored, see `crate::storage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Everything is kept in memory.
+    Memory,
+    /// Values are kept on disk, in `lsm-dir`, and cached in memory.
+    Lsm,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s.to_lowercase().as_str() {
+            "memory" => Ok(Backend::Memory),
+            "lsm" => Ok(Backend::Lsm),
+            _ => Err("argument(s) must be one of the following: memory, lsm".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Backend::Memory => "memory",
+            Backend::Lsm => "lsm",
+        };
+        fmt.write_str(name)
+    }
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> tracing::Level {
+        match level {
+            LogLevel::Debug => tracing::Level::TRACE,
+            LogLevel::Verbose => tracing::Level::DEBUG,
+            LogLevel::Notice => tracing::Level::INFO,
+            LogLevel::Warning => tracing::Level::WARN,
+        }
+    }
+}
+
+/// A configuration parameter.
+struct Param {
+    name: &'static str,
+    /// Whether `CONFIG SET` may change the parameter.
+    live: bool,
+    /// Whether the value is a list written as separate arguments, rather than
+    /// a single, possibly quoted, argument.
+    list: bool,
+    get: fn(&ServerConfig) -> String,
+    set: fn(&mut ServerConfig, &str) -> Result<(), String>,
+}
+
+const PARAMS: &[Param] = &[
+    Param {
+        name: "bind",
+        live: false,
+        list: true,
+        get: |config| config.bind.join(" "),
+        set: |config, value| {
+            config.bind = value.split_whitespace().map(str::to_string).collect();
+            Ok(())
+        },
+    },
+    Param {
+        name: "port",
+        live: false,
+        list: false,
+        get: |config| config.port.to_string(),
+        set: |config, value| parse_number(value).map(|port| config.port = port),
+    },
+    Param {
+        name: "unixsocket",
+        live: false,
+        list: false,
+        get: |config| path_or_empty(&config.unixsocket),
+        set: |config, value| {
+            config.unixsocket = non_empty(value).map(PathBuf::from);
+            Ok(())
+        },
+    },
+    Param {
+        name: "unixsocketperm",
+        live: false,
+        list: false,
+        get: |config| format!("{:o}", config.unixsocketperm),
+        set: |config, value| {
+            u32::from_str_radix(value, 8)
+                .map(|mode| config.unixsocketperm = mode)
+                .map_err(|_| "argument must be an octal number".to_string())
+        },
+    },
+    Param {
+        name: "tls-port",
+        live: false,
+        list: false,
+        get: |config| config.tls_port.to_string(),
+        set: |config, value| parse_number(value).map(|port| config.tls_port = port),
+    },
+    Param {
+        name: "tls-cert-file",
+        live: false,
+        list: false,
+        get: |config| path_or_empty(&config.tls_cert_file),
+        set: |config, value| {
+            config.tls_cert_file = non_empty(value).map(PathBuf::from);
+            Ok(())
+        },
+    },
+    Param {
+        name: "tls-key-file",
+        live: false,
+        list: false,
+        get: |config| path_or_empty(&config.tls_key_file),
+        set: |config, value| {
+            config.tls_key_file = non_empty(value).map(PathBuf::from);
+            Ok(())
+        },
+    },
+    Param {
+        name: "tls-ca-cert-file",
+        live: false,
+        list: false,
+        get: |config| path_or_empty(&config.tls_ca_cert_file),
+        set: |config, value| {
+            config.tls_ca_cert_file = non_empty(value).map(PathBuf::from);
+            Ok(())
+        },
+    },
+    Param {
+        name: "tls-auth-clients",
+        live: false,
+        list: false,
+        get: |config| yes_no(config.tls_auth_clients),
+        set: |config, value| parse_bool(value).map(|auth| config.tls_auth_clients = auth),
+    },
+    Param {
+        name: "maxclients",
+        live: true,
+        list: false,
+        get: |config| config.max_clients.to_string(),
+        set: |config, value| match parse_number(value)? {
+            0 => Err("argument must be greater than 0".to_string()),
+            max_clients => {
+                config.max_clients = max_clients;
+                Ok(())
+            }
+        },
+    },
+    Param {
+        name: "timeout",
+        live: true,
+        list: false,
+        get: |config| {
+            let secs = config.timeout.map_or(0, |timeout| timeout.as_secs());
+            secs.to_string()
+        },
+        set: |config, value| {
+            let secs: u64 = parse_number(value)?;
+            config.timeout = Some(Duration::from_secs(secs)).filter(|timeout| !timeout.is_zero());
+            Ok(())
+        },
+    },
+    Param {
+        name: "tcp-keepalive",
+        live: true,
+        list: false,
+        get: |config| {
+            let secs = config.tcp_keepalive.map_or(0, |idle| idle.as_secs());
+            secs.to_string()
+        },
+        set: |config, value| {
+            let secs: u64 = parse_number(value)?;
+            config.tcp_keepalive = Some(Duration::from_secs(secs)).filter(|idle| !idle.is_zero());
+            Ok(())
+        },
+    },
+    Param {
+        name: "proto-max-bulk-len",
+        live: true,
+        list: false,
+        get: |config| config.limits.max_bulk_len.to_string(),
+        set: |config, value| {
+            parse_memory(value).map(|len| config.limits.max_bulk_len = len as usize)
+        },
+    },
+    Param {
+        name: "client-query-buffer-limit",
+        live: true,
+        list: false,
+        get: |config| config.limits.max_query_buffer_len.to_string(),
+        set: |config, value| {
+            parse_memory(value).map(|len| config.limits.max_query_buffer_len = len as usize)
+        },
+    },
+    Param {
+        name: "requirepass",
+        live: true,
+        list: false,
+        get: |config| config.requirepass.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.requirepass = non_empty(value).map(str::to_string);
+            Ok(())
+        },
+    },
+    Param {
+        name: "aclfile",
+        live: false,
+        list: false,
+        get: |config| path_or_empty(&config.aclfile),
+        set: |config, value| {
+            config.aclfile = non_empty(value).map(PathBuf::from);
+            Ok(())
+        },
+    },
+    Param {
+        name: "dir",
+        live: false,
+        list: false,
+        get: |config| match std::env::current_dir() {
+            Ok(dir) => dir.display().to_string(),
+            Err(_) => path_or_empty(&config.dir),
+        },
+        set: |config, value| {
+            config.dir = non_empty(value).map(PathBuf::from);
+            Ok(())
+        },
+    },
+    Param {
+        name: "loglevel",
+        live: false,
+        list: false,
+        get: |config| config.loglevel.to_string(),
+        set: |config, value| value.parse().map(|level| config.loglevel = level),
+    },
+    Param {
+        name: "maxmemory",
+        live: true,
+        list: false,
+        get: |config| config.maxmemory.to_string(),
+        set: |config, value| parse_memory(value).map(|bytes| config.maxmemory = bytes),
+    },
+    Param {
+        name: "maxmemory-policy",
+        live: true,
+        list: false,
+        get: |config| config.maxmemory_policy.to_string(),
+        set: |config, value| value.parse().map(|policy| config.maxmemory_policy = policy),
+    },
+    Param {
+        name: "maxmemory-samples",
+        live: true,
+        list: false,
+        get: |config| config.maxmemory_samples.to_string(),
+        set: |config, value| match parse_number(value)? {
+            samples @ 1..=64 => {
+                config.maxmemory_samples = samples;
+                Ok(())
+            }
+            _ => Err("argument must be between 1 and 64 inclusive".to_string()),
+        },
+    },
+    Param {
+        name: "lazyfree-lazy-eviction",
+        live: true,
+        list: false,
+        get: |config| yes_no(config.lazyfree.eviction),
+        set: |config, value| parse_bool(value).map(|lazy| config.lazyfree.eviction = lazy),
+    },
+    Param {
+        name: "lazyfree-lazy-expire",
+        live: true,
+        list: false,
+        get: |config| yes_no(config.lazyfree.expire),
+        set: |config, value| parse_bool(value).map(|lazy| config.lazyfree.expire = lazy),
+    },
+    Param {
+        name: "lazyfree-lazy-server-del",
+        live: true,
+        list: false,
+        get: |config| yes_no(config.lazyfree.server_del),
+        set: |config, value| parse_bool(value).map(|lazy| config.lazyfree.server_del = lazy),
+    },
+    Param {
+        name: "lazyfree-lazy-user-del",
+        live: true,
+        list: false,
+        get: |config| yes_no(config.lazyfree.user_del),
+        set: |config, value| parse_bool(value).map(|lazy| config.lazyfree.user_del = lazy),
+    },
+    Param {
+        name: "lazyfree-lazy-user-flush",
+        live: true,
+        list: false,
+        get: |config| yes_no(config.lazyfree.user_flush),
+        set: |config, value| parse_bool(value).map(|lazy| config.lazyfree.user_flush = lazy),
+    },
+    Param {
+        name: "slowlog-log-slower-than",
+        live: true,
+        list: false,
+        get: |config| config.slowlog_log_slower_than.to_string(),
+        set: |config, value| {
+            parse_number(value).map(|micros| config.slowlog_log_slower_than = micros)
+        },
+    },
+    Param {
+        name: "slowlog-max-len",
+        live: true,
+        list: false,
+        get: |config| config.slowlog_max_len.to_string(),
+        set: |config, value| parse_number(value).map(|len| config.slowlog_max_len = len),
+    },
+    Param {
+        name: "metrics-addr",
+        live: false,
+        list: false,
+        get: |config| config.metrics_addr.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.metrics_addr = non_empty(value).map(str::to_string);
+            Ok(())
+        },
+    },
+    Param {
+        name: "engine",
+        live: false,
+        list: false,
+        get: |config| config.engine.to_string(),
+        set: |config, value| value.parse().map(|engine| config.engine = engine),
+    },
+    Param {
+        name: "cores",
+        live: false,
+        list: false,
+        get: |config| config.cores.to_string(),
+        set: |config, value| parse_number(value).map(|cores| config.cores = cores),
+    },
+    Param {
+        name: "backend",
+        live: false,
+        list: false,
+        get: |config| config.backend.to_string(),
+        set: |config, value| value.parse().map(|backend| config.backend = backend),
+    },
+    Param {
+        name: "lsm-dir",
+        live: false,
+        list: false,
+        get: |config| config.lsm_dir.display().to_string(),
+        set: |config, value| match non_empty(value) {
+            Some(dir) => {
+                config.lsm_dir = PathBuf::from(dir);
+                Ok(())
+            }
+            None => Err("argument must not be empty".to_string()),
+        },
+    },
+    Param {
+        name: "lsm-cache-size",
+        live: false,
+        list: false,
+        get: |config| config.lsm_cache_size.to_string(),
+        set: |config, value| parse_memory(value).map(|bytes| config.lsm_cache_size = bytes),
+    },
+    Param {
+        name: "lsm-memtable-size",
+        live: false,
+        list: false,
+        get: |config| config.lsm_memtable_size.to_string(),
+        set: |config, value| parse_memory(value).map(|bytes| config.lsm_memtable_size = bytes),
+    },
+    Param {
+        name: "replicaof",
+        live: false,
+        list: true,
+        get: |config| {
+            config
+                .replicaof
+                .as_ref()
+                .map(|(host, port)| format!("{} {}", host, port))
+                .unwrap_or_default()
+        },
+        set: |config, value| {
+            config.replicaof = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => None,
+                [host, port] => Some((host.to_string(), parse_number(port)?)),
+                _ => return Err("argument must be a host and a port".to_string()),
+            };
+            Ok(())
+        },
+    },
+    Param {
+        name: "masteruser",
+        live: true,
+        list: false,
+        get: |config| config.masteruser.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.masteruser = non_empty(value).map(str::to_string);
+            Ok(())
+        },
+    },
+    Param {
+        name: "masterauth",
+        live: true,
+        list: false,
+        get: |config| config.masterauth.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.masterauth = non_empty(value).map(str::to_string);
+            Ok(())
+        },
+    },
+    Param {
+        name: "replica-read-only",
+        live: true,
+        list: false,
+        get: |config| yes_no(config.replica_read_only),
+        set: |config, value| {
+            parse_bool(value).map(|read_only| config.replica_read_only = read_only)
+        },
+    },
+    Param {
+        name: "repl-backlog-size",
+        live: true,
+        list: false,
+        get: |config| config.repl_backlog_size.to_string(),
+        set: |config, value| match parse_memory(value)? {
+            0 => Err("argument must be greater than 0".to_string()),
+            bytes => {
+                config.repl_backlog_size = bytes;
+                Ok(())
+            }
+        },
+    },
+    Param {
+        name: "repl-timeout",
+        live: true,
+        list: false,
+        get: |config| config.repl_timeout.as_secs().to_string(),
+        set: |config, value| match parse_number(value)? {
+            0 => Err("argument must be greater than 0".to_string()),
+            secs => {
+                config.repl_timeout = Duration::from_secs(secs);
+                Ok(())
+            }
+        },
+    },
+];
+
+impl ServerConfig {
+    /// Load the configuration from a `redis.conf` style file: one `name
+    /// value` directive per line, `#` starting a comment. Settings missing
+    /// from the file keep their default.
+    pub fn from_file(path: impl AsRef<Path>) -> crate::Result<ServerConfig> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("can't open config file '{}': {}", path.display(), err))?;
+        let mut config = ServerConfig::default();
+        config.apply_file(&content)?;
+        config.config_file = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Apply the directives of a configuration file on top of the current
+    /// settings.
+    pub fn apply_file(&mut self, content: &str) -> crate::Result<()> {
+        for (i, line) in content.lines().enumerate() {
+            let args =
+                split_args(line).map_err(|err| format!("config file line {}: {}", i + 1, err))?;
+            let Some((name, values)) = args.split_first() else {
+                continue;
+            };
+            let res = match find_param(name) {
+                Some(param) => (param.set)(self, &values.join(" ")),
+                None => Err("Bad directive or wrong number of arguments".to_string()),
+            };
+            res.map_err(|err| format!("config file line {}: '{}': {}", i + 1, name, err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the TLS settings to serve `tls_port` with, or `None` when TLS
+    /// is disabled. Fails if the certificate or its key is missing.
+    pub fn tls(&self) -> crate::Result<Option<TlsConfig>> {
+        if self.tls_port == 0 {
+            return Ok(None);
+        }
+        let cert_file = self
+            .tls_cert_file
+            .as_ref()
+            .ok_or("tls-port requires tls-cert-file")?;
+        let key_file = self
+            .tls_key_file
+            .as_ref()
+            .ok_or("tls-port requires tls-key-file")?;
+        Ok(Some(TlsConfig {
+            ca_cert_file: self.tls_ca_cert_file.clone(),
+            auth_clients: self.tls_auth_clients,
+            ..TlsConfig::new(cert_file, key_file)
+        }))
+    }
+
+    /// Returns the name and value of every parameter matching the glob
+    /// `pattern`.
+    pub fn get(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_lowercase();
+        PARAMS
+            .iter()
+            .filter(|param| glob_match(&pattern, param.name))
+            .map(|param| (param.name, (param.get)(self)))
+            .collect()
+    }
+
+    /// Set a parameter from its textual form, as `CONFIG SET` does.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match find_param(name) {
+            Some(param) if !param.live => Err(format!(
+                "CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                param.name
+            )),
+            Some(param) => (param.set)(self, value).map_err(|err| {
+                format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {}",
+                    param.name, err
+                )
+            }),
+            None => Err(format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            )),
+        }
+    }
+
+    /// Returns `content`, a configuration file, updated with the current
+    /// settings. Comments and unknown lines are kept, a directive for a
+    /// setting differing from its default is appended if missing.
+    fn rewrite(&self, content: &str) -> String {
+        let defaults = ServerConfig::default();
+        let mut written = vec![false; PARAMS.len()];
+        let mut out = String::new();
+
+        for line in content.lines() {
+            let name = split_args(line)
+                .ok()
+                .and_then(|args| args.into_iter().next());
+            let index = name.and_then(|name| {
+                PARAMS
+                    .iter()
+                    .position(|param| param.name.eq_ignore_ascii_case(&name))
+            });
+            match index {
+                // Later duplicates of a directive are dropped.
+                Some(index) if written[index] => {}
+                Some(index) => {
+                    written[index] = true;
+                    out.push_str(&format_directive(&PARAMS[index], self));
+                    out.push('\n');
+                }
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+
+        for (param, written) in PARAMS.iter().zip(written) {
+            if !written && (param.get)(self) != (param.get)(&defaults) {
+                out.push_str(&format_directive(param, self));
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// The configuration of a running server, shared by every connection and
+/// changed by `CONFIG SET`. Cloning is shallow.
+#[derive(Clone, Debug)]
+pub struct LiveConfig {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: RwLock<ServerConfig>,
+
+    /// Limits the number of clients served at once, resized when
+    /// `maxclients` changes.
+    limit_connections: Arc<Semaphore>,
+}
+
+impl LiveConfig {
+    pub fn new(config: ServerConfig) -> LiveConfig {
+        let limit_connections = Arc::new(Semaphore::new(config.max_clients));
+        LiveConfig {
+            shared: Arc::new(Shared {
+                config: RwLock::new(config),
+                limit_connections,
+            }),
+        }
+    }
+
+    /// Returns a copy of the current settings.
+    pub fn snapshot(&self) -> ServerConfig {
+        self.shared.config.read().unwrap().clone()
+    }
+
+    /// Reads a setting without copying the whole configuration.
+    pub fn with<T>(&self, f: impl FnOnce(&ServerConfig) -> T) -> T {
+        f(&self.shared.config.read().unwrap())
+    }
+
+    pub(crate) fn limit_connections(&self) -> Arc<Semaphore> {
+        self.shared.limit_connections.clone()
+    }
+
+    /// Set several parameters at once. Either every parameter is changed or
+    /// none.
+    pub fn set(&self, pairs: &[(String, String)]) -> Result<(), String> {
+        let mut config = self.shared.config.write().unwrap();
+        let mut updated = config.clone();
+        for (name, value) in pairs {
+            updated.set(name, value)?;
+        }
+
+        let (old, new) = (config.max_clients, updated.max_clients);
+        *config = updated;
+        drop(config);
+        self.resize_connection_limit(old, new);
+        Ok(())
+    }
+
+    fn resize_connection_limit(&self, old: usize, new: usize) {
+        let semaphore = &self.shared.limit_connections;
+        if new > old {
+            semaphore.add_permits(new - old);
+        } else if new < old {
+            // Permits held by connected clients cannot be taken back. Wait
+            // for them to be released instead. The semaphore is fair, so the
+            // waiting task goes ahead of new connections.
+            let missing = (old - new) - semaphore.forget_permits(old - new);
+            if missing > 0 {
+                let semaphore = semaphore.clone();
+                tokio::spawn(async move {
+                    if let Ok(permits) = semaphore.acquire_many_owned(missing as u32).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+    }
+
+    /// Write the current settings back to the configuration file.
+    pub fn rewrite(&self) -> crate::Result<()> {
+        let config = self.snapshot();
+        let path = config
+            .config_file
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        write_atomically(path, &config.rewrite(&content))?;
+        Ok(())
+    }
+}
+
+/// Replaces the file at `path` with `content`. The content is written to a
+/// temporary file first, so a crash cannot leave a truncated file behind,
+/// and both the file and the rename are synced before returning.
+pub(crate) fn write_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    // The directory entry is only durable once the directory is synced.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn find_param(name: &str) -> Option<&'static Param> {
+    PARAMS
+        .iter()
+        .find(|param| param.name.eq_ignore_ascii_case(name))
+}
+
+fn format_directive(param: &Param, config: &ServerConfig) -> String {
+    let value = (param.get)(config);
+    if param.list {
+        format!("{} {}", param.name, value)
+    } else {
+        format!("{} {}", param.name, quote(&value))
+    }
+}
+
+/// Quotes `value` if it would not read back as a single argument.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '"' && c != '\'' && c != '\\');
+    if plain {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Splits a configuration line into arguments. Arguments are separated by
+/// whitespace and may be quoted with `"` (supporting `\` escapes) or `'`.
+/// Returns nothing for a blank line or a comment.
+pub(crate) fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    if chars.peek() == Some(&'#') {
+        return Ok(args);
+    }
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+
+        let mut arg = String::new();
+        if first == '"' || first == '\'' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some(c) if c == first => break,
+                    Some('\\') if first == '"' => match chars.next() {
+                        Some('n') => arg.push('\n'),
+                        Some('r') => arg.push('\r'),
+                        Some('t') => arg.push('\t'),
+                        Some(c) => arg.push(c),
+                        None => return Err("unbalanced quotes".to_string()),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err("unbalanced quotes".to_string()),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err("closing quote must be followed by a space".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+pub(crate) fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("argument couldn't be parsed into an integer: '{}'", value))
+}
+
+/// Parses a memory amount such as `100`, `64kb` or `1gb`. As in Redis, `k`,
+/// `m` and `g` are powers of 1000, `kb`, `mb` and `gb` powers of 1024.
+fn parse_memory(value: &str) -> Result<u64, String> {
+    let lower = value.to_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(split);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("argument must be a memory value: '{}'", value)),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("argument must be a memory value: '{}'", value))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+fn path_or_empty(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config_file() {
+        let mut config = ServerConfig::default();
+        config
+            .apply_file(
+                "# comment\n\
+                 bind 127.0.0.1 ::1\n\
+                 port 7000\n\
+                 maxmemory 1gb\n\
+                 requirepass \"with space\"\n",
+            )
+            .unwrap();
+        assert_eq!(config.bind, vec!["127.0.0.1", "::1"]);
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.maxmemory, 1 << 30);
+        assert_eq!(config.requirepass.as_deref(), Some("with space"));
+
+        assert!(config.apply_file("bogus 1\n").is_err());
+        assert!(config.apply_file("port \"7000\n").is_err());
+    }
+
+    #[test]
+    fn rewrite_config_file() {
+        let mut config = ServerConfig::default();
+        config
+            .apply_file("# keep me\nport 7000\nport 7001\n")
+            .unwrap();
+        config.set("maxclients", "10").unwrap();
+        config.set("requirepass", "a b").unwrap();
+
+        let rewritten = config.rewrite("# keep me\nport 7000\nport 7001\n");
+        assert_eq!(
+            rewritten,
+            "# keep me\nport 7001\nmaxclients 10\nrequirepass \"a b\"\n"
+        );
+        let mut reloaded = ServerConfig::default();
+        reloaded.apply_file(&rewritten).unwrap();
+        assert_eq!(reloaded.requirepass.as_deref(), Some("a b"));
+    }
+
+    #[test]
+    fn set_immutable_parameter() {
+        let mut config = ServerConfig::default();
+        assert!(config.set("port", "7000").is_err());
+        assert!(config.set("maxclients", "many").is_err());
+        // No client could ever be served.
+        assert!(config.set("maxclients", "0").is_err());
+        assert!(config.apply_file("maxclients 0\n").is_err());
+        assert_eq!(config.get("max*").len(), 4);
+    }
+}
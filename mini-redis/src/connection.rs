@@ -1,47 +1,91 @@
-use crate::frame::{self, Frame};
+use crate::codec::RespCodec;
+use crate::frame::{Frame, Limits};
 
-use bytes::{Buf, BytesMut};
-use std::io::{self, Cursor};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
-use tokio::net::TcpStream;
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, IoSlice};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::Decoder;
+
+/// Bulk payloads at least this large are not copied into the write buffer.
+/// They are queued as their own chunk and handed to the socket with a
+/// vectored write, next to the encoded headers.
+const VECTORED_WRITE_THRESHOLD: usize = 16 * 1024;
+
+/// Number of chunks submitted to a single vectored write.
+const MAX_IO_SLICES: usize = 64;
 
 /// Send and receive `Frame` values from a remote peer.
 ///
 /// When implementing networking protocols, a message on that protocol is
 /// often composed of several smaller messages known as frames. The purpose of
-/// `Connection` is to read and write frames on the underlying `TcpStream`.
+/// `Connection` is to read and write frames on the underlying stream. Any
+/// transport implementing `AsyncRead` and `AsyncWrite` can be used: a
+/// `TcpStream`, a `UnixStream`, a TLS stream or an in-memory
+/// `tokio::io::duplex` pipe.
 ///
 /// To read frames, the `Connection` uses an internal buffer, which is filled
 /// up until there are enough bytes to create a full frame. Once this happens,
-/// the `Connection` creates the frame and returns it to the caller.
+/// the `Connection` decodes the frame with `RespCodec` and returns it to the
+/// caller.
 ///
 /// When sending frames, the frame is first encoded into the write buffer.
 /// The contents of the write buffer are then written to the socket.
-#[derive(Debug)]
 pub struct Connection {
-    // The `TcpStream`. It is decorated with a `BufWriter`, which provides write
-    // level buffering. The `BufWriter` implementation provided by Tokio is
-    // sufficient for our needs.
-    stream: BufWriter<TcpStream>,
+    // The transport. It is boxed so that `Connection`, and everything passing
+    // it around, does not need a type parameter for the stream. Writes are
+    // buffered by `Connection` itself, so the stream is used directly.
+    stream: Box<dyn Stream>,
 
     // The buffer for reading frames.
     buffer: BytesMut,
+
+    // The buffer frames are encoded into. It is reused across calls to
+    // `write_frame`, so encoding does not allocate once it has grown to the
+    // size of a typical reply.
+    write_buf: BytesMut,
+
+    // Encoded chunks waiting to be written to the socket. Large bulk payloads
+    // are queued here by reference, between the chunks of `write_buf` that
+    // surround them.
+    write_queue: WriteQueue,
+
+    // Decodes frames from the read buffer, applying the configured limits.
+    codec: RespCodec,
+
+    // Time spent waiting on the socket to accept writes, since the last call
+    // to `take_write_time`.
+    write_time: Duration,
 }
 
 impl Connection {
     /// Create a new `Connection`, backed by `socket`. Read and write buffers
     /// are initialized.
-    pub fn new(socket: TcpStream) -> Connection {
+    pub fn new<S>(socket: S) -> Connection
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         Connection {
-            stream: BufWriter::new(socket),
+            stream: Box::new(socket),
             // Default to a 4KB read buffer. For the use case of mini redis,
             // this is fine. However, real applications will want to tune this
             // value to their specific use case. There is a high likelihood that
             // a larger read buffer will work better.
             buffer: BytesMut::with_capacity(4 * 1024),
+            write_buf: BytesMut::with_capacity(4 * 1024),
+            write_queue: WriteQueue::default(),
+            codec: RespCodec::new(),
+            write_time: Duration::ZERO,
         }
     }
 
+    /// Replace the limits applied to frames read from the peer.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.codec = RespCodec::with_limits(limits);
+    }
+
     /// Read a single `Frame` value from the underlying stream.
     ///
     /// The function waits until it has retrieved enough data to parse a frame.
@@ -50,14 +94,14 @@ impl Connection {
     ///
     /// # Returns
     ///
-    /// On success, the received frame is returned. If the `TcpStream`
+    /// On success, the received frame is returned. If the stream
     /// is closed in a way that doesn't break a frame in half, it returns
     /// `None`. Otherwise, an error is returned.
     pub async fn read_frame(&mut self) -> crate::Result<Option<Frame>> {
         loop {
             // Attempt to parse a frame from the buffered data. If enough data
             // has been buffered, the frame is returned.
-            if let Some(frame) = self.parse_frame()? {
+            if let Some(frame) = self.codec.decode(&mut self.buffer)? {
                 return Ok(Some(frame));
             }
 
@@ -65,172 +109,132 @@ impl Connection {
             // read more data from the socket.
             //
             // On success, the number of bytes is returned. `0` indicates "end
-            // of stream".
+            // of stream". The codec then decides whether the stream ended
+            // cleanly or in the middle of a frame.
             if 0 == self.stream.read_buf(&mut self.buffer).await? {
-                // The remote closed the connection. For this to be a clean
-                // shutdown, there should be no data in the read buffer. If
-                // there is, this means that the peer closed the socket while
-                // sending a frame.
-                if self.buffer.is_empty() {
-                    return Ok(None);
-                } else {
-                    return Err("connection reset by peer".into());
-                }
-            }
-        }
-    }
-
-    /// Tries to parse a frame from the buffer. If the buffer contains enough
-    /// data, the frame is returned and the data removed from the buffer. If not
-    /// enough data has been buffered yet, `Ok(None)` is returned. If the
-    /// buffered data does not represent a valid frame, `Err` is returned.
-    fn parse_frame(&mut self) -> crate::Result<Option<Frame>> {
-        use frame::Error::Incomplete;
-
-        // Cursor is used to track the "current" location in the
-        // buffer. Cursor also implements `Buf` from the `bytes` crate
-        // which provides a number of helpful utilities for working
-        // with bytes.
-        let mut buf = Cursor::new(&self.buffer[..]);
-
-        // The first step is to check if enough data has been buffered to parse
-        // a single frame. This step is usually much faster than doing a full
-        // parse of the frame, and allows us to skip allocating data structures
-        // to hold the frame data unless we know the full frame has been
-        // received.
-        match Frame::check(&mut buf) {
-            Ok(_) => {
-                // The `check` function will have advanced the cursor until the
-                // end of the frame. Since the cursor had position set to zero
-                // before `Frame::check` was called, we obtain the length of the
-                // frame by checking the cursor position.
-                let len = buf.position() as usize;
-
-                // Reset the position to zero before passing the cursor to
-                // `Frame::parse`.
-                buf.set_position(0);
-
-                // Parse the frame from the buffer. This allocates the necessary
-                // structures to represent the frame and returns the frame
-                // value.
-                //
-                // If the encoded frame representation is invalid, an error is
-                // returned. This should terminate the **current** connection
-                // but should not impact any other connected clients.
-                let frame = Frame::parse(&mut buf)?;
-
-                // Discard the parsed data from the read buffer.
-                //
-                // When `advance` is called on the read buffer, all of the data
-                // up to `len` is discarded. The details of how this works is
-                // left to `BytesMut`. This is often done by moving an internal
-                // cursor, but it may be done by reallocating and copying data.
-                self.buffer.advance(len);
-
-                // Return the parsed frame to the caller.
-                Ok(Some(frame))
+                return self.codec.decode_eof(&mut self.buffer);
             }
-            // There is not enough data present in the read buffer to parse a
-            // single frame. We must wait for more data to be received from the
-            // socket. Reading from the socket will be done in the statement
-            // after this `match`.
-            //
-            // We do not want to return `Err` from here as this "error" is an
-            // expected runtime condition.
-            Err(Incomplete) => Ok(None),
-            // An error was encountered while parsing the frame. The connection
-            // is now in an invalid state. Returning `Err` from here will result
-            // in the connection being closed.
-            Err(e) => Err(e.into()),
         }
     }
 
     /// Write a single `Frame` value to the underlying stream.
     ///
-    /// The `Frame` value is written to the socket using the various `write_*`
-    /// functions provided by `AsyncWrite`. Calling these functions directly on
-    /// a `TcpStream` is **not** advised, as this will result in a large number of
-    /// syscalls. However, it is fine to call these functions on a *buffered*
-    /// write stream. The data will be written to the buffer. Once the buffer is
-    /// full, it is flushed to the underlying socket.
+    /// The frame is encoded synchronously into the reusable write buffer, then
+    /// written to the socket. Small replies go out with a single `write`
+    /// syscall. Large bulk payloads are not copied: they are passed to the
+    /// socket alongside the encoded headers with a vectored write.
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        // Arrays are encoded by encoding each entry. All other frame types are
-        // considered literals. For now, mini-redis is not able to encode
-        // recursive frame structures. See below for more details.
-        match frame {
-            Frame::Array(val) => {
-                // Encode the frame type prefix. For an array, it is `*`.
-                self.stream.write_u8(b'*').await?;
-
-                // Encode the length of the array.
-                self.write_decimal(val.len() as u64).await?;
-
-                // Iterate and encode each entry in the array.
-                for entry in &**val {
-                    self.write_value(entry).await?;
-                }
+        let queue = &mut self.write_queue;
+
+        frame.encode_with(&mut self.write_buf, &mut |dst, data| {
+            if data.len() >= VECTORED_WRITE_THRESHOLD {
+                // Close the chunk encoded so far and queue the payload
+                // itself. Cloning `Bytes` only increments a ref count.
+                queue.push(dst.split().freeze());
+                queue.push(data.clone());
+            } else {
+                dst.extend_from_slice(data);
             }
-            // The frame type is a literal. Encode the value directly.
-            _ => self.write_value(frame).await?,
-        }
+        });
+        queue.push(self.write_buf.split().freeze());
 
-        // Ensure the encoded frame is written to the socket. The calls above
-        // are to the buffered stream and writes. Calling `flush` writes the
-        // remaining contents of the buffer to the socket.
-        self.stream.flush().await
+        let start = Instant::now();
+        let res = Self::write_queue(&mut self.stream, queue).await;
+        self.write_time += start.elapsed();
+        res
     }
 
-    /// Write a frame literal to the stream
-    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Error(val) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
-                self.write_decimal(*val).await?;
-            }
-            Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
-            }
-            Frame::Bulk(val) => {
-                let len = val.len();
+    async fn write_queue(stream: &mut dyn Stream, queue: &mut WriteQueue) -> io::Result<()> {
+        while queue.has_remaining() {
+            let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+            let cnt = queue.chunks_vectored(&mut slices);
+            let n = stream.write_vectored(&slices[..cnt]).await?;
 
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
             }
-            // Encoding an `Array` from within a value cannot be done using a
-            // recursive strategy. In general, async fns do not support
-            // recursion. Mini-redis has not needed to encode nested arrays yet,
-            // so for now it is skipped.
-            Frame::Array(_val) => unreachable!(),
+
+            queue.advance(n);
         }
 
         Ok(())
     }
 
-    /// Write a decimal frame to the stream
-    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
-        use std::io::Write;
+    /// Write bytes already encoded as frames, such as the replication stream
+    /// a master sends to its replicas.
+    pub(crate) async fn write_encoded(&mut self, data: &[u8]) -> io::Result<()> {
+        let start = Instant::now();
+        let res = self.stream.write_all(data).await;
+        self.write_time += start.elapsed();
+        res
+    }
 
-        // Convert the value to a string
-        let mut buf = [0u8; 20];
-        let mut buf = Cursor::new(&mut buf[..]);
-        write!(&mut buf, "{}", val)?;
+    /// Returns the time spent writing to the socket since the last call, so
+    /// that the time a command takes to run can be told apart from the time
+    /// a slow client takes to read the reply.
+    pub(crate) fn take_write_time(&mut self) -> Duration {
+        std::mem::take(&mut self.write_time)
+    }
+}
 
-        let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.stream.write_all(b"\r\n").await?;
+/// The transport underlying a `Connection`.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
 
-        Ok(())
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Connection")
+            .field("buffer", &self.buffer.len())
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Chunks of an encoded frame, consumed front to back as they are written.
+///
+/// Implementing `Buf` with `chunks_vectored` lets every queued chunk be
+/// submitted to the socket in one `writev` call.
+#[derive(Debug, Default)]
+struct WriteQueue {
+    chunks: VecDeque<Bytes>,
+}
+
+impl WriteQueue {
+    fn push(&mut self, chunk: Bytes) {
+        if !chunk.is_empty() {
+            self.chunks.push_back(chunk);
+        }
+    }
+}
+
+impl Buf for WriteQueue {
+    fn remaining(&self) -> usize {
+        self.chunks.iter().map(Bytes::len).sum()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.chunks.front().map(|chunk| &chunk[..]).unwrap_or(&[])
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let mut n = 0;
+        for (slot, chunk) in dst.iter_mut().zip(&self.chunks) {
+            *slot = IoSlice::new(chunk);
+            n += 1;
+        }
+        n
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        while cnt > 0 {
+            let front = self.chunks.front_mut().expect("advance past end of queue");
+            if cnt < front.len() {
+                front.advance(cnt);
+                return;
+            }
+            cnt -= front.len();
+            self.chunks.pop_front();
+        }
     }
 }
@@ -0,0 +1,291 @@
+//! The thread-per-core engine, selected with `engine thread-per-core`.
+//!
+//! Each core runs a current-thread runtime on its own thread and owns one
+//! shard of the keyspace: its storage, its share of `maxmemory`, and the task
+//! removing its expired keys. Connections are spread over the cores, their
+//! sockets polled by the core serving them, and a command on keys owned by
+//! another core is sent to that core as a message, which runs it and sends
+//! the reply back. As only its owner ever touches a shard, cores never wait
+//! on each other's locks.
+//!
+//! Commands on keys of several cores, `MGET`, `MSET`, `DEL` and `UNLINK`,
+//! are split into a command per core, and the replies merged. Unlike on a
+//! single shard, other clients may see such a command applied on some cores
+//! and not yet on others. Pub/sub channels and the statistics remain shared
+//! by the cores, through the `Db`, and so do commands on the whole keyspace
+//! such as `FLUSHALL` or `INFO`, which lock every shard in turn.
+
+use crate::cmd::{Del, Mget, Mset, Unlink};
+use crate::{Command, Db, Frame};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{io, thread};
+use tokio::runtime;
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+
+/// Handle to the cores. Cloning it is cheap. The cores stop once every
+/// handle and every task they run are dropped.
+#[derive(Clone, Debug)]
+pub(crate) struct Cores {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// Where each core receives its jobs.
+    mailboxes: Vec<mpsc::UnboundedSender<Job>>,
+
+    /// Used to hand new connections to the cores in turn.
+    next: AtomicUsize,
+
+    /// The keyspace, with a shard per core.
+    db: Db,
+}
+
+/// Work sent to a core.
+enum Job {
+    /// Runs a task on the core, such as a connection handler.
+    Spawn(Pin<Box<dyn Future<Output = ()> + Send>>),
+    /// Runs a command on keys owned by the core.
+    Execute(Command, oneshot::Sender<Frame>),
+}
+
+impl std::fmt::Debug for Job {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Job::Spawn(_) => fmt.write_str("Spawn"),
+            Job::Execute(cmd, _) => fmt.debug_tuple("Execute").field(cmd).finish(),
+        }
+    }
+}
+
+/// The part of a command run by a core.
+enum Part {
+    /// Run by the core of the caller.
+    Here(Command),
+    /// Sent to another core, which replies on the channel.
+    Sent(oneshot::Receiver<Frame>),
+}
+
+/// How the replies of the parts of a command are merged.
+enum Merge {
+    /// The command was not split.
+    One,
+    /// `MGET`: the values are put back in the order of the keys, given as
+    /// the part of each key.
+    Values(Vec<usize>),
+    /// `DEL` and `UNLINK`: the counts of keys removed are summed.
+    Sum,
+    /// `MSET`: `OK` once every part is.
+    Ok,
+}
+
+impl Cores {
+    /// Starts a core per shard of `db`, which must be owned by the cores,
+    /// see `DbDropGuard::owned_by_cores`.
+    pub(crate) fn start(db: Db) -> io::Result<Cores> {
+        let mut mailboxes = vec![];
+        for index in 0..db.shards() {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let db = db.clone();
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            thread::Builder::new()
+                .name(format!("core-{}", index))
+                .spawn(move || runtime.block_on(run(index, db, rx)))?;
+            mailboxes.push(tx);
+        }
+        let shared = Arc::new(Shared {
+            mailboxes,
+            next: AtomicUsize::new(0),
+            db,
+        });
+        Ok(Cores { shared })
+    }
+
+    /// Picks the core to serve a new connection.
+    pub(crate) fn next_core(&self) -> usize {
+        self.shared.next.fetch_add(1, Ordering::Relaxed) % self.shared.mailboxes.len()
+    }
+
+    /// Runs `task` on the core `index`.
+    pub(crate) fn spawn(&self, index: usize, task: impl Future<Output = ()> + Send + 'static) {
+        // The core only stops once every handle is dropped.
+        let _ = self.shared.mailboxes[index].send(Job::Spawn(Box::pin(task)));
+    }
+
+    /// Runs `cmd` on the cores owning its keys and returns its reply. The
+    /// part on keys of `local`, the core of the caller, and commands on no
+    /// key run right away.
+    pub(crate) async fn execute(&self, local: usize, cmd: Command) -> crate::Result<Frame> {
+        let (parts, merge) = self.split(local, cmd);
+        // Sent before running the local part, so that the other cores run
+        // theirs meanwhile.
+        let parts = parts
+            .into_iter()
+            .map(|(core, cmd)| self.send(core, local, cmd))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let db = &self.shared.db;
+        let mut replies = Vec::with_capacity(parts.len());
+        for part in parts {
+            replies.push(match part {
+                Part::Here(cmd) => {
+                    cmd.load(db).await;
+                    cmd.execute(db)
+                }
+                Part::Sent(reply) => reply.await?,
+            });
+        }
+        Ok(merge.merge(replies))
+    }
+
+    /// Splits `cmd` into a command per core owning some of its keys.
+    fn split(&self, local: usize, cmd: Command) -> (Vec<(usize, Command)>, Merge) {
+        match cmd {
+            Command::Mget(cmd) => {
+                let (parts, order) = self.group(cmd.into_keys(), |key| key.as_str());
+                let parts = parts
+                    .into_iter()
+                    .map(|(core, keys)| (core, Command::Mget(Mget::new(keys))))
+                    .collect();
+                (parts, Merge::Values(order))
+            }
+            Command::Mset(cmd) => {
+                let (parts, _) = self.group(cmd.into_pairs(), |(key, _)| key.as_str());
+                let parts = parts
+                    .into_iter()
+                    .map(|(core, pairs)| (core, Command::Mset(Mset::new(pairs))))
+                    .collect();
+                (parts, Merge::Ok)
+            }
+            Command::Del(cmd) => {
+                let (parts, _) = self.group(cmd.into_keys(), |key| key.as_str());
+                let parts = parts
+                    .into_iter()
+                    .map(|(core, keys)| (core, Command::Del(Del::new(keys))))
+                    .collect();
+                (parts, Merge::Sum)
+            }
+            Command::Unlink(cmd) => {
+                let (parts, _) = self.group(cmd.into_keys(), |key| key.as_str());
+                let parts = parts
+                    .into_iter()
+                    .map(|(core, keys)| (core, Command::Unlink(Unlink::new(keys))))
+                    .collect();
+                (parts, Merge::Sum)
+            }
+            // Other commands have a key at most.
+            cmd => {
+                let core = cmd
+                    .keys()
+                    .first()
+                    .map_or(local, |key| self.shared.db.shard_of(key));
+                (vec![(core, cmd)], Merge::One)
+            }
+        }
+    }
+
+    /// Groups `items` by the core owning their key, in the order of their
+    /// first item. Returns the groups, and the group of each item.
+    fn group<T>(
+        &self,
+        items: Vec<T>,
+        key: impl Fn(&T) -> &str,
+    ) -> (Vec<(usize, Vec<T>)>, Vec<usize>) {
+        let mut groups: Vec<(usize, Vec<T>)> = vec![];
+        let mut order = Vec::with_capacity(items.len());
+        for item in items {
+            let core = self.shared.db.shard_of(key(&item));
+            let group = match groups.iter().position(|(owner, _)| *owner == core) {
+                Some(group) => group,
+                None => {
+                    groups.push((core, vec![]));
+                    groups.len() - 1
+                }
+            };
+            groups[group].1.push(item);
+            order.push(group);
+        }
+        (groups, order)
+    }
+
+    /// Sends `cmd` to the core `index`, unless it is `local`.
+    fn send(&self, index: usize, local: usize, cmd: Command) -> crate::Result<Part> {
+        if index == local {
+            return Ok(Part::Here(cmd));
+        }
+        let (tx, rx) = oneshot::channel();
+        self.shared.mailboxes[index]
+            .send(Job::Execute(cmd, tx))
+            .map_err(|_| "core stopped")?;
+        Ok(Part::Sent(rx))
+    }
+}
+
+impl Merge {
+    /// Merges the replies of the parts, in the order of the parts. The
+    /// first error is the reply if a part failed.
+    fn merge(self, mut replies: Vec<Frame>) -> Frame {
+        if let Some(err) = replies
+            .iter()
+            .find(|reply| matches!(reply, Frame::Error(_)))
+        {
+            return err.clone();
+        }
+        match self {
+            Merge::One => replies.pop().expect("a command has a part"),
+            Merge::Values(order) => {
+                let mut values: Vec<_> = replies
+                    .into_iter()
+                    .map(|reply| match reply {
+                        Frame::Array(values) => values.into_iter(),
+                        _ => Vec::new().into_iter(),
+                    })
+                    .collect();
+                let mut frame = Frame::array();
+                for part in order {
+                    frame.push_frame(values[part].next().unwrap_or(Frame::Null));
+                }
+                frame
+            }
+            Merge::Sum => Frame::Integer(
+                replies
+                    .iter()
+                    .map(|reply| match reply {
+                        Frame::Integer(count) => *count,
+                        _ => 0,
+                    })
+                    .sum(),
+            ),
+            Merge::Ok => Frame::Simple("OK".to_string()),
+        }
+    }
+}
+
+/// Routine of a core, running until every sender of `jobs` is dropped.
+async fn run(index: usize, db: Db, mut jobs: mpsc::UnboundedReceiver<Job>) {
+    debug!(index, "core started");
+    tokio::spawn(db.clone().purge_expired_keys(index));
+    while let Some(job) = jobs.recv().await {
+        match job {
+            Job::Spawn(task) => {
+                tokio::spawn(task);
+            }
+            Job::Execute(cmd, reply) => {
+                // Commands forwarded here only touch keys of this core. A
+                // task loading nothing runs them right away, in order.
+                let db = db.clone();
+                tokio::spawn(async move {
+                    cmd.load(&db).await;
+                    let _ = reply.send(cmd.execute(&db));
+                });
+            }
+        }
+    }
+    debug!(index, "core stopped");
+}
@@ -1,9 +1,20 @@
+use crate::cmd::{Del, Flush, Mset, Set, Unlink};
+use crate::config::{LazyFree, MaxmemoryPolicy};
+use crate::eviction::{Eviction, Rng};
+use crate::lazy_free::{LazyFreer, LAZYFREE_THRESHOLD};
+use crate::storage::{self, Entry, MemoryStorage, Storage, ENTRY_OVERHEAD};
+use crate::Frame;
+
 use tokio::sync::{broadcast, Notify};
+use tokio::task;
 use tokio::time::{self, Duration, Instant};
 
 use bytes::Bytes;
-use std::collections::{BTreeSet, HashMap};
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
 use tracing::debug;
 
 /// A wrapper around a `Db` instance. This exists to allow orderly cleanup
@@ -18,7 +29,7 @@ pub struct DbDropGuard {
 
 /// Server state shared across all connections.
 ///
-/// `Db` contains a `HashMap` storing the key/value data and all
+/// `Db` contains the key/value data, split into shards, and all
 /// `broadcast::Sender` values for active pub/sub channels.
 ///
 /// A `Db` instance is a handle to shared state. Cloning `Db` is shallow and
@@ -35,9 +46,15 @@ pub struct Db {
     shared: Arc<Shared>,
 }
 
+/// Number of shards the keyspace is split into by default.
+pub(crate) const DEFAULT_SHARDS: usize = 16;
+
 #[derive(Debug)]
 struct Shared {
-    /// The shared state is guarded by a mutex. This is a `std::sync::Mutex` and
+    /// The keyspace, split into shards by the hash of the keys, so that
+    /// commands on different keys rarely wait on each other.
+    ///
+    /// Each shard is guarded by a mutex. This is a `std::sync::Mutex` and
     /// not a Tokio mutex. This is because there are no asynchronous operations
     /// being performed while holding the mutex. Additionally, the critical
     /// sections are very small.
@@ -49,58 +66,280 @@ struct Shared {
     /// operations), then the entire operation, including waiting for the mutex,
     /// is considered a "blocking" operation and `tokio::task::spawn_blocking`
     /// should be used.
-    state: Mutex<State>,
+    ///
+    /// Commands locking several shards lock them in increasing order, see
+    /// `lock_keys`, so they cannot deadlock.
+    shards: Box<[Mutex<Shard>]>,
+
+    /// The pub/sub key-space. Redis uses a **separate** key space for key-value
+    /// and pub/sub. `mini-redis` handles this by using a separate `HashMap`.
+    pub_sub: Mutex<PubSub>,
+
+    /// Copy of `Eviction::maxmemory`, so that writes check the limit without
+    /// taking a lock.
+    maxmemory: AtomicU64,
+
+    /// Whether each shard is owned by a core of the thread-per-core engine,
+    /// see `DbDropGuard::owned_by_cores`. The shards then evict and expire
+    /// their keys on their own.
+    owned: bool,
+
+    /// Sum of the memory used by the shards, kept up to date by `ShardGuard`.
+    used_memory: AtomicUsize,
+
+    /// Whether the storages keep the values on disk, in which case eviction
+    /// drops values from memory rather than keys, see `Storage::shrink`.
+    values_on_disk: bool,
+
+    /// The first I/O error of a storage, recorded by `ShardGuard`. Writes
+    /// are refused from then on.
+    storage_error: OnceLock<String>,
+
+    /// Where the writes are propagated once a replica connected, see
+    /// `propagate_to`. Weak, as the replication state holds the `Db`.
+    stream: OnceLock<Weak<dyn Propagate>>,
+
+    /// Counters of the expiration cycles, see `DbStats`.
+    expire_cycles: Mutex<ExpireCycles>,
+
+    /// Shard the next expiration cycle starts from. A cycle running out of
+    /// time leaves it at the shard it was purging, so that every shard gets
+    /// its turn.
+    expire_cursor: AtomicUsize,
+
+    /// Frees large values removed from the shards.
+    freer: LazyFreer,
+
+    /// True when the Db instance is shutting down. This happens when all `Db`
+    /// values drop. Setting this to `true` signals to the background task to
+    /// exit.
+    shutdown: AtomicBool,
 
     /// Notifies the background task handling entry expiration. The background
     /// task waits on this to be notified, then checks for expired values or the
-    /// shutdown signal.
-    background_task: Notify,
+    /// shutdown signal. There is a task per shard when the shards are
+    /// `owned`, and a single one otherwise.
+    background_tasks: Box<[Notify]>,
 }
 
+/// A part of the keyspace.
 #[derive(Debug)]
-struct State {
-    /// The key-value data. We are not trying to do anything fancy so a
-    /// `std::collections::HashMap` works fine.
-    entries: HashMap<String, Entry>,
+struct Shard {
+    /// The entries and their expirations, `MemoryStorage` unless the `Db`
+    /// was created with `with_storage`.
+    storage: Box<dyn Storage>,
 
-    /// The pub/sub key-space. Redis uses a **separate** key space for key-value
-    /// and pub/sub. `mini-redis` handles this by using a separate `HashMap`.
-    pub_sub: HashMap<String, broadcast::Sender<Bytes>>,
+    /// Used to count accesses for LFU and to break ties between keys to
+    /// evict.
+    rng: Rng,
 
-    /// Tracks key TTLs.
-    ///
-    /// A `BTreeSet` is used to maintain expirations sorted by when they expire.
-    /// This allows the background task to iterate this map to find the value
-    /// expiring next.
-    ///
-    /// While highly unlikely, it is possible for more than one expiration to be
-    /// created for the same instant. Because of this, the `Instant` is
-    /// insufficient for the key. A unique key (`String`) is used to
-    /// break these ties.
-    expirations: BTreeSet<(Instant, String)>,
+    /// Which removals free values on the background thread.
+    lazy_free: LazyFree,
 
-    /// True when the Db instance is shutting down. This happens when all `Db`
-    /// values drop. Setting this to `true` signals to the background task to
-    /// exit.
-    shutdown: bool,
+    /// Frees values on the background thread.
+    freer: LazyFreer,
+
+    /// Memory limit and eviction policy, the same for every shard.
+    eviction: Eviction,
+
+    /// Memory used by the keys and their bookkeeping, without the values.
+    keys_memory: usize,
+
+    /// Whether `storage` keeps the values on disk. `stats.dataset_memory`
+    /// is then left at zero, the storage accounting for the values in
+    /// memory.
+    values_on_disk: bool,
+
+    /// Counters reported by `INFO`. Kept under the same lock as the data they
+    /// describe, so they are updated without extra synchronization.
+    stats: DbStats,
+
+    /// Whether writes are propagated, copied from `Shared::stream` when the
+    /// shard is locked.
+    propagating: bool,
+
+    /// Frames of the writes to propagate, sent by `ShardGuard` when the
+    /// shard is released.
+    propagated: Vec<Frame>,
+}
+
+/// Receives the writes applied to the keyspace, as the commands replicas
+/// apply to reproduce them: the writes of the clients, as well as the keys
+/// removed because they expired or were evicted.
+pub(crate) trait Propagate: fmt::Debug + Send + Sync {
+    /// Called with the shards the write changed still locked, so that the
+    /// writes to a key are propagated in the order they were applied.
+    fn propagate(&self, frame: &Frame);
 }
 
-/// Entry in the key-value store
+/// A locked shard. Accounts for the memory the shard gained or freed in
+/// `Shared::used_memory` when released, and records the error of its
+/// storage, if any.
+struct ShardGuard<'a> {
+    shard: MutexGuard<'a, Shard>,
+    shared: &'a Shared,
+    /// Memory used by the shard when locked.
+    locked_with: usize,
+}
+
+/// Outcome of removing expired keys.
 #[derive(Debug)]
-struct Entry {
-    /// Stored data
-    data: Bytes,
+enum Purge {
+    /// Every expired key was removed. The next key expires at the instant,
+    /// if any.
+    Done(Option<Instant>),
+    /// Expired keys remain.
+    Pending,
+}
 
-    /// Instant at which the entry expires and should be removed from the
-    /// database.
-    expires_at: Option<Instant>,
+#[derive(Debug, Default)]
+struct ExpireCycles {
+    time_cap_reached: u64,
+    time: Duration,
+}
+
+#[derive(Debug, Default)]
+struct PubSub {
+    channels: HashMap<String, broadcast::Sender<Bytes>>,
+
+    /// Messages published, see `DbStats::pubsub_messages`.
+    messages: u64,
+
+    /// Messages delivered, see `DbStats::pubsub_deliveries`.
+    deliveries: u64,
+}
+
+/// Keyspace statistics reported by `INFO`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DbStats {
+    /// Number of keys.
+    pub(crate) keys: usize,
+
+    /// Number of keys with an expiration.
+    pub(crate) expires: usize,
+
+    /// Approximate number of bytes used by keys and values. Values kept on
+    /// disk only count if cached in memory.
+    pub(crate) used_memory: usize,
+
+    /// Part of `used_memory` used by the values themselves.
+    pub(crate) dataset_memory: usize,
+
+    /// Part of `used_memory` used to track expirations.
+    pub(crate) expires_memory: usize,
+
+    /// Lookups that found the key.
+    pub(crate) keyspace_hits: u64,
+
+    /// Lookups that did not find the key.
+    pub(crate) keyspace_misses: u64,
+
+    /// Keys removed because they expired, by the background task or when
+    /// accessed.
+    pub(crate) expired_keys: u64,
+
+    /// Expiration cycles that ran out of time before removing every expired
+    /// key.
+    pub(crate) expired_time_cap_reached: u64,
+
+    /// Time spent in expiration cycles.
+    pub(crate) expire_cycle_time: Duration,
+
+    /// Values waiting to be freed on the background thread.
+    pub(crate) lazyfree_pending_objects: usize,
+
+    /// Values freed on the background thread.
+    pub(crate) lazyfreed_objects: u64,
+
+    /// Keys removed to keep the memory used below `maxmemory`.
+    pub(crate) evicted_keys: u64,
+
+    /// Writes since the server started.
+    pub(crate) changes: u64,
+
+    /// Pub/sub channels with at least one subscriber.
+    pub(crate) pubsub_channels: usize,
+
+    /// Messages published, whether anyone received them or not.
+    pub(crate) pubsub_messages: u64,
+
+    /// Messages delivered to subscribers. A message published to a channel
+    /// with three subscribers counts three times.
+    pub(crate) pubsub_deliveries: u64,
+}
+
+/// Keys removed by the background task per lock of a shard, so that clients
+/// waiting on the shard get their turn.
+const EXPIRE_BATCH: usize = 20;
+
+/// Time an expiration cycle may run before the background task yields.
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(1);
+
+/// Time the background task rests between cycles that ran out of budget, so
+/// that a backlog of expired keys takes at most a quarter of a thread.
+const EXPIRE_CYCLE_PAUSE: Duration = Duration::from_millis(3);
+
+/// Messages buffered by a pub/sub channel for its slowest subscriber.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Introspection data about a key, reported by `OBJECT` and `MEMORY USAGE`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct KeyInfo {
+    /// Time since the key was last read or written.
+    pub(crate) idle: Duration,
+
+    /// Logarithmic access counter used by the LFU policies.
+    pub(crate) frequency: u8,
+
+    /// How Redis would encode the value: `int`, `embstr` or `raw`.
+    pub(crate) encoding: &'static str,
+
+    /// Approximate number of bytes used by the key and its value.
+    pub(crate) memory: usize,
+}
+
+/// Breakdown of the memory used, reported by `MEMORY STATS`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MemoryStats {
+    pub(crate) keys: usize,
+
+    /// Values stored.
+    pub(crate) dataset: usize,
+
+    /// Keys and bookkeeping of `entries`.
+    pub(crate) entries: usize,
+
+    /// Bookkeeping of `expirations`.
+    pub(crate) expirations: usize,
+
+    /// Channel names and message buffers of `pub_sub`.
+    pub(crate) pub_sub: usize,
 }
 
 impl DbDropGuard {
-    /// Create a new `DbHolder`, wrapping a `Db` instance. When this is dropped
-    /// the `Db`'s purge task will be shut down.
-    pub fn new() -> DbDropGuard {
-        DbDropGuard { db: Db::new() }
+    /// Create a new `DbHolder`, wrapping a `Db` instance keeping the keys of
+    /// each shard in the storage `open` returns for the index of the shard.
+    /// When this is dropped the `Db`'s purge task will be shut down.
+    pub fn with_storage(
+        shards: usize,
+        open: impl FnMut(usize) -> crate::Result<Box<dyn Storage>>,
+    ) -> crate::Result<DbDropGuard> {
+        Ok(DbDropGuard {
+            db: Db::with_storage(shards, open)?,
+        })
+    }
+
+    /// Same as `with_storage`, for the thread-per-core engine: the shards
+    /// are owned by a core each, which runs `Db::purge_expired_keys` for
+    /// its shard, and each shard keeps to its share of `maxmemory`.
+    pub(crate) fn owned_by_cores(
+        shards: usize,
+        open: impl FnMut(usize) -> crate::Result<Box<dyn Storage>>,
+    ) -> crate::Result<DbDropGuard> {
+        let storages = (0..shards.max(1)).map(open).collect::<crate::Result<_>>()?;
+        Ok(DbDropGuard {
+            db: Db::from_storages(storages, true),
+        })
     }
 
     /// Get the shared database. Internally, this is an
@@ -117,26 +356,119 @@ impl Drop for DbDropGuard {
     }
 }
 
+impl Default for Db {
+    fn default() -> Db {
+        Db::new()
+    }
+}
+
 impl Db {
     /// Create a new, empty, `Db` instance. Allocates shared state and spawns a
     /// background task to manage key expiration.
     pub fn new() -> Db {
+        Db::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Same as `new`, splitting the keyspace into `shards` shards. A single
+    /// shard makes every command wait on the same lock.
+    pub fn with_shards(shards: usize) -> Db {
+        let storages = (0..shards.max(1))
+            .map(|_| Box::new(MemoryStorage::new()) as Box<dyn Storage>)
+            .collect();
+        Db::from_storages(storages, false)
+    }
+
+    /// Same as `with_shards`, keeping the keys of each shard in the storage
+    /// `open` returns for the index of the shard, rather than in memory.
+    pub fn with_storage(
+        shards: usize,
+        open: impl FnMut(usize) -> crate::Result<Box<dyn Storage>>,
+    ) -> crate::Result<Db> {
+        let storages = (0..shards.max(1)).map(open).collect::<crate::Result<_>>()?;
+        Ok(Db::from_storages(storages, false))
+    }
+
+    fn from_storages(storages: Vec<Box<dyn Storage>>, owned: bool) -> Db {
+        let freer = LazyFreer::new();
+        let mut used_memory = 0;
+        let values_on_disk = storages
+            .iter()
+            .any(|storage| storage.resident_memory().is_some());
+        let shards = storages
+            .into_iter()
+            .map(|storage| {
+                // Account for the keys the storage opened with.
+                let mut shard = Shard {
+                    storage,
+                    rng: Rng::new(),
+                    lazy_free: LazyFree::default(),
+                    freer: freer.clone(),
+                    eviction: Eviction::default(),
+                    keys_memory: 0,
+                    values_on_disk,
+                    stats: DbStats::default(),
+                    propagating: false,
+                    propagated: Vec::new(),
+                };
+                let (mut keys_memory, mut stats) = (0, DbStats::default());
+                shard.storage.for_each_key(&mut |key, len, expires_at| {
+                    let expires = expires_at.is_some();
+                    keys_memory += storage::memory_usage(key, 0, expires);
+                    if !values_on_disk {
+                        stats.dataset_memory += len;
+                    }
+                    stats.expires_memory += storage::expiration_overhead(key, expires);
+                });
+                shard.keys_memory = keys_memory;
+                shard.stats = stats;
+                used_memory += shard.used_memory();
+                Mutex::new(shard)
+            })
+            .collect::<Box<[_]>>();
+        let tasks = if owned { shards.len() } else { 1 };
         let shared = Arc::new(Shared {
-            state: Mutex::new(State {
-                entries: HashMap::new(),
-                pub_sub: HashMap::new(),
-                expirations: BTreeSet::new(),
-                shutdown: false,
-            }),
-            background_task: Notify::new(),
+            shards,
+            pub_sub: Mutex::new(PubSub::default()),
+            maxmemory: AtomicU64::new(0),
+            owned,
+            used_memory: AtomicUsize::new(used_memory),
+            values_on_disk,
+            storage_error: OnceLock::new(),
+            stream: OnceLock::new(),
+            expire_cycles: Mutex::new(ExpireCycles::default()),
+            expire_cursor: AtomicUsize::new(0),
+            freer,
+            shutdown: AtomicBool::new(false),
+            background_tasks: (0..tasks).map(|_| Notify::new()).collect(),
         });
 
-        // Start the background task.
-        tokio::spawn(purge_expired_tasks(shared.clone()));
+        // Start the background task, unless the cores owning the shards run
+        // one each.
+        if !owned {
+            tokio::spawn(purge_expired_tasks(shared.clone(), None));
+        }
 
         Db { shared }
     }
 
+    /// Removes the expired keys of the shard `index` until the `Db` shuts
+    /// down. Run by the core owning the shard, see
+    /// `DbDropGuard::owned_by_cores`.
+    pub(crate) async fn purge_expired_keys(self, index: usize) {
+        purge_expired_tasks(self.shared, Some(index)).await
+    }
+
+    /// Number of shards the keyspace is split into.
+    pub(crate) fn shards(&self) -> usize {
+        self.shared.shards.len()
+    }
+
+    /// Index of the shard holding `key`, between 0 and the number of
+    /// shards.
+    pub(crate) fn shard_of(&self, key: &str) -> usize {
+        self.shared.shard_index(key)
+    }
+
     /// Get the value associated with a key.
     ///
     /// Returns `None` if there is no value associated with the key. This may be
@@ -147,8 +479,71 @@ impl Db {
         //
         // Because data is stored using `Bytes`, a clone here is a shallow
         // clone. Data is not copied.
-        let state = self.shared.state.lock().unwrap();
-        state.entries.get(key).map(|entry| entry.data.clone())
+        let mut shard = self.shared.lock_key(key);
+        shard.get(key)
+    }
+
+    /// Reads the values of `keys` the storages keep on disk, on a blocking
+    /// thread and without holding the locks of the shards, so that commands
+    /// on the keys then find them in memory. Called before running such
+    /// commands.
+    pub(crate) async fn load(&self, keys: &[&str]) {
+        if !self.shared.values_on_disk {
+            return;
+        }
+        let mut by_shard: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for key in keys {
+            by_shard
+                .entry(self.shared.shard_index(key))
+                .or_default()
+                .push(key);
+        }
+        for (index, keys) in by_shard {
+            let fetch = self.shared.lock(index).storage.fetch(&keys);
+            let Some(fetch) = fetch else {
+                continue;
+            };
+            if let Ok(fetched) = task::spawn_blocking(fetch).await {
+                self.shared.lock(index).storage.fetched(fetched);
+            }
+        }
+    }
+
+    /// Waits until the writes made so far to the shards at `indices`, or
+    /// to every shard if empty, are durable, for storages making them so in
+    /// the background. Called before replying to writes.
+    pub(crate) async fn written(&self, indices: &[usize]) {
+        if !self.shared.values_on_disk {
+            return;
+        }
+        let every = 0..self.shared.shards.len();
+        let indices: Vec<usize> = if indices.is_empty() {
+            every.collect()
+        } else {
+            indices.to_vec()
+        };
+        for index in indices {
+            let written = self.shared.lock(index).storage.written();
+            if let Some(written) = written {
+                written.await;
+            }
+        }
+    }
+
+    /// Whether the storage makes the writes durable, see `written`.
+    pub(crate) fn is_durable(&self) -> bool {
+        self.shared.values_on_disk
+    }
+
+    /// The I/O error after which a storage can't keep writes, if any.
+    pub(crate) fn storage_error(&self) -> Option<&str> {
+        self.shared.storage_error.get().map(String::as_str)
+    }
+
+    /// Get the values associated with several keys, as of the same instant.
+    pub fn mget(&self, keys: &[&str]) -> Vec<Option<Bytes>> {
+        let mut shards = self.shared.lock_keys(keys);
+        keys.iter().map(|key| shards.get(key).get(key)).collect()
     }
 
     /// Set the value associated with a key along with an optional expiration
@@ -156,66 +551,136 @@ impl Db {
     ///
     /// If a value is already associated with the key, it is removed.
     pub fn set(&self, key: String, value: Bytes, expire: Option<Duration>) {
-        let mut state = self.shared.state.lock().unwrap();
+        let index = self.shared.shard_index(&key);
+        let mut shard = self.shared.lock(index);
+
+        // `Instant` at which the key expires.
+        let expires_at = expire.map(|duration| Instant::now() + duration);
+        if shard.propagating {
+            let frame = Set::replicated(&key, value.clone(), expires_at);
+            shard.propagated.push(frame);
+        }
 
         // If this `set` becomes the key that expires **next**, the background
         // task needs to be notified so it can update its state.
         //
         // Whether or not the task needs to be notified is computed during the
         // `set` routine.
-        let mut notify = false;
-
-        let expires_at = expire.map(|duration| {
-            // `Instant` at which the key expires.
-            let when = Instant::now() + duration;
+        let notify = shard.set(key, value, expires_at);
 
-            // Only notify the worker task if the newly inserted expiration is the
-            // **next** key to evict. In this case, the worker needs to be woken up
-            // to update its state.
-            notify = state
-                .next_expiration()
-                .map(|expiration| expiration > when)
-                .unwrap_or(true);
+        // Release the mutex before notifying the background task. This helps
+        // reduce contention by avoiding the background task waking up only to
+        // be unable to acquire the mutex due to this function still holding it.
+        drop(shard);
 
-            when
-        });
+        if notify {
+            // Finally, only notify the background task if it needs to update
+            // its state to reflect a new expiration.
+            self.shared.background_task(index).notify_one();
+        }
+    }
 
-        // Insert the entry into the `HashMap`.
-        let prev = state.entries.insert(
-            key.clone(),
-            Entry {
-                data: value,
-                expires_at,
-            },
-        );
+    /// Set several keys at once. No client sees some of the keys set and not
+    /// the others.
+    pub fn mset(&self, pairs: Vec<(String, Bytes)>) {
+        let keys: Vec<&str> = pairs.iter().map(|(key, _)| &key[..]).collect();
+        let mut shards = self.shared.lock_keys(&keys);
+        shards.propagate(|| Mset::new(pairs.clone()).into_frame());
+        for (key, value) in pairs {
+            // Keys set this way never expire, so the background task has
+            // nothing to learn.
+            shards.get(&key).set(key, value, None);
+        }
+    }
 
-        // If there was a value previously associated with the key **and** it
-        // had an expiration time. The associated entry in the `expirations` map
-        // must also be removed. This avoids leaking data.
-        if let Some(prev) = prev {
-            if let Some(when) = prev.expires_at {
-                // clear expiration
-                state.expirations.remove(&(when, key.clone()));
-            }
+    /// Removes keys, returning how many existed. Their values are freed on
+    /// the background thread if `lazyfree-lazy-user-del` is set.
+    pub fn del(&self, keys: &[&str]) -> usize {
+        let mut shards = self.shared.lock_keys(keys);
+        let removed = remove_keys(&mut shards, keys, None);
+        if !removed.is_empty() {
+            shards.propagate(|| Del::new(removed.clone()).into_frame());
         }
+        removed.len()
+    }
 
-        // Track the expiration. If we insert before remove that will cause bug
-        // when current `(when, key)` equals prev `(when, key)`. Remove then insert
-        // can avoid this.
-        if let Some(when) = expires_at {
-            state.expirations.insert((when, key));
+    /// Same as `del`, always freeing the values on the background thread.
+    pub fn unlink(&self, keys: &[&str]) -> usize {
+        let mut shards = self.shared.lock_keys(keys);
+        let removed = remove_keys(&mut shards, keys, Some(true));
+        if !removed.is_empty() {
+            shards.propagate(|| Unlink::new(removed.clone()).into_frame());
         }
+        removed.len()
+    }
 
-        // Release the mutex before notifying the background task. This helps
-        // reduce contention by avoiding the background task waking up only to
-        // be unable to acquire the mutex due to this function still holding it.
-        drop(state);
+    /// Removes every key. The keyspace is freed on the background thread if
+    /// `lazy` is set, or when `None`, if `lazyfree-lazy-user-flush` is.
+    pub fn flush(&self, lazy: Option<bool>) {
+        // Every shard is locked, so that no client sees part of the keyspace
+        // flushed.
+        let mut shards: Vec<_> = (0..self.shared.shards.len())
+            .map(|index| self.shared.lock(index))
+            .collect();
+        let lazy = lazy.unwrap_or(shards[0].lazy_free.user_flush);
+        if shards[0].propagating {
+            // Replicas free their keyspace as they are configured to.
+            shards[0].propagated.push(Flush::new(None).into_frame());
+        }
+        let flushed: Vec<_> = shards.iter_mut().map(|shard| shard.flush()).collect();
+        drop(shards);
+        if lazy {
+            self.shared.freer.free(flushed);
+        }
+    }
 
-        if notify {
-            // Finally, only notify the background task if it needs to update
-            // its state to reflect a new expiration.
-            self.shared.background_task.notify_one();
+    /// Lists every key after calling `cut`. Every shard is locked meanwhile,
+    /// so that the list reflects a single instant, before which `cut` sees
+    /// every write propagated, and none after. The keys of a shard are
+    /// listed together, see `read_values`.
+    pub(crate) fn snapshot_keys<T>(&self, cut: impl FnOnce() -> T) -> (T, Vec<String>) {
+        let shards: Vec<_> = (0..self.shared.shards.len())
+            .map(|index| self.shared.lock(index))
+            .collect();
+        let cut = cut();
+        let mut keys = vec![];
+        for shard in &shards {
+            shard
+                .storage
+                .for_each_key(&mut |key, _, _| keys.push(key.to_string()));
         }
+        (cut, keys)
+    }
+
+    /// Reads the values of `keys`, with the instant they expire at, without
+    /// keeping those on disk in memory. `None` for the keys removed or
+    /// expired since. A shard is locked while its keys are read, in turn,
+    /// and values kept on disk are read, so this blocks.
+    pub(crate) fn read_values(&self, keys: &[String]) -> Vec<Option<(Bytes, Option<Instant>)>> {
+        let now = Instant::now();
+        let mut locked: Option<(usize, ShardGuard<'_>)> = None;
+        keys.iter()
+            .map(|key| {
+                let index = self.shared.shard_index(key);
+                if locked.as_ref().is_none_or(|(locked, _)| *locked != index) {
+                    // The previous shard is released first.
+                    drop(locked.take());
+                    locked = Some((index, self.shared.lock(index)));
+                }
+                let (_, shard) = locked.as_ref()?;
+                let entry = shard.storage.read(key)?;
+                match entry.expires_at {
+                    Some(when) if when <= now => None,
+                    expires_at => Some((entry.data, expires_at)),
+                }
+            })
+            .collect()
+    }
+
+    /// Propagates the writes to `stream` from now on, unless they already
+    /// are.
+    pub(crate) fn propagate_to(&self, stream: Weak<dyn Propagate>) {
+        let _ = self.shared.stream.set(stream);
     }
 
     /// Returns a `Receiver` for the requested channel.
@@ -226,25 +691,25 @@ impl Db {
         use std::collections::hash_map::Entry;
 
         // Acquire the mutex
-        let mut state = self.shared.state.lock().unwrap();
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
 
         // If there is no entry for the requested channel, then create a new
         // broadcast channel and associate it with the key. If one already
         // exists, return an associated receiver.
-        match state.pub_sub.entry(key) {
+        match pub_sub.channels.entry(key) {
             Entry::Occupied(e) => e.get().subscribe(),
             Entry::Vacant(e) => {
                 // No broadcast channel exists yet, so create one.
                 //
-                // The channel is created with a capacity of `1024` messages. A
-                // message is stored in the channel until **all** subscribers
-                // have seen it. This means that a slow subscriber could result
-                // in messages being held indefinitely.
+                // The channel is created with a capacity of `CHANNEL_CAPACITY`
+                // messages. A message is stored in the channel until **all**
+                // subscribers have seen it. This means that a slow subscriber
+                // could result in messages being held indefinitely.
                 //
                 // When the channel's capacity fills up, publishing will result
                 // in old messages being dropped. This prevents slow consumers
                 // from blocking the entire system.
-                let (tx, rx) = broadcast::channel(1024);
+                let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
                 e.insert(tx);
                 rx
             }
@@ -254,10 +719,10 @@ impl Db {
     /// Publish a message to the channel. Returns the number of subscribers
     /// listening on the channel.
     pub fn publish(&self, key: &str, value: Bytes) -> usize {
-        let state = self.shared.state.lock().unwrap();
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
 
-        state
-            .pub_sub
+        let receivers = pub_sub
+            .channels
             .get(key)
             // On a successful message send on the broadcast channel, the number
             // of subscribers is returned. An error indicates there are no
@@ -265,60 +730,353 @@ impl Db {
             .map(|tx| tx.send(value).unwrap_or(0))
             // If there is no entry for the channel key, then there are no
             // subscribers. In this case, return `0`.
-            .unwrap_or(0)
+            .unwrap_or(0);
+        pub_sub.messages += 1;
+        pub_sub.deliveries += receivers as u64;
+        receivers
+    }
+
+    /// Changes which removals free values on the background thread.
+    pub(crate) fn set_lazy_free(&self, lazy_free: LazyFree) {
+        for index in 0..self.shared.shards.len() {
+            self.shared.lock(index).lazy_free = lazy_free;
+        }
+    }
+
+    /// Changes the memory limit and eviction policy, see
+    /// `perform_evictions`.
+    pub(crate) fn set_eviction(&self, eviction: Eviction) {
+        for index in 0..self.shared.shards.len() {
+            self.shared.lock(index).eviction = eviction;
+        }
+        self.shared
+            .maxmemory
+            .store(eviction.maxmemory, Ordering::Relaxed);
+    }
+
+    /// Evicts keys, as selected by the eviction policy, until the memory used
+    /// is below `maxmemory`. Called before running commands that may use
+    /// more memory. Returns `false` if the memory used is still above the
+    /// limit, in which case the command must be refused.
+    ///
+    /// Each shard proposes its best candidate, sampling its share of
+    /// `maxmemory-samples` keys, and the best of them is evicted. When the
+    /// values are kept on disk, the shards rather drop values from memory,
+    /// the keys staying, whatever the policy.
+    ///
+    /// Shards owned by cores each evict their own keys, see
+    /// `perform_evictions_for`.
+    pub(crate) fn perform_evictions(&self) -> bool {
+        let shared = &self.shared;
+        if shared.owned {
+            let mut below = true;
+            for index in 0..shared.shards.len() {
+                below &= shared.evict_shard(index);
+            }
+            return below;
+        }
+        let maxmemory = shared.maxmemory.load(Ordering::Relaxed);
+        if maxmemory == 0 || shared.used_memory.load(Ordering::Relaxed) as u64 <= maxmemory {
+            return true;
+        }
+        if shared.values_on_disk {
+            loop {
+                let used_memory = shared.used_memory.load(Ordering::Relaxed) as u64;
+                if used_memory <= maxmemory {
+                    return true;
+                }
+                let share = (used_memory - maxmemory).div_ceil(shared.shards.len() as u64);
+                let freed: usize = (0..shared.shards.len())
+                    .map(|index| shared.lock(index).storage.shrink(share as usize))
+                    .sum();
+                if freed == 0 {
+                    return false;
+                }
+            }
+        }
+        let mut eviction = shared.lock(0).eviction;
+        eviction.samples = eviction.samples.div_ceil(shared.shards.len());
+        while shared.used_memory.load(Ordering::Relaxed) as u64 > maxmemory {
+            let mut best: Option<(u128, usize, String)> = None;
+            for index in 0..shared.shards.len() {
+                let candidate = shared.lock(index).eviction_candidate(&eviction);
+                if let Some((score, key)) = candidate {
+                    if best.as_ref().is_none_or(|(best, ..)| score > *best) {
+                        best = Some((score, index, key));
+                    }
+                }
+            }
+            let Some((_, index, key)) = best else {
+                return false;
+            };
+            debug!(%key, "evicting key");
+            let mut shard = shared.lock(index);
+            // Another connection may have removed the key in the meantime.
+            if let Some(entry) = shard.remove(&key) {
+                shard.stats.evicted_keys += 1;
+                let lazy = shard.lazy_free.eviction;
+                shard.dispose(entry, lazy);
+                shard.propagate_removal(key);
+            }
+        }
+        true
+    }
+
+    /// Same as `perform_evictions`, before running a command on `keys`.
+    /// Shards owned by cores only evict keys of the shards holding `keys`,
+    /// until these are below their share of `maxmemory`, so that a core
+    /// never waits on the shards of the others.
+    pub(crate) fn perform_evictions_for(&self, keys: &[&str]) -> bool {
+        let shared = &self.shared;
+        if !shared.owned {
+            return self.perform_evictions();
+        }
+        let mut indices: Vec<usize> = keys.iter().map(|key| shared.shard_index(key)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        let mut below = true;
+        for index in indices {
+            below &= shared.evict_shard(index);
+        }
+        below
+    }
+
+    /// Returns introspection data about `key`, without counting it as an
+    /// access.
+    pub(crate) fn key_info(&self, key: &str) -> Option<KeyInfo> {
+        let mut shard = self.shared.lock_key(key);
+        shard.expire_if_needed(key);
+        shard.storage.get(key).map(|entry| KeyInfo {
+            idle: entry.accessed_at.elapsed(),
+            frequency: entry.lfu.frequency(),
+            encoding: entry.encoding(),
+            memory: entry.memory_usage(key),
+        })
+    }
+
+    /// Returns the breakdown of the memory used.
+    pub(crate) fn memory_stats(&self) -> MemoryStats {
+        let stats = self.stats();
+        // The message buffer of a channel is allocated up front.
+        let buffer = CHANNEL_CAPACITY * std::mem::size_of::<Option<Bytes>>();
+        MemoryStats {
+            keys: stats.keys,
+            dataset: stats.dataset_memory,
+            entries: stats.used_memory - stats.dataset_memory - stats.expires_memory,
+            expirations: stats.expires_memory,
+            pub_sub: self
+                .shared
+                .pub_sub
+                .lock()
+                .unwrap()
+                .channels
+                .keys()
+                .map(|channel| ENTRY_OVERHEAD + channel.len() + buffer)
+                .sum(),
+        }
+    }
+
+    /// Returns the keyspace statistics.
+    ///
+    /// The shards are visited one at a time, so the statistics may not
+    /// reflect a single instant.
+    pub(crate) fn stats(&self) -> DbStats {
+        let mut stats = DbStats::default();
+        for index in 0..self.shared.shards.len() {
+            let shard = self.shared.lock(index);
+            let dataset_memory = shard.storage.resident_memory();
+            stats.merge(&DbStats {
+                keys: shard.storage.len(),
+                expires: shard.storage.expires(),
+                used_memory: shard.used_memory(),
+                dataset_memory: dataset_memory.unwrap_or(shard.stats.dataset_memory),
+                ..shard.stats
+            });
+        }
+        let expire_cycles = self.shared.expire_cycles.lock().unwrap();
+        stats.expired_time_cap_reached = expire_cycles.time_cap_reached;
+        stats.expire_cycle_time = expire_cycles.time;
+        drop(expire_cycles);
+        stats.lazyfree_pending_objects = self.shared.freer.pending();
+        stats.lazyfreed_objects = self.shared.freer.freed();
+        let pub_sub = self.shared.pub_sub.lock().unwrap();
+        stats.pubsub_channels = pub_sub
+            .channels
+            .values()
+            .filter(|tx| tx.receiver_count() > 0)
+            .count();
+        stats.pubsub_messages = pub_sub.messages;
+        stats.pubsub_deliveries = pub_sub.deliveries;
+        stats
+    }
+
+    /// Resets the counters of `stats`, as done by `CONFIG RESETSTAT`.
+    pub(crate) fn reset_stats(&self) {
+        for index in 0..self.shared.shards.len() {
+            let mut shard = self.shared.lock(index);
+            shard.stats.keyspace_hits = 0;
+            shard.stats.keyspace_misses = 0;
+            shard.stats.expired_keys = 0;
+            shard.stats.evicted_keys = 0;
+        }
+        *self.shared.expire_cycles.lock().unwrap() = ExpireCycles::default();
+        self.shared.freer.reset_freed();
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+        pub_sub.messages = 0;
+        pub_sub.deliveries = 0;
     }
 
     /// Signals the purge background task to shut down. This is called by the
     /// `DbShutdown`s `Drop` implementation.
     fn shutdown_purge_task(&self) {
         // The background task must be signaled to shut down. This is done by
-        // setting `State::shutdown` to `true` and signalling the task.
-        let mut state = self.shared.state.lock().unwrap();
-        state.shutdown = true;
+        // setting `Shared::shutdown` to `true` and signalling the task.
+        self.shared.shutdown.store(true, Ordering::Release);
+        for task in &self.shared.background_tasks[..] {
+            task.notify_one();
+        }
+    }
+}
 
-        // Drop the lock before signalling the background task. This helps
-        // reduce lock contention by ensuring the background task doesn't
-        // wake up only to be unable to acquire the mutex.
-        drop(state);
-        self.shared.background_task.notify_one();
+impl DbStats {
+    /// Adds the statistics of another shard.
+    fn merge(&mut self, other: &DbStats) {
+        self.keys += other.keys;
+        self.expires += other.expires;
+        self.used_memory += other.used_memory;
+        self.dataset_memory += other.dataset_memory;
+        self.expires_memory += other.expires_memory;
+        self.keyspace_hits += other.keyspace_hits;
+        self.keyspace_misses += other.keyspace_misses;
+        self.expired_keys += other.expired_keys;
+        self.evicted_keys += other.evicted_keys;
+        self.changes += other.changes;
     }
 }
 
 impl Shared {
-    /// Purge all expired keys and return the `Instant` at which the **next**
-    /// key will expire. The background task will sleep until this instant.
-    fn purge_expired_keys(&self) -> Option<Instant> {
-        let mut state = self.state.lock().unwrap();
+    /// Locks the shard at `index`.
+    fn lock(&self, index: usize) -> ShardGuard<'_> {
+        let mut shard = self.shards[index].lock().unwrap();
+        shard.propagating = self.stream.get().is_some();
+        ShardGuard {
+            locked_with: shard.used_memory(),
+            shard,
+            shared: self,
+        }
+    }
+
+    /// Index of the shard holding `key`. The hash is the same in every
+    /// process, so keys kept on disk are found in the same shard after a
+    /// restart.
+    fn shard_index(&self, key: &str) -> usize {
+        (hash_key(key) % self.shards.len() as u64) as usize
+    }
+
+    /// Locks the shard holding `key`.
+    fn lock_key(&self, key: &str) -> ShardGuard<'_> {
+        self.lock(self.shard_index(key))
+    }
 
-        if state.shutdown {
+    /// Locks the shards holding `keys`, in increasing order.
+    fn lock_keys(&self, keys: &[&str]) -> LockedShards<'_> {
+        let mut indices: Vec<usize> = keys.iter().map(|key| self.shard_index(key)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        LockedShards {
+            shared: self,
+            shards: indices
+                .into_iter()
+                .map(|index| (index, self.lock(index)))
+                .collect(),
+        }
+    }
+
+    /// The background task purging the shard at `index`.
+    fn background_task(&self, index: usize) -> &Notify {
+        &self.background_tasks[index % self.background_tasks.len()]
+    }
+
+    /// Evicts keys of the shard at `index`, owned by a core, until it uses
+    /// no more than its share of `maxmemory`. Returns `false` if it still
+    /// uses more.
+    fn evict_shard(&self, index: usize) -> bool {
+        let maxmemory = self.maxmemory.load(Ordering::Relaxed);
+        if maxmemory == 0 {
+            return true;
+        }
+        let share = maxmemory / self.shards.len() as u64;
+        let mut shard = self.lock(index);
+        while shard.used_memory() as u64 > share {
+            let excess = shard.used_memory() as u64 - share;
+            if self.values_on_disk {
+                if shard.storage.shrink(excess as usize) == 0 {
+                    return false;
+                }
+                continue;
+            }
+            let eviction = shard.eviction;
+            let Some((_, key)) = shard.eviction_candidate(&eviction) else {
+                return false;
+            };
+            debug!(%key, "evicting key");
+            if let Some(entry) = shard.remove(&key) {
+                let lazy = shard.lazy_free.eviction;
+                shard.dispose(entry, lazy);
+            }
+            shard.stats.evicted_keys += 1;
+            shard.propagate_removal(key);
+        }
+        true
+    }
+
+    /// Removes expired keys, a batch at a time, until none is left or the
+    /// cycle runs out of time. Keys are removed in the order they expire, so
+    /// unlike Redis there is no need to sample keys to find expired ones.
+    ///
+    /// Only the shard `only` is purged if set, by the core owning it.
+    fn expire_cycle(&self, only: Option<usize>) -> Purge {
+        if self.is_shutdown() {
             // The database is shutting down. All handles to the shared state
             // have dropped. The background task should exit.
-            return None;
+            return Purge::Done(None);
         }
 
-        // This is needed to make the borrow checker happy. In short, `lock()`
-        // returns a `MutexGuard` and not a `&mut State`. The borrow checker is
-        // not able to see "through" the mutex guard and determine that it is
-        // safe to access both `state.expirations` and `state.entries` mutably,
-        // so we get a "real" mutable reference to `State` outside of the loop.
-        let state = &mut *state;
-
         // Find all keys scheduled to expire **before** now.
-        let now = Instant::now();
-
-        while let Some(&(when, ref key)) = state.expirations.iter().next() {
-            if when > now {
-                // Done purging, `when` is the instant at which the next key
-                // expires. The worker task will wait until this instant.
-                return Some(when);
+        let start = Instant::now();
+        let mut next = None;
+        let mut outcome = None;
+        let cursor = self.expire_cursor.load(Ordering::Relaxed);
+        let len = self.shards.len();
+        let order = match only {
+            Some(index) => (index..index + 1).chain(0..0),
+            None => (cursor..len).chain(0..cursor),
+        };
+        'shards: for index in order {
+            loop {
+                match self.lock(index).purge_expired_keys(start, EXPIRE_BATCH) {
+                    Purge::Done(when) => {
+                        next = next.into_iter().chain(when).min();
+                        break;
+                    }
+                    // Other shards may have expired keys too, but they wait
+                    // for the next cycle.
+                    Purge::Pending if start.elapsed() >= EXPIRE_CYCLE_BUDGET => {
+                        self.expire_cursor.store(index, Ordering::Relaxed);
+                        outcome = Some(Purge::Pending);
+                        break 'shards;
+                    }
+                    Purge::Pending => {}
+                }
             }
-
-            // The key expired, remove it
-            state.entries.remove(key);
-            state.expirations.remove(&(when, key.clone()));
         }
 
-        None
+        let mut expire_cycles = self.expire_cycles.lock().unwrap();
+        expire_cycles.time += start.elapsed();
+        if outcome.is_some() {
+            expire_cycles.time_cap_reached += 1;
+        }
+        outcome.unwrap_or(Purge::Done(next))
     }
 
     /// Returns `true` if the database is shutting down
@@ -326,44 +1084,389 @@ impl Shared {
     /// The `shutdown` flag is set when all `Db` values have dropped, indicating
     /// that the shared state can no longer be accessed.
     fn is_shutdown(&self) -> bool {
-        self.state.lock().unwrap().shutdown
+        self.shutdown.load(Ordering::Acquire)
+    }
+}
+
+/// Shards locked together by a command on several keys.
+struct LockedShards<'a> {
+    shared: &'a Shared,
+    /// Sorted by index.
+    shards: Vec<(usize, ShardGuard<'a>)>,
+}
+
+impl<'a> LockedShards<'a> {
+    /// Propagates the write made by the command, once every shard it
+    /// changed is released.
+    fn propagate(&mut self, frame: impl FnOnce() -> Frame) {
+        // The first shard is released first, while the others are still
+        // locked.
+        if let Some((_, shard)) = self.shards.first_mut() {
+            if shard.propagating {
+                shard.propagated.push(frame());
+            }
+        }
+    }
+
+    /// The shard holding `key`, which must be one of the keys locked.
+    fn get(&mut self, key: &str) -> &mut ShardGuard<'a> {
+        let index = self.shared.shard_index(key);
+        let position = self
+            .shards
+            .binary_search_by_key(&index, |(index, _)| *index)
+            .expect("key not locked");
+        &mut self.shards[position].1
+    }
+}
+
+impl Deref for ShardGuard<'_> {
+    type Target = Shard;
+
+    fn deref(&self) -> &Shard {
+        &self.shard
     }
 }
 
-impl State {
+impl DerefMut for ShardGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Shard {
+        &mut self.shard
+    }
+}
+
+impl Drop for ShardGuard<'_> {
+    fn drop(&mut self) {
+        if !self.shard.propagated.is_empty() {
+            let propagated = std::mem::take(&mut self.shard.propagated);
+            if let Some(stream) = self.shared.stream.get().and_then(Weak::upgrade) {
+                for frame in &propagated {
+                    stream.propagate(frame);
+                }
+            }
+        }
+        let used_memory = self.shard.used_memory();
+        if used_memory > self.locked_with {
+            self.shared
+                .used_memory
+                .fetch_add(used_memory - self.locked_with, Ordering::Relaxed);
+        } else if used_memory < self.locked_with {
+            self.shared
+                .used_memory
+                .fetch_sub(self.locked_with - used_memory, Ordering::Relaxed);
+        }
+        if self.shared.storage_error.get().is_none() {
+            if let Some(err) = self.shard.storage.error() {
+                let _ = self.shared.storage_error.set(err);
+            }
+        }
+    }
+}
+
+impl Shard {
+    /// Approximate number of bytes used by the keys, and the values in
+    /// memory.
+    fn used_memory(&self) -> usize {
+        self.keys_memory
+            + self
+                .storage
+                .resident_memory()
+                .unwrap_or(self.stats.dataset_memory)
+    }
+
     fn next_expiration(&self) -> Option<Instant> {
-        self.expirations
-            .iter()
-            .next()
-            .map(|expiration| expiration.0)
+        self.storage.next_expiration().map(|(when, _)| when)
+    }
+
+    /// Removes `key` if it expired, in case the background task didn't get
+    /// to it yet.
+    fn expire_if_needed(&mut self, key: &str) {
+        let now = Instant::now();
+        let expired = self.storage.expires_at(key).is_some_and(|when| when <= now);
+        if !expired {
+            return;
+        }
+        if let Some(entry) = self.remove(key) {
+            self.stats.expired_keys += 1;
+            self.dispose(entry, self.lazy_free.expire);
+            self.propagate_removal(key.to_string());
+        }
+    }
+
+    /// Propagates the removal of a key that expired or was evicted.
+    fn propagate_removal(&mut self, key: String) {
+        if self.propagating {
+            self.propagated.push(Del::new(vec![key]).into_frame());
+        }
+    }
+
+    /// Looks `key` up, counting the access.
+    fn get(&mut self, key: &str) -> Option<Bytes> {
+        self.expire_if_needed(key);
+        let value = self.storage.get(key).map(|entry| {
+            entry.accessed_at = Instant::now();
+            entry.lfu.touch(&mut self.rng);
+            entry.data.clone()
+        });
+        if value.is_some() {
+            self.stats.keyspace_hits += 1;
+        } else {
+            self.stats.keyspace_misses += 1;
+        }
+        value
+    }
+
+    /// Sets `key`, replacing any previous value. Returns `true` if the key
+    /// is now the first of the shard to expire, in which case the background
+    /// task must be notified.
+    fn set(&mut self, key: String, value: Bytes, expires_at: Option<Instant>) -> bool {
+        // Only notify the worker task if the newly inserted expiration is the
+        // **next** key to evict. In this case, the worker needs to be woken up
+        // to update its state.
+        let notify = expires_at.is_some_and(|when| {
+            self.next_expiration()
+                .map(|expiration| expiration > when)
+                .unwrap_or(true)
+        });
+
+        self.stats.changes += 1;
+
+        // If there was a value previously associated with the key, it is
+        // removed along with its expiration. This avoids leaking data. If we
+        // insert before remove that will cause bug when current `(when, key)`
+        // equals prev `(when, key)`. Remove then insert can avoid this.
+        if let Some(entry) = self.remove(&key) {
+            self.dispose(entry, self.lazy_free.server_del);
+        }
+        self.insert(key, value, expires_at);
+        notify
+    }
+
+    /// Stores a new entry. There must be no entry for `key` already.
+    fn insert(&mut self, key: String, data: Bytes, expires_at: Option<Instant>) {
+        let entry = Entry::new(data, expires_at);
+        self.keys_memory += storage::memory_usage(&key, 0, expires_at.is_some());
+        if !self.values_on_disk {
+            self.stats.dataset_memory += entry.data.len();
+        }
+        self.stats.expires_memory += entry.expiration_overhead(&key);
+        self.storage.insert(key, entry);
+    }
+
+    /// Removes the entry for `key` along with its expiration.
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.storage.remove(key)?;
+        self.keys_memory -= storage::memory_usage(key, 0, entry.expires_at.is_some());
+        if !self.values_on_disk {
+            self.stats.dataset_memory -= entry.data.len();
+        }
+        self.stats.expires_memory -= entry.expiration_overhead(key);
+        Some(entry)
+    }
+
+    /// Removes up to `limit` keys expired at `now`.
+    fn purge_expired_keys(&mut self, now: Instant, limit: usize) -> Purge {
+        for _ in 0..limit {
+            let Some((when, key)) = self.storage.next_expiration() else {
+                return Purge::Done(None);
+            };
+            let key = key.to_string();
+            if when > now {
+                // Done purging, `when` is the instant at which the next key
+                // expires. The worker task will wait until this instant.
+                return Purge::Done(Some(when));
+            }
+
+            // The key expired, remove it along with its expiration.
+            if let Some(entry) = self.remove(&key) {
+                self.dispose(entry, self.lazy_free.expire);
+            }
+            self.stats.expired_keys += 1;
+            self.propagate_removal(key);
+        }
+
+        match self.next_expiration() {
+            Some(when) if when <= now => Purge::Pending,
+            when => Purge::Done(when),
+        }
+    }
+
+    /// Drops a removed entry. Large values are freed on the background
+    /// thread if `lazy` is set.
+    fn dispose(&self, entry: Entry, lazy: bool) {
+        if lazy && entry.data.len() >= LAZYFREE_THRESHOLD {
+            self.freer.free(entry.data);
+        }
+    }
+
+    /// Empties the shard, returning what it held so it can be dropped
+    /// later.
+    fn flush(&mut self) -> Box<dyn Send> {
+        self.stats.changes += self.storage.len() as u64;
+        self.keys_memory = 0;
+        self.stats.dataset_memory = 0;
+        self.stats.expires_memory = 0;
+        self.storage.clear()
+    }
+
+    /// Picks the next key of the shard to evict according to the eviction
+    /// policy, or `None` if there is none. The key comes with a score: the
+    /// higher, the sooner it should be evicted.
+    fn eviction_candidate(&mut self, eviction: &Eviction) -> Option<(u128, String)> {
+        use MaxmemoryPolicy::*;
+
+        let Eviction {
+            policy, samples, ..
+        } = *eviction;
+        let volatile = match policy {
+            NoEviction => return None,
+            // Rather than sampling, the key expiring next is known.
+            VolatileTtl => {
+                let (when, key) = self.storage.next_expiration()?;
+                let ttl = when.saturating_duration_since(Instant::now());
+                return Some((u128::MAX - ttl.as_micros(), key.to_string()));
+            }
+            AllkeysLru | AllkeysLfu | AllkeysRandom => false,
+            VolatileLru | VolatileLfu | VolatileRandom => true,
+        };
+        if matches!(policy, AllkeysRandom | VolatileRandom) {
+            let key = self.storage.sample(volatile)?;
+            return Some((self.rng.next() as u128, key));
+        }
+
+        // Among the sampled keys, evict the one that is idle the longest, or
+        // the least frequently used.
+        let mut best: Option<(u128, String)> = None;
+        for _ in 0..samples {
+            let key = self.storage.sample(volatile)?;
+            // The access times of keys out of memory are unknown.
+            let Some(entry) = self.storage.peek(&key) else {
+                continue;
+            };
+            let score = match policy {
+                AllkeysLfu | VolatileLfu => (u8::MAX - entry.lfu.frequency()) as u128,
+                _ => entry.accessed_at.elapsed().as_micros(),
+            };
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, key));
+            }
+        }
+        best
+    }
+}
+
+/// Removes the keys of `del` and `unlink`, returning those that existed.
+/// Their values are freed on the background thread if `lazy` is set, or
+/// when `None`, if `lazyfree-lazy-user-del` is.
+fn remove_keys(shards: &mut LockedShards<'_>, keys: &[&str], lazy: Option<bool>) -> Vec<String> {
+    let mut removed = vec![];
+    for key in keys {
+        let shard = shards.get(key);
+        shard.expire_if_needed(key);
+        if let Some(entry) = shard.remove(key) {
+            shard.stats.changes += 1;
+            let lazy = lazy.unwrap_or(shard.lazy_free.user_del);
+            shard.dispose(entry, lazy);
+            removed.push(key.to_string());
+        }
+    }
+    removed
+}
+
+/// FNV-1a hash of `key`, with its bits mixed so that its remainder picks a
+/// shard evenly.
+fn hash_key(key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
     }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^ (hash >> 33)
 }
 
 /// Routine executed by the background task.
 ///
 /// Wait to be notified. On notification, purge any expired keys from the shared
-/// state handle. If `shutdown` is set, terminate the task.
-async fn purge_expired_tasks(shared: Arc<Shared>) {
+/// state handle, or only from the shard `only` if set. If `shutdown` is set,
+/// terminate the task.
+async fn purge_expired_tasks(shared: Arc<Shared>, only: Option<usize>) {
+    let background_task = shared.background_task(only.unwrap_or(0));
     // If the shutdown flag is set, then the task should exit.
     while !shared.is_shutdown() {
-        // Purge all keys that are expired. The function returns the instant at
-        // which the **next** key will expire. The worker should wait until the
-        // instant has passed then purge again.
-        if let Some(when) = shared.purge_expired_keys() {
+        // Purge keys that are expired. Unless the cycle ran out of time, the
+        // function returns the instant at which the **next** key will expire.
+        // The worker should wait until the instant has passed then purge
+        // again.
+        let when = match shared.expire_cycle(only) {
+            Purge::Done(when) => when,
+            // Let other tasks run before going on.
+            Purge::Pending => {
+                time::sleep(EXPIRE_CYCLE_PAUSE).await;
+                continue;
+            }
+        };
+        if let Some(when) = when {
             // Wait until the next key expires **or** until the background task
             // is notified. If the task is notified, then it must reload its
             // state as new keys have been set to expire early. This is done by
             // looping.
             tokio::select! {
                 _ = time::sleep_until(when) => {}
-                _ = shared.background_task.notified() => {}
+                _ = background_task.notified() => {}
             }
         } else {
             // There are no keys expiring in the future. Wait until the task is
             // notified.
-            shared.background_task.notified().await;
+            background_task.notified().await;
         }
     }
 
     debug!("Purge background task shut down")
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn expire_keys_on_access() {
+        let db = Db::new();
+        db.set(
+            "key".to_string(),
+            Bytes::from("value"),
+            Some(Duration::from_millis(1)),
+        );
+        // Blocks the runtime, so the background task can't run.
+        std::thread::sleep(Duration::from_millis(10));
+
+        assert_eq!(db.get("key"), None);
+        let stats = db.stats();
+        assert_eq!(stats.keys, 0);
+        assert_eq!(stats.expired_keys, 1);
+        assert_eq!(stats.keyspace_misses, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expire_many_keys_in_several_cycles() {
+        let db = Db::with_shards(4);
+        for i in 0..100_000 {
+            db.set(
+                format!("key:{}", i),
+                Bytes::new(),
+                Some(Duration::from_millis(10)),
+            );
+        }
+        db.set("persistent".to_string(), Bytes::new(), None);
+
+        let purged = time::timeout(Duration::from_secs(10), async {
+            while db.stats().expires > 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        purged.await.unwrap();
+        let stats = db.stats();
+        assert_eq!(stats.keys, 1);
+        assert_eq!(stats.expired_keys, 100_000);
+        assert!(stats.expired_time_cap_reached > 0);
+    }
+}
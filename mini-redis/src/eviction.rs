@@ -0,0 +1,182 @@
+//! Building blocks of the `maxmemory` eviction, see
+//! `Db::perform_evictions`.
+//!
+//! As in Redis, the LRU and LFU policies are approximated: a few random keys
+//! are sampled and the best candidate among them is evicted, which keeps the
+//! cost of an eviction independent of the number of keys.
+
+use crate::config::{MaxmemoryPolicy, ServerConfig};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, Instant};
+
+/// Counter value of new keys, so they are not evicted right away by LFU.
+const LFU_INIT_VAL: u8 = 5;
+
+/// The higher, the more accesses are needed to increment the counter.
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// The counter is decremented once per period without access.
+const LFU_DECAY_TIME: Duration = Duration::from_secs(60);
+
+/// Memory limit enforced by `Db::perform_evictions`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Eviction {
+    /// In bytes, 0 means no limit.
+    pub(crate) maxmemory: u64,
+    pub(crate) policy: MaxmemoryPolicy,
+    /// Keys sampled to pick each one to evict.
+    pub(crate) samples: usize,
+}
+
+impl Default for Eviction {
+    fn default() -> Eviction {
+        Eviction {
+            maxmemory: 0,
+            policy: MaxmemoryPolicy::NoEviction,
+            samples: 5,
+        }
+    }
+}
+
+impl From<&ServerConfig> for Eviction {
+    fn from(config: &ServerConfig) -> Eviction {
+        Eviction {
+            maxmemory: config.maxmemory,
+            policy: config.maxmemory_policy,
+            samples: config.maxmemory_samples,
+        }
+    }
+}
+
+/// Keys in an order allowing to pick one at random in constant time.
+///
+/// Each entry of the keyspace remembers its slot in the pool, so it can be
+/// removed in constant time as well.
+#[derive(Debug, Default)]
+pub(crate) struct KeyPool {
+    keys: Vec<String>,
+}
+
+impl KeyPool {
+    /// Adds `key`, returning its slot.
+    pub(crate) fn insert(&mut self, key: String) -> usize {
+        self.keys.push(key);
+        self.keys.len() - 1
+    }
+
+    /// Removes the key in `slot`. The last key is moved into the slot, and
+    /// returned so its entry can be updated.
+    pub(crate) fn remove(&mut self, slot: usize) -> Option<&str> {
+        self.keys.swap_remove(slot);
+        self.keys.get(slot).map(String::as_str)
+    }
+
+    /// Returns a random key.
+    pub(crate) fn sample(&self, rng: &mut Rng) -> Option<&str> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let slot = rng.next() as usize % self.keys.len();
+        Some(&self.keys[slot])
+    }
+}
+
+/// Xorshift generator, good enough to sample keys.
+#[derive(Debug)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new() -> Rng {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_nanos() as u64)
+            .unwrap_or_default();
+        // The state must not be zero.
+        Rng(seed | 1)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Logarithmic access frequency of a key, as in Redis: the counter saturates
+/// at 255 after about a million accesses, and decays while the key is not
+/// accessed.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Lfu {
+    counter: u8,
+    decayed_at: Instant,
+}
+
+impl Lfu {
+    pub(crate) fn new() -> Lfu {
+        Lfu {
+            counter: LFU_INIT_VAL,
+            decayed_at: Instant::now(),
+        }
+    }
+
+    /// The counter once decayed.
+    pub(crate) fn frequency(&self) -> u8 {
+        let periods = self.decayed_at.elapsed().as_secs() / LFU_DECAY_TIME.as_secs();
+        self.counter.saturating_sub(periods.min(255) as u8)
+    }
+
+    /// Records an access.
+    pub(crate) fn touch(&mut self, rng: &mut Rng) {
+        let counter = self.frequency();
+        if counter < self.counter {
+            self.decayed_at = Instant::now();
+        }
+        self.counter = counter;
+        if self.counter == u8::MAX {
+            return;
+        }
+        let base = self.counter.saturating_sub(LFU_INIT_VAL) as f64;
+        if rng.next_f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remove_keys_from_pool() {
+        let mut pool = KeyPool::default();
+        assert_eq!(pool.insert("a".to_string()), 0);
+        assert_eq!(pool.insert("b".to_string()), 1);
+        assert_eq!(pool.insert("c".to_string()), 2);
+
+        // The last key takes the place of the removed one.
+        assert_eq!(pool.remove(0), Some("c"));
+        assert_eq!(pool.remove(1), None);
+        let mut rng = Rng::new();
+        assert_eq!(pool.sample(&mut rng), Some("c"));
+        assert_eq!(pool.remove(0), None);
+        assert_eq!(pool.sample(&mut rng), None);
+    }
+
+    #[test]
+    fn count_accesses_logarithmically() {
+        let mut rng = Rng::new();
+        let mut lfu = Lfu::new();
+        for _ in 0..100 {
+            lfu.touch(&mut rng);
+        }
+        let frequency = lfu.frequency();
+        assert!(frequency > LFU_INIT_VAL && frequency < 30, "{}", frequency);
+    }
+}
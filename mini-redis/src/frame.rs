@@ -1,13 +1,18 @@
 //! Provides a type representing a Redis protocol frame as well as utilities for
 //! parsing frames from a byte array.
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::cmp;
 use std::convert::TryInto;
 use std::fmt;
 use std::io::Cursor;
 use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 
+/// Bulk payloads at least this large are sliced out of the read buffer by
+/// `Frame::parse_bytes` instead of being copied.
+const ZERO_COPY_THRESHOLD: usize = 1024;
+
 /// A frame in the Redis protocol.
 #[derive(Clone, Debug)]
 pub enum Frame {
@@ -19,11 +24,51 @@ pub enum Frame {
     Array(Vec<Frame>),
 }
 
+/// Upper bounds applied while decoding frames received from a peer.
+///
+/// The lengths advertised in `$` and `*` headers come straight from the
+/// network. Without a bound, a single header is enough to make the decoder
+/// wait for (and buffer) an arbitrary amount of data.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Maximum length of a single bulk string, `proto-max-bulk-len` in Redis.
+    pub max_bulk_len: usize,
+
+    /// Maximum number of entries in a single array.
+    pub max_multibulk_len: usize,
+
+    /// Maximum number of bytes buffered for a single connection while waiting
+    /// for a complete frame, `client-query-buffer-limit` in Redis.
+    pub max_query_buffer_len: usize,
+
+    /// Maximum number of arrays nested in one another, at most
+    /// `MAX_NESTING_DEPTH`. Commands are flat arrays, deeper frames only
+    /// serve to exhaust the stack of the recursive decoder.
+    pub max_nesting_depth: usize,
+}
+
+/// Deepest nesting of arrays ever decoded, whatever the `Limits`.
+pub const MAX_NESTING_DEPTH: usize = 1024;
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+            max_query_buffer_len: 1024 * 1024 * 1024,
+            max_nesting_depth: 128,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     /// Not enough data is available to parse a message
     Incomplete,
 
+    /// The message exceeds one of the configured `Limits`
+    LimitExceeded(String),
+
     /// Invalid message encoding
     Other(crate::Error),
 }
@@ -62,8 +107,35 @@ impl Frame {
         }
     }
 
+    /// Push any frame into the array, e.g. a nested array. `self` must be an
+    /// Array frame.
+    ///
+    /// # Panics
+    ///
+    /// panics if `self` is not an array
+    pub fn push_frame(&mut self, frame: Frame) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(frame);
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
     /// Checks if an entire message can be decoded from `src`
     pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        Frame::check_with_limits(src, &Limits::default())
+    }
+
+    /// Checks if an entire message can be decoded from `src`, rejecting
+    /// messages whose advertised lengths exceed `limits`.
+    pub fn check_with_limits(src: &mut Cursor<&[u8]>, limits: &Limits) -> Result<(), Error> {
+        let max_depth = cmp::min(limits.max_nesting_depth, MAX_NESTING_DEPTH);
+        Frame::check_nested(src, limits, max_depth)
+    }
+
+    /// Checks a frame that may still contain `depth` levels of arrays.
+    fn check_nested(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<(), Error> {
         match get_u8(src)? {
             b'+' => {
                 get_line(src)?;
@@ -85,15 +157,26 @@ impl Frame {
                     // Read the bulk string
                     let len: usize = get_decimal(src)?.try_into()?;
 
+                    if len > limits.max_bulk_len {
+                        return Err(Error::LimitExceeded("invalid bulk length".into()));
+                    }
+
                     // skip that number of bytes + 2 (\r\n).
                     skip(src, len + 2)
                 }
             }
             b'*' => {
-                let len = get_decimal(src)?;
+                let len: usize = get_decimal(src)?.try_into()?;
+
+                if len > limits.max_multibulk_len {
+                    return Err(Error::LimitExceeded("invalid multibulk length".into()));
+                }
+                if depth == 0 {
+                    return Err(Error::LimitExceeded("invalid nesting depth".into()));
+                }
 
                 for _ in 0..len {
-                    Frame::check(src)?;
+                    Frame::check_nested(src, limits, depth - 1)?;
                 }
 
                 Ok(())
@@ -104,6 +187,23 @@ impl Frame {
 
     /// The message has already been validated with `check`.
     pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
+        Frame::parse_inner(src, None, MAX_NESTING_DEPTH)
+    }
+
+    /// Parses a complete message that has already been validated with
+    /// `check`. Large bulk payloads are sliced out of `src` rather than copied,
+    /// so the frame shares memory with the buffer it was read from.
+    pub fn parse_bytes(src: &Bytes) -> Result<Frame, Error> {
+        Frame::parse_inner(&mut Cursor::new(&src[..]), Some(src), MAX_NESTING_DEPTH)
+    }
+
+    /// Parses a frame that may still contain `depth` levels of arrays. The
+    /// bound holds even for messages that were not checked first.
+    fn parse_inner(
+        src: &mut Cursor<&[u8]>,
+        shared: Option<&Bytes>,
+        depth: usize,
+    ) -> Result<Frame, Error> {
         match get_u8(src)? {
             b'+' => {
                 // Read the line and convert it to `Vec<u8>`
@@ -145,7 +245,16 @@ impl Frame {
                         return Err(Error::Incomplete);
                     }
 
-                    let data = Bytes::copy_from_slice(&src.chunk()[..len]);
+                    let data = match shared {
+                        // Small payloads are copied. Slicing keeps the whole
+                        // read buffer allocation alive for as long as the
+                        // value lives, which is a poor trade for a few bytes.
+                        Some(shared) if len >= ZERO_COPY_THRESHOLD => {
+                            let start = src.position() as usize;
+                            shared.slice(start..start + len)
+                        }
+                        _ => Bytes::copy_from_slice(&src.chunk()[..len]),
+                    };
 
                     // skip that number of bytes + 2 (\r\n).
                     skip(src, n)?;
@@ -155,10 +264,18 @@ impl Frame {
             }
             b'*' => {
                 let len = get_decimal(src)?.try_into()?;
-                let mut out = Vec::with_capacity(len);
+
+                if depth == 0 {
+                    return Err(Error::LimitExceeded("invalid nesting depth".into()));
+                }
+
+                // Every entry takes at least three bytes, so never reserve
+                // more than the remaining input can hold. This keeps a bogus
+                // length from triggering a huge allocation.
+                let mut out = Vec::with_capacity(cmp::min(len, src.remaining() / 3));
 
                 for _ in 0..len {
-                    out.push(Frame::parse(src)?);
+                    out.push(Frame::parse_inner(src, shared, depth - 1)?);
                 }
 
                 Ok(Frame::Array(out))
@@ -167,6 +284,52 @@ impl Frame {
         }
     }
 
+    /// Encodes the frame into `dst`.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        self.encode_with(dst, &mut |dst, data| dst.put_slice(data));
+    }
+
+    /// Encodes the frame into `dst`, handing bulk payloads to `put_bulk`
+    /// instead of copying them. This lets the caller keep large payloads out
+    /// of `dst` and write them separately.
+    pub(crate) fn encode_with<F>(&self, dst: &mut BytesMut, put_bulk: &mut F)
+    where
+        F: FnMut(&mut BytesMut, &Bytes),
+    {
+        match self {
+            Frame::Simple(val) => {
+                dst.put_u8(b'+');
+                dst.put_slice(val.as_bytes());
+                dst.put_slice(b"\r\n");
+            }
+            Frame::Error(val) => {
+                dst.put_u8(b'-');
+                dst.put_slice(val.as_bytes());
+                dst.put_slice(b"\r\n");
+            }
+            Frame::Integer(val) => {
+                dst.put_u8(b':');
+                put_decimal(dst, *val);
+            }
+            Frame::Null => {
+                dst.put_slice(b"$-1\r\n");
+            }
+            Frame::Bulk(val) => {
+                dst.put_u8(b'$');
+                put_decimal(dst, val.len() as u64);
+                put_bulk(dst, val);
+                dst.put_slice(b"\r\n");
+            }
+            Frame::Array(val) => {
+                dst.put_u8(b'*');
+                put_decimal(dst, val.len() as u64);
+                for entry in val {
+                    entry.encode_with(dst, put_bulk);
+                }
+            }
+        }
+    }
+
     /// Converts the frame to an "unexpected frame" error
     pub fn to_error(&self) -> crate::Error {
         format!("unexpected frame: {}", self).into()
@@ -212,6 +375,15 @@ impl fmt::Display for Frame {
     }
 }
 
+/// Write a decimal followed by a new-line
+fn put_decimal(dst: &mut BytesMut, val: u64) {
+    use std::fmt::Write;
+
+    // Writing to a `BytesMut` cannot fail, it grows as needed.
+    let _ = write!(dst, "{}", val);
+    dst.put_slice(b"\r\n");
+}
+
 fn peek_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
     if !src.has_remaining() {
         return Err(Error::Incomplete);
@@ -296,6 +468,7 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Incomplete => "stream ended early".fmt(fmt),
+            Error::LimitExceeded(msg) => write!(fmt, "Protocol error: {}", msg),
             Error::Other(err) => err.fmt(fmt),
         }
     }
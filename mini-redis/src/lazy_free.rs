@@ -0,0 +1,72 @@
+//! Frees removed values on a background thread, so that dropping a large
+//! value or a whole keyspace doesn't hold up the clients waiting on a shard.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tracing::debug;
+
+/// Values smaller than this are freed right away: sending them to the
+/// background thread would cost more than freeing them.
+pub(crate) const LAZYFREE_THRESHOLD: usize = 64 * 1024;
+
+/// Handle to the background thread. The thread stops once every handle is
+/// dropped.
+#[derive(Clone, Debug)]
+pub(crate) struct LazyFreer {
+    tx: mpsc::Sender<Box<dyn Send>>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    /// Objects waiting to be freed.
+    pending: AtomicUsize,
+    /// Objects freed since the start.
+    freed: AtomicU64,
+}
+
+impl LazyFreer {
+    pub(crate) fn new() -> LazyFreer {
+        let (tx, rx) = mpsc::channel::<Box<dyn Send>>();
+        let counters = Arc::new(Counters::default());
+        let worker = counters.clone();
+        thread::Builder::new()
+            .name("lazyfree".to_string())
+            .spawn(move || {
+                for object in rx {
+                    drop(object);
+                    worker.pending.fetch_sub(1, Ordering::Relaxed);
+                    worker.freed.fetch_add(1, Ordering::Relaxed);
+                }
+                debug!("lazy free thread shut down");
+            })
+            .expect("failed to spawn the lazy free thread");
+        LazyFreer { tx, counters }
+    }
+
+    /// Frees `object` on the background thread.
+    pub(crate) fn free<T: Send + 'static>(&self, object: T) {
+        self.counters.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(mpsc::SendError(object)) = self.tx.send(Box::new(object)) {
+            // The thread is gone, which only happens if it panicked.
+            self.counters.pending.fetch_sub(1, Ordering::Relaxed);
+            drop(object);
+        }
+    }
+
+    /// Objects waiting to be freed.
+    pub(crate) fn pending(&self) -> usize {
+        self.counters.pending.load(Ordering::Relaxed)
+    }
+
+    /// Objects freed by the background thread since the start, or the last
+    /// `reset_freed`.
+    pub(crate) fn freed(&self) -> u64 {
+        self.counters.freed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset_freed(&self) {
+        self.counters.freed.store(0, Ordering::Relaxed);
+    }
+}
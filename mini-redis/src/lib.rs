@@ -1,12 +1,28 @@
+pub mod acl;
 pub mod clients;
 pub mod cmd;
+pub mod codec;
+pub mod config;
 pub mod frame;
+pub mod listener;
+pub mod sentinel;
 pub mod server;
+pub mod storage;
+pub mod tls;
 
+mod client_list;
 mod connection;
+mod cores;
 mod db;
+mod eviction;
+mod lazy_free;
+mod metrics;
 mod parse;
+mod pattern;
+mod replication;
 mod shutdown;
+mod slowlog;
+mod stats;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -15,10 +31,13 @@ pub use clients::BlockingClient;
 pub use clients::BufferedClient;
 pub use clients::Client;
 pub use cmd::Command;
+pub use codec::RespCodec;
 pub use connection::Connection;
 pub use db::Db;
 pub use frame::Frame;
+pub use listener::Listener;
+pub use listener::MultiListener;
 pub use server::run;
 pub use shutdown::Shutdown;
 
-use parse::{Parse, ParseError};
+use parse::Parse;
@@ -0,0 +1,271 @@
+//! Sources of client connections accepted by `server::run`.
+
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+#[cfg(unix)]
+use std::{fs, os::unix::fs::FileTypeExt, os::unix::fs::PermissionsExt, path::Path};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// A listener the server accepts client connections from.
+///
+/// Implemented for `TcpListener` and, on Unix platforms, `UnixListener`.
+#[async_trait]
+pub trait Listener: Debug + Send {
+    /// The stream of an accepted connection.
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Accept the next inbound connection. Returns the stream along with a
+    /// printable address of the peer.
+    async fn accept(&mut self) -> io::Result<(Self::Stream, String)>;
+
+    /// Send TCP keepalive probes on `stream` once the connection has been
+    /// idle for `idle`, or never with `None`. Does nothing by default, for
+    /// streams other than TCP.
+    fn set_keepalive(stream: &Self::Stream, idle: Option<Duration>) -> io::Result<()> {
+        let _ = (stream, idle);
+        Ok(())
+    }
+
+    /// Moves `stream` to the I/O driver of the runtime this is called from,
+    /// so that the thread running the connection polls its socket, see the
+    /// thread-per-core engine. Streams stay with the driver of the listener
+    /// by default, as TLS streams do.
+    fn reregister(stream: Self::Stream) -> io::Result<Self::Stream> {
+        Ok(stream)
+    }
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&mut self) -> io::Result<(TcpStream, String)> {
+        let (socket, addr) = TcpListener::accept(self).await?;
+        Ok((socket, addr.to_string()))
+    }
+
+    fn set_keepalive(stream: &TcpStream, idle: Option<Duration>) -> io::Result<()> {
+        let socket = socket2::SockRef::from(stream);
+        match idle {
+            Some(idle) => socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle)),
+            None => socket.set_keepalive(false),
+        }
+    }
+
+    fn reregister(stream: TcpStream) -> io::Result<TcpStream> {
+        TcpStream::from_std(stream.into_std()?)
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    async fn accept(&mut self) -> io::Result<(UnixStream, String)> {
+        let (socket, _) = UnixListener::accept(self).await?;
+        // Clients connecting to a Unix socket are usually unnamed. Report the
+        // local path instead, the same way Redis does.
+        let addr = match self.local_addr()?.as_pathname() {
+            Some(path) => format!("{}:0", path.display()),
+            None => "unix:0".to_string(),
+        };
+        Ok((socket, addr))
+    }
+
+    fn reregister(stream: UnixStream) -> io::Result<UnixStream> {
+        UnixStream::from_std(stream.into_std()?)
+    }
+}
+
+/// A stream of any type a listener may accept, see `MultiListener`.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {
+    /// Sets keepalive the way the listener the stream comes from does, see
+    /// `Listener::set_keepalive`.
+    fn set_keepalive(&self, idle: Option<Duration>) -> io::Result<()>;
+
+    /// Moves the stream the way the listener it comes from does, see
+    /// `Listener::reregister`.
+    fn reregister(self: Box<Self>) -> io::Result<Box<dyn AsyncStream>>;
+}
+
+/// A stream accepted by `L`, boxed by `MultiListener`.
+struct Accepted<L: Listener>(L::Stream);
+
+impl<L: Listener + 'static> AsyncStream for Accepted<L> {
+    fn set_keepalive(&self, idle: Option<Duration>) -> io::Result<()> {
+        L::set_keepalive(&self.0, idle)
+    }
+
+    fn reregister(self: Box<Self>) -> io::Result<Box<dyn AsyncStream>> {
+        Ok(Box::new(Accepted::<L>(L::reregister(self.0)?)))
+    }
+}
+
+impl<L: Listener> AsyncRead for Accepted<L> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl<L: Listener> AsyncWrite for Accepted<L> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+type AcceptResult = io::Result<(Box<dyn AsyncStream>, String)>;
+
+/// Accepts connections from several listeners at once, e.g. one per bind
+/// address plus a Unix socket.
+///
+/// Every listener is driven by its own task. The tasks are aborted when the
+/// `MultiListener` is dropped.
+#[derive(Debug)]
+pub struct MultiListener {
+    tx: mpsc::Sender<AcceptResult>,
+    rx: mpsc::Receiver<AcceptResult>,
+    tasks: JoinSet<()>,
+}
+
+impl MultiListener {
+    pub fn new() -> MultiListener {
+        let (tx, rx) = mpsc::channel(1);
+        MultiListener {
+            tx,
+            rx,
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Start accepting connections from `listener`.
+    pub fn add<L: Listener + 'static>(&mut self, mut listener: L) {
+        let tx = self.tx.clone();
+        self.tasks.spawn(async move {
+            loop {
+                let accepted = listener
+                    .accept()
+                    .await
+                    .map(|(socket, addr)| {
+                        (Box::new(Accepted::<L>(socket)) as Box<dyn AsyncStream>, addr)
+                    });
+                // Errors are passed on as well, so the server applies its
+                // usual backoff.
+                if tx.send(accepted).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+impl Default for MultiListener {
+    fn default() -> MultiListener {
+        MultiListener::new()
+    }
+}
+
+#[async_trait]
+impl Listener for MultiListener {
+    type Stream = Box<dyn AsyncStream>;
+
+    async fn accept(&mut self) -> io::Result<(Box<dyn AsyncStream>, String)> {
+        // `self` holds a sender, so the channel is never closed.
+        self.rx
+            .recv()
+            .await
+            .expect("sender is held by the listener")
+    }
+
+    fn set_keepalive(stream: &Box<dyn AsyncStream>, idle: Option<Duration>) -> io::Result<()> {
+        stream.set_keepalive(idle)
+    }
+
+    fn reregister(stream: Box<dyn AsyncStream>) -> io::Result<Box<dyn AsyncStream>> {
+        stream.reregister()
+    }
+}
+
+/// Bind a `UnixListener` at `path` and set the permissions of the socket file
+/// to `mode`, e.g. `0o700` to restrict access to the server's user.
+///
+/// A socket file left behind by a previous run is removed first. Any other
+/// kind of file at `path` is left alone and binding fails.
+///
+/// The socket is created with the permissions of the process umask, so it is
+/// bound in a private directory next to `path` and only linked at `path` once
+/// its permissions are set. It is never reachable with more permissive ones.
+#[cfg(unix)]
+pub fn bind_unix(path: impl AsRef<Path>, mode: u32) -> io::Result<UnixListener> {
+    use std::os::unix::fs::DirBuilderExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    let path = path.as_ref();
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = parent.join(format!(
+        ".unixsocket-{}-{}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let private = dir.join("socket");
+    let res = UnixListener::bind(&private).and_then(|listener| {
+        fs::set_permissions(&private, fs::Permissions::from_mode(mode))?;
+        // Unlike a rename, linking fails rather than replace another file.
+        fs::hard_link(&private, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&private);
+    let _ = fs::remove_dir(&dir);
+    res
+}
@@ -0,0 +1,271 @@
+//! Prometheus exporter, serving the server statistics over HTTP at
+//! `/metrics` when `metrics-addr` is configured.
+
+use crate::config::LiveConfig;
+use crate::stats::{Stats, LATENCY_BUCKETS};
+use crate::{Db, Shutdown};
+
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time::{self, Duration};
+use tracing::{debug, error};
+
+/// Requests larger than this are refused.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Time a scraper has to send its request and read the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Scrapes served at the same time. Further connections wait in the
+/// listener's backlog.
+const MAX_CONNECTIONS: usize = 16;
+
+/// State the metrics are computed from.
+#[derive(Clone, Debug)]
+pub(crate) struct Exporter {
+    pub(crate) db: Db,
+    pub(crate) config: LiveConfig,
+    pub(crate) stats: Arc<Stats>,
+}
+
+impl Exporter {
+    /// Serves scrapes on `listener` until `shutdown` fires.
+    pub(crate) async fn serve(self, listener: TcpListener, mut shutdown: Shutdown) {
+        let limit_connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let permit = tokio::select! {
+                permit = limit_connections.clone().acquire_owned() => {
+                    // The semaphore is never closed.
+                    permit.unwrap()
+                }
+                _ = shutdown.recv() => return,
+            };
+            let socket = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((socket, addr)) => {
+                        debug!(%addr, "accepted metrics connection");
+                        socket
+                    }
+                    Err(err) => {
+                        error!(case = %err, "failed to accept metrics connection");
+                        continue;
+                    }
+                },
+                _ = shutdown.recv() => return,
+            };
+            let exporter = self.clone();
+            tokio::spawn(async move {
+                match time::timeout(REQUEST_TIMEOUT, exporter.handle(socket)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => debug!(case = %err, "metrics connection err"),
+                    Err(_) => debug!("metrics request timed out"),
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    /// Answers a single HTTP/1.x request and closes the connection.
+    async fn handle(&self, mut socket: TcpStream) -> crate::Result<()> {
+        let mut buf = Vec::with_capacity(1024);
+        while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+            if buf.len() >= MAX_REQUEST_LEN {
+                return Err("request too large".into());
+            }
+            let mut chunk = [0; 1024];
+            match socket.read(&mut chunk).await {
+                Ok(0) | Err(_) => return Err("connection closed".into()),
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+
+        let request = String::from_utf8_lossy(&buf);
+        let mut parts = request.lines().next().unwrap_or_default().split(' ');
+        let (method, path) = (parts.next(), parts.next().unwrap_or_default());
+        // The query string, if any, is ignored.
+        let path = path.split('?').next().unwrap_or_default();
+        let (status, body) = match (method, path) {
+            (Some("GET"), "/metrics") => ("200 OK", self.render()),
+            (Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+            _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await?;
+        socket.shutdown().await?;
+        Ok(())
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub(crate) fn render(&self) -> String {
+        let db_stats = self.db.stats();
+        let (max_clients, maxmemory) = self
+            .config
+            .with(|config| (config.max_clients, config.maxmemory));
+        let stats = &self.stats;
+
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            );
+        };
+        metric(
+            "miniredis_uptime_seconds",
+            "gauge",
+            "Time since the server started.",
+            stats.uptime().as_secs_f64(),
+        );
+        metric(
+            "miniredis_connected_clients",
+            "gauge",
+            "Clients currently connected.",
+            stats.connected_clients() as f64,
+        );
+        metric(
+            "miniredis_max_clients",
+            "gauge",
+            "Maximum number of clients served at the same time.",
+            max_clients as f64,
+        );
+        metric(
+            "miniredis_connections_received_total",
+            "counter",
+            "Connections accepted by the server.",
+            stats.connections_received() as f64,
+        );
+        metric(
+            "miniredis_rejected_connections_total",
+            "counter",
+            "Connections refused because maxclients was reached.",
+            stats.rejected_connections() as f64,
+        );
+        metric(
+            "miniredis_commands_processed_total",
+            "counter",
+            "Commands run by clients.",
+            stats.commands_processed() as f64,
+        );
+        metric(
+            "miniredis_keys",
+            "gauge",
+            "Number of keys.",
+            db_stats.keys as f64,
+        );
+        metric(
+            "miniredis_keys_with_expiry",
+            "gauge",
+            "Number of keys with an expiration.",
+            db_stats.expires as f64,
+        );
+        metric(
+            "miniredis_expired_keys_total",
+            "counter",
+            "Keys removed because they expired.",
+            db_stats.expired_keys as f64,
+        );
+        metric(
+            "miniredis_evicted_keys_total",
+            "counter",
+            "Keys removed to keep the memory used below maxmemory.",
+            db_stats.evicted_keys as f64,
+        );
+        metric(
+            "miniredis_keyspace_hits_total",
+            "counter",
+            "Lookups that found the key.",
+            db_stats.keyspace_hits as f64,
+        );
+        metric(
+            "miniredis_keyspace_misses_total",
+            "counter",
+            "Lookups that did not find the key.",
+            db_stats.keyspace_misses as f64,
+        );
+        metric(
+            "miniredis_pubsub_channels",
+            "gauge",
+            "Pub/sub channels with at least one subscriber.",
+            db_stats.pubsub_channels as f64,
+        );
+        metric(
+            "miniredis_pubsub_messages_total",
+            "counter",
+            "Messages published.",
+            db_stats.pubsub_messages as f64,
+        );
+        metric(
+            "miniredis_pubsub_deliveries_total",
+            "counter",
+            "Messages delivered to subscribers.",
+            db_stats.pubsub_deliveries as f64,
+        );
+        metric(
+            "miniredis_memory_used_bytes",
+            "gauge",
+            "Approximate memory used by keys and values.",
+            db_stats.used_memory as f64,
+        );
+        metric(
+            "miniredis_memory_max_bytes",
+            "gauge",
+            "Configured maxmemory, 0 when unlimited.",
+            maxmemory as f64,
+        );
+
+        let commands = stats.commands();
+        out.push_str("# HELP miniredis_command_calls_total Calls by command.\n");
+        out.push_str("# TYPE miniredis_command_calls_total counter\n");
+        for (name, command) in &commands {
+            let _ = writeln!(
+                out,
+                "miniredis_command_calls_total{{cmd=\"{}\"}} {}",
+                name, command.calls
+            );
+        }
+        out.push_str("# HELP miniredis_command_duration_seconds Latency by command.\n");
+        out.push_str("# TYPE miniredis_command_duration_seconds histogram\n");
+        for (name, command) in &commands {
+            if command.timed_calls == 0 {
+                continue;
+            }
+            // Prometheus buckets are cumulative.
+            let mut count = 0;
+            for (bound, calls) in LATENCY_BUCKETS.iter().zip(command.buckets) {
+                count += calls;
+                let _ = writeln!(
+                    out,
+                    "miniredis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}",
+                    name,
+                    *bound as f64 / 1e6,
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "miniredis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}",
+                name, command.timed_calls
+            );
+            let _ = writeln!(
+                out,
+                "miniredis_command_duration_seconds_sum{{cmd=\"{}\"}} {}",
+                name,
+                command.duration.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "miniredis_command_duration_seconds_count{{cmd=\"{}\"}} {}",
+                name, command.timed_calls
+            );
+        }
+        out
+    }
+}
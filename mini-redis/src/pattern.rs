@@ -0,0 +1,122 @@
+//! Glob-style pattern matching, as used by Redis for key patterns, channel
+//! patterns and configuration parameter names.
+
+/// Returns `true` if `string` matches the glob `pattern`.
+///
+/// Supported syntax:
+///
+/// * `?` matches any single byte
+/// * `*` matches any sequence of bytes, including none
+/// * `[abc]`, `[^abc]` and `[a-z]` match a byte in, or not in, a set
+/// * `\x` matches `x` literally
+pub(crate) fn glob_match(pattern: &str, string: &str) -> bool {
+    match_bytes(pattern.as_bytes(), string.as_bytes())
+}
+
+fn match_bytes(mut pattern: &[u8], mut string: &[u8]) -> bool {
+    while let Some(&p) = pattern.first() {
+        match p {
+            b'*' => {
+                // Collapse consecutive stars, then try every suffix.
+                while pattern.first() == Some(&b'*') {
+                    pattern = &pattern[1..];
+                }
+                if pattern.is_empty() {
+                    return true;
+                }
+                return (0..=string.len()).any(|i| match_bytes(pattern, &string[i..]));
+            }
+            b'?' => {
+                if string.is_empty() {
+                    return false;
+                }
+                pattern = &pattern[1..];
+                string = &string[1..];
+            }
+            b'[' => {
+                let Some(&c) = string.first() else {
+                    return false;
+                };
+                let (matched, rest) = match_class(&pattern[1..], c);
+                if !matched {
+                    return false;
+                }
+                pattern = rest;
+                string = &string[1..];
+            }
+            _ => {
+                let literal = if p == b'\\' && pattern.len() > 1 {
+                    pattern = &pattern[1..];
+                    pattern[0]
+                } else {
+                    p
+                };
+                if string.first() != Some(&literal) {
+                    return false;
+                }
+                pattern = &pattern[1..];
+                string = &string[1..];
+            }
+        }
+    }
+    string.is_empty()
+}
+
+/// Matches `c` against the class starting right after `[`. Returns whether it
+/// matched and the pattern following the closing `]`.
+fn match_class(mut pattern: &[u8], c: u8) -> (bool, &[u8]) {
+    let negate = pattern.first() == Some(&b'^');
+    if negate {
+        pattern = &pattern[1..];
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern {
+            // An unterminated class matches up to the end of the pattern.
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == c;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= (*low..=*high).contains(&c);
+                pattern = rest;
+            }
+            [literal, rest @ ..] => {
+                matched |= *literal == c;
+                pattern = rest;
+            }
+        }
+    }
+    (matched != negate, pattern)
+}
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("user:*", "user:42"));
+        assert!(!glob_match("user:*", "session:42"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(!glob_match("h?llo", "hllo"));
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("key[0-9]", "key7"));
+        assert!(glob_match("a\\*b", "a*b"));
+        assert!(!glob_match("a\\*b", "axb"));
+        assert!(glob_match("*max*", "maxmemory-policy"));
+    }
+}
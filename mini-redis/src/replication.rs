@@ -0,0 +1,993 @@
+//! Master-replica replication.
+//!
+//! A server is either a master, or the replica of another server set with
+//! `REPLICAOF`. A master turns every write it applies back into a command
+//! frame and appends it to its replication stream. The stream has a random
+//! ID, and the offset of a byte in the stream is the number of bytes before
+//! it.
+//!
+//! A replica connects to its master and asks for the stream with `PSYNC`,
+//! giving the ID of the stream it followed and its offset in it. If the
+//! backlog of the master, a buffer of the latest bytes of the stream, still
+//! holds what follows that offset, the master replies `+CONTINUE` and sends
+//! the stream from there. Otherwise it replies `+FULLRESYNC` with its ID and
+//! offset, then sends a snapshot of its keyspace: the number of keys at that
+//! offset, and a `SET` command per key, or `DEL` if the key was removed by
+//! the time its value is read. The values are read after the offset, as they
+//! are sent, so a value may be more recent than the offset: the stream, which
+//! follows from that offset, writes it again. Replicas
+//! acknowledge the offset they applied every second with `REPLCONF ACK`, and
+//! right away when the master feeds `REPLCONF GETACK` to the stream, as it
+//! does for clients blocked in `WAIT`.
+//!
+//! Replicas keep a backlog of the stream as well. They stream it to replicas
+//! of their own and, once promoted with `REPLICAOF NO ONE`, let the other
+//! replicas of their former master continue from where they were: the
+//! promoted replica starts a new stream, and remembers where the former one
+//! ended.
+//!
+//! Writes are appended to the stream by the `Db`, see `Propagate`, while the
+//! keys they change are still locked, so that replicas apply the writes to a
+//! key in the order the master did. Keys that expire or are evicted on the
+//! master are removed from its replicas with `DEL`. `SET` commands carry the
+//! Unix time keys expire at, so that a key expires at the same time on the
+//! master and its replicas. Replicas don't evict keys to make room for the
+//! writes of their master.
+
+use crate::cmd::{Del, Psync, Replconf, Set};
+use crate::config::LiveConfig;
+use crate::db::Propagate;
+use crate::eviction::Rng;
+use crate::{Command, Connection, Db, Frame, Shutdown};
+
+use bytes::{Bytes, BytesMut};
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::task;
+use tokio::time::{self, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// How often a master pings its replicas through the stream, so that they
+/// can tell an idle master from a dead one.
+const PING_PERIOD: Duration = Duration::from_secs(10);
+
+/// How often a replica acknowledges the stream it applied.
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Delay before a replica connects again to its master.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Largest part of the backlog sent to a replica at once.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Keys whose values are read at once when sending a snapshot.
+const SNAPSHOT_BATCH: usize = 256;
+
+/// Reported as the ID of the previous stream of a server that followed a
+/// single one.
+const NO_REPLID: &str = "0000000000000000000000000000000000000000";
+
+/// Replication state of a server, shared by every connection. Cloning is
+/// shallow.
+#[derive(Clone, Debug)]
+pub(crate) struct Replication {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    db: Db,
+    config: LiveConfig,
+    state: Mutex<State>,
+
+    /// Notified whenever the stream grows or is replaced.
+    fed: Notify,
+
+    /// Notified whenever a replica acknowledges the stream.
+    acked: Notify,
+
+    /// Cancelled when the server shuts down, which stops the link to the
+    /// master.
+    stopped: CancellationToken,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The master replicated, `None` on a master.
+    master: Option<Master>,
+
+    /// ID of the stream.
+    replid: String,
+
+    /// ID of the stream followed before `replid`, and the offset where it was
+    /// left. Replicas of that stream may continue up to that offset.
+    replid2: String,
+    second_offset: Option<u64>,
+
+    /// Offset of the end of the stream.
+    offset: u64,
+
+    /// Created when the first replica connects, or when syncing with a
+    /// master.
+    backlog: Option<Backlog>,
+
+    /// Changed when the stream is replaced by the one of a new master, which
+    /// drops the replicas following the former stream.
+    history: u64,
+
+    /// Replicas being served, by ID.
+    replicas: BTreeMap<u64, Replica>,
+    next_id: u64,
+
+    /// Syncs served, starting over or continuing the stream.
+    full_syncs: u64,
+    partial_syncs: u64,
+
+    rng: Rng,
+}
+
+#[derive(Debug)]
+struct Master {
+    host: String,
+    port: u16,
+    link: LinkState,
+    /// Last time anything was received from the master.
+    last_io: Instant,
+    /// Stops the task replicating the master.
+    stop: CancellationToken,
+}
+
+/// State of the link of a replica to its master, named as in `ROLE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LinkState {
+    /// Waiting to connect.
+    Connect,
+    /// Connecting and asking for the stream.
+    Connecting,
+    /// Loading a snapshot of the master.
+    Sync,
+    /// Following the stream.
+    Connected,
+}
+
+#[derive(Debug)]
+struct Replica {
+    ip: String,
+    port: u16,
+    /// Offset acknowledged by the replica.
+    ack: u64,
+    /// Offset the replica made durable, 0 if it keeps its data in memory.
+    durable: u64,
+    acked_at: Instant,
+}
+
+/// The latest bytes of the stream.
+#[derive(Debug)]
+struct Backlog {
+    /// Offset of the first byte held.
+    start: u64,
+    data: VecDeque<u8>,
+    size: usize,
+}
+
+/// What `INFO` and `ROLE` report.
+#[derive(Debug)]
+pub(crate) struct ReplicationInfo {
+    /// Set on a replica.
+    pub(crate) master: Option<MasterInfo>,
+    pub(crate) replicas: Vec<ReplicaInfo>,
+    pub(crate) replid: String,
+    pub(crate) replid2: String,
+    pub(crate) offset: u64,
+    pub(crate) second_offset: Option<u64>,
+    /// Offset of the first byte of the backlog and its length, if any.
+    pub(crate) backlog: Option<(u64, usize)>,
+    pub(crate) backlog_size: u64,
+    pub(crate) full_syncs: u64,
+    pub(crate) partial_syncs: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct MasterInfo {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) state: LinkState,
+    /// Time since anything was received from the master.
+    pub(crate) last_io: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct ReplicaInfo {
+    pub(crate) ip: String,
+    pub(crate) port: u16,
+    pub(crate) ack: u64,
+    /// Time since the replica last acknowledged the stream.
+    pub(crate) lag: Duration,
+}
+
+impl Replication {
+    /// Starts as a master, or as a replica of `replicaof` if set. The link to
+    /// the master is closed when `shutdown` fires.
+    pub(crate) fn start(db: Db, config: LiveConfig, shutdown: Shutdown) -> Replication {
+        let mut rng = Rng::new();
+        let state = State {
+            master: None,
+            replid: random_id(&mut rng),
+            replid2: NO_REPLID.to_string(),
+            second_offset: None,
+            offset: 0,
+            backlog: None,
+            history: 0,
+            replicas: BTreeMap::new(),
+            next_id: 0,
+            full_syncs: 0,
+            partial_syncs: 0,
+            rng,
+        };
+        let master = config.with(|config| config.replicaof.clone());
+        let replication = Replication {
+            shared: Arc::new(Shared {
+                db,
+                config,
+                state: Mutex::new(state),
+                fed: Notify::new(),
+                acked: Notify::new(),
+                stopped: CancellationToken::new(),
+            }),
+        };
+        tokio::spawn(replication.clone().ping_replicas(shutdown));
+        if master.is_some() {
+            replication.replica_of(master);
+        }
+        replication
+    }
+
+    /// Replicates `master`, given as a host and a port, or stops replicating
+    /// when `None`. Returns `false` if the server already replicates
+    /// `master`.
+    pub(crate) fn replica_of(&self, master: Option<(String, u16)>) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        if let (Some((host, port)), Some(current)) = (&master, &state.master) {
+            if current.host == *host && current.port == *port {
+                return false;
+            }
+        }
+        let promoted = match state.master.take() {
+            Some(previous) => {
+                previous.stop.cancel();
+                true
+            }
+            None => false,
+        };
+        match master {
+            Some((host, port)) => {
+                info!(%host, port, "replicating master");
+                let stop = self.shared.stopped.child_token();
+                state.master = Some(Master {
+                    host: host.clone(),
+                    port,
+                    link: LinkState::Connect,
+                    last_io: Instant::now(),
+                    stop: stop.clone(),
+                });
+                tokio::spawn(self.clone().replicate(host, port, stop));
+            }
+            None if promoted => {
+                // The writes of the clients start a new stream. Replicas of
+                // the former master may continue up to where it was left.
+                info!("promoted to master");
+                let replid = random_id(&mut state.rng);
+                state.replid2 = mem::replace(&mut state.replid, replid);
+                state.second_offset = Some(state.offset);
+            }
+            None => {}
+        }
+        true
+    }
+
+    pub(crate) fn is_replica(&self) -> bool {
+        self.shared.state.lock().unwrap().master.is_some()
+    }
+
+    /// Whether clients may not write: on a replica, unless
+    /// `replica-read-only` is off.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.is_replica() && self.shared.config.with(|config| config.replica_read_only)
+    }
+
+    /// Offset of the end of the stream, which covers every write applied so
+    /// far.
+    pub(crate) fn offset(&self) -> u64 {
+        self.shared.state.lock().unwrap().offset
+    }
+
+    pub(crate) fn info(&self) -> ReplicationInfo {
+        let state = self.shared.state.lock().unwrap();
+        ReplicationInfo {
+            master: state.master.as_ref().map(|master| MasterInfo {
+                host: master.host.clone(),
+                port: master.port,
+                state: master.link,
+                last_io: master.last_io.elapsed(),
+            }),
+            replicas: state
+                .replicas
+                .values()
+                .map(|replica| ReplicaInfo {
+                    ip: replica.ip.clone(),
+                    port: replica.port,
+                    ack: replica.ack,
+                    lag: replica.acked_at.elapsed(),
+                })
+                .collect(),
+            replid: state.replid.clone(),
+            replid2: state.replid2.clone(),
+            offset: state.offset,
+            second_offset: state.second_offset,
+            backlog: state
+                .backlog
+                .as_ref()
+                .map(|backlog| (backlog.start, backlog.data.len())),
+            backlog_size: self.shared.backlog_size() as u64,
+            full_syncs: state.full_syncs,
+            partial_syncs: state.partial_syncs,
+        }
+    }
+
+    /// Serves the stream to a replica that sent `PSYNC`, until it
+    /// disconnects or falls behind the backlog. `ip` and `port` are where the
+    /// replica listens, as reported by `INFO` and `ROLE`.
+    pub(crate) async fn serve_replica(
+        &self,
+        psync: Psync,
+        ip: &str,
+        port: u16,
+        connection: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        let continued = {
+            let mut state = self.shared.state.lock().unwrap();
+            match &state.master {
+                Some(master) if master.link != LinkState::Connected => Err(()),
+                _ => match state.continue_from(psync.replid(), psync.offset()) {
+                    Some(offset) => {
+                        state.partial_syncs += 1;
+                        Ok(Some((offset, state.replid.clone(), state.history)))
+                    }
+                    None => {
+                        state.full_syncs += 1;
+                        Ok(None)
+                    }
+                },
+            }
+        };
+        let (offset, history) = match continued {
+            Err(()) => {
+                let message = "NOMASTERLINK Can't SYNC while not connected with my master";
+                connection
+                    .write_frame(&Frame::Error(message.to_string()))
+                    .await?;
+                return Ok(());
+            }
+            Ok(Some((offset, replid, history))) => {
+                let frame = Frame::Simple(format!("CONTINUE {}", replid));
+                connection.write_frame(&frame).await?;
+                (offset, history)
+            }
+            Ok(None) => self.full_sync(connection).await?,
+        };
+        info!(ip, port, offset, "replica synchronized");
+
+        let served = Served::new(&self.shared, ip, port, offset);
+        self.stream(connection, offset, history, served.id, shutdown)
+            .await
+    }
+
+    /// Sends `+FULLRESYNC` and a snapshot of the keyspace. Returns the offset
+    /// the stream continues from, and the history it belongs to.
+    async fn full_sync(&self, connection: &mut Connection) -> crate::Result<(u64, u64)> {
+        let size = self.shared.backlog_size();
+        let shared = self.shared.clone();
+        // The writes applied before the cut are in the snapshot, and those
+        // applied after are propagated from its offset.
+        let cut = move || {
+            let mut state = shared.state.lock().unwrap();
+            let offset = state.offset;
+            state
+                .backlog
+                .get_or_insert_with(|| Backlog::new(offset, size));
+            shared.propagate_writes();
+            (state.replid.clone(), offset, state.history)
+        };
+        let db = self.shared.db.clone();
+        let ((replid, offset, history), keys) =
+            task::spawn_blocking(move || db.snapshot_keys(cut)).await?;
+
+        let frame = Frame::Simple(format!("FULLRESYNC {} {}", replid, offset));
+        connection.write_frame(&frame).await?;
+        connection
+            .write_frame(&Frame::Integer(keys.len() as u64))
+            .await?;
+        // The values are read after the cut, a batch at a time. A value
+        // written since is written again by the stream, and a key removed
+        // since is sent as removed.
+        let mut keys = keys.into_iter();
+        loop {
+            let batch: Vec<_> = keys.by_ref().take(SNAPSHOT_BATCH).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let db = self.shared.db.clone();
+            let (batch, values) = task::spawn_blocking(move || {
+                let values = db.read_values(&batch);
+                (batch, values)
+            })
+            .await?;
+            for (key, value) in batch.into_iter().zip(values) {
+                let frame = match value {
+                    Some((value, expires_at)) => Set::replicated(&key, value, expires_at),
+                    None => Del::new(vec![key]).into_frame(),
+                };
+                connection.write_frame(&frame).await?;
+            }
+        }
+        Ok((offset, history))
+    }
+
+    /// Sends the stream from `offset` to a replica, and records its
+    /// acknowledgments.
+    async fn stream(
+        &self,
+        connection: &mut Connection,
+        mut offset: u64,
+        history: u64,
+        id: u64,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        loop {
+            // Registered before reading the backlog, so that bytes fed after
+            // the read wake the task.
+            let fed = self.shared.fed.notified();
+            tokio::pin!(fed);
+            fed.as_mut().enable();
+
+            let chunk = {
+                let state = self.shared.state.lock().unwrap();
+                if state.history != history {
+                    return Err("replication stream replaced".into());
+                }
+                state
+                    .backlog
+                    .as_ref()
+                    .and_then(|backlog| backlog.read(offset, CHUNK_SIZE))
+                    .ok_or("replica fell behind the replication backlog")?
+            };
+            if !chunk.is_empty() {
+                connection.write_encoded(&chunk).await?;
+                offset += chunk.len() as u64;
+                continue;
+            }
+
+            tokio::select! {
+                _ = fed => {}
+                res = connection.read_frame() => match res? {
+                    Some(frame) => self.acknowledge(id, frame)?,
+                    None => return Ok(()),
+                },
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
+
+    /// Records the offset a replica acknowledged.
+    fn acknowledge(&self, id: u64, frame: Frame) -> crate::Result<()> {
+        match Command::from_frame(frame)? {
+            Command::Replconf(cmd) => {
+                if let Some(ack) = cmd.ack() {
+                    let mut state = self.shared.state.lock().unwrap();
+                    if let Some(replica) = state.replicas.get_mut(&id) {
+                        replica.ack = ack;
+                        replica.durable = cmd.fack().unwrap_or(replica.durable);
+                        replica.acked_at = Instant::now();
+                    }
+                    drop(state);
+                    self.shared.acked.notify_waiters();
+                }
+            }
+            cmd => debug!(cmd = cmd.get_name(), "ignoring command from replica"),
+        }
+        Ok(())
+    }
+
+    /// Waits until `numreplicas` replicas acknowledged the stream up to
+    /// `offset`, or until `timeout` elapses if set. Returns the number of
+    /// replicas that did.
+    pub(crate) async fn wait(
+        &self,
+        offset: u64,
+        numreplicas: usize,
+        timeout: Option<Duration>,
+    ) -> usize {
+        self.wait_for(offset, numreplicas, timeout, |replica| replica.ack)
+            .await
+    }
+
+    /// Same as `wait`, for replicas that made the stream durable up to
+    /// `offset`.
+    pub(crate) async fn wait_durable(
+        &self,
+        offset: u64,
+        numreplicas: usize,
+        timeout: Option<Duration>,
+    ) -> usize {
+        self.wait_for(offset, numreplicas, timeout, |replica| replica.durable)
+            .await
+    }
+
+    /// Waits until `numreplicas` replicas are at `offset` or past it, as
+    /// told by `offset_of`.
+    async fn wait_for(
+        &self,
+        offset: u64,
+        numreplicas: usize,
+        timeout: Option<Duration>,
+        offset_of: fn(&Replica) -> u64,
+    ) -> usize {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut asked = false;
+        loop {
+            let acked = self.shared.acked.notified();
+            tokio::pin!(acked);
+            acked.as_mut().enable();
+
+            let (count, replicas) = {
+                let state = self.shared.state.lock().unwrap();
+                let count = state
+                    .replicas
+                    .values()
+                    .filter(|replica| offset_of(replica) >= offset)
+                    .count();
+                (count, state.replicas.len())
+            };
+            if count >= numreplicas || deadline.is_some_and(|at| Instant::now() >= at) {
+                return count;
+            }
+            // Rather than waiting for the periodic acknowledgments, ask
+            // the replicas once for theirs.
+            if !asked && replicas > 0 {
+                let frame = Replconf::new("getack", "*").into_frame();
+                self.shared.propagate(&frame);
+                asked = true;
+            }
+            match deadline {
+                Some(deadline) => {
+                    let _ = time::timeout_at(deadline, acked).await;
+                }
+                None => acked.await,
+            }
+        }
+    }
+
+    /// Pings the replicas through the stream until the server shuts down,
+    /// then closes the link to the master.
+    async fn ping_replicas(self, mut shutdown: Shutdown) {
+        let mut ping = time::interval(PING_PERIOD);
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {}
+                _ = shutdown.recv() => break,
+            }
+            if !self.shared.state.lock().unwrap().replicas.is_empty() {
+                let mut frame = Frame::array();
+                frame.push_bulk(Bytes::from("ping".as_bytes()));
+                self.shared.propagate(&frame);
+            }
+        }
+        self.shared.stopped.cancel();
+    }
+
+    /// Replicates `host:port` until `stop` is cancelled, connecting again
+    /// whenever the link breaks.
+    async fn replicate(self, host: String, port: u16, stop: CancellationToken) {
+        loop {
+            tokio::select! {
+                res = self.sync_with(&host, port, &stop) => {
+                    if let Err(err) = res {
+                        warn!(case = %err, %host, port, "lost the link to master");
+                    }
+                }
+                _ = stop.cancelled() => return,
+            }
+            let _ = self.with_link(&stop, |_, master| master.link = LinkState::Connect);
+            tokio::select! {
+                _ = time::sleep(RECONNECT_DELAY) => {}
+                _ = stop.cancelled() => return,
+            }
+        }
+    }
+
+    /// Connects to the master, syncs with it and applies its stream until the
+    /// link breaks.
+    async fn sync_with(
+        &self,
+        host: &str,
+        port: u16,
+        stop: &CancellationToken,
+    ) -> crate::Result<()> {
+        self.with_link(stop, |_, master| master.link = LinkState::Connecting)?;
+        let (timeout, listening_port, user, password) = self.shared.config.with(|config| {
+            (
+                config.repl_timeout,
+                config.port,
+                config.masteruser.clone(),
+                config.masterauth.clone(),
+            )
+        });
+        let socket = time::timeout(timeout, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| "timed out connecting to master")??;
+        let mut connection = Connection::new(socket);
+
+        if let Some(password) = password {
+            let mut frame = Frame::array();
+            frame.push_bulk(Bytes::from("auth".as_bytes()));
+            if let Some(user) = user {
+                frame.push_bulk(Bytes::from(user.into_bytes()));
+            }
+            frame.push_bulk(Bytes::from(password.into_bytes()));
+            expect_ok(call(&mut connection, frame, timeout).await?)?;
+        }
+        let frame = Replconf::new("listening-port", listening_port).into_frame();
+        expect_ok(call(&mut connection, frame, timeout).await?)?;
+
+        let (replid, offset) = {
+            let state = self.shared.state.lock().unwrap();
+            (state.replid.clone(), state.offset)
+        };
+        let frame = Psync::new(replid, offset as i64).into_frame();
+        let reply = match call(&mut connection, frame, timeout).await? {
+            Frame::Simple(reply) => reply,
+            frame => return Err(frame.to_error()),
+        };
+        let words: Vec<&str> = reply.split_whitespace().collect();
+        match words[..] {
+            ["FULLRESYNC", replid, offset] => {
+                let offset = offset.parse().map_err(|_| "bad offset in FULLRESYNC")?;
+                info!(%host, port, replid, offset, "full sync with master");
+                self.load_snapshot(&mut connection, replid, offset, timeout, stop)
+                    .await?;
+            }
+            ["CONTINUE", ..] => {
+                info!(%host, port, "continuing the stream of master");
+                self.continue_stream(words.get(1).copied(), stop)?;
+            }
+            _ => return Err(format!("unexpected reply to PSYNC: {}", reply).into()),
+        }
+        self.with_link(stop, |_, master| master.link = LinkState::Connected)?;
+        self.follow(&mut connection, stop).await
+    }
+
+    /// Replaces the keyspace with the snapshot following `+FULLRESYNC`, and
+    /// the stream with the one of the master.
+    async fn load_snapshot(
+        &self,
+        connection: &mut Connection,
+        replid: &str,
+        offset: u64,
+        timeout: Duration,
+        stop: &CancellationToken,
+    ) -> crate::Result<()> {
+        let size = self.shared.backlog_size();
+        self.with_link(stop, |state, master| {
+            master.link = LinkState::Sync;
+            state.replid = replid.to_string();
+            state.replid2 = NO_REPLID.to_string();
+            state.second_offset = None;
+            state.offset = offset;
+            state.backlog = Some(Backlog::new(offset, size));
+            state.history += 1;
+        })?;
+        self.shared.propagate_writes();
+        self.shared.db.flush(None);
+        // Replicas of this server notice the stream was replaced.
+        self.shared.fed.notify_waiters();
+
+        let count = match read(connection, timeout).await? {
+            Frame::Integer(count) => count,
+            frame => return Err(format!("unexpected snapshot header: {}", frame).into()),
+        };
+        for _ in 0..count {
+            match Command::from_frame(read(connection, timeout).await?)? {
+                cmd @ (Command::Set(_) | Command::Del(_)) => {
+                    cmd.execute_replicated(&self.shared.db)
+                }
+                cmd => {
+                    return Err(
+                        format!("unexpected command in snapshot: {}", cmd.get_name()).into(),
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Continues the stream after `+CONTINUE`, which gives the ID of the
+    /// stream of the master if it changed.
+    fn continue_stream(&self, replid: Option<&str>, stop: &CancellationToken) -> crate::Result<()> {
+        let size = self.shared.backlog_size();
+        self.with_link(stop, |state, _| {
+            if let Some(replid) = replid.filter(|replid| *replid != state.replid) {
+                state.replid2 = mem::replace(&mut state.replid, replid.to_string());
+                state.second_offset = Some(state.offset);
+            }
+            let offset = state.offset;
+            state
+                .backlog
+                .get_or_insert_with(|| Backlog::new(offset, size));
+        })?;
+        self.shared.propagate_writes();
+        Ok(())
+    }
+
+    /// Applies the stream of the master until the link breaks, acknowledging
+    /// it every second and when asked to.
+    async fn follow(
+        &self,
+        connection: &mut Connection,
+        stop: &CancellationToken,
+    ) -> crate::Result<()> {
+        let mut ack = time::interval(ACK_PERIOD);
+        loop {
+            tokio::select! {
+                res = connection.read_frame() => {
+                    let frame = res?.ok_or("connection closed by master")?;
+                    if !self.apply(frame, stop).await? {
+                        continue;
+                    }
+                }
+                _ = ack.tick() => {
+                    let timeout = self.shared.config.with(|config| config.repl_timeout);
+                    let last_io = self.with_link(stop, |_, master| master.last_io)?;
+                    if last_io.elapsed() > timeout {
+                        return Err("timed out waiting for master".into());
+                    }
+                }
+            }
+            let offset = self.shared.state.lock().unwrap().offset;
+            let mut ack = Replconf::new("ack", offset);
+            let db = &self.shared.db;
+            if db.is_durable() {
+                // The stream applied so far is durable once written.
+                db.written(&[]).await;
+                if db.storage_error().is_none() {
+                    ack = ack.with("fack", offset);
+                }
+            }
+            connection.write_frame(&ack.into_frame()).await?;
+        }
+    }
+
+    /// Applies a frame of the stream of the master, and feeds it to the
+    /// stream of this server. Returns whether the master asks for an
+    /// acknowledgment.
+    async fn apply(&self, frame: Frame, stop: &CancellationToken) -> crate::Result<bool> {
+        let mut data = BytesMut::new();
+        frame.encode(&mut data);
+        let cmd = Command::from_frame(frame)?;
+        let getack = matches!(&cmd, Command::Replconf(cmd) if cmd.is_getack());
+
+        let size = self.shared.backlog_size();
+        if cmd.is_write() {
+            cmd.execute_replicated(&self.shared.db);
+        } else {
+            debug!(cmd = cmd.get_name(), "received from master");
+        }
+        self.with_link(stop, |state, master| {
+            master.last_io = Instant::now();
+            state.feed(&data, size);
+        })?;
+        self.shared.fed.notify_waiters();
+        Ok(getack)
+    }
+
+    /// Runs `f` on the state and the master replicated, unless `stop` was
+    /// cancelled: the master is no longer replicated by the task holding
+    /// `stop`.
+    fn with_link<T>(
+        &self,
+        stop: &CancellationToken,
+        f: impl FnOnce(&mut State, &mut Master) -> T,
+    ) -> crate::Result<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        // The token is cancelled while holding the lock.
+        if stop.is_cancelled() {
+            return Err("replication stopped".into());
+        }
+        let mut master = state.master.take().expect("replicating without a master");
+        let res = f(&mut state, &mut master);
+        state.master = Some(master);
+        Ok(res)
+    }
+}
+
+impl Shared {
+    fn backlog_size(&self) -> usize {
+        self.config
+            .with(|config| config.repl_backlog_size)
+            .try_into()
+            .unwrap_or(usize::MAX)
+    }
+}
+
+impl Shared {
+    /// Has the `Db` propagate the writes once the stream has a backlog.
+    fn propagate_writes(self: &Arc<Self>) {
+        self.db
+            .propagate_to(Arc::downgrade(self) as Weak<dyn Propagate>);
+    }
+}
+
+impl Propagate for Shared {
+    /// Appends the frame of a write to the stream, unless there is no
+    /// replica to send it to yet.
+    fn propagate(&self, frame: &Frame) {
+        let size = self.backlog_size();
+        let mut state = self.state.lock().unwrap();
+        // The writes of the clients of a writable replica are not
+        // replicated.
+        if state.master.is_some() || state.backlog.is_none() {
+            return;
+        }
+        let mut data = BytesMut::new();
+        frame.encode(&mut data);
+        state.feed(&data, size);
+        drop(state);
+        self.fed.notify_waiters();
+    }
+}
+
+impl State {
+    /// The offset a replica that followed the stream `replid` up to `offset`
+    /// continues from, if the backlog holds what follows.
+    fn continue_from(&self, replid: &str, offset: Option<u64>) -> Option<u64> {
+        let offset = offset?;
+        let backlog = self.backlog.as_ref()?;
+        let known = replid == self.replid
+            || (replid == self.replid2 && self.second_offset.is_some_and(|end| offset <= end));
+        (known && offset >= backlog.start && offset <= backlog.end()).then_some(offset)
+    }
+
+    /// Appends encoded frames to the stream, keeping the last `size` bytes in
+    /// the backlog.
+    fn feed(&mut self, data: &[u8], size: usize) {
+        self.offset += data.len() as u64;
+        if let Some(backlog) = &mut self.backlog {
+            backlog.size = size;
+            backlog.push(data);
+        }
+    }
+}
+
+impl Backlog {
+    fn new(start: u64, size: usize) -> Backlog {
+        Backlog {
+            start,
+            data: VecDeque::new(),
+            size,
+        }
+    }
+
+    /// Offset of the end of the stream.
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.data.extend(data);
+        if self.data.len() > self.size {
+            let excess = self.data.len() - self.size;
+            self.data.drain(..excess);
+            self.start += excess as u64;
+        }
+    }
+
+    /// Up to `max` bytes from `offset`, or `None` if the backlog doesn't hold
+    /// them.
+    fn read(&self, offset: u64, max: usize) -> Option<Bytes> {
+        if offset < self.start || offset > self.end() {
+            return None;
+        }
+        let from = (offset - self.start) as usize;
+        let to = self.data.len().min(from + max);
+        let (front, back) = self.data.as_slices();
+        let mut chunk = Vec::with_capacity(to - from);
+        if from < front.len() {
+            chunk.extend_from_slice(&front[from..to.min(front.len())]);
+        }
+        if to > front.len() {
+            chunk.extend_from_slice(&back[from.saturating_sub(front.len())..to - front.len()]);
+        }
+        Some(Bytes::from(chunk))
+    }
+}
+
+impl LinkState {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+/// A replica listed by `INFO` and `ROLE` while it is served.
+struct Served<'a> {
+    shared: &'a Shared,
+    id: u64,
+}
+
+impl<'a> Served<'a> {
+    fn new(shared: &'a Shared, ip: &str, port: u16, offset: u64) -> Served<'a> {
+        let mut state = shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.replicas.insert(
+            id,
+            Replica {
+                ip: ip.to_string(),
+                port,
+                ack: offset,
+                durable: 0,
+                acked_at: Instant::now(),
+            },
+        );
+        Served { shared, id }
+    }
+}
+
+impl Drop for Served<'_> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().replicas.remove(&self.id);
+    }
+}
+
+/// Sends a command to the master and returns its reply.
+async fn call(
+    connection: &mut Connection,
+    frame: Frame,
+    timeout: Duration,
+) -> crate::Result<Frame> {
+    connection.write_frame(&frame).await?;
+    read(connection, timeout).await
+}
+
+async fn read(connection: &mut Connection, timeout: Duration) -> crate::Result<Frame> {
+    match time::timeout(timeout, connection.read_frame()).await {
+        Ok(res) => res?.ok_or_else(|| "connection closed by master".into()),
+        Err(_) => Err("timed out waiting for master".into()),
+    }
+}
+
+fn expect_ok(reply: Frame) -> crate::Result<()> {
+    match reply {
+        Frame::Simple(_) => Ok(()),
+        frame => Err(frame.to_error()),
+    }
+}
+
+/// A random ID of 40 hexadecimal digits, as Redis gives to replication
+/// streams and sentinels.
+pub(crate) fn random_id(rng: &mut Rng) -> String {
+    format!(
+        "{:016x}{:016x}{:08x}",
+        rng.next(),
+        rng.next(),
+        rng.next() as u32
+    )
+}
@@ -0,0 +1,211 @@
+use crate::config::{parse_number, split_args, LogLevel};
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Port a sentinel listens on unless configured otherwise.
+pub const DEFAULT_SENTINEL_PORT: u16 = 26379;
+
+/// Settings of a sentinel started with `sentinel::run`.
+#[derive(Clone, Debug)]
+pub struct SentinelConfig {
+    /// Addresses to listen on for TCP connections.
+    pub bind: Vec<String>,
+
+    /// TCP port to listen on, announced to the other sentinels.
+    pub port: u16,
+
+    /// Address announced to the other sentinels. By default, the local
+    /// address of the connection to each instance.
+    pub announce_ip: Option<String>,
+
+    /// Verbosity of the log.
+    pub loglevel: LogLevel,
+
+    /// The masters monitored.
+    pub masters: Vec<MasterConfig>,
+}
+
+/// A master monitored by a sentinel, set with `sentinel monitor`.
+#[derive(Clone, Debug)]
+pub struct MasterConfig {
+    /// Name clients ask the address of the master by.
+    pub name: String,
+
+    /// Address of the master when the sentinel starts. Failovers change it.
+    pub host: String,
+    pub port: u16,
+
+    /// Number of sentinels that must agree the master is down before it is
+    /// failed over. The sentinel leading the failover must also be elected
+    /// by a majority of the sentinels.
+    pub quorum: usize,
+
+    /// Time without a valid reply after which an instance is considered
+    /// down.
+    pub down_after: Duration,
+
+    /// Time a failover may take before it is aborted. No new failover of the
+    /// master starts within twice that time.
+    pub failover_timeout: Duration,
+
+    /// Credentials to authenticate to the master and its replicas with.
+    pub auth_user: Option<String>,
+    pub auth_pass: Option<String>,
+}
+
+impl MasterConfig {
+    pub fn new(name: impl ToString, host: impl ToString, port: u16, quorum: usize) -> MasterConfig {
+        MasterConfig {
+            name: name.to_string(),
+            host: host.to_string(),
+            port,
+            quorum,
+            down_after: Duration::from_secs(30),
+            failover_timeout: Duration::from_secs(180),
+            auth_user: None,
+            auth_pass: None,
+        }
+    }
+}
+
+impl Default for SentinelConfig {
+    fn default() -> SentinelConfig {
+        SentinelConfig {
+            bind: vec!["127.0.0.1".to_string()],
+            port: DEFAULT_SENTINEL_PORT,
+            announce_ip: None,
+            loglevel: LogLevel::Notice,
+            masters: Vec::new(),
+        }
+    }
+}
+
+impl SentinelConfig {
+    /// Load the configuration from a `sentinel.conf` style file. Besides
+    /// `bind`, `port` and `loglevel`, it holds `sentinel` directives:
+    ///
+    /// ```text
+    /// sentinel monitor <name> <host> <port> <quorum>
+    /// sentinel down-after-milliseconds <name> <milliseconds>
+    /// sentinel failover-timeout <name> <milliseconds>
+    /// sentinel auth-user <name> <username>
+    /// sentinel auth-pass <name> <password>
+    /// sentinel announce-ip <ip>
+    /// ```
+    pub fn from_file(path: impl AsRef<Path>) -> crate::Result<SentinelConfig> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("can't open config file '{}': {}", path.display(), err))?;
+        let mut config = SentinelConfig::default();
+        config.apply_file(&content)?;
+        Ok(config)
+    }
+
+    /// Apply the directives of a configuration file on top of the current
+    /// settings.
+    pub fn apply_file(&mut self, content: &str) -> crate::Result<()> {
+        for (i, line) in content.lines().enumerate() {
+            let args =
+                split_args(line).map_err(|err| format!("config file line {}: {}", i + 1, err))?;
+            if args.is_empty() {
+                continue;
+            }
+            self.apply_directive(&args)
+                .map_err(|err| format!("config file line {}: '{}': {}", i + 1, args[0], err))?;
+        }
+        Ok(())
+    }
+
+    fn apply_directive(&mut self, args: &[String]) -> Result<(), String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match &args[..] {
+            [name, bind @ ..] if name.eq_ignore_ascii_case("bind") && !bind.is_empty() => {
+                self.bind = bind.iter().map(|addr| addr.to_string()).collect();
+            }
+            [name, port] if name.eq_ignore_ascii_case("port") => self.port = parse_number(port)?,
+            [name, level] if name.eq_ignore_ascii_case("loglevel") => {
+                self.loglevel = level.parse()?;
+            }
+            [name, option, args @ ..] if name.eq_ignore_ascii_case("sentinel") => {
+                self.apply_sentinel(&option.to_lowercase(), args)?;
+            }
+            _ => return Err("Bad directive or wrong number of arguments".to_string()),
+        }
+        Ok(())
+    }
+
+    fn apply_sentinel(&mut self, option: &str, args: &[&str]) -> Result<(), String> {
+        match (option, args) {
+            ("monitor", [name, host, port, quorum]) => {
+                let quorum = parse_number(quorum)?;
+                if quorum == 0 {
+                    return Err("Quorum must be 1 or greater.".to_string());
+                }
+                if self.masters.iter().any(|master| master.name == *name) {
+                    return Err("Duplicated master name.".to_string());
+                }
+                let master = MasterConfig::new(name, host, parse_number(port)?, quorum);
+                self.masters.push(master);
+            }
+            ("down-after-milliseconds", [name, ms]) => {
+                self.master(name)?.down_after = parse_millis(ms)?;
+            }
+            ("failover-timeout", [name, ms]) => {
+                self.master(name)?.failover_timeout = parse_millis(ms)?;
+            }
+            ("auth-user", [name, user]) => self.master(name)?.auth_user = Some(user.to_string()),
+            ("auth-pass", [name, pass]) => self.master(name)?.auth_pass = Some(pass.to_string()),
+            ("announce-ip", [ip]) => self.announce_ip = Some(ip.to_string()),
+            _ => return Err("Unrecognized sentinel configuration statement.".to_string()),
+        }
+        Ok(())
+    }
+
+    fn master(&mut self, name: &str) -> Result<&mut MasterConfig, String> {
+        self.masters
+            .iter_mut()
+            .find(|master| master.name == name)
+            .ok_or_else(|| "No such master with specified name.".to_string())
+    }
+}
+
+fn parse_millis(value: &str) -> Result<Duration, String> {
+    match parse_number(value)? {
+        0 => Err("argument must be positive".to_string()),
+        ms => Ok(Duration::from_millis(ms)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config_file() {
+        let mut config = SentinelConfig::default();
+        config
+            .apply_file(
+                "port 26380\n\
+                 # comment\n\
+                 sentinel monitor mymaster 127.0.0.1 6379 2\n\
+                 sentinel down-after-milliseconds mymaster 5000\n\
+                 sentinel auth-pass mymaster \"secret pass\"\n",
+            )
+            .unwrap();
+        assert_eq!(config.port, 26380);
+        let master = &config.masters[0];
+        assert_eq!(master.name, "mymaster");
+        assert_eq!((master.host.as_str(), master.port), ("127.0.0.1", 6379));
+        assert_eq!(master.quorum, 2);
+        assert_eq!(master.down_after, Duration::from_secs(5));
+        assert_eq!(master.failover_timeout, Duration::from_secs(180));
+        assert_eq!(master.auth_pass.as_deref(), Some("secret pass"));
+
+        let err = config
+            .apply_file("sentinel failover-timeout other 1000")
+            .unwrap_err();
+        assert!(err.to_string().contains("No such master"), "{}", err);
+    }
+}
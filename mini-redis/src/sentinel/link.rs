@@ -0,0 +1,549 @@
+//! Tasks linked to the instances and the other sentinels, which ping them,
+//! ask them for their state and exchange hello messages.
+
+use super::{
+    Addr, Instance, Monitored, Peer, Report, Role, Sentinel, Step, ASK_PERIOD, FAST_INFO_PERIOD,
+    HELLO_CHANNEL, HELLO_PERIOD, INFO_PERIOD, PING_PERIOD,
+};
+use crate::cmd::{Info, Ping, Publish, Sentinel as SentinelCmd, Subscribe};
+use crate::{Connection, Frame};
+
+use bytes::Bytes;
+use std::net::IpAddr;
+use tokio::net::TcpStream;
+use tokio::time::{self, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// How often a link checks whether something is due.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Time allowed to connect to an instance and to run a command sent by
+/// `command`.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl Sentinel {
+    /// Pings the master or replica at `addr`, asks it for `INFO` and
+    /// exchanges hello messages through it until `stop` is cancelled.
+    pub(super) async fn instance_link(self, name: String, addr: Addr, stop: CancellationToken) {
+        self.keep_linked(&stop, || self.serve_instance(&name, &addr))
+            .await
+    }
+
+    /// Pings the sentinel `runid` at `addr`, and asks it whether the master
+    /// is down, until `stop` is cancelled.
+    pub(super) async fn peer_link(
+        self,
+        name: String,
+        runid: String,
+        addr: Addr,
+        stop: CancellationToken,
+    ) {
+        self.keep_linked(&stop, || self.serve_peer(&name, &runid, &addr))
+            .await
+    }
+
+    /// Runs `serve` until `stop` is cancelled, again whenever it fails.
+    async fn keep_linked<F>(&self, stop: &CancellationToken, serve: impl Fn() -> F)
+    where
+        F: std::future::Future<Output = crate::Result<()>>,
+    {
+        loop {
+            tokio::select! {
+                res = serve() => {
+                    if let Err(err) = res {
+                        debug!(case = %err, "link failed");
+                    }
+                }
+                _ = stop.cancelled() => return,
+            }
+            tokio::select! {
+                _ = time::sleep(PING_PERIOD) => {}
+                _ = stop.cancelled() => return,
+            }
+        }
+    }
+
+    async fn serve_instance(&self, name: &str, addr: &Addr) -> crate::Result<()> {
+        let (user, pass) = self
+            .with_master(name, |monitored| {
+                let config = &monitored.config;
+                (config.auth_user.clone(), config.auth_pass.clone())
+            })
+            .ok_or("master no longer monitored")?;
+        let (user, pass) = (user.as_deref(), pass.as_deref());
+        let (mut commands, local_ip) = connect(addr, user, pass).await?;
+        let (mut hellos, _) = connect(addr, user, pass).await?;
+        let frame = Subscribe::new(vec![HELLO_CHANNEL.to_string()]).into_frame();
+        hellos.write_frame(&frame).await?;
+
+        tokio::select! {
+            res = self.poll_instance(name, addr, &mut commands, local_ip) => res,
+            res = self.read_hellos(&mut hellos) => res,
+        }
+    }
+
+    async fn poll_instance(
+        &self,
+        name: &str,
+        addr: &Addr,
+        connection: &mut Connection,
+        local_ip: IpAddr,
+    ) -> crate::Result<()> {
+        let mut tick = time::interval(TICK);
+        let (mut pinged, mut informed, mut greeted) = (None, None, None);
+        loop {
+            tick.tick().await;
+            let (ping_period, info_period) =
+                self.periods(name).ok_or("master no longer monitored")?;
+
+            if is_due(pinged, ping_period) {
+                pinged = Some(Instant::now());
+                let reply = call(connection, Ping::new(None).into_frame(), ping_period).await?;
+                // An instance loading its data or cut from its master is up.
+                let valid = match &reply {
+                    Frame::Error(err) => {
+                        err.starts_with("LOADING") || err.starts_with("MASTERDOWN")
+                    }
+                    _ => true,
+                };
+                if valid {
+                    self.with_instance(name, addr, |instance, _| {
+                        instance.last_pong = Some(Instant::now())
+                    });
+                }
+            }
+
+            if is_due(informed, info_period) {
+                informed = Some(Instant::now());
+                let frame = Info::new(&["replication"]).into_frame();
+                match call(connection, frame, ping_period).await? {
+                    Frame::Bulk(info) => {
+                        let report = parse_info(&String::from_utf8_lossy(&info));
+                        self.record_report(name, addr, report);
+                    }
+                    frame => return Err(frame.to_error()),
+                }
+            }
+
+            if is_due(greeted, HELLO_PERIOD) {
+                greeted = Some(Instant::now());
+                if let Some(hello) = self.hello(name, local_ip) {
+                    let frame = Publish::new(HELLO_CHANNEL, Bytes::from(hello)).into_frame();
+                    call(connection, frame, ping_period).await?;
+                }
+            }
+        }
+    }
+
+    async fn read_hellos(&self, connection: &mut Connection) -> crate::Result<()> {
+        loop {
+            let frame = connection
+                .read_frame()
+                .await?
+                .ok_or("connection closed by instance")?;
+            if let Frame::Array(parts) = frame {
+                if let [kind, _, Frame::Bulk(hello)] = &parts[..] {
+                    if *kind == "message" {
+                        self.hello_received(&String::from_utf8_lossy(hello));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn serve_peer(&self, name: &str, runid: &str, addr: &Addr) -> crate::Result<()> {
+        let (mut connection, _) = connect(addr, None, None).await?;
+        let mut tick = time::interval(TICK);
+        let mut pinged = None;
+        loop {
+            tick.tick().await;
+            let (period, _) = self.periods(name).ok_or("master no longer monitored")?;
+
+            if is_due(pinged, period) {
+                pinged = Some(Instant::now());
+                let reply = call(&mut connection, Ping::new(None).into_frame(), period).await?;
+                if !matches!(reply, Frame::Error(_)) {
+                    self.with_peer(name, runid, |peer| peer.last_pong = Some(Instant::now()));
+                }
+            }
+
+            if let Some(cmd) = self.ask(name, runid) {
+                match call(&mut connection, cmd.into_frame(), period).await? {
+                    Frame::Array(reply) => self.record_answer(name, runid, &reply),
+                    frame => return Err(frame.to_error()),
+                }
+            }
+        }
+    }
+
+    /// Periods of the pings and of `INFO` for the instances of `name`.
+    fn periods(&self, name: &str) -> Option<(Duration, Duration)> {
+        self.with_master(name, |monitored| {
+            let config = &monitored.config;
+            let info =
+                if monitored.failover.is_some() || monitored.master.is_down(config.down_after) {
+                    FAST_INFO_PERIOD
+                } else {
+                    INFO_PERIOD
+                };
+            (PING_PERIOD.min(config.down_after), info)
+        })
+    }
+
+    /// Records the state an instance reported. The replicas of a master are
+    /// monitored as well.
+    fn record_report(&self, name: &str, addr: &Addr, report: Report) {
+        let replicas = report.replicas.clone();
+        let is_master = self
+            .with_instance(name, addr, |instance, is_master| {
+                let role_changed = instance
+                    .report
+                    .as_ref()
+                    .is_none_or(|previous| previous.role != report.role);
+                if role_changed {
+                    instance.role_since = Instant::now();
+                }
+                instance.report = Some(report);
+                is_master
+            })
+            .unwrap_or(false);
+        if !is_master {
+            return;
+        }
+        let mut state = self.lock();
+        let Some(monitored) = state.masters.get_mut(name) else {
+            return;
+        };
+        for replica in replicas {
+            if replica != monitored.addr && !monitored.replicas.contains_key(&replica) {
+                info!(%name, addr = ?replica, "found replica");
+                let instance = self.spawn_instance(name, replica.clone());
+                monitored.replicas.insert(replica, instance);
+            }
+        }
+    }
+
+    /// The hello message announcing this sentinel and its configuration of
+    /// the master `name`, as
+    /// `ip,port,runid,current_epoch,name,master_ip,master_port,config_epoch`.
+    fn hello(&self, name: &str, local_ip: IpAddr) -> Option<String> {
+        let state = self.lock();
+        let monitored = state.masters.get(name)?;
+        let ip = match &self.shared.announce_ip {
+            Some(ip) => ip.clone(),
+            None => local_ip.to_string(),
+        };
+        Some(format!(
+            "{},{},{},{},{},{},{},{}",
+            ip,
+            self.shared.port,
+            self.shared.myid,
+            state.current_epoch,
+            name,
+            monitored.addr.0,
+            monitored.addr.1,
+            monitored.config_epoch
+        ))
+    }
+
+    /// Records the sentinel announced by a hello message, and adopts its
+    /// configuration of the master if it is newer.
+    fn hello_received(&self, hello: &str) {
+        let fields: Vec<&str> = hello.split(',').collect();
+        let [ip, port, runid, epoch, name, master_ip, master_port, config_epoch] = fields[..]
+        else {
+            return;
+        };
+        let (Ok(port), Ok(epoch), Ok(master_port), Ok(config_epoch)) = (
+            port.parse::<u16>(),
+            epoch.parse::<u64>(),
+            master_port.parse::<u16>(),
+            config_epoch.parse::<u64>(),
+        ) else {
+            return;
+        };
+        if runid == self.shared.myid {
+            return;
+        }
+
+        let mut state = self.lock();
+        state.current_epoch = state.current_epoch.max(epoch);
+        let Some(monitored) = state.masters.get_mut(name) else {
+            return;
+        };
+        let addr = (ip.to_string(), port);
+        // A sentinel restarted at the same address has a new ID.
+        monitored.sentinels.retain(|id, peer| {
+            let stale = id != runid && peer.addr == addr;
+            if stale {
+                peer.link.cancel();
+            }
+            !stale
+        });
+        match monitored.sentinels.get_mut(runid) {
+            Some(peer) if peer.addr == addr => peer.last_hello = Instant::now(),
+            _ => {
+                info!(%name, runid, ?addr, "found sentinel");
+                if let Some(previous) = monitored.sentinels.remove(runid) {
+                    previous.link.cancel();
+                }
+                let link = self.shared.stopped.child_token();
+                tokio::spawn(self.clone().peer_link(
+                    name.to_string(),
+                    runid.to_string(),
+                    addr.clone(),
+                    link.clone(),
+                ));
+                let now = Instant::now();
+                let peer = Peer {
+                    addr,
+                    link,
+                    created: now,
+                    last_pong: None,
+                    last_hello: now,
+                    master_down: None,
+                    vote: None,
+                    asked: None,
+                };
+                monitored.sentinels.insert(runid.to_string(), peer);
+            }
+        }
+
+        let master = (master_ip.to_string(), master_port);
+        if config_epoch > monitored.config_epoch {
+            if master == monitored.addr {
+                monitored.config_epoch = config_epoch;
+            } else {
+                info!(%name, runid, config_epoch, "configuration updated by sentinel");
+                self.switch(monitored, master, config_epoch);
+            }
+        }
+    }
+
+    /// Records the answer of another sentinel to `SENTINEL
+    /// IS-MASTER-DOWN-BY-ADDR`: whether it sees the master down, and its
+    /// vote.
+    fn record_answer(&self, name: &str, runid: &str, reply: &[Frame]) {
+        let [Frame::Integer(down), leader, Frame::Integer(epoch)] = reply else {
+            return;
+        };
+        let leader = match leader {
+            Frame::Bulk(leader) => String::from_utf8_lossy(leader).into_owned(),
+            Frame::Simple(leader) => leader.clone(),
+            _ => return,
+        };
+        self.with_peer(name, runid, |peer| {
+            peer.master_down = Some((*down == 1, Instant::now()));
+            if leader != "*" {
+                peer.vote = Some((leader, *epoch));
+            }
+        });
+    }
+
+    fn with_master<T>(&self, name: &str, f: impl FnOnce(&mut Monitored) -> T) -> Option<T> {
+        self.lock().masters.get_mut(name).map(f)
+    }
+
+    /// Runs `f` on the instance at `addr`, also telling whether it is the
+    /// master.
+    fn with_instance<T>(
+        &self,
+        name: &str,
+        addr: &Addr,
+        f: impl FnOnce(&mut Instance, bool) -> T,
+    ) -> Option<T> {
+        self.with_master(name, |monitored| {
+            if monitored.addr == *addr {
+                Some(f(&mut monitored.master, true))
+            } else {
+                monitored
+                    .replicas
+                    .get_mut(addr)
+                    .map(|replica| f(replica, false))
+            }
+        })
+        .flatten()
+    }
+
+    fn with_peer<T>(&self, name: &str, runid: &str, f: impl FnOnce(&mut Peer) -> T) -> Option<T> {
+        let mut state = self.lock();
+        let peer = state.masters.get_mut(name)?.sentinels.get_mut(runid)?;
+        Some(f(peer))
+    }
+
+    /// The `SENTINEL IS-MASTER-DOWN-BY-ADDR` to send to the sentinel `runid`
+    /// while the master is down, asking for its vote during an election.
+    /// Sent again after `ASK_PERIOD`, or as soon as the question changes.
+    fn ask(&self, name: &str, runid: &str) -> Option<SentinelCmd> {
+        let mut state = self.lock();
+        let epoch = state.current_epoch;
+        let monitored = state.masters.get_mut(name)?;
+        if !monitored.master.is_down(monitored.config.down_after) {
+            return None;
+        }
+        let candidate = match &monitored.failover {
+            Some(failover) if matches!(failover.step, Step::Election) => self.myid(),
+            _ => "*",
+        };
+        let (ip, port) = monitored.addr.clone();
+        let peer = monitored.sentinels.get_mut(runid)?;
+        let due = peer.asked.as_ref().is_none_or(|(at, asked_epoch, asked)| {
+            at.elapsed() >= ASK_PERIOD || *asked_epoch != epoch || asked != candidate
+        });
+        if !due {
+            return None;
+        }
+        peer.asked = Some((Instant::now(), epoch, candidate.to_string()));
+        Some(SentinelCmd::is_master_down_by_addr(
+            ip, port, epoch, candidate,
+        ))
+    }
+}
+
+fn is_due(last: Option<Instant>, period: Duration) -> bool {
+    last.is_none_or(|at| at.elapsed() >= period)
+}
+
+/// Connects to `addr`, authenticating if a password is given. Returns the
+/// connection and its local address.
+async fn connect(
+    addr: &Addr,
+    user: Option<&str>,
+    pass: Option<&str>,
+) -> crate::Result<(Connection, IpAddr)> {
+    let socket = time::timeout(COMMAND_TIMEOUT, TcpStream::connect((&addr.0[..], addr.1)))
+        .await
+        .map_err(|_| "timed out connecting")??;
+    let local_ip = socket.local_addr()?.ip();
+    let mut connection = Connection::new(socket);
+    if let Some(pass) = pass {
+        let frame = crate::cmd::Auth::new(user, pass).into_frame();
+        match call(&mut connection, frame, COMMAND_TIMEOUT).await? {
+            Frame::Simple(_) => {}
+            frame => return Err(frame.to_error()),
+        }
+    }
+    Ok((connection, local_ip))
+}
+
+/// Sends a single command to the instance at `addr` on a new connection.
+pub(super) async fn command(
+    addr: &Addr,
+    user: Option<&str>,
+    pass: Option<&str>,
+    frame: Frame,
+) -> crate::Result<()> {
+    let (mut connection, _) = connect(addr, user, pass).await?;
+    match call(&mut connection, frame, COMMAND_TIMEOUT).await? {
+        Frame::Error(err) => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn call(
+    connection: &mut Connection,
+    frame: Frame,
+    timeout: Duration,
+) -> crate::Result<Frame> {
+    connection.write_frame(&frame).await?;
+    match time::timeout(timeout, connection.read_frame()).await {
+        Ok(res) => res?.ok_or_else(|| "connection closed by peer".into()),
+        Err(_) => Err("timed out waiting for a reply".into()),
+    }
+}
+
+/// Reads the replication section of `INFO`.
+fn parse_info(info: &str) -> Report {
+    let field = |name: &str| {
+        info.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+    };
+    let number = |name: &str| field(name).and_then(|value| value.parse().ok());
+    let role = match field("role") {
+        Some("slave") => Role::Replica {
+            master: (
+                field("master_host").unwrap_or_default().to_string(),
+                number("master_port").unwrap_or_default() as u16,
+            ),
+            link_up: field("master_link_status") == Some("up"),
+        },
+        _ => Role::Master,
+    };
+    let offset = match role {
+        Role::Master => number("master_repl_offset"),
+        Role::Replica { .. } => number("slave_repl_offset"),
+    };
+    // Replicas are listed as `slave0:ip=...,port=...,state=...`.
+    let replicas = info
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let index = name.strip_prefix("slave")?;
+            if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let mut ip = None;
+            let mut port = None;
+            for pair in value.split(',') {
+                match pair.split_once('=') {
+                    Some(("ip", value)) => ip = Some(value.to_string()),
+                    Some(("port", value)) => port = value.parse().ok(),
+                    _ => {}
+                }
+            }
+            Some((ip?, port?))
+        })
+        .collect();
+    Report {
+        at: Instant::now(),
+        role,
+        offset: offset.unwrap_or_default(),
+        replicas,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_replication_info() {
+        let report = parse_info(
+            "# Replication\r\n\
+             role:master\r\n\
+             connected_slaves:2\r\n\
+             slave0:ip=127.0.0.1,port=6380,state=online,offset=42,lag=0\r\n\
+             slave1:ip=127.0.0.1,port=6381,state=online,offset=40,lag=1\r\n\
+             master_repl_offset:42\r\n",
+        );
+        assert_eq!(report.role, Role::Master);
+        assert_eq!(report.offset, 42);
+        assert_eq!(
+            report.replicas,
+            [
+                ("127.0.0.1".to_string(), 6380),
+                ("127.0.0.1".to_string(), 6381)
+            ]
+        );
+
+        let report = parse_info(
+            "role:slave\r\n\
+             master_host:127.0.0.1\r\n\
+             master_port:6379\r\n\
+             master_link_status:up\r\n\
+             slave_repl_offset:40\r\n\
+             slave_read_only:1\r\n",
+        );
+        let master = ("127.0.0.1".to_string(), 6379);
+        assert_eq!(
+            report.role,
+            Role::Replica {
+                master,
+                link_up: true
+            }
+        );
+        assert_eq!(report.offset, 40);
+        assert!(report.replicas.is_empty());
+    }
+}
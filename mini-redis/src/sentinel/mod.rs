@@ -0,0 +1,669 @@
+//! Sentinel mode: monitors masters and their replicas, and fails a master
+//! over to one of its replicas when it is down.
+//!
+//! A sentinel pings every instance it monitors every second, and asks them
+//! for their role and replication offset with `INFO`. The replicas of a
+//! master are found in its `INFO`. A master not replying for
+//! `down-after-milliseconds` is subjectively down.
+//!
+//! Sentinels monitoring the same master find each other through the
+//! `__sentinel__:hello` channel of the instances. Every two seconds, each
+//! publishes its address and ID, along with the address of the master and
+//! the epoch of that configuration. Once a master is subjectively down, a
+//! sentinel asks the others whether they agree with `SENTINEL
+//! IS-MASTER-DOWN-BY-ADDR`. When `quorum` sentinels do, the master is
+//! objectively down and a failover starts.
+//!
+//! A failover needs a leader. The sentinel starting it increments its
+//! current epoch and asks the others for their vote in that epoch. Each
+//! sentinel votes once per epoch, for the first sentinel asking. Once
+//! elected by a majority of the sentinels and by at least `quorum` of them,
+//! the leader promotes the replica with the largest replication offset with
+//! `REPLICAOF NO ONE`, and points the other replicas to it. Its hello
+//! messages then carry the new address of the master with the epoch of the
+//! failover, which the other sentinels adopt as it is higher than theirs.
+//!
+//! Instances reporting a role the configuration disagrees with for a while,
+//! such as a former master back online, are made replicas of the current
+//! master.
+//!
+//! Unlike Redis, a sentinel doesn't write the state it learned back to its
+//! configuration file: when restarted, it starts over from the masters in
+//! the file.
+
+mod config;
+pub use config::{MasterConfig, SentinelConfig, DEFAULT_SENTINEL_PORT};
+
+mod link;
+mod monitor;
+
+use crate::cmd::{error_reply, Unknown};
+use crate::eviction::Rng;
+use crate::listener::Listener;
+use crate::replication::random_id;
+use crate::{Command, Connection, Frame};
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Channel of the instances the sentinels announce themselves on.
+const HELLO_CHANNEL: &str = "__sentinel__:hello";
+
+/// How often instances and other sentinels are pinged, unless
+/// `down-after-milliseconds` is shorter.
+const PING_PERIOD: Duration = Duration::from_secs(1);
+
+/// How often instances are asked for `INFO`.
+const INFO_PERIOD: Duration = Duration::from_secs(10);
+
+/// How often instances are asked for `INFO` while their master is down or
+/// failed over, to notice promotions quickly.
+const FAST_INFO_PERIOD: Duration = Duration::from_secs(1);
+
+/// How often a sentinel announces itself on `HELLO_CHANNEL`.
+const HELLO_PERIOD: Duration = Duration::from_secs(2);
+
+/// How often the other sentinels are asked whether a master is down, and
+/// how long their replies are trusted.
+const ASK_PERIOD: Duration = Duration::from_secs(1);
+const REPLY_VALIDITY: Duration = Duration::from_secs(5);
+
+/// Time given to a sentinel to be elected leader, unless the failover
+/// timeout is shorter.
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest random delay before a failover starts, so that sentinels seeing
+/// the master down at once don't all ask for votes in the same epoch.
+const MAX_DESYNC_MS: u64 = 1000;
+
+/// How long an instance may report a role disagreeing with the
+/// configuration before it is reconfigured. Leaves time for a new
+/// configuration to spread through the hello messages.
+const RECONFIGURE_DELAY: Duration = Duration::from_secs(8);
+
+/// An address as a host and a port.
+type Addr = (String, u16);
+
+/// Runs a sentinel monitoring the masters of `config`, answering the
+/// clients connecting to `listener`, until `shutdown` completes.
+pub async fn run<L: Listener>(mut listener: L, config: SentinelConfig, shutdown: impl Future) {
+    let sentinel = Sentinel::start(config);
+    tokio::select! {
+        res = sentinel.serve(&mut listener) => {
+            if let Err(err) = res {
+                error!(case = %err, "failed to accept");
+            }
+        }
+        _ = shutdown => {
+            info!("shutting down");
+        }
+    }
+    sentinel.shared.stopped.cancel();
+}
+
+/// State of a sentinel, shared by its connections and its monitoring
+/// tasks. Cloning is shallow.
+#[derive(Clone, Debug)]
+pub(crate) struct Sentinel {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// ID of the sentinel, which the other sentinels know it and vote for it
+    /// by.
+    myid: String,
+    /// Port announced to the other sentinels.
+    port: u16,
+    announce_ip: Option<String>,
+    state: Mutex<State>,
+    /// Cancelled when the sentinel stops, which stops every task.
+    stopped: CancellationToken,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Highest epoch seen. Failovers start a new one.
+    current_epoch: u64,
+    /// Monitored masters, by name.
+    masters: BTreeMap<String, Monitored>,
+    rng: Rng,
+}
+
+/// A monitored master, its replicas and the other sentinels monitoring it.
+#[derive(Debug)]
+struct Monitored {
+    config: MasterConfig,
+    /// Current address of the master.
+    addr: Addr,
+    /// Epoch of the failover that made `addr` the master, 0 for the
+    /// configured one.
+    config_epoch: u64,
+    master: Instance,
+    replicas: BTreeMap<Addr, Instance>,
+    /// Other sentinels, by ID.
+    sentinels: BTreeMap<String, Peer>,
+    /// Sentinel voted for as the leader of a failover, and the epoch of the
+    /// vote.
+    leader: Option<String>,
+    leader_epoch: u64,
+    /// When a failover starts, set once the master is objectively down.
+    start_at: Option<Instant>,
+    failover: Option<Failover>,
+    /// When the last failover started, or a vote was given to another
+    /// sentinel. No failover starts within twice the failover timeout.
+    last_failover: Option<Instant>,
+}
+
+/// A master or a replica.
+#[derive(Debug)]
+struct Instance {
+    /// Stops the task linked to the instance.
+    link: CancellationToken,
+    created: Instant,
+    /// Last valid reply to `PING`.
+    last_pong: Option<Instant>,
+    report: Option<Report>,
+    /// When the instance started reporting its current role.
+    role_since: Instant,
+    /// Last time the instance was made a replica of the master.
+    reconfigured_at: Option<Instant>,
+}
+
+/// What an instance reports in `INFO`.
+#[derive(Debug)]
+struct Report {
+    at: Instant,
+    role: Role,
+    offset: u64,
+    /// Replicas of a master.
+    replicas: Vec<Addr>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Role {
+    Master,
+    Replica { master: Addr, link_up: bool },
+}
+
+/// Another sentinel.
+#[derive(Debug)]
+struct Peer {
+    addr: Addr,
+    link: CancellationToken,
+    created: Instant,
+    last_pong: Option<Instant>,
+    last_hello: Instant,
+    /// Last reply on whether the master is down, and when it came.
+    master_down: Option<(bool, Instant)>,
+    /// Last vote reported by the sentinel, and its epoch.
+    vote: Option<(String, u64)>,
+    /// Last time the sentinel was asked whether the master is down, and the
+    /// epoch and candidate asked for.
+    asked: Option<(Instant, u64, String)>,
+}
+
+#[derive(Debug)]
+struct Failover {
+    epoch: u64,
+    /// Started with `SENTINEL FAILOVER`, which needs no election.
+    forced: bool,
+    started: Instant,
+    step: Step,
+}
+
+#[derive(Debug)]
+enum Step {
+    /// Waiting for the votes of the other sentinels.
+    Election,
+    /// `REPLICAOF NO ONE` was sent to the replica at `addr`, waiting for it
+    /// to report being a master.
+    Promotion { addr: Addr, sent: Instant },
+}
+
+/// What `SENTINEL MASTER` reports.
+#[derive(Debug)]
+pub(crate) struct MasterReport {
+    pub(crate) name: String,
+    pub(crate) ip: String,
+    pub(crate) port: u16,
+    pub(crate) flags: Vec<&'static str>,
+    pub(crate) replicas: usize,
+    pub(crate) sentinels: usize,
+    pub(crate) quorum: usize,
+    pub(crate) config_epoch: u64,
+    pub(crate) down_after: Duration,
+    pub(crate) failover_timeout: Duration,
+}
+
+/// What `SENTINEL REPLICAS` reports.
+#[derive(Debug)]
+pub(crate) struct ReplicaReport {
+    pub(crate) ip: String,
+    pub(crate) port: u16,
+    pub(crate) flags: Vec<&'static str>,
+    /// Master the replica follows, and whether the link to it is up. `None`
+    /// until the replica reported it, or if it reports being a master.
+    pub(crate) master: Option<(Addr, bool)>,
+    pub(crate) offset: u64,
+}
+
+/// What `SENTINEL SENTINELS` reports.
+#[derive(Debug)]
+pub(crate) struct PeerReport {
+    pub(crate) runid: String,
+    pub(crate) ip: String,
+    pub(crate) port: u16,
+    pub(crate) flags: Vec<&'static str>,
+    /// Time since the last hello message of the sentinel.
+    pub(crate) last_hello: Duration,
+    pub(crate) vote: Option<(String, u64)>,
+}
+
+impl Sentinel {
+    /// Starts monitoring the masters of `config`.
+    fn start(config: SentinelConfig) -> Sentinel {
+        let mut rng = Rng::new();
+        let sentinel = Sentinel {
+            shared: Arc::new(Shared {
+                myid: random_id(&mut rng),
+                port: config.port,
+                announce_ip: config.announce_ip,
+                state: Mutex::new(State {
+                    current_epoch: 0,
+                    masters: BTreeMap::new(),
+                    rng,
+                }),
+                stopped: CancellationToken::new(),
+            }),
+        };
+        for master in config.masters {
+            info!(name = %master.name, host = %master.host, port = master.port, "monitoring master");
+            let name = master.name.clone();
+            let addr = (master.host.clone(), master.port);
+            let monitored = Monitored {
+                config: master,
+                master: sentinel.spawn_instance(&name, addr.clone()),
+                addr,
+                config_epoch: 0,
+                replicas: BTreeMap::new(),
+                sentinels: BTreeMap::new(),
+                leader: None,
+                leader_epoch: 0,
+                start_at: None,
+                failover: None,
+                last_failover: None,
+            };
+            sentinel.lock().masters.insert(name.clone(), monitored);
+            tokio::spawn(sentinel.clone().monitor(name));
+        }
+        sentinel
+    }
+
+    /// Accepts connections until the listener fails.
+    async fn serve<L: Listener>(&self, listener: &mut L) -> crate::Result<()> {
+        info!(id = %self.shared.myid, "sentinel accepting inbound connections");
+        loop {
+            let (socket, addr) = listener.accept().await?;
+            debug!(%addr, "accepted connection");
+            let sentinel = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = sentinel.handle(Connection::new(socket)).await {
+                    debug!(case = %err, "connection err");
+                }
+            });
+        }
+    }
+
+    /// Answers the commands of a client. Only the commands making sense for
+    /// a sentinel are served.
+    async fn handle(&self, mut connection: Connection) -> crate::Result<()> {
+        loop {
+            let frame = tokio::select! {
+                res = connection.read_frame() => match res? {
+                    Some(frame) => frame,
+                    None => return Ok(()),
+                },
+                _ = self.shared.stopped.cancelled() => return Ok(()),
+            };
+            let cmd = match Command::from_frame(frame) {
+                Ok(cmd) => cmd,
+                Err(err) => {
+                    connection.write_frame(&error_reply(&err)).await?;
+                    continue;
+                }
+            };
+            let frame = match cmd {
+                Command::Ping(cmd) => Frame::Bulk(cmd.get_msg()),
+                Command::Role(_) => {
+                    let mut names = Frame::array();
+                    for name in self.lock().masters.keys() {
+                        names.push_bulk(Bytes::from(name.clone().into_bytes()));
+                    }
+                    let mut frame = Frame::array();
+                    frame.push_bulk(Bytes::from("sentinel".as_bytes()));
+                    frame.push_frame(names);
+                    frame
+                }
+                Command::Sentinel(cmd) => {
+                    cmd.apply(self, &mut connection).await?;
+                    continue;
+                }
+                cmd => {
+                    Unknown::new(cmd.get_name()).apply(&mut connection).await?;
+                    continue;
+                }
+            };
+            debug!(?frame);
+            connection.write_frame(&frame).await?;
+        }
+    }
+
+    pub(crate) fn myid(&self) -> &str {
+        &self.shared.myid
+    }
+
+    /// Current address of the master `name`.
+    pub(crate) fn master_addr(&self, name: &str) -> Option<(String, u16)> {
+        Some(self.lock().masters.get(name)?.addr.clone())
+    }
+
+    pub(crate) fn masters(&self) -> Vec<MasterReport> {
+        let state = self.lock();
+        state.masters.values().map(Monitored::report).collect()
+    }
+
+    pub(crate) fn master(&self, name: &str) -> Option<MasterReport> {
+        Some(self.lock().masters.get(name)?.report())
+    }
+
+    pub(crate) fn replicas(&self, name: &str) -> Option<Vec<ReplicaReport>> {
+        let state = self.lock();
+        let monitored = state.masters.get(name)?;
+        let down_after = monitored.config.down_after;
+        let replicas = monitored
+            .replicas
+            .iter()
+            .map(|((ip, port), replica)| {
+                let mut flags = vec!["slave"];
+                if replica.is_down(down_after) {
+                    flags.push("s_down");
+                }
+                let report = replica.report.as_ref();
+                ReplicaReport {
+                    ip: ip.clone(),
+                    port: *port,
+                    flags,
+                    master: report.and_then(|report| match &report.role {
+                        Role::Replica { master, link_up } => Some((master.clone(), *link_up)),
+                        Role::Master => None,
+                    }),
+                    offset: report.map_or(0, |report| report.offset),
+                }
+            })
+            .collect();
+        Some(replicas)
+    }
+
+    pub(crate) fn sentinels(&self, name: &str) -> Option<Vec<PeerReport>> {
+        let state = self.lock();
+        let monitored = state.masters.get(name)?;
+        let down_after = monitored.config.down_after;
+        let peers = monitored
+            .sentinels
+            .iter()
+            .map(|(runid, peer)| {
+                let mut flags = vec!["sentinel"];
+                if is_down(peer.last_pong, peer.created, down_after) {
+                    flags.push("s_down");
+                }
+                PeerReport {
+                    runid: runid.clone(),
+                    ip: peer.addr.0.clone(),
+                    port: peer.addr.1,
+                    flags,
+                    last_hello: peer.last_hello.elapsed(),
+                    vote: peer.vote.clone(),
+                }
+            })
+            .collect();
+        Some(peers)
+    }
+
+    /// Answers another sentinel asking whether the master at `ip:port` is
+    /// down. Unless `runid` is `*`, the sentinel also asks for a vote in
+    /// `epoch` for `runid`. Returns whether the master is down, and the
+    /// leader voted for with the epoch of the vote.
+    pub(crate) fn is_master_down_by_addr(
+        &self,
+        ip: &str,
+        port: u16,
+        epoch: u64,
+        runid: &str,
+    ) -> (bool, Option<String>, u64) {
+        let mut state = self.lock();
+        let State {
+            current_epoch,
+            masters,
+            ..
+        } = &mut *state;
+        let Some(monitored) = masters
+            .values_mut()
+            .find(|monitored| monitored.addr.0 == ip && monitored.addr.1 == port)
+        else {
+            return (false, None, 0);
+        };
+        let down = monitored.master.is_down(monitored.config.down_after);
+        if runid != "*" {
+            if epoch > *current_epoch {
+                *current_epoch = epoch;
+            }
+            // A single vote per epoch, for the first sentinel asking.
+            if monitored.leader_epoch < epoch && *current_epoch <= epoch {
+                info!(name = %monitored.config.name, leader = runid, epoch, "voted for leader");
+                monitored.leader = Some(runid.to_string());
+                monitored.leader_epoch = *current_epoch;
+                if runid != self.shared.myid {
+                    // Leaves the failover to the sentinel voted for.
+                    monitored.last_failover = Some(Instant::now());
+                }
+            }
+        }
+        (down, monitored.leader.clone(), monitored.leader_epoch)
+    }
+
+    /// Starts a failover of the master `name` without asking the other
+    /// sentinels, as `SENTINEL FAILOVER` does.
+    pub(crate) fn failover(&self, name: &str) -> Result<(), String> {
+        let mut state = self.lock();
+        let State {
+            current_epoch,
+            masters,
+            ..
+        } = &mut *state;
+        let monitored = masters
+            .get_mut(name)
+            .ok_or("ERR No such master with that name")?;
+        if monitored.failover.is_some() {
+            return Err("INPROG Failover already in progress".to_string());
+        }
+        if monitored.select_replica().is_none() {
+            return Err("NOGOODSLAVE No suitable replica to promote".to_string());
+        }
+        *current_epoch += 1;
+        monitored.start_failover(&self.shared.myid, *current_epoch, true);
+        Ok(())
+    }
+
+    /// Starts the task linked to the master or replica at `addr`.
+    fn spawn_instance(&self, name: &str, addr: Addr) -> Instance {
+        let link = self.shared.stopped.child_token();
+        tokio::spawn(
+            self.clone()
+                .instance_link(name.to_string(), addr, link.clone()),
+        );
+        let now = Instant::now();
+        Instance {
+            link,
+            created: now,
+            last_pong: None,
+            report: None,
+            role_since: now,
+            reconfigured_at: None,
+        }
+    }
+
+    /// Makes `addr` the master, after a failover by this sentinel or
+    /// another one. The former master and its replicas become replicas of
+    /// `addr`.
+    fn switch(&self, monitored: &mut Monitored, addr: Addr, epoch: u64) {
+        let name = monitored.config.name.clone();
+        warn!(%name, from = ?monitored.addr, to = ?addr, epoch, "switched master");
+        let mut replicas: Vec<Addr> = monitored.replicas.keys().cloned().collect();
+        replicas.push(monitored.addr.clone());
+        replicas.retain(|replica| *replica != addr);
+
+        monitored.master.link.cancel();
+        for replica in monitored.replicas.values() {
+            replica.link.cancel();
+        }
+        monitored.master = self.spawn_instance(&name, addr.clone());
+        monitored.replicas = replicas
+            .into_iter()
+            .map(|replica| (replica.clone(), self.spawn_instance(&name, replica)))
+            .collect();
+        monitored.addr = addr;
+        monitored.config_epoch = epoch;
+        monitored.failover = None;
+        monitored.start_at = None;
+        for peer in monitored.sentinels.values_mut() {
+            peer.master_down = None;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
+    }
+}
+
+impl Monitored {
+    fn report(&self) -> MasterReport {
+        let mut flags = vec!["master"];
+        if self.master.is_down(self.config.down_after) {
+            flags.push("s_down");
+        }
+        if self.is_objectively_down() {
+            flags.push("o_down");
+        }
+        if self.failover.is_some() {
+            flags.push("failover_in_progress");
+        }
+        MasterReport {
+            name: self.config.name.clone(),
+            ip: self.addr.0.clone(),
+            port: self.addr.1,
+            flags,
+            replicas: self.replicas.len(),
+            sentinels: self.sentinels.len(),
+            quorum: self.config.quorum,
+            config_epoch: self.config_epoch,
+            down_after: self.config.down_after,
+            failover_timeout: self.config.failover_timeout,
+        }
+    }
+
+    /// Whether `quorum` sentinels, this one included, see the master down.
+    fn is_objectively_down(&self) -> bool {
+        if !self.master.is_down(self.config.down_after) {
+            return false;
+        }
+        let agreeing = self
+            .sentinels
+            .values()
+            .filter(|peer| {
+                peer.master_down
+                    .is_some_and(|(down, at)| down && at.elapsed() < REPLY_VALIDITY)
+            })
+            .count();
+        1 + agreeing >= self.config.quorum
+    }
+
+    /// Starts a failover in `epoch`, voting for this sentinel.
+    fn start_failover(&mut self, myid: &str, epoch: u64, forced: bool) {
+        warn!(name = %self.config.name, epoch, forced, "starting failover");
+        let now = Instant::now();
+        self.leader = Some(myid.to_string());
+        self.leader_epoch = epoch;
+        self.last_failover = Some(now);
+        self.start_at = None;
+        self.failover = Some(Failover {
+            epoch,
+            forced,
+            started: now,
+            step: Step::Election,
+        });
+    }
+
+    /// The sentinel elected leader in `epoch`, if any got the votes of a
+    /// majority of the sentinels and of at least `quorum` of them.
+    fn leader(&self, epoch: u64) -> Option<&str> {
+        let mut votes: BTreeMap<&str, usize> = BTreeMap::new();
+        let peers = self
+            .sentinels
+            .values()
+            .filter_map(|peer| peer.vote.as_ref());
+        for (leader, _) in peers.filter(|(_, vote_epoch)| *vote_epoch == epoch) {
+            *votes.entry(leader).or_default() += 1;
+        }
+        if let Some(leader) = self
+            .leader
+            .as_deref()
+            .filter(|_| self.leader_epoch == epoch)
+        {
+            *votes.entry(leader).or_default() += 1;
+        }
+        let voters = self.sentinels.len() + 1;
+        let (leader, count) = votes.into_iter().max_by_key(|(_, count)| *count)?;
+        (count > voters / 2 && count >= self.config.quorum).then_some(leader)
+    }
+
+    /// The replica to promote: among the replicas up, and that reported
+    /// their state recently, the one with the largest replication offset.
+    fn select_replica(&self) -> Option<Addr> {
+        let down_after = self.config.down_after;
+        let validity = if self.master.is_down(down_after) {
+            FAST_INFO_PERIOD * 5
+        } else {
+            INFO_PERIOD * 3
+        };
+        self.replicas
+            .iter()
+            .filter(|(_, replica)| !replica.is_down(down_after))
+            .filter_map(|(addr, replica)| {
+                let report = replica.report.as_ref()?;
+                let valid =
+                    report.at.elapsed() < validity && matches!(report.role, Role::Replica { .. });
+                valid.then_some((report.offset, addr))
+            })
+            // Ties go to the lowest address.
+            .max_by(|(a, a_addr), (b, b_addr)| a.cmp(b).then(b_addr.cmp(a_addr)))
+            .map(|(_, addr)| addr.clone())
+    }
+}
+
+impl Instance {
+    fn is_down(&self, down_after: Duration) -> bool {
+        is_down(self.last_pong, self.created, down_after)
+    }
+}
+
+/// Whether an instance or a sentinel gave no valid reply to `PING` within
+/// `down_after`, counting from when it became known.
+fn is_down(last_pong: Option<Instant>, created: Instant, down_after: Duration) -> bool {
+    last_pong.unwrap_or(created).elapsed() > down_after
+}
@@ -0,0 +1,178 @@
+//! The periodic check of a monitored master, driving its failovers.
+
+use super::link::command;
+use super::{
+    Addr, Failover, Monitored, Role, Sentinel, State, Step, ELECTION_TIMEOUT, MAX_DESYNC_MS,
+    RECONFIGURE_DELAY,
+};
+use crate::cmd::ReplicaOf;
+use crate::eviction::Rng;
+
+use tokio::time::{self, Duration, Instant};
+use tracing::{info, warn};
+
+/// How often the state of a master is checked.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Commands sent to instances, once the state is unlocked.
+#[derive(Debug)]
+enum Action {
+    /// Makes the replica at the address a master.
+    Promote(Addr),
+    /// Makes the instance at the first address a replica of the second.
+    Reconfigure(Addr, Addr),
+}
+
+impl Sentinel {
+    /// Checks the master `name` every `TICK` until the sentinel stops.
+    pub(super) async fn monitor(self, name: String) {
+        let mut tick = time::interval(TICK);
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = self.shared.stopped.cancelled() => return,
+            }
+            let (actions, auth) = {
+                let mut state = self.lock();
+                let State {
+                    current_epoch,
+                    masters,
+                    rng,
+                } = &mut *state;
+                let Some(monitored) = masters.get_mut(&name) else {
+                    return;
+                };
+                let actions = self.check(monitored, current_epoch, rng);
+                let config = &monitored.config;
+                (
+                    actions,
+                    (config.auth_user.clone(), config.auth_pass.clone()),
+                )
+            };
+            for action in actions {
+                let (addr, cmd) = match action {
+                    Action::Promote(addr) => {
+                        warn!(%name, ?addr, "promoting replica");
+                        (addr, ReplicaOf::new(None))
+                    }
+                    Action::Reconfigure(addr, master) => {
+                        info!(%name, ?addr, ?master, "reconfiguring instance as replica");
+                        (addr, ReplicaOf::new(Some(master)))
+                    }
+                };
+                let (user, pass) = (auth.0.as_deref(), auth.1.as_deref());
+                if let Err(err) = command(&addr, user, pass, cmd.into_frame()).await {
+                    warn!(case = %err, %name, ?addr, "failed to reconfigure instance");
+                }
+            }
+        }
+    }
+
+    /// Moves the failover of a master forward, or starts one if the master
+    /// is objectively down. `epoch` is the current epoch, incremented when a
+    /// failover starts. Returns the commands to send.
+    fn check(&self, monitored: &mut Monitored, epoch: &mut u64, rng: &mut Rng) -> Vec<Action> {
+        let mut actions = vec![];
+        let now = Instant::now();
+        let config = monitored.config.clone();
+        let down = monitored.master.is_down(config.down_after);
+
+        match &monitored.failover {
+            None if monitored.is_objectively_down() => {
+                let allowed = monitored
+                    .last_failover
+                    .is_none_or(|at| at.elapsed() > config.failover_timeout * 2);
+                match monitored.start_at {
+                    _ if !allowed => {}
+                    None => {
+                        warn!(name = %config.name, "master is objectively down");
+                        let delay = Duration::from_millis(rng.next() % MAX_DESYNC_MS);
+                        monitored.start_at = Some(now + delay);
+                    }
+                    Some(at) if now >= at => {
+                        *epoch += 1;
+                        monitored.start_failover(&self.shared.myid, *epoch, false);
+                    }
+                    Some(_) => {}
+                }
+            }
+            None => monitored.start_at = None,
+            Some(Failover {
+                epoch: failover_epoch,
+                forced,
+                started,
+                step: Step::Election,
+            }) => {
+                let elected = *forced || monitored.leader(*failover_epoch) == Some(self.myid());
+                if elected {
+                    match monitored.select_replica() {
+                        Some(addr) => {
+                            info!(name = %config.name, epoch = failover_epoch, "elected leader");
+                            actions.push(Action::Promote(addr.clone()));
+                            if let Some(failover) = &mut monitored.failover {
+                                failover.step = Step::Promotion { addr, sent: now };
+                            }
+                        }
+                        None => self.abort(monitored, "no suitable replica to promote"),
+                    }
+                } else if started.elapsed() > ELECTION_TIMEOUT.min(config.failover_timeout) {
+                    self.abort(monitored, "not elected leader");
+                }
+            }
+            Some(Failover {
+                epoch: failover_epoch,
+                started,
+                step: Step::Promotion { addr, sent },
+                ..
+            }) => {
+                let promoted = monitored.replicas.get(addr).is_some_and(|replica| {
+                    replica
+                        .report
+                        .as_ref()
+                        .is_some_and(|report| report.at > *sent && report.role == Role::Master)
+                });
+                if promoted {
+                    let (addr, failover_epoch) = (addr.clone(), *failover_epoch);
+                    for replica in monitored.replicas.keys().filter(|r| **r != addr) {
+                        actions.push(Action::Reconfigure(replica.clone(), addr.clone()));
+                    }
+                    // The former master is reconfigured once back online.
+                    self.switch(monitored, addr, failover_epoch);
+                } else if started.elapsed() > config.failover_timeout {
+                    self.abort(monitored, "timed out waiting for the promotion");
+                }
+            }
+        }
+
+        // Instances disagreeing with the configuration are only fixed while
+        // the master is up, as they may have been promoted by another
+        // sentinel that did not announce it yet.
+        if monitored.failover.is_none() && !down {
+            let master = monitored.addr.clone();
+            for (addr, replica) in &mut monitored.replicas {
+                let Some(report) = &replica.report else {
+                    continue;
+                };
+                let stray = match &report.role {
+                    Role::Master => true,
+                    Role::Replica { master: other, .. } => *other != master,
+                };
+                if stray
+                    && replica.role_since.elapsed() > RECONFIGURE_DELAY
+                    && replica
+                        .reconfigured_at
+                        .is_none_or(|at| at.elapsed() > RECONFIGURE_DELAY)
+                {
+                    replica.reconfigured_at = Some(now);
+                    actions.push(Action::Reconfigure(addr.clone(), master.clone()));
+                }
+            }
+        }
+        actions
+    }
+
+    fn abort(&self, monitored: &mut Monitored, reason: &str) {
+        warn!(name = %monitored.config.name, reason, "failover aborted");
+        monitored.failover = None;
+    }
+}
@@ -1,13 +1,30 @@
-use crate::shutdown;
-use crate::{db::DbDropGuard, Command, Connection, Db, Shutdown};
-use std::future::Future;
+use crate::acl::{AccessControl, Denial};
+use crate::client_list::{ClientHandle, ClientList};
+use crate::cmd::{error_reply, Unknown};
+use crate::config::{Backend, Engine, LiveConfig};
+use crate::cores::Cores;
+use crate::db::{DbDropGuard, DEFAULT_SHARDS};
+use crate::eviction::Eviction;
+use crate::frame::{self, Frame};
+use crate::listener::Listener;
+use crate::metrics::Exporter;
+use crate::replication::Replication;
+use crate::slowlog::{self, SlowLog};
+use crate::stats::Stats;
+use crate::storage::{lsm, LsmOptions, LsmStorage, MemoryStorage, Storage};
+use crate::{Command, Connection, Db, Shutdown};
+use std::future::{self, Future};
+use std::io;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use std::thread;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc, Semaphore};
-use tokio::time::{self, Duration};
+use tokio::time::{self, Duration, Instant};
 use tracing::{debug, error, info, instrument};
 
-const MAX_CONNECTIONS: usize = 250;
+pub use crate::config::{ServerConfig, DEFAULT_MAX_CLIENTS};
+
 const BACKOFF_MAX: u64 = 64;
 
 /// Used as part of the graceful shutdown process to wait for client
@@ -22,13 +39,121 @@ const BACKOFF_MAX: u64 = 64;
 /// complete, all clones of the `Sender` are also dropped. This results in
 /// `shutdown_complete_rx.recv()` completing with `None`. At this point, it
 /// is safe to exit the server process.
-pub async fn run(listener: TcpListener, shutdown: impl Future) {
+///
+/// Connections are accepted from any `Listener`, such as a `TcpListener` or a
+/// `UnixListener`.
+pub async fn run<L: Listener>(listener: L, shutdown: impl Future) {
+    run_with_config(listener, ServerConfig::default(), shutdown).await
+}
+
+/// Same as `run`, using the settings in `config`.
+pub async fn run_with_config<L: Listener>(
+    listener: L,
+    config: ServerConfig,
+    shutdown: impl Future,
+) {
+    match config.backend {
+        Backend::Memory => {
+            let memory = |_| Ok(Box::new(MemoryStorage::new()) as Box<dyn Storage>);
+            run_with_storage(listener, config, memory, shutdown).await
+        }
+        Backend::Lsm => {
+            let dir = config.lsm_dir.clone();
+            if let Err(err) = lsm::check_shards(&dir, shard_count(&config)) {
+                error!(case = %err, dir = %dir.display(), "failed to open the lsm storage");
+                return;
+            }
+            let options = LsmOptions::from(&config);
+            let open = move |index| {
+                let storage = LsmStorage::open(dir.join(format!("shard-{}", index)), options)?;
+                Ok(Box::new(storage) as Box<dyn Storage>)
+            };
+            run_with_storage(listener, config, open, shutdown).await
+        }
+    }
+}
+
+/// Same as `run_with_config`, keeping the keys in the storage `open_storage`
+/// returns for each shard of the keyspace, given the index of the shard.
+pub async fn run_with_storage<L: Listener>(
+    listener: L,
+    config: ServerConfig,
+    open_storage: impl FnMut(usize) -> crate::Result<Box<dyn Storage>>,
+    shutdown: impl Future,
+) {
+    let acl = match &config.aclfile {
+        Some(path) => match AccessControl::with_file(path) {
+            Ok(acl) => acl,
+            Err(err) => {
+                error!(case = %err, path = %path.display(), "failed to load ACL file");
+                return;
+            }
+        },
+        None => AccessControl::new(config.requirepass.as_deref()),
+    };
     let (notify_shutdown, _) = broadcast::channel(1);
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
+    let db_holder = match config.engine {
+        Engine::Shared => DbDropGuard::with_storage(shard_count(&config), open_storage),
+        Engine::ThreadPerCore => DbDropGuard::owned_by_cores(shard_count(&config), open_storage),
+    };
+    let db_holder = match db_holder {
+        Ok(db_holder) => db_holder,
+        Err(err) => {
+            error!(case = %err, "failed to open the storage");
+            return;
+        }
+    };
+    let cores = match config.engine {
+        Engine::Shared => None,
+        Engine::ThreadPerCore => match Cores::start(db_holder.db()) {
+            Ok(cores) => Some(cores),
+            Err(err) => {
+                error!(case = %err, "failed to start the cores");
+                return;
+            }
+        },
+    };
+    let config = LiveConfig::new(config);
+    let replication = Replication::start(
+        db_holder.db(),
+        config.clone(),
+        Shutdown::new(notify_shutdown.subscribe()),
+    );
+    let context = Context {
+        config,
+        acl,
+        clients: ClientList::new(),
+        stats: Arc::new(Stats::new()),
+        slowlog: SlowLog::default(),
+        replication,
+        cores,
+    };
+    let eviction = context.config.with(|config| Eviction::from(config));
+    db_holder.db().set_eviction(eviction);
+    db_holder
+        .db()
+        .set_lazy_free(context.config.with(|config| config.lazyfree));
+    if let Some(addr) = context.config.with(|config| config.metrics_addr.clone()) {
+        let metrics_listener = match TcpListener::bind(&addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(case = %err, %addr, "failed to bind metrics listener");
+                return;
+            }
+        };
+        info!(%addr, "serving metrics");
+        let exporter = Exporter {
+            db: db_holder.db(),
+            config: context.config.clone(),
+            stats: context.stats.clone(),
+        };
+        tokio::spawn(exporter.serve(metrics_listener, Shutdown::new(notify_shutdown.subscribe())));
+    }
     let mut server = Server::new(
         listener,
-        DbDropGuard::new(),
-        Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+        db_holder,
+        context,
         notify_shutdown,
         shutdown_complete_tx,
     );
@@ -53,27 +178,54 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
     let _ = shutdown_complete_rx.recv().await;
 }
 
+/// Number of shards the keyspace is split into.
+fn shard_count(config: &ServerConfig) -> usize {
+    match config.engine {
+        Engine::Shared => DEFAULT_SHARDS,
+        // Each core owns a shard.
+        Engine::ThreadPerCore => match config.cores {
+            0 => thread::available_parallelism().map_or(1, |count| count.get()),
+            count => count,
+        },
+    }
+}
+
+/// Server-wide state shared by every connection handler.
+#[derive(Clone, Debug)]
+struct Context {
+    config: LiveConfig,
+    acl: AccessControl,
+    clients: ClientList,
+    stats: Arc<Stats>,
+    slowlog: SlowLog,
+    replication: Replication,
+    /// Set when running the thread-per-core engine.
+    cores: Option<Cores>,
+}
+
 #[derive(Debug)]
-pub struct Server {
+pub struct Server<L> {
     db_holder: DbDropGuard,
-    listener: TcpListener,
+    listener: L,
     limit_connections: Arc<Semaphore>,
+    context: Context,
     notify_shutdown: broadcast::Sender<()>,
     shutdown_complete_tx: mpsc::Sender<()>,
 }
 
-impl Server {
+impl<L: Listener> Server<L> {
     fn new(
-        listener: TcpListener,
+        listener: L,
         db_holder: DbDropGuard,
-        limit_connections: Arc<Semaphore>,
+        context: Context,
         notify_shutdown: broadcast::Sender<()>,
         shutdown_complete_tx: mpsc::Sender<()>,
-    ) -> Server {
+    ) -> Server<L> {
         Server {
             db_holder,
             listener,
-            limit_connections,
+            limit_connections: context.config.limit_connections(),
+            context,
             notify_shutdown,
             shutdown_complete_tx,
         }
@@ -82,33 +234,75 @@ impl Server {
     async fn run(&mut self) -> crate::Result<()> {
         info!("accpting inbound connections");
         loop {
-            let permit = self
-                .limit_connections
-                .clone()
-                .acquire_owned()
-                .await
-                .unwrap();
-            let socket = self.accept().await?;
-            let mut handler = Handler::new(
-                self.db_holder.db(),
-                socket,
-                Shutdown::new(self.notify_shutdown.subscribe()),
-                self.shutdown_complete_tx.clone(),
-            );
-            tokio::spawn(async move {
-                if let Err(err) = handler.run().await {
-                    error!(case = ?err, "connection err");
+            let (socket, addr) = self.accept().await?;
+            let stats = self.context.stats.clone();
+            stats.connection_received();
+            let permit = match self.limit_connections.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    stats.connection_rejected();
+                    tokio::spawn(async move {
+                        let mut connection = Connection::new(socket);
+                        let frame = Frame::Error("ERR max number of clients reached".to_string());
+                        let _ = connection.write_frame(&frame).await;
+                    });
+                    continue;
+                }
+            };
+            stats.client_connected();
+            let db = self.db_holder.db();
+            let context = self.context.clone();
+            let shutdown = Shutdown::new(self.notify_shutdown.subscribe());
+            let shutdown_complete = self.shutdown_complete_tx.clone();
+            let core = context.cores.as_ref().map_or(0, Cores::next_core);
+            let reregister: fn(L::Stream) -> io::Result<L::Stream> = match &context.cores {
+                Some(_) => L::reregister,
+                None => Ok,
+            };
+            let task = async move {
+                // With the thread-per-core engine, the socket is polled by the
+                // core serving the connection.
+                match reregister(socket) {
+                    Ok(socket) => {
+                        let mut handler = Handler::new(
+                            db,
+                            socket,
+                            addr,
+                            core,
+                            context,
+                            shutdown,
+                            shutdown_complete,
+                        );
+                        if let Err(err) = handler.run().await {
+                            error!(case = ?err, "connection err");
+                        }
+                    }
+                    Err(err) => error!(case = %err, "failed to move the connection to its core"),
                 }
+                stats.client_disconnected();
                 drop(permit);
-            });
+            };
+            match &self.context.cores {
+                Some(cores) => cores.spawn(core, task),
+                None => {
+                    tokio::spawn(task);
+                }
+            }
         }
     }
 
-    async fn accept(&mut self) -> crate::Result<TcpStream> {
+    async fn accept(&mut self) -> crate::Result<(L::Stream, String)> {
         let mut backoff = 1;
         loop {
             match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
+                Ok((socket, addr)) => {
+                    debug!(%addr, "accepted connection");
+                    let keepalive = self.context.config.with(|config| config.tcp_keepalive);
+                    if let Err(err) = L::set_keepalive(&socket, keepalive) {
+                        debug!(case = %err, %addr, "failed to set keepalive");
+                    }
+                    return Ok((socket, addr));
+                }
                 Err(err) => {
                     if backoff > BACKOFF_MAX {
                         return Err(err.into());
@@ -124,43 +318,411 @@ impl Server {
 struct Handler {
     db: Db,
     connection: Connection,
+    // Address of the peer, reported in the ACL log and the slow log.
+    addr: String,
+    // Core running the handler, with the thread-per-core engine.
+    core: usize,
+    context: Context,
+    // The user the connection runs as. Set once the client passed `AUTH`, or
+    // from the start when the default user needs no password.
+    user: Option<String>,
+    // Entry in the registry of connections, removed when the handler is
+    // dropped.
+    client: ClientHandle,
+    // Port a replica listens on, announced with `REPLCONF`.
+    listening_port: Option<u16>,
+    // End of the replication stream after the last write of the connection,
+    // which `WAIT` waits for.
+    written: u64,
     shutdown: Shutdown,
     _shutdown_complete: mpsc::Sender<()>,
 }
 
 impl Handler {
-    fn new(
+    fn new<S>(
         db: Db,
-        socket: TcpStream,
+        socket: S,
+        addr: String,
+        core: usize,
+        context: Context,
         shutdown: Shutdown,
         shutdown_complete: mpsc::Sender<()>,
-    ) -> Handler {
+    ) -> Handler
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut connection = Connection::new(socket);
+        connection.set_limits(context.config.with(|config| config.limits));
+        let user = context.acl.default_login();
+        let client = context.clients.register(&addr, user.clone());
         Handler {
-            db: db,
-            connection: Connection::new(socket),
-            shutdown: shutdown,
+            db,
+            connection,
+            addr,
+            core,
+            user,
+            client,
+            listening_port: None,
+            written: 0,
+            context,
+            shutdown,
             _shutdown_complete: shutdown_complete,
         }
     }
 
     #[instrument(skip(self))]
     async fn run(&mut self) -> crate::Result<()> {
+        let killed = self.client.kill_token();
         while !self.shutdown.is_shutdown() {
+            // Subscribers wait in `apply`, so they are never timed out.
+            let timeout = self.context.config.with(|config| config.timeout);
+            let idle = async {
+                match timeout {
+                    Some(timeout) => time::sleep(timeout).await,
+                    None => future::pending().await,
+                }
+            };
             let option_frame = tokio::select! {
-                res = self.connection.read_frame() => res?,
+                res = self.connection.read_frame() => match res {
+                    Ok(frame) => frame,
+                    Err(err) => return self.reject(err).await,
+                },
                 _ = self.shutdown.recv() => {
                     return Ok(());
                 }
+                _ = killed.cancelled() => {
+                    return Ok(());
+                }
+                _ = idle => {
+                    debug!(addr = %self.addr, "closing idle connection");
+                    return Ok(());
+                }
             };
             let frame = match option_frame {
                 Some(frame) => frame,
                 None => return Ok(()),
             };
-            let cmd = Command::from_frame(frame)?;
+            let (threshold, max_len) = self
+                .context
+                .config
+                .with(|config| (config.slowlog_log_slower_than, config.slowlog_max_len));
+            // Only copied when the slow log is enabled, as the frame is
+            // consumed by the command.
+            let args = (threshold >= 0).then(|| slowlog::command_args(&frame));
+
+            let cmd = match Command::from_frame(frame) {
+                Ok(cmd) => cmd,
+                // As in Redis, a malformed command is refused and the
+                // connection stays open.
+                Err(err) => {
+                    self.connection.write_frame(&error_reply(&err)).await?;
+                    continue;
+                }
+            };
             debug!(?cmd);
-            cmd.apply(&self.db, &mut self.connection, &mut self.shutdown)
-                .await?;
+            self.context
+                .clients
+                .interaction(self.client.id(), cmd.get_name());
+            tokio::select! {
+                _ = self.context.clients.wait_unpaused(&cmd) => {}
+                _ = self.shutdown.recv() => {
+                    return Ok(());
+                }
+                _ = killed.cancelled() => {
+                    return Ok(());
+                }
+            }
+            // A subscriber stays in `apply` until it leaves the subscribe
+            // mode, which says nothing about the server being slow.
+            let subscribe = matches!(cmd, Command::Subscribe(_));
+            // Unknown commands are not tracked by name, so that clients
+            // can't grow the per-command statistics without bound.
+            let name = match cmd {
+                Command::Unknown(_) => None,
+                _ => Some(cmd.get_name().to_string()),
+            };
+            self.connection.take_write_time();
+            let start = Instant::now();
+            tokio::select! {
+                res = self.apply(cmd) => res?,
+                // The command may be cut short, the connection is closed
+                // anyway.
+                _ = killed.cancelled() => {
+                    return Ok(());
+                }
+            }
+            // A client slow to read its reply doesn't make the command slow.
+            let executed = start
+                .elapsed()
+                .saturating_sub(self.connection.take_write_time());
+            self.context.stats.command_processed();
+            if let Some(name) = name {
+                let latency = (!subscribe).then_some(executed);
+                self.context.stats.command_call(&name, latency);
+            }
+
+            if let Some(args) = args {
+                if !subscribe && executed.as_micros() >= threshold as u128 {
+                    let client_name = self
+                        .context
+                        .clients
+                        .get(self.client.id())
+                        .map(|client| client.name)
+                        .unwrap_or_default();
+                    self.context
+                        .slowlog
+                        .record(args, executed, &self.addr, &client_name, max_len);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a command in the context of this connection. Commands that
+    /// change the connection state are handled here, everything else is
+    /// checked against the user's permissions and passed on to
+    /// `Command::apply`.
+    async fn apply(&mut self, cmd: Command) -> crate::Result<()> {
+        let client_info = self.client_info();
+        let (cmd, user) = match (cmd, self.user.clone()) {
+            (Command::Auth(cmd), _) => {
+                if let Some(user) = cmd
+                    .apply(&self.context.acl, &client_info, &mut self.connection)
+                    .await?
+                {
+                    self.set_user(Some(user));
+                }
+                return Ok(());
+            }
+            (Command::Hello(cmd), _) => {
+                let user = cmd
+                    .apply(
+                        &self.context.acl,
+                        self.user.take(),
+                        &client_info,
+                        &mut self.connection,
+                    )
+                    .await?;
+                self.set_user(user);
+                return Ok(());
+            }
+            // Until authenticated, a client may only ping the server.
+            (cmd @ Command::Ping(_), None) => {
+                return cmd
+                    .apply(&self.db, &mut self.connection, &mut self.shutdown)
+                    .await;
+            }
+            (_, None) => {
+                let frame = Frame::Error("NOAUTH Authentication required.".to_string());
+                self.connection.write_frame(&frame).await?;
+                return Ok(());
+            }
+            // Unknown commands are reported as such, whatever the user may
+            // run.
+            (cmd @ Command::Unknown(_), Some(_)) => {
+                return cmd
+                    .apply(&self.db, &mut self.connection, &mut self.shutdown)
+                    .await;
+            }
+            (cmd, Some(user)) => (cmd, user),
+        };
+
+        if let Err(denial) = self.context.acl.check(&user, &cmd, &client_info) {
+            let message = match denial {
+                Denial::Command => format!(
+                    "NOPERM User {} has no permissions to run the '{}' command",
+                    user,
+                    cmd.get_name()
+                ),
+                Denial::Key => "NOPERM No permissions to access a key".to_string(),
+                Denial::Channel => "NOPERM No permissions to access a channel".to_string(),
+                // The user was deleted or disabled since the connection
+                // authenticated, so the connection is dropped.
+                Denial::User => return Err(format!("user {} no longer exists", user).into()),
+            };
+            self.connection.write_frame(&Frame::Error(message)).await?;
+            return Ok(());
+        }
+        if cmd.is_write() && self.context.replication.is_read_only() {
+            let message = "READONLY You can't write against a read only replica.";
+            let frame = Frame::Error(message.to_string());
+            self.connection.write_frame(&frame).await?;
+            return Ok(());
+        }
+
+        match cmd {
+            Command::Acl(cmd) => {
+                cmd.apply(&self.context.acl, &user, &mut self.connection)
+                    .await
+            }
+            Command::Client(cmd) => {
+                cmd.apply(
+                    &self.context.clients,
+                    self.client.id(),
+                    &mut self.connection,
+                )
+                .await
+            }
+            Command::Config(cmd) => {
+                let Context {
+                    config, acl, stats, ..
+                } = &self.context;
+                cmd.apply(&self.db, config, acl, stats, &mut self.connection)
+                    .await
+            }
+            Command::Info(cmd) => {
+                let Context {
+                    config,
+                    stats,
+                    replication,
+                    ..
+                } = &self.context;
+                cmd.apply(&self.db, config, stats, replication, &mut self.connection)
+                    .await
+            }
+            Command::ReplicaOf(cmd) => {
+                cmd.apply(&self.context.replication, &mut self.connection)
+                    .await
+            }
+            Command::Role(cmd) => {
+                cmd.apply(&self.context.replication, &mut self.connection)
+                    .await
+            }
+            Command::Replconf(cmd) => {
+                // Acknowledgments are only expected once the stream is
+                // served, and get no reply.
+                if cmd.ack().is_some() {
+                    return Ok(());
+                }
+                if let Some(port) = cmd.listening_port() {
+                    self.listening_port = Some(port);
+                }
+                let frame = Frame::Simple("OK".to_string());
+                self.connection.write_frame(&frame).await?;
+                Ok(())
+            }
+            Command::Psync(cmd) => {
+                let (ip, port) = split_addr(&self.addr);
+                let port = self.listening_port.unwrap_or(port);
+                self.context
+                    .replication
+                    .serve_replica(cmd, ip, port, &mut self.connection, &mut self.shutdown)
+                    .await
+            }
+            Command::Wait(cmd) => {
+                cmd.apply(
+                    &self.context.replication,
+                    self.written,
+                    &mut self.connection,
+                    &mut self.shutdown,
+                )
+                .await
+            }
+            Command::WaitAof(cmd) => {
+                cmd.apply(
+                    &self.db,
+                    &self.context.replication,
+                    self.written,
+                    &mut self.connection,
+                    &mut self.shutdown,
+                )
+                .await
+            }
+            Command::Slowlog(cmd) => cmd.apply(&self.context.slowlog, &mut self.connection).await,
+            // Only known to servers running in sentinel mode.
+            Command::Sentinel(_) => Unknown::new("sentinel").apply(&mut self.connection).await,
+            Command::Subscribe(cmd) => {
+                let acl = &self.context.acl;
+                let allow_channel = |channel: &str| acl.check_channel(&user, channel, &client_info);
+                let (clients, id) = (&self.context.clients, self.client.id());
+                let subscribed = |count| clients.update(id, |client| client.subscriptions = count);
+                cmd.run(
+                    &self.db,
+                    &mut self.connection,
+                    &mut self.shutdown,
+                    &allow_channel,
+                    &subscribed,
+                )
+                .await
+            }
+            cmd if cmd.is_write() => self.apply_write(cmd).await,
+            cmd => match &self.context.cores {
+                Some(cores) if !cmd.keys().is_empty() => {
+                    let frame = cores.execute(self.core, cmd).await?;
+                    debug!(?frame);
+                    self.connection.write_frame(&frame).await?;
+                    Ok(())
+                }
+                _ => {
+                    cmd.apply(&self.db, &mut self.connection, &mut self.shutdown)
+                        .await
+                }
+            },
         }
+    }
+
+    /// Applies a command changing the keyspace, which the `Db` propagates to
+    /// the replicas.
+    async fn apply_write(&mut self, cmd: Command) -> crate::Result<()> {
+        if let Some(err) = self.db.storage_error() {
+            let frame = Frame::Error(format!(
+                "MISCONF Errors writing to the storage, commands that may modify the data set are disabled: {}",
+                err
+            ));
+            self.connection.write_frame(&frame).await?;
+            return Ok(());
+        }
+        let shards: Vec<usize> = cmd.keys().iter().map(|key| self.db.shard_of(key)).collect();
+        let frame = match &self.context.cores {
+            Some(cores) => cores.execute(self.core, cmd).await?,
+            None => cmd.execute(&self.db),
+        };
+        // The write was propagated to the replicas as it was applied.
+        self.written = self.context.replication.offset();
+        // Acknowledged writes survive a crash of the process.
+        self.db.written(&shards).await;
+        debug!(?frame);
+        self.connection.write_frame(&frame).await?;
         Ok(())
     }
+
+    /// Changes the user the connection runs as, after `AUTH` or `HELLO`.
+    fn set_user(&mut self, user: Option<String>) {
+        self.context
+            .clients
+            .update(self.client.id(), |client| client.user = user.clone());
+        self.user = user;
+    }
+
+    /// Describes the connection in the ACL log.
+    fn client_info(&self) -> String {
+        format!(
+            "addr={} user={}",
+            self.addr,
+            self.user.as_deref().unwrap_or("")
+        )
+    }
+
+    /// Reports a read error back to the client before the connection is
+    /// closed. Only limit violations are reported, other errors usually mean
+    /// the peer is gone or not speaking RESP at all.
+    async fn reject(&mut self, err: crate::Error) -> crate::Result<()> {
+        if let Some(frame::Error::LimitExceeded(_)) = err.downcast_ref() {
+            let response = Frame::Error(format!("ERR {}", err));
+            self.connection.write_frame(&response).await?;
+        }
+        Err(err)
+    }
+}
+
+/// Splits the address of a TCP peer into its IP and port. Other peers have
+/// no port.
+fn split_addr(addr: &str) -> (&str, u16) {
+    match addr.rsplit_once(':') {
+        Some((ip, port)) => (
+            ip.trim_start_matches('[').trim_end_matches(']'),
+            port.parse().unwrap_or(0),
+        ),
+        None => (addr, 0),
+    }
 }
@@ -15,7 +15,7 @@ impl Shutdown {
     }
 
     pub fn is_shutdown(&self) -> bool {
-        return self.is_shutdown
+        self.is_shutdown
     }
 
     pub async fn recv(&mut self) {
@@ -0,0 +1,127 @@
+//! Log of commands exceeding the `slowlog-log-slower-than` threshold,
+//! inspected with `SLOWLOG GET`.
+
+use crate::Frame;
+
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of arguments of a command kept in an entry.
+const MAX_ARGS: usize = 32;
+
+/// Number of bytes of an argument kept in an entry.
+const MAX_ARG_LEN: usize = 128;
+
+/// Shared handle to the slow log. Cloning is shallow.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SlowLog {
+    shared: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Newest entry first.
+    entries: VecDeque<Entry>,
+    next_id: u64,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub(crate) id: u64,
+    /// Unix time the command was run at, in seconds.
+    pub(crate) timestamp: u64,
+    pub(crate) duration: Duration,
+    pub(crate) args: Vec<Bytes>,
+    pub(crate) client_addr: String,
+    /// Set by `CLIENT SETNAME`, empty otherwise.
+    pub(crate) client_name: String,
+}
+
+impl SlowLog {
+    /// Record a command, keeping at most `max_len` entries.
+    pub(crate) fn record(
+        &self,
+        args: Vec<Bytes>,
+        duration: Duration,
+        client_addr: &str,
+        client_name: &str,
+        max_len: usize,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let mut state = self.shared.lock().unwrap();
+        let entry = Entry {
+            id: state.next_id,
+            timestamp,
+            duration,
+            args,
+            client_addr: client_addr.to_string(),
+            client_name: client_name.to_string(),
+        };
+        state.next_id += 1;
+        state.entries.push_front(entry);
+        state.entries.truncate(max_len);
+    }
+
+    /// Returns the `count` most recent entries, newest first.
+    pub(crate) fn entries(&self, count: usize) -> Vec<Entry> {
+        let state = self.shared.lock().unwrap();
+        state.entries.iter().take(count).cloned().collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shared.lock().unwrap().entries.len()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.shared.lock().unwrap().entries.clear();
+    }
+}
+
+/// Extracts the arguments of a command frame as recorded in the slow log.
+///
+/// Long argument lists and arguments are shortened, and secrets, like the
+/// password of `AUTH`, are redacted.
+pub(crate) fn command_args(frame: &Frame) -> Vec<Bytes> {
+    let Frame::Array(parts) = frame else {
+        return vec![];
+    };
+    let mut args: Vec<Bytes> = parts
+        .iter()
+        .take(MAX_ARGS)
+        .map(|part| match part {
+            Frame::Bulk(bytes) if bytes.len() > MAX_ARG_LEN => {
+                let mut arg = bytes[..MAX_ARG_LEN].to_vec();
+                let more = format!("... ({} more bytes)", bytes.len() - MAX_ARG_LEN);
+                arg.extend_from_slice(more.as_bytes());
+                Bytes::from(arg)
+            }
+            Frame::Bulk(bytes) => bytes.clone(),
+            Frame::Simple(s) => Bytes::from(s.clone()),
+            Frame::Integer(n) => Bytes::from(n.to_string()),
+            _ => Bytes::new(),
+        })
+        .collect();
+
+    if parts.len() > MAX_ARGS {
+        let more = format!("... ({} more arguments)", parts.len() - MAX_ARGS + 1);
+        args[MAX_ARGS - 1] = Bytes::from(more);
+    }
+
+    // Keep the command and, for commands with subcommands, the subcommand.
+    let name = args.first().map(|name| name.to_ascii_lowercase());
+    let keep = match name.as_deref() {
+        Some(b"auth") | Some(b"hello") => 1,
+        Some(b"acl") | Some(b"config") => 2,
+        _ => args.len(),
+    };
+    if args.len() > keep {
+        args.truncate(keep);
+        args.push(Bytes::from_static(b"(redacted)"));
+    }
+    args
+}
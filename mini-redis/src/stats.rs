@@ -0,0 +1,143 @@
+//! Server-wide counters reported by `INFO`, reset by `CONFIG RESETSTAT`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds, in microseconds, of the command latency histogram buckets.
+/// Latencies above the last bound are only counted in the total.
+pub(crate) const LATENCY_BUCKETS: &[u64] = &[
+    10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+];
+
+#[derive(Debug)]
+pub(crate) struct Stats {
+    /// When the server started.
+    started_at: Instant,
+
+    /// Clients currently connected.
+    connected_clients: AtomicU64,
+
+    /// Connections accepted by the server.
+    connections_received: AtomicU64,
+
+    /// Connections refused because `maxclients` was reached.
+    rejected_connections: AtomicU64,
+
+    /// Commands run by clients, whether they succeeded or not.
+    commands_processed: AtomicU64,
+
+    /// Calls and latencies, by command name.
+    commands: Mutex<HashMap<String, CommandStats>>,
+}
+
+/// Calls of a single command.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CommandStats {
+    pub(crate) calls: u64,
+
+    /// Calls whose latency was recorded.
+    pub(crate) timed_calls: u64,
+
+    /// Total time spent running the timed calls.
+    pub(crate) duration: Duration,
+
+    /// Number of calls that took at most the matching `LATENCY_BUCKETS`
+    /// bound, and more than the previous one.
+    pub(crate) buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+impl Stats {
+    pub(crate) fn new() -> Stats {
+        Stats {
+            started_at: Instant::now(),
+            connected_clients: AtomicU64::new(0),
+            connections_received: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            commands_processed: AtomicU64::new(0),
+            commands: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn connection_received(&self) {
+        self.connections_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_rejected(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn command_processed(&self) {
+        self.commands_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a call of the command `name` and, when it makes sense for the
+    /// command, how long it ran.
+    pub(crate) fn command_call(&self, name: &str, elapsed: Option<Duration>) {
+        let mut commands = self.commands.lock().unwrap();
+        let stats = match commands.get_mut(name) {
+            Some(stats) => stats,
+            None => commands.entry(name.to_string()).or_default(),
+        };
+        stats.calls += 1;
+        let Some(elapsed) = elapsed else {
+            return;
+        };
+        stats.timed_calls += 1;
+        stats.duration += elapsed;
+        let micros = elapsed.as_micros();
+        if let Some(i) = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| micros <= bound as u128)
+        {
+            stats.buckets[i] += 1;
+        }
+    }
+
+    pub(crate) fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub(crate) fn connected_clients(&self) -> u64 {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn connections_received(&self) -> u64 {
+        self.connections_received.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn commands_processed(&self) -> u64 {
+        self.commands_processed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the calls of every command that ran, sorted by name.
+    pub(crate) fn commands(&self) -> Vec<(String, CommandStats)> {
+        let commands = self.commands.lock().unwrap();
+        let mut commands: Vec<_> = commands
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect();
+        commands.sort_by(|a, b| a.0.cmp(&b.0));
+        commands
+    }
+
+    pub(crate) fn reset(&self) {
+        self.connections_received.store(0, Ordering::Relaxed);
+        self.rejected_connections.store(0, Ordering::Relaxed);
+        self.commands_processed.store(0, Ordering::Relaxed);
+        self.commands.lock().unwrap().clear();
+    }
+}
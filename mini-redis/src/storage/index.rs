@@ -0,0 +1,161 @@
+use crate::eviction::{KeyPool, Rng};
+
+use std::collections::{BTreeSet, HashMap};
+use tokio::time::Instant;
+
+/// Keys along with a value of type `V`, tracking their expirations and
+/// sampling them. `MemoryStorage` keeps its entries in one, `LsmStorage` the
+/// keys it has on disk and the entries it caches.
+#[derive(Debug)]
+pub(crate) struct KeyIndex<V> {
+    /// We are not trying to do anything fancy so a
+    /// `std::collections::HashMap` works fine.
+    entries: HashMap<String, Indexed<V>>,
+
+    /// Tracks key TTLs.
+    ///
+    /// A `BTreeSet` is used to maintain expirations sorted by when they expire.
+    /// This allows the background task to iterate this map to find the value
+    /// expiring next.
+    ///
+    /// While highly unlikely, it is possible for more than one expiration to be
+    /// created for the same instant. Because of this, the `Instant` is
+    /// insufficient for the key. A unique key (`String`) is used to
+    /// break these ties.
+    expirations: BTreeSet<(Instant, String)>,
+
+    /// Every key, to sample keys to evict from.
+    keys: KeyPool,
+
+    /// Keys with an expiration, sampled by the `volatile-*` policies.
+    volatile_keys: KeyPool,
+
+    /// Used to sample keys.
+    rng: Rng,
+}
+
+/// A value along with its slots in the key pools.
+#[derive(Debug)]
+struct Indexed<V> {
+    value: V,
+
+    expires_at: Option<Instant>,
+
+    /// Slot of the key in `KeyIndex::keys`.
+    slot: usize,
+
+    /// Slot of the key in `KeyIndex::volatile_keys`, if it expires.
+    volatile_slot: Option<usize>,
+}
+
+impl<V> KeyIndex<V> {
+    pub(crate) fn new() -> KeyIndex<V> {
+        KeyIndex {
+            entries: HashMap::new(),
+            expirations: BTreeSet::new(),
+            keys: KeyPool::default(),
+            volatile_keys: KeyPool::default(),
+            rng: Rng::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key).map(|indexed| &indexed.value)
+    }
+
+    /// The value of `key` along with its expiration.
+    pub(crate) fn get_with_expiration(&self, key: &str) -> Option<(&V, Option<Instant>)> {
+        self.entries
+            .get(key)
+            .map(|indexed| (&indexed.value, indexed.expires_at))
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|indexed| &mut indexed.value)
+    }
+
+    /// Adds `key`, expiring at `expires_at` if set. There must be no value
+    /// for `key` already.
+    pub(crate) fn insert(&mut self, key: String, value: V, expires_at: Option<Instant>) {
+        let slot = self.keys.insert(key.clone());
+        let volatile_slot = expires_at.map(|when| {
+            self.expirations.insert((when, key.clone()));
+            self.volatile_keys.insert(key.clone())
+        });
+        let indexed = Indexed {
+            value,
+            expires_at,
+            slot,
+            volatile_slot,
+        };
+        self.entries.insert(key, indexed);
+    }
+
+    /// Removes `key` along with its expiration.
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        let Indexed {
+            value,
+            expires_at,
+            slot,
+            volatile_slot,
+        } = self.entries.remove(key)?;
+        // Another key may take the freed slot.
+        if let Some(moved) = self.keys.remove(slot) {
+            if let Some(moved) = self.entries.get_mut(moved) {
+                moved.slot = slot;
+            }
+        }
+        if let Some(slot) = volatile_slot {
+            if let Some(moved) = self.volatile_keys.remove(slot) {
+                if let Some(moved) = self.entries.get_mut(moved) {
+                    moved.volatile_slot = Some(slot);
+                }
+            }
+        }
+        if let Some(when) = expires_at {
+            self.expirations.remove(&(when, key.to_string()));
+        }
+        Some(value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of keys with an expiration.
+    pub(crate) fn expires(&self) -> usize {
+        self.expirations.len()
+    }
+
+    /// The key expiring first, along with the instant it expires at.
+    pub(crate) fn next_expiration(&self) -> Option<(Instant, &str)> {
+        self.expirations
+            .iter()
+            .next()
+            .map(|(when, key)| (*when, key.as_str()))
+    }
+
+    /// Returns a random key, among the keys with an expiration if
+    /// `volatile` is set.
+    pub(crate) fn sample(&mut self, volatile: bool) -> Option<&str> {
+        let pool = if volatile {
+            &self.volatile_keys
+        } else {
+            &self.keys
+        };
+        pool.sample(&mut self.rng)
+    }
+
+    /// Visits every key, along with its value and expiration.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&str, &V, Option<Instant>)) {
+        for (key, indexed) in &self.entries {
+            f(key, &indexed.value, indexed.expires_at);
+        }
+    }
+}
+
+impl<V> Default for KeyIndex<V> {
+    fn default() -> KeyIndex<V> {
+        KeyIndex::new()
+    }
+}
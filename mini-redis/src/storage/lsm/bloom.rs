@@ -0,0 +1,102 @@
+use bytes::{Buf, BufMut};
+use std::io;
+
+/// Bits per key. With `HASHES` hashes, about 1% of the lookups of missing
+/// keys read the segment anyway.
+const BITS_PER_KEY: usize = 10;
+
+const HASHES: u64 = 7;
+
+/// Tells, for each segment, whether a key may be in it, so that lookups
+/// skip the segments that don't hold the key without reading them.
+#[derive(Debug)]
+pub(super) struct Bloom {
+    bits: Vec<u64>,
+}
+
+impl Bloom {
+    /// An empty filter sized for `keys` keys.
+    pub(super) fn new(keys: usize) -> Bloom {
+        let words = (keys * BITS_PER_KEY).div_ceil(64).max(1);
+        Bloom {
+            bits: vec![0; words],
+        }
+    }
+
+    pub(super) fn insert(&mut self, key: &str) {
+        for bit in self.positions(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns `false` if `key` was never inserted. `true` means it probably
+    /// was.
+    pub(super) fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// The bits of `key`, derived from two hashes as described by Kirsch and
+    /// Mitzenmacher.
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() as u64 * 64;
+        let h1 = hash(key.as_bytes(), 0);
+        let h2 = hash(key.as_bytes(), h1) | 1;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub(super) fn encode(&self, buf: &mut Vec<u8>) {
+        for word in &self.bits {
+            buf.put_u64_le(*word);
+        }
+    }
+
+    pub(super) fn decode(mut buf: &[u8]) -> io::Result<Bloom> {
+        if buf.is_empty() || !buf.len().is_multiple_of(8) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad bloom filter",
+            ));
+        }
+        let mut bits = Vec::with_capacity(buf.len() / 8);
+        while buf.has_remaining() {
+            bits.push(buf.get_u64_le());
+        }
+        Ok(Bloom { bits })
+    }
+}
+
+/// FNV-1a hash of `bytes` starting from `seed`, with its bits mixed.
+fn hash(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325 ^ seed;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn few_false_positives() {
+        let mut bloom = Bloom::new(1000);
+        for i in 0..1000 {
+            bloom.insert(&format!("key:{}", i));
+        }
+        assert!((0..1000).all(|i| bloom.contains(&format!("key:{}", i))));
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.contains(&format!("other:{}", i)))
+            .count();
+        assert!(false_positives < 300, "{}", false_positives);
+
+        let mut buf = Vec::new();
+        bloom.encode(&mut buf);
+        let decoded = Bloom::decode(&buf).unwrap();
+        assert!(decoded.contains("key:42"));
+    }
+}
@@ -0,0 +1,904 @@
+//! A storage keeping the values on disk, for datasets larger than memory,
+//! selected with `backend lsm`.
+//!
+//! This is a log-structured merge tree. Writes are appended to a write-ahead
+//! log and kept in a sorted memtable. When the memtable is full, it is
+//! written to an immutable segment file, sorted by key, and the log is
+//! deleted. Lookups check the memtable then the segments from the newest to
+//! the oldest, skipping those whose bloom filter rules the key out. Once
+//! there are `COMPACTION_THRESHOLD` segments, a background thread merges them
+//! into one, dropping the overwritten values and removed keys.
+//!
+//! As in Redis on Flash, the keys themselves stay in memory, along with their
+//! expirations, so that misses, sampling and expiring keys never read the
+//! disk. The values of the keys used last are cached in memory too, up to
+//! `LsmOptions::cache_size` bytes.
+//!
+//! Commands don't wait on the disk while holding the lock of their shard:
+//! the log is appended to by a background thread, which replies to writes
+//! wait for, see `Storage::written`, and the values a command reads are
+//! loaded beforehand on a blocking thread, see `Storage::fetch`.
+//! After an I/O error, the server refuses writes with a `MISCONF` error, as
+//! the storage can't go on without losing them, and the data is recovered
+//! from the disk at the next start.
+
+mod bloom;
+mod record;
+mod segment;
+mod wal;
+
+use super::index::KeyIndex;
+use super::{from_unix, to_unix, Entry, Fetch, Fetched, Storage, Written};
+use crate::config::ServerConfig;
+use record::{Value, RECORD_OVERHEAD};
+use segment::Segment;
+use wal::Wal;
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{io, thread};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, error};
+
+/// Number of segments merged by a compaction.
+const COMPACTION_THRESHOLD: usize = 4;
+
+/// Cached entries sampled to pick the one to drop from the cache.
+const CACHE_SAMPLES: usize = 5;
+
+/// Sizes of the memory buffers of `LsmStorage`.
+#[derive(Clone, Copy, Debug)]
+pub struct LsmOptions {
+    /// Bytes of values cached in memory.
+    pub cache_size: usize,
+
+    /// Bytes of writes buffered in the memtable before they are written to
+    /// a segment.
+    pub memtable_size: usize,
+}
+
+impl Default for LsmOptions {
+    fn default() -> LsmOptions {
+        LsmOptions {
+            cache_size: 64 * 1024 * 1024,
+            memtable_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl From<&ServerConfig> for LsmOptions {
+    fn from(config: &ServerConfig) -> LsmOptions {
+        LsmOptions {
+            cache_size: config.lsm_cache_size as usize,
+            memtable_size: config.lsm_memtable_size as usize,
+        }
+    }
+}
+
+/// Keeps the values on disk, see the module documentation.
+#[derive(Debug)]
+pub struct LsmStorage {
+    dir: PathBuf,
+
+    options: LsmOptions,
+
+    /// Every key, along with the length of its value.
+    keys: KeyIndex<usize>,
+
+    /// Entries of the keys used last.
+    cache: KeyIndex<Entry>,
+
+    /// Bytes of values in `cache`.
+    cached: usize,
+
+    /// Writes not in a segment yet, also in the current log.
+    memtable: BTreeMap<String, Value>,
+
+    /// Approximate bytes of `memtable`.
+    memtable_size: usize,
+
+    /// The previous memtable, while it is written to a segment in the
+    /// background.
+    flushing: Option<Flushing>,
+
+    /// Number of the current log, also given to the segment its records are
+    /// flushed to.
+    log_id: u64,
+
+    /// Number of the last log before the keyspace was flushed. Segments
+    /// written in the background from older logs are discarded.
+    cleared: u64,
+
+    /// Records sent to `writer` so far.
+    appended: u64,
+
+    /// Memtables written to a segment so far. Values read in the background
+    /// before a flush may be older than those it wrote, so they are not
+    /// cached.
+    flushes: u64,
+
+    /// Sorted from the oldest to the newest.
+    segments: Vec<Arc<Segment>>,
+
+    /// Receives the segment written by the compaction running in the
+    /// background, if any.
+    compaction: Option<mpsc::Receiver<io::Result<Segment>>>,
+
+    writer: Writer,
+}
+
+/// A memtable being written to a segment.
+#[derive(Debug)]
+struct Flushing {
+    memtable: Arc<BTreeMap<String, Value>>,
+
+    /// Approximate bytes of `memtable`.
+    size: usize,
+
+    /// Number of the log holding the records, and of the segment.
+    id: u64,
+
+    /// Receives the segment once written.
+    done: mpsc::Receiver<io::Result<Segment>>,
+}
+
+/// Values read by `Storage::fetch`.
+struct Loaded {
+    /// `LsmStorage::flushes` when the values were read.
+    flushes: u64,
+    values: Vec<(String, io::Result<Option<Value>>)>,
+}
+
+/// Thread appending to the log and removing files, in the order the
+/// storage asks, so that commands don't wait on the disk.
+#[derive(Debug)]
+struct Writer {
+    tasks: Option<mpsc::Sender<Task>>,
+
+    thread: Option<thread::JoinHandle<()>>,
+
+    /// Number of records appended to the log by the thread.
+    written: watch::Receiver<u64>,
+
+    /// The first I/O error of the storage, which refuses writes from then
+    /// on.
+    error: Arc<Mutex<Option<String>>>,
+}
+
+/// File operation run by `Writer`.
+#[derive(Debug)]
+enum Task {
+    /// Appends a record encoded by `Wal::encode` to the current log.
+    Append(Vec<u8>),
+
+    /// Starts the log numbered so.
+    Rotate(u64),
+
+    /// Writes an empty segment covering the logs up to the one numbered so,
+    /// then deletes the segments it replaces.
+    Clear(u64, Vec<Arc<Segment>>),
+
+    /// Deletes segments replaced by a compaction, or written for a keyspace
+    /// flushed since.
+    RemoveSegments(Vec<Arc<Segment>>),
+
+    /// Deletes the log numbered so, whose records are in a segment.
+    RemoveLog(u64),
+}
+
+impl LsmStorage {
+    /// Opens the storage in `dir`, created if missing. Writes logged but not
+    /// written to a segment when the process stopped are recovered.
+    pub fn open(dir: impl Into<PathBuf>, options: LsmOptions) -> io::Result<LsmStorage> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut found = Vec::new();
+        let mut wals = Vec::new();
+        for file in fs::read_dir(&dir)? {
+            let path = file?.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("seg") => found.push(Segment::open(&path)?),
+                Some("wal") => {
+                    if let Some(id) = Wal::parse_name(&path) {
+                        wals.push((id, path));
+                    }
+                }
+                // Left by a crash while writing a segment.
+                Some("tmp") => fs::remove_file(&path)?,
+                _ => {}
+            }
+        }
+
+        // Drop the segments replaced by a compaction or a flush of the
+        // keyspace, which a crash prevented from being deleted. Sorted this
+        // way, a segment comes after those covering it.
+        found.sort_by_key(|segment| (segment.first, u64::MAX - segment.last));
+        let mut segments: Vec<Segment> = Vec::new();
+        for segment in found {
+            if segments.iter().any(|kept| kept.covers(&segment)) {
+                segment.remove()?;
+            } else {
+                segments.push(segment);
+            }
+        }
+        segments.sort_by_key(|segment| segment.last);
+
+        // Replay the logs whose records are not in a segment yet, and write
+        // them to one.
+        wals.sort();
+        let last_id = segments.last().map_or(0, |segment| segment.last);
+        let next_id = wals.last().map_or(0, |(id, _)| *id).max(last_id) + 1;
+        let mut memtable = BTreeMap::new();
+        let mut replayed = None;
+        for (id, path) in &wals {
+            if *id > last_id {
+                Wal::replay(path, |key, value| {
+                    memtable.insert(key, value);
+                })?;
+                replayed = replayed.or(Some(*id));
+            }
+        }
+        if let Some(first) = replayed.filter(|_| !memtable.is_empty()) {
+            let len = memtable.len();
+            let records = memtable.into_iter().map(Ok);
+            segments.push(Segment::write(&dir, first, next_id - 1, len, records)?);
+        }
+        for (_, path) in &wals {
+            fs::remove_file(path)?;
+        }
+
+        let mut storage = LsmStorage {
+            writer: Writer::start(dir.clone(), Wal::create(&dir, next_id)?)?,
+            dir,
+            options,
+            keys: KeyIndex::new(),
+            cache: KeyIndex::new(),
+            cached: 0,
+            memtable: BTreeMap::new(),
+            memtable_size: 0,
+            flushing: None,
+            log_id: next_id,
+            cleared: 0,
+            appended: 0,
+            flushes: 0,
+            segments: segments.into_iter().map(Arc::new).collect(),
+            compaction: None,
+        };
+        // Later segments override the keys of earlier ones.
+        for segment in &storage.segments {
+            for (key, header) in segment.headers()? {
+                storage.keys.remove(&key);
+                if let Some((len, expires_at)) = header {
+                    storage.keys.insert(key, len, expires_at.map(from_unix));
+                }
+            }
+        }
+        debug!(dir = %storage.dir.display(), keys = storage.keys.len(), segments = storage.segments.len(), "opened lsm storage");
+        storage.compact_if_needed();
+        Ok(storage)
+    }
+
+    /// Loads the entry of `key` from the memtables or the segments.
+    fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+        let value = match self.in_memory(key) {
+            Some(value) => Some(value.clone()),
+            None => find(&self.segments, key)?,
+        };
+        Ok(into_entry(value))
+    }
+
+    /// The last write of `key` not in a segment yet, if any.
+    fn in_memory(&self, key: &str) -> Option<&Value> {
+        self.memtable
+            .get(key)
+            .or_else(|| self.flushing.as_ref()?.memtable.get(key))
+    }
+
+    /// Adds `entry` to the cache, first dropping the entries used the
+    /// longest ago to make room for it.
+    fn cache(&mut self, key: String, entry: Entry) {
+        let len = entry.data.len();
+        while self.cached + len > self.options.cache_size && self.drop_oldest().is_some() {}
+        self.cached += len;
+        self.cache.insert(key, entry, None);
+    }
+
+    /// Drops from the cache the entry used the longest ago among a few
+    /// sampled, returning the bytes of its value, or `None` if the cache is
+    /// empty.
+    fn drop_oldest(&mut self) -> Option<usize> {
+        let mut oldest: Option<(Instant, String)> = None;
+        for _ in 0..CACHE_SAMPLES {
+            let Some(sampled) = self.cache.sample(false).map(str::to_string) else {
+                break;
+            };
+            let accessed_at = self.cache.get(&sampled).map(|entry| entry.accessed_at);
+            if let Some(accessed_at) = accessed_at {
+                if oldest
+                    .as_ref()
+                    .is_none_or(|(oldest, _)| accessed_at < *oldest)
+                {
+                    oldest = Some((accessed_at, sampled));
+                }
+            }
+        }
+        let (_, oldest) = oldest?;
+        self.uncache(&oldest).map(|entry| entry.data.len())
+    }
+
+    fn uncache(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.cache.remove(key)?;
+        self.cached -= entry.data.len();
+        Some(entry)
+    }
+
+    /// Logs the write of `key` and applies it to the memtable, which is
+    /// written to a segment once full.
+    fn write(&mut self, key: String, value: Value) {
+        let mut record = Vec::new();
+        Wal::encode(&mut record, &key, &value);
+        self.writer.send(Task::Append(record));
+        self.appended += 1;
+        self.memtable_size += key.len() + RECORD_OVERHEAD;
+        if let Value::Put { data, .. } = &value {
+            self.memtable_size += data.len();
+        }
+        self.memtable.insert(key, value);
+        // Otherwise the memtable grows until the previous one is written.
+        if self.memtable_size >= self.options.memtable_size && self.flushing.is_none() {
+            self.flush_memtable();
+        }
+    }
+
+    /// Starts writing the memtable to a segment in the background, and a new
+    /// log for the writes to come. Lookups use the memtable until the
+    /// segment replaces it.
+    fn flush_memtable(&mut self) {
+        let id = self.log_id;
+        self.log_id += 1;
+        self.writer.send(Task::Rotate(self.log_id));
+        let memtable = Arc::new(std::mem::take(&mut self.memtable));
+        let size = std::mem::take(&mut self.memtable_size);
+        let (tx, rx) = mpsc::channel();
+        let (dir, records) = (self.dir.clone(), memtable.clone());
+        let spawned = thread::Builder::new()
+            .name("flush".to_string())
+            .spawn(move || {
+                let len = records.len();
+                let iter = records
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), value.clone())));
+                let _ = tx.send(Segment::write(&dir, id, id, len, iter));
+            });
+        // Without a thread, `done` is disconnected and the flush fails.
+        if let Err(err) = spawned {
+            self.writer.fail(err);
+        }
+        self.flushing = Some(Flushing {
+            memtable,
+            size,
+            id,
+            done: rx,
+        });
+    }
+
+    /// Applies the flush and the compaction done in the background.
+    fn finish_background(&mut self) {
+        self.finish_flush();
+        self.finish_compaction();
+    }
+
+    /// Replaces the memtable being flushed with its segment, once written.
+    fn finish_flush(&mut self) {
+        let Some(flushing) = &self.flushing else {
+            return;
+        };
+        let written = match flushing.done.try_recv() {
+            Ok(written) => written,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Err(io::Error::other("the flush thread stopped"))
+            }
+        };
+        let Some(flushing) = self.flushing.take() else {
+            return;
+        };
+        let segment = match written {
+            Ok(segment) => segment,
+            Err(err) => {
+                // The records stay in memory, and in their log for the next
+                // start, but the storage refuses writes from now on.
+                self.writer.fail(err);
+                let memtable = Arc::unwrap_or_clone(flushing.memtable);
+                for (key, value) in memtable {
+                    self.memtable.entry(key).or_insert(value);
+                }
+                self.memtable_size += flushing.size;
+                return;
+            }
+        };
+        debug!(id = flushing.id, records = segment.len, "flushed memtable");
+        self.flushes += 1;
+        if segment.last <= self.cleared {
+            // The keyspace was flushed meanwhile.
+            self.writer
+                .send(Task::RemoveSegments(vec![Arc::new(segment)]));
+        } else {
+            self.segments.push(Arc::new(segment));
+        }
+        // The records are safe in the segment, so their log can go.
+        self.writer.send(Task::RemoveLog(flushing.id));
+        if self.memtable_size >= self.options.memtable_size {
+            self.flush_memtable();
+        }
+        self.compact_if_needed();
+    }
+
+    /// Starts merging the segments in the background if there are enough.
+    fn compact_if_needed(&mut self) {
+        if self.compaction.is_some() || self.segments.len() < COMPACTION_THRESHOLD {
+            return;
+        }
+        let inputs = self.segments.clone();
+        let dir = self.dir.clone();
+        let (tx, rx) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("compaction".to_string())
+            .spawn(move || {
+                let _ = tx.send(compact(&dir, &inputs));
+            });
+        match spawned {
+            Ok(_) => self.compaction = Some(rx),
+            Err(err) => error!(case = %err, "failed to start the compaction"),
+        }
+    }
+
+    /// Replaces the merged segments with the output of the compaction, once
+    /// done.
+    fn finish_compaction(&mut self) {
+        let Some(rx) = &self.compaction else {
+            return;
+        };
+        let output = match rx.try_recv() {
+            Ok(Ok(output)) => output,
+            Ok(Err(err)) => {
+                // Try again later.
+                error!(case = %err, "compaction failed");
+                self.compaction = None;
+                return;
+            }
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.compaction = None;
+                return;
+            }
+        };
+        self.compaction = None;
+        if output.last <= self.cleared {
+            // The keyspace was flushed during the compaction.
+            self.writer
+                .send(Task::RemoveSegments(vec![Arc::new(output)]));
+            return;
+        }
+        let (merged, kept) = std::mem::take(&mut self.segments)
+            .into_iter()
+            .partition(|segment| output.covers(segment));
+        debug!(
+            first = output.first,
+            last = output.last,
+            records = output.len,
+            "compacted segments"
+        );
+        self.segments = kept;
+        self.segments.insert(0, Arc::new(output));
+        self.writer.send(Task::RemoveSegments(merged));
+        self.compact_if_needed();
+    }
+}
+
+impl Storage for LsmStorage {
+    fn get(&mut self, key: &str) -> Option<&mut Entry> {
+        self.finish_background();
+        self.keys.get(key)?;
+        if self.cache.get(key).is_none() {
+            // Usually loaded by `fetch` already, off the runtime.
+            let entry = match self.load(key) {
+                Ok(entry) => entry?,
+                Err(err) => {
+                    self.writer.fail(err);
+                    return None;
+                }
+            };
+            self.cache(key.to_string(), entry);
+        }
+        self.cache.get_mut(key)
+    }
+
+    fn peek(&self, key: &str) -> Option<&Entry> {
+        self.cache.get(key)
+    }
+
+    fn read(&self, key: &str) -> Option<Entry> {
+        self.keys.get(key)?;
+        if let Some(entry) = self.cache.get(key) {
+            return Some(entry.clone());
+        }
+        match self.load(key) {
+            Ok(entry) => entry,
+            Err(err) => {
+                self.writer.fail(err);
+                None
+            }
+        }
+    }
+
+    fn expires_at(&self, key: &str) -> Option<Instant> {
+        self.keys
+            .get_with_expiration(key)
+            .and_then(|(_, expires_at)| expires_at)
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        self.finish_background();
+        let value = Value::Put {
+            data: entry.data.clone(),
+            expires_at: entry.expires_at.map(to_unix),
+        };
+        self.write(key.clone(), value);
+        self.keys
+            .insert(key.clone(), entry.data.len(), entry.expires_at);
+        self.cache(key, entry);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        self.finish_background();
+        let (_, expires_at) = self.keys.get_with_expiration(key)?;
+        // The value isn't read just to be dropped.
+        let entry = self
+            .uncache(key)
+            .unwrap_or_else(|| Entry::new(Bytes::new(), expires_at));
+        self.write(key.to_string(), Value::Deleted);
+        self.keys.remove(key);
+        Some(entry)
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn expires(&self) -> usize {
+        self.keys.expires()
+    }
+
+    fn next_expiration(&self) -> Option<(Instant, &str)> {
+        self.keys.next_expiration()
+    }
+
+    fn sample(&mut self, volatile: bool) -> Option<String> {
+        self.keys.sample(volatile).map(str::to_string)
+    }
+
+    fn clear(&mut self) -> Box<dyn Send> {
+        // An empty segment covering every log so far replaces the segments,
+        // even those a flush or a compaction is still writing.
+        let id = self.log_id;
+        self.cleared = id;
+        self.log_id += 1;
+        let segments = std::mem::take(&mut self.segments);
+        self.writer.send(Task::Clear(id, segments));
+        self.writer.send(Task::Rotate(self.log_id));
+        self.writer.send(Task::RemoveLog(id));
+        self.memtable_size = 0;
+        self.cached = 0;
+        Box::new((
+            std::mem::take(&mut self.keys),
+            std::mem::take(&mut self.cache),
+            std::mem::take(&mut self.memtable),
+        ))
+    }
+
+    fn for_each_key(&self, f: &mut dyn FnMut(&str, usize, Option<Instant>)) {
+        self.keys
+            .for_each(|key, len, expires_at| f(key, *len, expires_at));
+    }
+
+    fn resident_memory(&self) -> Option<usize> {
+        let flushing = self.flushing.as_ref().map_or(0, |flushing| flushing.size);
+        Some(self.cached + self.memtable_size + flushing)
+    }
+
+    fn shrink(&mut self, bytes: usize) -> usize {
+        self.finish_background();
+        let mut freed = 0;
+        while freed < bytes {
+            match self.drop_oldest() {
+                Some(len) => freed += len,
+                None => break,
+            }
+        }
+        // The memtable is freed once written to a segment.
+        if freed < bytes && self.flushing.is_none() && !self.memtable.is_empty() {
+            self.flush_memtable();
+        }
+        freed
+    }
+
+    fn fetch(&self, keys: &[&str]) -> Option<Fetch> {
+        let missing: Vec<String> = keys
+            .iter()
+            .filter(|key| {
+                self.keys.get(key).is_some()
+                    && self.cache.get(key).is_none()
+                    && self.in_memory(key).is_none()
+            })
+            .map(|key| key.to_string())
+            .collect();
+        if missing.is_empty() {
+            return None;
+        }
+        let segments = self.segments.clone();
+        let flushes = self.flushes;
+        Some(Box::new(move || {
+            let values = missing
+                .into_iter()
+                .map(|key| {
+                    let value = find(&segments, &key);
+                    (key, value)
+                })
+                .collect();
+            Box::new(Loaded { flushes, values }) as Fetched
+        }))
+    }
+
+    fn fetched(&mut self, fetched: Fetched) {
+        let Ok(loaded) = fetched.downcast::<Loaded>() else {
+            return;
+        };
+        if loaded.flushes != self.flushes {
+            return;
+        }
+        for (key, value) in loaded.values {
+            let value = match value {
+                Ok(value) => value,
+                Err(err) => {
+                    self.writer.fail(err);
+                    continue;
+                }
+            };
+            // The key may have been written, removed or loaded meanwhile.
+            if self.keys.get(&key).is_none()
+                || self.cache.get(&key).is_some()
+                || self.in_memory(&key).is_some()
+            {
+                continue;
+            }
+            if let Some(entry) = into_entry(value) {
+                self.cache(key, entry);
+            }
+        }
+    }
+
+    fn written(&self) -> Option<Written> {
+        let mut written = self.writer.written.clone();
+        let appended = self.appended;
+        Some(Box::pin(async move {
+            // Also resolves if the thread stopped on an error.
+            let _ = written.wait_for(|written| *written >= appended).await;
+        }))
+    }
+
+    fn error(&self) -> Option<String> {
+        self.writer.error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for LsmStorage {
+    fn drop(&mut self) {
+        // Wait for the segments being written, so that the storage can be
+        // opened again right away.
+        if let Some(flushing) = &self.flushing {
+            let _ = flushing.done.recv();
+        }
+        if let Some(compaction) = &self.compaction {
+            let _ = compaction.recv();
+        }
+    }
+}
+
+impl Writer {
+    /// Starts the thread, appending to `wal` until told otherwise.
+    fn start(dir: PathBuf, wal: Wal) -> io::Result<Writer> {
+        let (tx, rx) = mpsc::channel();
+        let (written_tx, written) = watch::channel(0);
+        let error = Arc::new(Mutex::new(None));
+        let failed = error.clone();
+        let thread = thread::Builder::new()
+            .name("lsm-writer".to_string())
+            .spawn(move || {
+                if let Err(err) = run_tasks(&dir, wal, rx, &written_tx) {
+                    fail(&failed, err);
+                }
+            })?;
+        Ok(Writer {
+            tasks: Some(tx),
+            thread: Some(thread),
+            written,
+            error,
+        })
+    }
+
+    fn send(&self, task: Task) {
+        // After an error, the thread is gone and the task is dropped.
+        if let Some(tasks) = &self.tasks {
+            let _ = tasks.send(task);
+        }
+    }
+
+    fn fail(&self, err: io::Error) {
+        fail(&self.error, err);
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        // Let the thread run the tasks queued before returning.
+        drop(self.tasks.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Routine of the `Writer` thread, running the tasks until the storage is
+/// dropped or one of them fails.
+fn run_tasks(
+    dir: &Path,
+    mut wal: Wal,
+    tasks: mpsc::Receiver<Task>,
+    written: &watch::Sender<u64>,
+) -> io::Result<()> {
+    let mut next = tasks.recv().ok();
+    while let Some(task) = next.take() {
+        match task {
+            Task::Append(mut records) => {
+                // The records appended meanwhile are written at once.
+                let mut count = 1;
+                loop {
+                    match tasks.try_recv() {
+                        Ok(Task::Append(more)) => {
+                            records.extend_from_slice(&more);
+                            count += 1;
+                        }
+                        Ok(task) => {
+                            next = Some(task);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                wal.write(&records)?;
+                written.send_modify(|written| *written += count);
+            }
+            Task::Rotate(id) => wal = Wal::create(dir, id)?,
+            Task::Clear(id, segments) => {
+                Segment::write(dir, 0, id, 0, std::iter::empty())?;
+                for segment in segments {
+                    segment.remove()?;
+                }
+            }
+            Task::RemoveSegments(segments) => {
+                for segment in segments {
+                    segment.remove()?;
+                }
+            }
+            Task::RemoveLog(id) => Wal::remove(dir, id)?,
+        }
+        if next.is_none() {
+            next = tasks.recv().ok();
+        }
+    }
+    Ok(())
+}
+
+/// Records the first I/O error of a storage.
+fn fail(error: &Mutex<Option<String>>, err: io::Error) {
+    error!(case = %err, "lsm storage failed, refusing writes");
+    error.lock().unwrap().get_or_insert_with(|| err.to_string());
+}
+
+/// Looks `key` up in `segments`, from the newest.
+fn find(segments: &[Arc<Segment>], key: &str) -> io::Result<Option<Value>> {
+    for segment in segments.iter().rev() {
+        if let Some(value) = segment.get(key)? {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// The entry of the last write of a key, `None` if it removed the key.
+fn into_entry(value: Option<Value>) -> Option<Entry> {
+    match value {
+        Some(Value::Put { data, expires_at }) => Some(Entry::new(data, expires_at.map(from_unix))),
+        Some(Value::Deleted) | None => None,
+    }
+}
+
+/// Merges `inputs`, sorted from the oldest to the newest, into a segment
+/// covering them all. As the oldest segment is merged, there is nothing left
+/// for the removed keys to hide, so they are dropped.
+fn compact(dir: &Path, inputs: &[Arc<Segment>]) -> io::Result<Segment> {
+    let mut iters: Vec<_> = inputs
+        .iter()
+        .map(|segment| segment.iter().peekable())
+        .collect();
+    let capacity = inputs.iter().map(|segment| segment.len).sum();
+    let merged = std::iter::from_fn(move || loop {
+        // The smallest key, from the newest segment holding it.
+        let mut next: Option<(usize, String)> = None;
+        for (position, iter) in iters.iter_mut().enumerate() {
+            match iter.peek() {
+                Some(Ok((key, _))) if next.as_ref().is_none_or(|(_, next)| key <= next) => {
+                    next = Some((position, key.clone()));
+                }
+                Some(Err(_)) => return iter.next(),
+                _ => {}
+            }
+        }
+        let (newest, key) = next?;
+        let mut record = None;
+        for (position, iter) in iters.iter_mut().enumerate() {
+            if matches!(iter.peek(), Some(Ok((next, _))) if *next == key) {
+                let taken = iter.next();
+                if position == newest {
+                    record = taken;
+                }
+            }
+        }
+        match record {
+            Some(Ok((_, Value::Deleted))) => continue,
+            record => return record,
+        }
+    });
+    let first = inputs[0].first;
+    let last = inputs[inputs.len() - 1].last;
+    Segment::write(dir, first, last, capacity, merged)
+}
+
+/// Refuses to open a directory written with a different number of shards,
+/// whose keys would be looked up in the wrong shards.
+pub fn check_shards(dir: &Path, shards: usize) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("shards");
+    match fs::read_to_string(&path) {
+        Ok(content) if content.trim() == shards.to_string() => Ok(()),
+        Ok(content) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' holds {} shards, not {}",
+                dir.display(),
+                content.trim(),
+                shards
+            ),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            fs::write(&path, format!("{}\n", shards))?;
+            sync_dir(dir)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Makes the creation, renaming or removal of files in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
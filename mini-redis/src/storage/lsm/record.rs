@@ -0,0 +1,118 @@
+//! Encoding of the records written to the write-ahead log and the segments.
+//!
+//! A record starts with a header: the length of the key as a little endian
+//! `u32`, the key, then a tag byte. A put goes on with the expiration in
+//! milliseconds since the Unix epoch as a `u64`, 0 if the key doesn't
+//! expire, and the length of the value as a `u32`, followed by the value
+//! itself. Segments also list the headers on their own, see `Segment`.
+
+use bytes::{Buf, BufMut, Bytes};
+use std::io;
+
+const PUT: u8 = 0;
+const DELETED: u8 = 1;
+
+/// Bytes of a record besides its key and value.
+pub(super) const RECORD_OVERHEAD: usize = 17;
+
+/// The last write of a key.
+#[derive(Clone, Debug)]
+pub(super) enum Value {
+    /// The key holds `data`, expiring at `expires_at` milliseconds since the
+    /// Unix epoch if set.
+    Put {
+        data: Bytes,
+        expires_at: Option<u64>,
+    },
+    /// The key was removed.
+    Deleted,
+}
+
+/// What the header of a record says about its key: `None` if it was
+/// removed, or the length of its value and its expiration.
+pub(super) type Header = Option<(usize, Option<u64>)>;
+
+impl Value {
+    pub(super) fn header(&self) -> Header {
+        match self {
+            Value::Put { data, expires_at } => Some((data.len(), *expires_at)),
+            Value::Deleted => None,
+        }
+    }
+}
+
+/// Appends the record of `key` to `buf`.
+pub(super) fn encode(buf: &mut Vec<u8>, key: &str, value: &Value) {
+    encode_header(buf, key, value.header());
+    if let Value::Put { data, .. } = value {
+        buf.put_slice(data);
+    }
+}
+
+/// Appends the header of the record of `key` to `buf`.
+pub(super) fn encode_header(buf: &mut Vec<u8>, key: &str, header: Header) {
+    buf.put_u32_le(key.len() as u32);
+    buf.put_slice(key.as_bytes());
+    match header {
+        Some((len, expires_at)) => {
+            buf.put_u8(PUT);
+            buf.put_u64_le(expires_at.unwrap_or(0));
+            buf.put_u32_le(len as u32);
+        }
+        None => buf.put_u8(DELETED),
+    }
+}
+
+/// Decodes the record at the start of `buf`, advancing past it. Values are
+/// copied out of `buf`.
+pub(super) fn decode(buf: &mut &[u8]) -> io::Result<(String, Value)> {
+    let (key, header) = decode_header(buf)?;
+    let value = match header {
+        Some((len, expires_at)) => {
+            if buf.len() < len {
+                return Err(truncated());
+            }
+            let data = Bytes::copy_from_slice(&buf[..len]);
+            buf.advance(len);
+            Value::Put { data, expires_at }
+        }
+        None => Value::Deleted,
+    };
+    Ok((key, value))
+}
+
+/// Decodes the header at the start of `buf`, advancing past it.
+pub(super) fn decode_header(buf: &mut &[u8]) -> io::Result<(String, Header)> {
+    if buf.len() < 4 {
+        return Err(truncated());
+    }
+    let len = buf.get_u32_le() as usize;
+    if buf.len() < len + 1 {
+        return Err(truncated());
+    }
+    let key = String::from_utf8(buf[..len].to_vec())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    buf.advance(len);
+    let header = match buf.get_u8() {
+        PUT => {
+            if buf.len() < 12 {
+                return Err(truncated());
+            }
+            let expires_at = Some(buf.get_u64_le()).filter(|&at| at != 0);
+            let len = buf.get_u32_le() as usize;
+            Some((len, expires_at))
+        }
+        DELETED => None,
+        tag => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown record tag {}", tag),
+            ))
+        }
+    };
+    Ok((key, header))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record")
+}
@@ -0,0 +1,277 @@
+use super::bloom::Bloom;
+use super::record::{self, Header, Value};
+use super::sync_dir;
+
+use bytes::{Buf, BufMut};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// One entry of the sparse index per this many records. Lookups read the
+/// records between two entries.
+const INDEX_INTERVAL: usize = 16;
+
+/// Marks the end of a complete segment file.
+const MAGIC: u64 = 0x6d696e692d6c736d;
+
+/// Offsets of the sections, the number of records and `MAGIC`.
+const FOOTER_LEN: u64 = 40;
+
+/// An immutable file of records sorted by key, written when the memtable
+/// is full or by a compaction.
+///
+/// The file holds the records, then their headers again so that the keys
+/// can be listed without reading the values, the sparse index, the bloom
+/// filter and the footer. Files are written under a temporary name and
+/// renamed once complete, so a segment file is never partially written.
+///
+/// A segment is named after the range of write-ahead logs whose records it
+/// holds. A segment covering the range of another replaces it.
+#[derive(Debug)]
+pub(super) struct Segment {
+    /// First write-ahead log covered.
+    pub(super) first: u64,
+    /// Last write-ahead log covered.
+    pub(super) last: u64,
+    path: PathBuf,
+    file: Mutex<File>,
+    /// Offset of the headers, and end of the records.
+    keys_offset: u64,
+    /// End of the headers.
+    index_offset: u64,
+    /// Every `INDEX_INTERVAL`th key along with the offset of its record.
+    index: Vec<(String, u64)>,
+    bloom: Bloom,
+    /// Number of records.
+    pub(super) len: usize,
+}
+
+impl Segment {
+    /// Writes the records of `records`, sorted by key, to a new segment
+    /// covering the logs from `first` to `last`. `capacity` is an upper bound
+    /// on the number of records, used to size the bloom filter.
+    pub(super) fn write(
+        dir: &Path,
+        first: u64,
+        last: u64,
+        capacity: usize,
+        records: impl Iterator<Item = io::Result<(String, Value)>>,
+    ) -> io::Result<Segment> {
+        let path = dir.join(format!("{:020}-{:020}.seg", first, last));
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let mut offset = 0;
+        let mut headers = Vec::new();
+        let mut index = Vec::new();
+        let mut bloom = Bloom::new(capacity);
+        let mut len = 0;
+        let mut buf = Vec::new();
+        for record in records {
+            let (key, value) = record?;
+            if len % INDEX_INTERVAL == 0 {
+                index.put_u32_le(key.len() as u32);
+                index.put_slice(key.as_bytes());
+                index.put_u64_le(offset);
+            }
+            buf.clear();
+            record::encode(&mut buf, &key, &value);
+            writer.write_all(&buf)?;
+            offset += buf.len() as u64;
+            record::encode_header(&mut headers, &key, value.header());
+            bloom.insert(&key);
+            len += 1;
+        }
+        let keys_offset = offset;
+        writer.write_all(&headers)?;
+        let index_offset = keys_offset + headers.len() as u64;
+        writer.write_all(&index)?;
+        let bloom_offset = index_offset + index.len() as u64;
+        let mut footer = Vec::new();
+        bloom.encode(&mut footer);
+        footer.put_u64_le(keys_offset);
+        footer.put_u64_le(index_offset);
+        footer.put_u64_le(bloom_offset);
+        footer.put_u64_le(len as u64);
+        footer.put_u64_le(MAGIC);
+        writer.write_all(&footer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        sync_dir(dir)?;
+        Segment::open(&path)
+    }
+
+    /// Opens the segment at `path`, loading its index and bloom filter.
+    pub(super) fn open(path: &Path) -> io::Result<Segment> {
+        let (first, last) = Segment::parse_name(path)
+            .ok_or_else(|| invalid(format!("bad segment name '{}'", path.display())))?;
+        let mut file = File::open(path)?;
+        let size = file.seek(SeekFrom::End(0))?;
+        if size < FOOTER_LEN {
+            return Err(invalid(format!("truncated segment '{}'", path.display())));
+        }
+        let mut footer = [0; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(size - FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        let mut footer = &footer[..];
+        let keys_offset = footer.get_u64_le();
+        let index_offset = footer.get_u64_le();
+        let bloom_offset = footer.get_u64_le();
+        let len = footer.get_u64_le() as usize;
+        if footer.get_u64_le() != MAGIC
+            || !(keys_offset <= index_offset
+                && index_offset <= bloom_offset
+                && bloom_offset <= size - FOOTER_LEN)
+        {
+            return Err(invalid(format!("corrupt segment '{}'", path.display())));
+        }
+
+        let mut tail = vec![0; (size - FOOTER_LEN - index_offset) as usize];
+        file.seek(SeekFrom::Start(index_offset))?;
+        file.read_exact(&mut tail)?;
+        let (mut buf, bloom) = tail.split_at((bloom_offset - index_offset) as usize);
+        let mut index = Vec::new();
+        while buf.has_remaining() {
+            if buf.len() < 4 {
+                return Err(invalid("truncated index".to_string()));
+            }
+            let key_len = buf.get_u32_le() as usize;
+            if buf.len() < key_len + 8 {
+                return Err(invalid("truncated index".to_string()));
+            }
+            let key = String::from_utf8(buf[..key_len].to_vec())
+                .map_err(|err| invalid(err.to_string()))?;
+            buf.advance(key_len);
+            index.push((key, buf.get_u64_le()));
+        }
+        Ok(Segment {
+            first,
+            last,
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            keys_offset,
+            index_offset,
+            index,
+            bloom: Bloom::decode(bloom)?,
+            len,
+        })
+    }
+
+    /// Range of logs covered by the segment at `path`, if it is named like
+    /// one.
+    pub(super) fn parse_name(path: &Path) -> Option<(u64, u64)> {
+        let (first, last) = path.file_stem()?.to_str()?.split_once('-')?;
+        Some((first.parse().ok()?, last.parse().ok()?))
+    }
+
+    /// Returns `true` if the segment replaces `other`.
+    pub(super) fn covers(&self, other: &Segment) -> bool {
+        (self.first, self.last) != (other.first, other.last)
+            && self.first <= other.first
+            && other.last <= self.last
+    }
+
+    /// Looks up the record of `key`.
+    pub(super) fn get(&self, key: &str) -> io::Result<Option<Value>> {
+        if !self.bloom.contains(key) {
+            return Ok(None);
+        }
+        // The block starting with the last indexed key before `key`.
+        let block = self
+            .index
+            .partition_point(|(indexed, _)| &indexed[..] <= key);
+        if block == 0 {
+            return Ok(None);
+        }
+        let found = self
+            .read_block(block - 1)?
+            .into_iter()
+            .find(|(found, _)| found == key);
+        Ok(found.map(|(_, value)| value))
+    }
+
+    /// Reads the records of the `block`th block of the sparse index.
+    fn read_block(&self, block: usize) -> io::Result<VecDeque<(String, Value)>> {
+        let start = self.index[block].1;
+        let end = self
+            .index
+            .get(block + 1)
+            .map_or(self.keys_offset, |(_, offset)| *offset);
+        let mut content = vec![0; (end - start) as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut content)?;
+        drop(file);
+
+        let mut buf = &content[..];
+        let mut records = VecDeque::new();
+        while buf.has_remaining() {
+            records.push_back(record::decode(&mut buf)?);
+        }
+        Ok(records)
+    }
+
+    /// The header of every record, sorted by key.
+    pub(super) fn headers(&self) -> io::Result<Vec<(String, Header)>> {
+        let mut content = vec![0; (self.index_offset - self.keys_offset) as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(self.keys_offset))?;
+        file.read_exact(&mut content)?;
+        drop(file);
+
+        let mut buf = &content[..];
+        let mut headers = Vec::with_capacity(self.len);
+        while buf.has_remaining() {
+            headers.push(record::decode_header(&mut buf)?);
+        }
+        Ok(headers)
+    }
+
+    /// Iterates over the records, sorted by key, a block at a time.
+    pub(super) fn iter(self: &Arc<Segment>) -> Records {
+        Records {
+            segment: self.clone(),
+            block: 0,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Deletes the file of the segment.
+    pub(super) fn remove(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Iterator over the records of a segment.
+pub(super) struct Records {
+    segment: Arc<Segment>,
+    block: usize,
+    records: VecDeque<(String, Value)>,
+}
+
+impl Iterator for Records {
+    type Item = io::Result<(String, Value)>;
+
+    fn next(&mut self) -> Option<io::Result<(String, Value)>> {
+        while self.records.is_empty() {
+            if self.block == self.segment.index.len() {
+                return None;
+            }
+            match self.segment.read_block(self.block) {
+                Ok(records) => self.records = records,
+                Err(err) => {
+                    // Stop after reporting the error.
+                    self.block = self.segment.index.len();
+                    return Some(Err(err));
+                }
+            }
+            self.block += 1;
+        }
+        self.records.pop_front().map(Ok)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,132 @@
+use super::record::{self, Value};
+use super::sync_dir;
+
+use bytes::{Buf, BufMut};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Log of the writes held by the memtable, replayed when the storage is
+/// opened after a crash.
+///
+/// Each record is framed by its length and CRC-32, as a `u32` each, so that
+/// a record cut short by a crash is detected and dropped. Records are
+/// written by the background thread of the storage, which acknowledges them
+/// once handed to the operating system without waiting for the disk: writes
+/// acknowledged before the process dies survive it, but not necessarily a
+/// crash of the whole machine.
+#[derive(Debug)]
+pub(super) struct Wal {
+    file: File,
+}
+
+impl Wal {
+    /// Creates the log numbered `id` in `dir`.
+    pub(super) fn create(dir: &Path, id: u64) -> io::Result<Wal> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(Wal::path(dir, id))?;
+        sync_dir(dir)?;
+        Ok(Wal { file })
+    }
+
+    /// Path of the log numbered `id` in `dir`.
+    pub(super) fn path(dir: &Path, id: u64) -> PathBuf {
+        dir.join(format!("{:020}.wal", id))
+    }
+
+    /// Number of the log at `path`, if it is named like one.
+    pub(super) fn parse_name(path: &Path) -> Option<u64> {
+        path.file_stem()?.to_str()?.parse().ok()
+    }
+
+    /// Appends the framed record of `key` to `buf`, to be written with
+    /// `write`.
+    pub(super) fn encode(buf: &mut Vec<u8>, key: &str, value: &Value) {
+        let start = buf.len();
+        buf.put_u64_le(0);
+        record::encode(buf, key, value);
+        let len = (buf.len() - start - 8) as u32;
+        let crc = crc32(&buf[start + 8..]);
+        buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        buf[start + 4..start + 8].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Appends records encoded with `encode`.
+    pub(super) fn write(&mut self, records: &[u8]) -> io::Result<()> {
+        self.file.write_all(records)
+    }
+
+    /// Calls `f` with each record of the log at `path`, in the order they
+    /// were appended. A torn record at the end is dropped with the ones after
+    /// it.
+    pub(super) fn replay(path: &Path, mut f: impl FnMut(String, Value)) -> io::Result<()> {
+        let content = fs::read(path)?;
+        let mut buf = &content[..];
+        while buf.len() >= 8 {
+            let len = (&buf[..4]).get_u32_le() as usize;
+            let crc = (&buf[4..8]).get_u32_le();
+            let Some(mut payload) = buf.get(8..8 + len) else {
+                break;
+            };
+            if crc32(payload) != crc {
+                break;
+            }
+            let (key, value) = record::decode(&mut payload)?;
+            f(key, value);
+            buf = &buf[8 + len..];
+        }
+        if !buf.is_empty() {
+            warn!(path = %path.display(), bytes = buf.len(), "dropping torn write-ahead log records");
+        }
+        Ok(())
+    }
+
+    /// Deletes the log numbered `id` in `dir`, once its records are in a
+    /// segment.
+    pub(super) fn remove(dir: &Path, id: u64) -> io::Result<()> {
+        fs::remove_file(Wal::path(dir, id))
+    }
+}
+
+/// Table of the CRC-32 used by zlib and Ethernet.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+}
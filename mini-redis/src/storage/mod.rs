@@ -0,0 +1,297 @@
+//! Where the shards of a `Db` keep their keys.
+//!
+//! A shard handles the policies: expiration, eviction, lazy freeing and the
+//! statistics. It stores and looks up the entries through the `Storage`
+//! trait, so the same command layer can run on another store. `MemoryStorage`,
+//! the default, keeps everything in memory, and `LsmStorage` keeps the values
+//! on disk.
+//!
+//! A storage is only ever used by one shard at a time, under the shard's
+//! lock, so its methods take `&mut self` and need no synchronization.
+
+mod index;
+pub mod lsm;
+
+pub use lsm::{LsmOptions, LsmStorage};
+
+use crate::eviction::Lfu;
+use index::KeyIndex;
+
+use bytes::Bytes;
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, Instant};
+
+/// Approximate bookkeeping cost of a key, on top of the key and value
+/// themselves: the entry, its hash table slot and its slot in the key pools.
+pub(crate) const ENTRY_OVERHEAD: usize = 96;
+
+/// Operations a shard performs on its keys.
+pub trait Storage: fmt::Debug + Send {
+    /// Returns the entry stored under `key`, loading it in memory if
+    /// needed.
+    fn get(&mut self, key: &str) -> Option<&mut Entry>;
+
+    /// Returns the entry stored under `key` if it is in memory. Used to pick
+    /// keys to evict without loading them.
+    fn peek(&self, key: &str) -> Option<&Entry>;
+
+    /// Returns a copy of the entry stored under `key`, reading it if needed
+    /// but, unlike `get`, without keeping it in memory. Used to send the
+    /// keyspace to replicas without evicting the values in use.
+    fn read(&self, key: &str) -> Option<Entry> {
+        self.peek(key).cloned()
+    }
+
+    /// Instant at which `key` expires, if it exists and has an expiration.
+    /// Used to expire keys without loading them.
+    fn expires_at(&self, key: &str) -> Option<Instant> {
+        self.peek(key).and_then(|entry| entry.expires_at)
+    }
+
+    /// Stores `entry` under `key`. There must be no entry for `key` already.
+    fn insert(&mut self, key: String, entry: Entry);
+
+    /// Removes the entry stored under `key`, along with its expiration. A
+    /// storage keeping the value out of memory may return it empty rather
+    /// than load it.
+    fn remove(&mut self, key: &str) -> Option<Entry>;
+
+    /// Number of keys.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if there are no keys.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of keys with an expiration.
+    fn expires(&self) -> usize;
+
+    /// The key expiring first, along with the instant it expires at.
+    fn next_expiration(&self) -> Option<(Instant, &str)>;
+
+    /// Returns a random key, picked among the keys with an expiration if
+    /// `volatile` is set. Used to sample keys to evict.
+    fn sample(&mut self, volatile: bool) -> Option<String>;
+
+    /// Removes every entry, returning them so they can be dropped later.
+    fn clear(&mut self) -> Box<dyn Send>;
+
+    /// Visits every key, along with the length of its value and its
+    /// expiration. Used to account for the keys a storage holds when
+    /// opened.
+    fn for_each_key(&self, f: &mut dyn FnMut(&str, usize, Option<Instant>));
+
+    /// Bytes of values held in memory, for a storage keeping the values
+    /// elsewhere too. Only those count towards `maxmemory`, and eviction
+    /// calls `shrink` rather than removing keys. `None` if every value is
+    /// in memory.
+    fn resident_memory(&self) -> Option<usize> {
+        None
+    }
+
+    /// Frees about `bytes` of the values held in memory, used the longest
+    /// ago first, without losing them. Returns the bytes freed.
+    fn shrink(&mut self, _bytes: usize) -> usize {
+        0
+    }
+
+    /// Returns a function reading the values of `keys` that are out of
+    /// memory, to be called on a blocking thread without the shard's lock.
+    /// Its result is handed to `fetched`, so that commands then find the
+    /// values in memory. `None` if there is nothing to read.
+    fn fetch(&self, _keys: &[&str]) -> Option<Fetch> {
+        None
+    }
+
+    /// Keeps in memory the values read by a function `fetch` returned.
+    fn fetched(&mut self, _fetched: Fetched) {}
+
+    /// Returns a future resolving once the writes made so far are durable,
+    /// for a storage making them so in the background. Replies to writes
+    /// wait for it, without the shard's lock. `None` if they already are.
+    fn written(&self) -> Option<Written> {
+        None
+    }
+
+    /// The I/O error after which the storage can't keep writes, if any.
+    fn error(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Resolves once writes are durable, see `Storage::written`.
+pub type Written = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Reads values out of memory, see `Storage::fetch`.
+pub type Fetch = Box<dyn FnOnce() -> Fetched + Send>;
+
+/// Values read by a `Fetch`, in a form only the storage that returned it
+/// knows.
+pub type Fetched = Box<dyn Any + Send>;
+
+/// Entry in the key-value store
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Stored data
+    pub(crate) data: Bytes,
+
+    /// Instant at which the entry expires and should be removed from the
+    /// database.
+    pub(crate) expires_at: Option<Instant>,
+
+    /// When the key was last read or written, for the LRU policies.
+    pub(crate) accessed_at: Instant,
+
+    /// How often the key is accessed, for the LFU policies.
+    pub(crate) lfu: Lfu,
+}
+
+/// Keeps the keys in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: KeyIndex<Entry>,
+}
+
+impl Entry {
+    /// A new entry, expiring at `expires_at` if set.
+    pub fn new(data: Bytes, expires_at: Option<Instant>) -> Entry {
+        Entry {
+            data,
+            expires_at,
+            accessed_at: Instant::now(),
+            lfu: Lfu::new(),
+        }
+    }
+
+    /// The stored value.
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// Instant at which the entry expires, if it does.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    /// Approximate number of bytes used by the entry stored under `key`.
+    pub(crate) fn memory_usage(&self, key: &str) -> usize {
+        memory_usage(key, self.data.len(), self.expires_at.is_some())
+    }
+
+    /// Part of `memory_usage` for the copies of the key tracking its
+    /// expiration.
+    pub(crate) fn expiration_overhead(&self, key: &str) -> usize {
+        expiration_overhead(key, self.expires_at.is_some())
+    }
+
+    /// The encoding Redis would use for the value. Integers are stored as
+    /// such, short strings along with their header.
+    pub(crate) fn encoding(&self) -> &'static str {
+        let is_int = self.data.len() <= 20
+            && std::str::from_utf8(&self.data)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .is_some();
+        if is_int {
+            "int"
+        } else if self.data.len() <= 44 {
+            "embstr"
+        } else {
+            "raw"
+        }
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&mut self, key: &str) -> Option<&mut Entry> {
+        self.entries.get_mut(key)
+    }
+
+    fn peek(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        let expires_at = entry.expires_at;
+        self.entries.insert(key, entry, expires_at);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn expires(&self) -> usize {
+        self.entries.expires()
+    }
+
+    fn next_expiration(&self) -> Option<(Instant, &str)> {
+        self.entries.next_expiration()
+    }
+
+    fn sample(&mut self, volatile: bool) -> Option<String> {
+        self.entries.sample(volatile).map(str::to_string)
+    }
+
+    fn clear(&mut self) -> Box<dyn Send> {
+        Box::new(std::mem::take(&mut self.entries))
+    }
+
+    fn for_each_key(&self, f: &mut dyn FnMut(&str, usize, Option<Instant>)) {
+        self.entries
+            .for_each(|key, entry, expires_at| f(key, entry.data.len(), expires_at));
+    }
+}
+
+/// Approximate number of bytes used by `key` and a value of `len` bytes. The
+/// key is stored twice, plus the copies tracking its expiration.
+pub(crate) fn memory_usage(key: &str, len: usize, expires: bool) -> usize {
+    ENTRY_OVERHEAD + 2 * key.len() + len + expiration_overhead(key, expires)
+}
+
+/// Part of `memory_usage` for the copies of the key in the expirations and
+/// the key pool of the `volatile-*` policies.
+pub(crate) fn expiration_overhead(key: &str, expires: bool) -> usize {
+    if expires {
+        2 * key.len()
+    } else {
+        0
+    }
+}
+
+/// Milliseconds since the Unix epoch at `when`, as stored on disk and sent
+/// to replicas.
+pub(crate) fn to_unix(when: Instant) -> u64 {
+    let now = Instant::now();
+    let at = if when >= now {
+        SystemTime::now() + (when - now)
+    } else {
+        SystemTime::now() - (now - when)
+    };
+    at.duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_millis().max(1) as u64)
+}
+
+/// The instant at `millis` milliseconds since the Unix epoch.
+pub(crate) fn from_unix(millis: u64) -> Instant {
+    let now = Instant::now();
+    let at = UNIX_EPOCH + Duration::from_millis(millis);
+    match at.duration_since(SystemTime::now()) {
+        Ok(ahead) => now + ahead,
+        Err(err) => now.checked_sub(err.duration()).unwrap_or(now),
+    }
+}
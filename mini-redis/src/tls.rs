@@ -0,0 +1,218 @@
+//! TLS termination for the server and TLS connections for clients, based on
+//! `rustls`.
+//!
+//! The server side wraps any `Listener` in a `TlsListener`, configured from
+//! PEM files by `TlsConfig`. Clients connect with `Client::connect_tls`.
+
+use crate::listener::Listener;
+
+use async_trait::async_trait;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio::time::{self, Duration};
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{debug, error};
+
+pub use tokio_rustls::rustls;
+
+/// Time allowed for a client to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of handshakes in progress at once.
+const MAX_HANDSHAKES: usize = 128;
+
+/// Server side TLS settings, mirroring the `tls-*` options of Redis.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// PEM file holding the server certificate chain.
+    pub cert_file: PathBuf,
+
+    /// PEM file holding the private key of the server certificate.
+    pub key_file: PathBuf,
+
+    /// PEM file holding the CA certificates client certificates are verified
+    /// against.
+    pub ca_cert_file: Option<PathBuf>,
+
+    /// Require clients to present a certificate signed by `ca_cert_file`.
+    pub auth_clients: bool,
+}
+
+impl TlsConfig {
+    /// Create a configuration serving `cert_file` without client
+    /// authentication.
+    pub fn new(cert_file: impl Into<PathBuf>, key_file: impl Into<PathBuf>) -> TlsConfig {
+        TlsConfig {
+            cert_file: cert_file.into(),
+            key_file: key_file.into(),
+            ca_cert_file: None,
+            auth_clients: false,
+        }
+    }
+
+    /// Load the certificates and keys and build the `rustls` server
+    /// configuration.
+    pub fn server_config(&self) -> crate::Result<Arc<ServerConfig>> {
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if self.auth_clients {
+            let ca_cert_file = self
+                .ca_cert_file
+                .as_ref()
+                .ok_or("client authentication requires a CA certificate file")?;
+            let roots = load_root_store(ca_cert_file)?;
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let certs = load_certs(&self.cert_file)?;
+        let key = load_private_key(&self.key_file)?;
+        Ok(Arc::new(builder.with_single_cert(certs, key)?))
+    }
+}
+
+/// A `Listener` performing the TLS handshake on connections accepted by an
+/// inner listener.
+///
+/// Handshakes run on their own tasks, so a slow or malicious client cannot
+/// hold up the accept loop. Failed handshakes are logged and dropped. Once
+/// `max_handshakes` are in progress, no connection is accepted until one of
+/// them completes, so clients never finishing theirs can't pile up sockets.
+pub struct TlsListener<L: Listener> {
+    inner: L,
+    acceptor: TlsAcceptor,
+    handshakes: JoinSet<(io::Result<TlsStream<L::Stream>>, String)>,
+    max_handshakes: usize,
+}
+
+impl<L: Listener> TlsListener<L> {
+    /// Wrap `inner`, terminating TLS as described by `config`.
+    pub fn new(inner: L, config: &TlsConfig) -> crate::Result<TlsListener<L>> {
+        Ok(TlsListener::with_server_config(inner, config.server_config()?))
+    }
+
+    /// Wrap `inner`, terminating TLS with an existing `rustls` configuration.
+    pub fn with_server_config(inner: L, config: Arc<ServerConfig>) -> TlsListener<L> {
+        TlsListener {
+            inner,
+            acceptor: TlsAcceptor::from(config),
+            handshakes: JoinSet::new(),
+            max_handshakes: MAX_HANDSHAKES,
+        }
+    }
+
+    /// Set the number of handshakes in progress at once, 128 by default.
+    pub fn set_max_handshakes(&mut self, max: usize) {
+        self.max_handshakes = max.max(1);
+    }
+}
+
+#[async_trait]
+impl<L: Listener> Listener for TlsListener<L> {
+    type Stream = TlsStream<L::Stream>;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, String)> {
+        loop {
+            tokio::select! {
+                res = self.inner.accept(), if self.handshakes.len() < self.max_handshakes => {
+                    let (socket, addr) = res?;
+                    let handshake = self.acceptor.accept(socket);
+                    self.handshakes.spawn(async move {
+                        let res = match time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                            Ok(res) => res,
+                            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+                        };
+                        (res, addr)
+                    });
+                }
+                Some(res) = self.handshakes.join_next() => match res {
+                    Ok((Ok(stream), addr)) => return Ok((stream, addr)),
+                    Ok((Err(err), addr)) => debug!(%addr, %err, "TLS handshake failed"),
+                    Err(err) => error!(%err, "TLS handshake task failed"),
+                },
+            }
+        }
+    }
+
+    fn set_keepalive(stream: &Self::Stream, idle: Option<Duration>) -> io::Result<()> {
+        L::set_keepalive(stream.get_ref().0, idle)
+    }
+}
+
+impl<L: Listener> fmt::Debug for TlsListener<L> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TlsListener")
+            .field("inner", &self.inner)
+            .field("handshakes", &self.handshakes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Build a client configuration trusting the certificates in `roots`.
+pub fn client_config(roots: RootCertStore) -> Arc<ClientConfig> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// Build a client configuration trusting the certificates in `roots` and
+/// presenting `certs` to servers requiring client authentication.
+pub fn client_config_with_auth(
+    roots: RootCertStore,
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> crate::Result<Arc<ClientConfig>> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Load every certificate from the PEM file at `path`.
+pub fn load_certs(path: impl AsRef<Path>) -> crate::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err("no certificate found".into());
+    }
+    Ok(certs)
+}
+
+/// Load the first private key from the PEM file at `path`.
+pub fn load_private_key(path: impl AsRef<Path>) -> crate::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| "no private key found".into())
+}
+
+/// Load the certificates from the PEM file at `path` into a root store.
+pub fn load_root_store(path: impl AsRef<Path>) -> crate::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+/// The crypto provider is chosen explicitly, rather than relying on the
+/// process-wide default, so that other crates enabling a different `rustls`
+/// backend do not make configuration fail.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
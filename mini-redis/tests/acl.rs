@@ -0,0 +1,177 @@
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use std::path::PathBuf;
+
+pub mod common;
+use common::{call, connect, start_server};
+
+fn assert_error(frame: Frame, prefix: &str) {
+    match frame {
+        Frame::Error(msg) if msg.starts_with(prefix) => {}
+        frame => panic!("expected `{}` error, got {:?}", prefix, frame),
+    }
+}
+
+#[tokio::test]
+async fn restrict_commands_and_keys() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+    let reply = call(
+        &mut admin,
+        &["ACL", "SETUSER", "alice", "on", ">secret", "~cache:*", "+@read"],
+    )
+    .await;
+    assert!(reply == "OK");
+
+    let mut client = mini_redis::Client::connect(addr).await.unwrap();
+    let err = client.auth(Some("alice"), "wrong").await.unwrap_err();
+    assert!(err.to_string().starts_with("WRONGPASS"));
+    client.auth(Some("alice"), "secret").await.unwrap();
+
+    assert_eq!(client.get("cache:1").await.unwrap(), None);
+    let err = client.get("session:1").await.unwrap_err();
+    assert_eq!(err.to_string(), "NOPERM No permissions to access a key");
+    let err = client.set("cache:1", "v".into()).await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "NOPERM User alice has no permissions to run the 'set' command"
+    );
+
+    let log = call(&mut admin, &["ACL", "LOG"]).await;
+    let Frame::Array(entries) = log else {
+        panic!("unexpected ACL LOG reply {:?}", log);
+    };
+    // Newest first: the denied SET, the denied key and the failed AUTH.
+    assert_eq!(entries.len(), 3);
+    let Frame::Array(fields) = &entries[0] else {
+        panic!("unexpected ACL LOG entry {:?}", entries[0]);
+    };
+    assert!(fields[3] == "command");
+    assert!(fields[7] == "set");
+    assert!(fields[9] == "alice");
+
+    let reply = call(&mut admin, &["ACL", "LOG", "RESET"]).await;
+    assert!(reply == "OK");
+    let log = call(&mut admin, &["ACL", "LOG"]).await;
+    assert!(matches!(log, Frame::Array(entries) if entries.is_empty()));
+}
+
+#[tokio::test]
+async fn refuse_malformed_subcommands() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    let reply = call(&mut client, &["ACL", "FOO"]).await;
+    assert_error(reply, "ERR unknown subcommand 'foo'");
+    let reply = call(&mut client, &["ACL", "LOG", "-1"]).await;
+    assert_error(reply, "ERR value is out of range");
+    let reply = call(&mut client, &["ACL", "GETUSER"]).await;
+    assert_error(reply, "ERR");
+    // The connection is still open.
+    let reply = call(&mut client, &["PING"]).await;
+    assert!(reply == "PONG");
+}
+
+#[tokio::test]
+async fn restrict_channels() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+    call(
+        &mut admin,
+        &["ACL", "SETUSER", "bob", "on", "nopass", "&news.*", "+@pubsub"],
+    )
+    .await;
+
+    let mut bob = connect(addr).await;
+    assert!(call(&mut bob, &["AUTH", "bob", "any"]).await == "OK");
+    assert_error(
+        call(&mut bob, &["PUBLISH", "sport", "goal"]).await,
+        "NOPERM No permissions to access a channel",
+    );
+    let reply = call(&mut bob, &["PUBLISH", "news.today", "hi"]).await;
+    assert!(matches!(reply, Frame::Integer(0)));
+
+    // Channels are checked in subscribe mode too.
+    call(&mut bob, &["SUBSCRIBE", "news.today"]).await;
+    assert_error(
+        call(&mut bob, &["SUBSCRIBE", "sport"]).await,
+        "NOPERM No permissions to access a channel",
+    );
+}
+
+#[tokio::test]
+async fn inspect_users() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+
+    let whoami = call(&mut admin, &["ACL", "WHOAMI"]).await;
+    assert!(whoami == "default");
+
+    call(
+        &mut admin,
+        &["ACL", "SETUSER", "carol", "on", "nopass", "~*", "+@all", "-set"],
+    )
+    .await;
+    let Frame::Array(user) = call(&mut admin, &["ACL", "GETUSER", "carol"]).await else {
+        panic!("unexpected ACL GETUSER reply");
+    };
+    assert!(user[0] == "flags");
+    assert!(user[4] == "commands");
+    assert!(user[5] == "+@all -set");
+    assert!(user[7] == "~*");
+
+    let list = call(&mut admin, &["ACL", "LIST"]).await;
+    let Frame::Array(lines) = list else {
+        panic!("unexpected ACL LIST reply {:?}", list);
+    };
+    assert!(lines[0] == "user carol on nopass ~* +@all -set");
+    assert!(lines[1] == "user default on nopass ~* &* +@all");
+
+    let Frame::Array(commands) = call(&mut admin, &["ACL", "CAT", "read"]).await else {
+        panic!("unexpected ACL CAT reply");
+    };
+    for name in ["get", "mget", "memory", "object"] {
+        assert!(
+            commands.iter().any(|command| *command == name),
+            "{} missing from {:?}",
+            name,
+            commands
+        );
+    }
+    assert!(!commands.iter().any(|command| *command == "set"));
+
+    assert_error(
+        call(&mut admin, &["ACL", "SETUSER", "carol", "+bogus"]).await,
+        "ERR Error in ACL SETUSER modifier '+bogus'",
+    );
+    assert_error(
+        call(&mut admin, &["ACL", "DELUSER", "default"]).await,
+        "ERR The 'default' user cannot be removed",
+    );
+    let reply = call(&mut admin, &["ACL", "DELUSER", "carol", "nobody"]).await;
+    assert!(matches!(reply, Frame::Integer(1)));
+}
+
+#[tokio::test]
+async fn save_and_load_acl_file() {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}.acl", std::process::id()));
+    std::fs::write(&path, "user default on nopass ~* &* +@all\n").unwrap();
+    let addr = start_server(ServerConfig {
+        aclfile: Some(PathBuf::from(&path)),
+        ..ServerConfig::default()
+    })
+    .await;
+
+    let mut admin = connect(addr).await;
+    call(&mut admin, &["ACL", "SETUSER", "dave", "on", ">pw", "+get"]).await;
+    assert!(call(&mut admin, &["ACL", "SAVE"]).await == "OK");
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(content.contains("user dave on #"));
+    assert!(!content.contains("pw "));
+
+    call(&mut admin, &["ACL", "DELUSER", "dave"]).await;
+    assert!(call(&mut admin, &["ACL", "LOAD"]).await == "OK");
+    let mut client = mini_redis::Client::connect(addr).await.unwrap();
+    client.auth(Some("dave"), "pw").await.unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+}
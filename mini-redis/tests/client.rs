@@ -1,7 +1,9 @@
 use bytes::Bytes;
-use mini_redis::{clients::Client, server};
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::{clients::Client, Command, Connection, Db, MultiListener, Shutdown};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
 
 async fn start_server() -> (SocketAddr, JoinHandle<()>) {
@@ -41,6 +43,15 @@ async fn key_value_get_set() {
     assert_eq!(b"world", &got[..]);
 }
 
+#[tokio::test]
+async fn key_value_get_set_large_value() {
+    let mut client = start_server_client().await;
+    let value: Bytes = (0..1024 * 1024).map(|i| i as u8).collect();
+    client.set("large", value.clone()).await.unwrap();
+    let got = client.get("large").await.unwrap().unwrap();
+    assert_eq!(value, got);
+}
+
 #[tokio::test]
 async fn receive_message_subscribed_channel() {
     let (addr, _) = start_server().await;
@@ -65,7 +76,7 @@ async fn unsubscribes_from_channels_specific() {
         .await
         .unwrap();
 
-    subscriber.unsubscribe(&vec!["hello".into()]).await;
+    subscriber.unsubscribe(&["hello".into()]).await.unwrap();
     assert_eq!(subscriber.get_subscribed().len(), 1);
     assert_eq!(subscriber.get_subscribed()[0], "world");
 }
@@ -80,6 +91,127 @@ async fn unsubscribes_from_channels() {
         .await
         .unwrap();
 
-    subscriber.unsubscribe(&[]).await;
+    subscriber.unsubscribe(&[]).await.unwrap();
     assert_eq!(subscriber.get_subscribed().len(), 0);
 }
+
+#[tokio::test]
+async fn key_value_get_set_in_memory() {
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+
+    tokio::spawn(async move {
+        let db = Db::new();
+        let (_notify, rx) = broadcast::channel(1);
+        let mut shutdown = Shutdown::new(rx);
+        let mut connection = Connection::new(server_stream);
+        while let Some(frame) = connection.read_frame().await.unwrap() {
+            let cmd = Command::from_frame(frame).unwrap();
+            cmd.apply(&db, &mut connection, &mut shutdown)
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut client = Client::from_stream(client_stream);
+    client.set("hello", "world".into()).await.unwrap();
+    let got = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &got[..]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn key_value_get_set_unix_socket() {
+    use mini_redis::listener;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("mini-redis-{}.sock", std::process::id()));
+    let listener = listener::bind_unix(&path, 0o700).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+    tokio::spawn(async move {
+        server::run(listener, tokio::signal::ctrl_c()).await;
+    });
+
+    let mut client = Client::connect_unix(&path).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    let got = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &got[..]);
+
+    std::fs::remove_file(&path).unwrap();
+
+    // Other files are not replaced, and nothing is left next to them.
+    std::fs::write(&path, "data").unwrap();
+    assert!(listener::bind_unix(&path, 0o700).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), b"data");
+    let prefix = format!(".unixsocket-{}-", std::process::id());
+    let leftovers = std::fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with(&prefix)
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+    std::fs::remove_file(&path).unwrap();
+}
+
+async fn start_server_with_password(password: &str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        requirepass: Some(password.to_string()),
+        ..ServerConfig::default()
+    };
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+    });
+    addr
+}
+
+#[tokio::test]
+async fn require_auth_before_commands() {
+    let addr = start_server_with_password("secret").await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let pong = client.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+
+    let err = client.get("hello").await.unwrap_err();
+    assert_eq!(err.to_string(), "NOAUTH Authentication required.");
+
+    let err = client.auth(None, "wrong").await.unwrap_err();
+    assert!(err.to_string().starts_with("WRONGPASS"));
+
+    client.auth(Some("default"), "secret").await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    let got = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &got[..]);
+}
+
+#[tokio::test]
+async fn connect_url_authenticates() {
+    let addr = start_server_with_password("p@ss").await;
+    let url = format!("redis://:p%40ss@{}", addr);
+    let mut client = Client::connect_url(&url).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+
+    let url = format!("redis://:wrong@{}", addr);
+    assert!(Client::connect_url(&url).await.is_err());
+}
+
+#[tokio::test]
+async fn serve_multiple_listeners() {
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (first_addr, second_addr) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+    let mut listener = MultiListener::new();
+    listener.add(first);
+    listener.add(second);
+    tokio::spawn(async move { server::run(listener, tokio::signal::ctrl_c()).await });
+
+    let mut client = Client::connect(first_addr).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    let mut client = Client::connect(second_addr).await.unwrap();
+    let got = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &got[..]);
+}
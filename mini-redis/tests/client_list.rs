@@ -0,0 +1,178 @@
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use tokio::time::{self, Duration};
+use tokio_stream::StreamExt;
+
+pub mod common;
+use common::{call, connect, send, start_server};
+
+/// Returns the text of a bulk string reply.
+fn text(frame: Frame) -> String {
+    match frame {
+        Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+        frame => panic!("expected a bulk string, got {:?}", frame),
+    }
+}
+
+/// Returns the value of `field` in a `CLIENT LIST` line.
+fn field<'a>(line: &'a str, field: &str) -> &'a str {
+    line.trim_end()
+        .split(' ')
+        .find_map(|pair| pair.strip_prefix(field)?.strip_prefix('='))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn name_and_list_clients() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    let Frame::Integer(id) = call(&mut client, &["CLIENT", "ID"]).await else {
+        panic!("expected an integer");
+    };
+    assert!(matches!(
+        call(&mut client, &["CLIENT", "GETNAME"]).await,
+        Frame::Null
+    ));
+    assert!(call(&mut client, &["CLIENT", "SETNAME", "worker-1"]).await == "OK");
+    assert!(call(&mut client, &["CLIENT", "GETNAME"]).await == "worker-1");
+    let reply = call(&mut client, &["CLIENT", "SETNAME", "bad name"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.contains("cannot contain spaces")));
+
+    let info = text(call(&mut client, &["CLIENT", "INFO"]).await);
+    assert_eq!(field(&info, "id"), id.to_string());
+    assert_eq!(field(&info, "name"), "worker-1");
+    assert_eq!(field(&info, "user"), "default");
+    assert_eq!(field(&info, "cmd"), "client");
+
+    call(&mut other, &["PING"]).await;
+    let list = text(call(&mut client, &["CLIENT", "LIST"]).await);
+    let lines: Vec<_> = list.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(field(lines[1], "cmd"), "ping");
+
+    let list = text(call(&mut client, &["CLIENT", "LIST", "ID", &id.to_string()]).await);
+    assert_eq!(list.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn refuse_malformed_subcommands() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    let error = |reply: Frame, prefix: &str| match reply {
+        Frame::Error(msg) => assert!(msg.starts_with(prefix), "{}", msg),
+        frame => panic!("expected an error, got {:?}", frame),
+    };
+    error(
+        call(&mut client, &["CLIENT", "FOO"]).await,
+        "ERR unknown subcommand",
+    );
+    error(call(&mut client, &["CLIENT", "SETNAME"]).await, "ERR");
+    error(
+        call(&mut client, &["CLIENT", "KILL", "ID", "x"]).await,
+        "ERR",
+    );
+    error(
+        call(&mut client, &["CLIENT", "KILL", "SKIPME", "maybe"]).await,
+        "ERR syntax error",
+    );
+    error(
+        call(&mut client, &["CLIENT", "PAUSE", "soon"]).await,
+        "ERR timeout is not an integer",
+    );
+    error(
+        call(&mut client, &["CLIENT", "PAUSE", "10", "READ"]).await,
+        "ERR syntax error",
+    );
+    // The connection is still open.
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+}
+
+#[tokio::test]
+async fn count_subscriptions() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    let mut subscriber = connect(addr).await;
+    call(&mut subscriber, &["SUBSCRIBE", "a"]).await;
+    send(&mut subscriber, &["SUBSCRIBE", "b"]).await;
+    subscriber.next().await.unwrap().unwrap();
+
+    let list = text(call(&mut client, &["CLIENT", "LIST"]).await);
+    let line = list.lines().find(|line| field(line, "cmd") == "subscribe");
+    assert_eq!(field(line.unwrap(), "sub"), "2");
+}
+
+#[tokio::test]
+async fn kill_clients() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+    let mut victim = connect(addr).await;
+    let Frame::Integer(id) = call(&mut victim, &["CLIENT", "ID"]).await else {
+        panic!("expected an integer");
+    };
+
+    let reply = call(&mut admin, &["CLIENT", "KILL", "ID", &id.to_string()]).await;
+    assert!(matches!(reply, Frame::Integer(1)));
+    assert!(victim.next().await.is_none());
+
+    // The old form kills by address.
+    let mut victim = connect(addr).await;
+    let info = text(call(&mut victim, &["CLIENT", "INFO"]).await);
+    let reply = call(&mut admin, &["CLIENT", "KILL", field(&info, "addr")]).await;
+    assert!(reply == "OK");
+    assert!(victim.next().await.is_none());
+    let reply = call(&mut admin, &["CLIENT", "KILL", "127.0.0.1:1"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg == "ERR No such client"));
+
+    // The calling connection is spared unless told otherwise.
+    let mut victim = connect(addr).await;
+    call(&mut victim, &["PING"]).await;
+    let reply = call(&mut admin, &["CLIENT", "KILL", "USER", "default"]).await;
+    assert!(matches!(reply, Frame::Integer(1)));
+    assert!(victim.next().await.is_none());
+    assert!(call(&mut admin, &["PING"]).await == "PONG");
+}
+
+#[tokio::test]
+async fn pause_clients() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+    let mut client = connect(addr).await;
+
+    let reply = call(&mut admin, &["CLIENT", "PAUSE", "10000", "WRITE"]).await;
+    assert!(reply == "OK");
+    // Reads go on, writes wait.
+    assert!(matches!(
+        call(&mut client, &["GET", "hello"]).await,
+        Frame::Null
+    ));
+    send(&mut client, &["SET", "hello", "world"]).await;
+    let paused = time::timeout(Duration::from_millis(100), client.next()).await;
+    assert!(paused.is_err());
+
+    assert!(call(&mut admin, &["CLIENT", "UNPAUSE"]).await == "OK");
+    assert!(client.next().await.unwrap().unwrap() == "OK");
+
+    // A pause ends on its own after the timeout.
+    call(&mut admin, &["CLIENT", "PAUSE", "50"]).await;
+    assert!(call(&mut client, &["GET", "hello"]).await == "world");
+}
+
+#[tokio::test]
+async fn pause_for_longest_timeout() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+
+    // Whether the clock reaches that far depends on the platform, the
+    // server stays up either way.
+    let timeout = u64::MAX.to_string();
+    match call(&mut admin, &["CLIENT", "PAUSE", &timeout, "WRITE"]).await {
+        Frame::Simple(ok) => assert_eq!(ok, "OK"),
+        Frame::Error(err) => assert_eq!(err, "ERR timeout is out of range"),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    assert!(call(&mut admin, &["CLIENT", "UNPAUSE"]).await == "OK");
+    assert!(call(&mut admin, &["SET", "hello", "world"]).await == "OK");
+}
@@ -0,0 +1,44 @@
+//! Helpers shared by the tests talking to a server over RESP. Test files
+//! declare the module `pub`, so that the helpers some of them don't use are
+//! not reported as dead code.
+
+use bytes::Bytes;
+use futures::SinkExt;
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::{Frame, RespCodec};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+pub type Client = Framed<TcpStream, RespCodec>;
+
+/// Starts a server on a free port, until the test ends.
+pub async fn start_server(config: ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+    });
+    addr
+}
+
+pub async fn connect(addr: SocketAddr) -> Client {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    Framed::new(stream, RespCodec::new())
+}
+
+/// Sends a command without waiting for the reply.
+pub async fn send(client: &mut Client, args: &[&str]) {
+    let mut frame = Frame::array();
+    for arg in args {
+        frame.push_bulk(Bytes::from(arg.to_string()));
+    }
+    client.send(frame).await.unwrap();
+}
+
+/// Sends a command and returns the reply.
+pub async fn call(client: &mut Client, args: &[&str]) -> Frame {
+    send(client, args).await;
+    client.next().await.unwrap().unwrap()
+}
@@ -0,0 +1,196 @@
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use std::path::Path;
+
+pub mod common;
+use common::{call, connect, start_server};
+
+/// Returns the items of an array reply.
+fn items(frame: Frame) -> Vec<Frame> {
+    match frame {
+        Frame::Array(items) => items,
+        frame => panic!("expected an array, got {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn get_and_set_parameters() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    let params = items(call(&mut client, &["CONFIG", "GET", "max*"]).await);
+    assert_eq!(params.len(), 8);
+    assert!(params[0] == "maxclients");
+    assert!(params[1] == "250");
+    assert!(params[2] == "maxmemory");
+    assert!(params[3] == "0");
+    assert!(params[4] == "maxmemory-policy");
+    assert!(params[5] == "noeviction");
+    assert!(params[6] == "maxmemory-samples");
+    assert!(params[7] == "5");
+
+    let reply = call(
+        &mut client,
+        &["CONFIG", "SET", "maxclients", "10", "maxmemory", "1mb"],
+    )
+    .await;
+    assert!(reply == "OK");
+    let params = items(call(&mut client, &["CONFIG", "GET", "maxmemory"]).await);
+    assert!(params[1] == "1048576");
+
+    // A failing parameter leaves every other one unchanged.
+    let reply = call(
+        &mut client,
+        &["CONFIG", "SET", "maxclients", "20", "port", "7000"],
+    )
+    .await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.contains("can't set immutable config")));
+    let params = items(call(&mut client, &["CONFIG", "GET", "maxclients"]).await);
+    assert!(params[1] == "10");
+}
+
+#[tokio::test]
+async fn load_tls_parameters() {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}-tls.conf", std::process::id()));
+    std::fs::write(
+        &path,
+        "tls-port 6380\n\
+         tls-cert-file server.crt\n\
+         tls-key-file server.key\n\
+         tls-ca-cert-file ca.crt\n\
+         tls-auth-clients yes\n",
+    )
+    .unwrap();
+    let config = ServerConfig::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config.tls_port, 6380);
+    let tls = config.tls().unwrap().unwrap();
+    assert_eq!(tls.cert_file, Path::new("server.crt"));
+    assert_eq!(tls.key_file, Path::new("server.key"));
+    assert_eq!(tls.ca_cert_file.as_deref(), Some(Path::new("ca.crt")));
+    assert!(tls.auth_clients);
+
+    // TLS is off without a port, and needs a certificate and key with one.
+    assert!(ServerConfig::default().tls().unwrap().is_none());
+    let mut config = ServerConfig::default();
+    config.apply_file("tls-port 6380\n").unwrap();
+    assert!(config.tls().is_err());
+    assert!(config.apply_file("tls-auth-clients maybe\n").is_err());
+
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    let params = items(call(&mut client, &["CONFIG", "GET", "tls-*"]).await);
+    assert_eq!(params.len(), 10);
+    let params = items(call(&mut client, &["CONFIG", "GET", "tls-port"]).await);
+    assert!(params[1] == "0");
+    let reply = call(&mut client, &["CONFIG", "SET", "tls-port", "6380"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.contains("can't set immutable config")));
+}
+
+#[tokio::test]
+async fn set_requirepass() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut admin = connect(addr).await;
+    let reply = call(&mut admin, &["CONFIG", "SET", "requirepass", "secret"]).await;
+    assert!(reply == "OK");
+
+    let mut client = connect(addr).await;
+    let reply = call(&mut client, &["GET", "hello"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.starts_with("NOAUTH")));
+    // Whether a command exists is not revealed either.
+    let reply = call(&mut client, &["NOSUCHCOMMAND"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.starts_with("NOAUTH")));
+    assert!(call(&mut client, &["AUTH", "secret"]).await == "OK");
+}
+
+#[tokio::test]
+async fn keep_acl_file_users_on_set_requirepass() {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}-pass.acl", std::process::id()));
+    std::fs::write(&path, "user default on nopass ~* &* +@all\n").unwrap();
+    let config = ServerConfig {
+        aclfile: Some(path.clone()),
+        ..ServerConfig::default()
+    };
+    let addr = start_server(config).await;
+    let mut admin = connect(addr).await;
+    let reply = call(&mut admin, &["CONFIG", "SET", "requirepass", "secret"]).await;
+    assert!(reply == "OK");
+
+    // The users of the ACL file are left as they are.
+    let mut client = connect(addr).await;
+    assert!(call(&mut client, &["SET", "hello", "world"]).await == "OK");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn rewrite_config_file() {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}.conf", std::process::id()));
+    std::fs::write(&path, "# test server\nport 0\n").unwrap();
+    let addr = start_server(ServerConfig::from_file(&path).unwrap()).await;
+    let mut client = connect(addr).await;
+
+    let reply = call(&mut client, &["CONFIG", "SET", "timeout", "30"]).await;
+    assert!(reply == "OK");
+    assert!(call(&mut client, &["CONFIG", "REWRITE"]).await == "OK");
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content, "# test server\nport 0\ntimeout 30\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn record_slow_commands() {
+    let config = ServerConfig {
+        slowlog_log_slower_than: 0,
+        ..ServerConfig::default()
+    };
+    let addr = start_server(config).await;
+    let mut client = connect(addr).await;
+
+    call(&mut client, &["CLIENT", "SETNAME", "worker"]).await;
+    call(&mut client, &["SET", "hello", "world"]).await;
+    call(&mut client, &["AUTH", "secret"]).await;
+    let entries = items(call(&mut client, &["SLOWLOG", "GET"]).await);
+    assert_eq!(entries.len(), 3);
+
+    let auth = items(entries[0].clone());
+    assert!(auth[5] == "worker");
+    let args = items(auth[3].clone());
+    assert_eq!(args.len(), 2);
+    assert!(args[0] == "AUTH");
+    assert!(args[1] == "(redacted)");
+    let set = items(entries[1].clone());
+    assert_eq!(items(set[3].clone()).len(), 3);
+
+    assert!(call(&mut client, &["SLOWLOG", "RESET"]).await == "OK");
+    let len = call(&mut client, &["SLOWLOG", "LEN"]).await;
+    // The RESET itself is recorded after it ran.
+    assert!(matches!(len, Frame::Integer(1)));
+}
+
+#[tokio::test]
+async fn refuse_malformed_slowlog_subcommands() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    let reply = call(&mut client, &["SLOWLOG", "FOO"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.starts_with("ERR unknown subcommand 'foo'")));
+    let reply = call(&mut client, &["SLOWLOG", "GET", "-5"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.starts_with("ERR count should be")));
+    // The connection is still open.
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+}
+
+#[tokio::test]
+async fn refuse_malformed_config_subcommands() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    let reply = call(&mut client, &["CONFIG", "FOO"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg.starts_with("ERR unknown subcommand 'foo'")));
+    let reply = call(&mut client, &["CONFIG", "SET", "timeout"]).await;
+    assert!(matches!(reply, Frame::Error(_)), "{:?}", reply);
+    // The connection is still open.
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+}
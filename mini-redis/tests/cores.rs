@@ -0,0 +1,138 @@
+use mini_redis::config::{Engine, MaxmemoryPolicy};
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use std::net::SocketAddr;
+use tokio::time::{self, Duration, Instant};
+use tokio_stream::StreamExt;
+
+pub mod common;
+use common::{call, connect, start_server, Client};
+
+async fn start_cores() -> SocketAddr {
+    let config = ServerConfig {
+        engine: Engine::ThreadPerCore,
+        cores: 4,
+        ..ServerConfig::default()
+    };
+    start_server(config).await
+}
+
+#[tokio::test]
+async fn forward_commands_to_the_owning_core() {
+    let addr = start_cores().await;
+    let mut client = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    // With four cores, most of the keys belong to another core than the one
+    // serving the connection.
+    for i in 0..50 {
+        let key = format!("key:{}", i);
+        assert!(call(&mut client, &["SET", &key, &i.to_string()]).await == "OK");
+    }
+    for i in 0..50 {
+        let key = format!("key:{}", i);
+        assert!(call(&mut other, &["GET", &key]).await == i.to_string().as_str());
+    }
+    assert!(matches!(
+        call(&mut other, &["GET", "missing"]).await,
+        Frame::Null
+    ));
+}
+
+#[tokio::test]
+async fn split_keys_of_several_cores() {
+    let addr = start_cores().await;
+    let mut client = connect(addr).await;
+
+    let keys: Vec<String> = (0..20).map(|i| format!("key:{}", i)).collect();
+    let mut args = vec!["MSET"];
+    for key in &keys {
+        args.extend([key.as_str(), key.as_str()]);
+    }
+    assert!(call(&mut client, &args).await == "OK");
+
+    // The values come back in the order of the keys.
+    let mut args = vec!["MGET", "missing"];
+    args.extend(keys.iter().map(String::as_str));
+    let Frame::Array(values) = call(&mut client, &args).await else {
+        panic!("expected an array");
+    };
+    assert_eq!(values.len(), 21);
+    assert!(matches!(values[0], Frame::Null));
+    for (value, key) in values[1..].iter().zip(&keys) {
+        assert!(*value == key.as_str(), "{:?}", value);
+    }
+
+    let mut args = vec!["DEL", "missing"];
+    args.extend(keys[..10].iter().map(String::as_str));
+    assert!(matches!(call(&mut client, &args).await, Frame::Integer(10)));
+    let mut args = vec!["UNLINK"];
+    args.extend(keys.iter().map(String::as_str));
+    assert!(matches!(call(&mut client, &args).await, Frame::Integer(10)));
+}
+
+#[tokio::test]
+async fn evict_and_expire_on_each_core() {
+    let config = ServerConfig {
+        engine: Engine::ThreadPerCore,
+        cores: 4,
+        maxmemory: 48_000,
+        maxmemory_policy: MaxmemoryPolicy::AllkeysLru,
+        ..ServerConfig::default()
+    };
+    let addr = start_server(config).await;
+    let mut client = connect(addr).await;
+
+    // Each core keeps to its share of `maxmemory`.
+    let value = "x".repeat(1000);
+    for i in 0..200 {
+        let key = format!("key:{}", i);
+        assert!(call(&mut client, &["SET", &key, &value]).await == "OK");
+    }
+    let count = keys(&mut client).await;
+    assert!(count > 0 && count < 48, "{}", count);
+
+    // Removed by the cores without being read.
+    call(&mut client, &["FLUSHALL"]).await;
+    for i in 0..20 {
+        let key = format!("key:{}", i);
+        call(&mut client, &["SET", &key, "value", "PX", "20"]).await;
+    }
+    let start = Instant::now();
+    while keys(&mut client).await > 0 {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// The number of keys, as `INFO keyspace` reports.
+async fn keys(client: &mut Client) -> usize {
+    let Frame::Bulk(info) = call(client, &["INFO", "keyspace"]).await else {
+        panic!("expected a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    info.lines()
+        .find_map(|line| line.strip_prefix("db0:keys=")?.split(',').next())
+        .map_or(0, |keys| keys.parse().unwrap())
+}
+
+#[tokio::test]
+async fn publish_across_cores() {
+    let addr = start_cores().await;
+    let mut subscribers = vec![];
+    for _ in 0..4 {
+        let mut subscriber = connect(addr).await;
+        call(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+        subscribers.push(subscriber);
+    }
+
+    let mut publisher = connect(addr).await;
+    let reply = call(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+    assert!(matches!(reply, Frame::Integer(4)));
+    for subscriber in &mut subscribers {
+        let Frame::Array(message) = subscriber.next().await.unwrap().unwrap() else {
+            panic!("expected a message");
+        };
+        assert!(message[2] == "hello");
+    }
+}
@@ -0,0 +1,165 @@
+use mini_redis::config::MaxmemoryPolicy;
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use std::net::SocketAddr;
+
+pub mod common;
+use common::{call, connect, start_server, Client};
+
+/// Room for about ten of the keys set by the tests.
+const MAXMEMORY: u64 = 12_000;
+
+async fn start_with_policy(policy: MaxmemoryPolicy) -> SocketAddr {
+    let config = ServerConfig {
+        maxmemory: MAXMEMORY,
+        maxmemory_policy: policy,
+        // Sample many keys, so the tests don't depend on luck.
+        maxmemory_samples: 64,
+        ..ServerConfig::default()
+    };
+    start_server(config).await
+}
+
+/// Sets `key` to a value of 1000 bytes, expiring after `ttl` seconds if
+/// given.
+async fn set(framed: &mut Client, key: &str, ttl: Option<&str>) -> Frame {
+    let value = "x".repeat(1000);
+    match ttl {
+        Some(ttl) => call(framed, &["SET", key, &value, "EX", ttl]).await,
+        None => call(framed, &["SET", key, &value]).await,
+    }
+}
+
+/// Returns the value of `field` in the reply to `INFO`.
+async fn info_field(framed: &mut Client, field: &str) -> u64 {
+    let info = match call(framed, &["INFO"]).await {
+        Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+        frame => panic!("expected a bulk string, got {:?}", frame),
+    };
+    info.lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn refuse_writes_without_eviction() {
+    let addr = start_with_policy(MaxmemoryPolicy::NoEviction).await;
+    let mut client = connect(addr).await;
+
+    let mut refused = false;
+    for i in 0..20 {
+        match set(&mut client, &format!("key:{}", i), None).await {
+            Frame::Error(msg) => {
+                assert!(msg.starts_with("OOM "), "{}", msg);
+                refused = true;
+                break;
+            }
+            reply => assert!(reply == "OK"),
+        }
+    }
+    assert!(refused);
+    // Reads are still served.
+    assert!(matches!(
+        call(&mut client, &["GET", "key:0"]).await,
+        Frame::Bulk(_)
+    ));
+    assert_eq!(info_field(&mut client, "evicted_keys").await, 0);
+}
+
+#[tokio::test]
+async fn evict_least_recently_used() {
+    let addr = start_with_policy(MaxmemoryPolicy::AllkeysLru).await;
+    let mut client = connect(addr).await;
+
+    set(&mut client, "hot", None).await;
+    for i in 0..50 {
+        assert!(set(&mut client, &format!("key:{}", i), None).await == "OK");
+        call(&mut client, &["GET", "hot"]).await;
+    }
+    assert!(matches!(
+        call(&mut client, &["GET", "hot"]).await,
+        Frame::Bulk(_)
+    ));
+    assert!(matches!(
+        call(&mut client, &["GET", "key:0"]).await,
+        Frame::Null
+    ));
+    assert!(info_field(&mut client, "evicted_keys").await >= 40);
+    assert!(info_field(&mut client, "used_memory").await <= MAXMEMORY + 1200);
+}
+
+#[tokio::test]
+async fn evict_least_frequently_used() {
+    let addr = start_with_policy(MaxmemoryPolicy::AllkeysLfu).await;
+    let mut client = connect(addr).await;
+
+    set(&mut client, "hot", None).await;
+    for _ in 0..100 {
+        call(&mut client, &["GET", "hot"]).await;
+    }
+    for i in 0..50 {
+        assert!(set(&mut client, &format!("key:{}", i), None).await == "OK");
+    }
+    assert!(matches!(
+        call(&mut client, &["GET", "hot"]).await,
+        Frame::Bulk(_)
+    ));
+}
+
+#[tokio::test]
+async fn evict_keys_expiring_first() {
+    let addr = start_with_policy(MaxmemoryPolicy::VolatileTtl).await;
+    let mut client = connect(addr).await;
+
+    set(&mut client, "persistent", None).await;
+    set(&mut client, "soon", Some("100")).await;
+    for i in 0..8 {
+        set(&mut client, &format!("later:{}", i), Some("1000")).await;
+    }
+    assert!(set(&mut client, "new", Some("1000")).await == "OK");
+    assert!(set(&mut client, "newer", Some("1000")).await == "OK");
+    assert!(matches!(
+        call(&mut client, &["GET", "soon"]).await,
+        Frame::Null
+    ));
+    assert!(matches!(
+        call(&mut client, &["GET", "persistent"]).await,
+        Frame::Bulk(_)
+    ));
+
+    // Persistent keys are never evicted by the volatile policies.
+    call(
+        &mut client,
+        &["CONFIG", "SET", "maxmemory-policy", "volatile-random"],
+    )
+    .await;
+    let mut refused = false;
+    for i in 0..20 {
+        if let Frame::Error(msg) = set(&mut client, &format!("key:{}", i), None).await {
+            assert!(msg.starts_with("OOM "), "{}", msg);
+            refused = true;
+            break;
+        }
+    }
+    assert!(refused);
+    assert!(matches!(
+        call(&mut client, &["GET", "persistent"]).await,
+        Frame::Bulk(_)
+    ));
+}
+
+#[tokio::test]
+async fn evict_when_lowering_maxmemory() {
+    let addr = start_with_policy(MaxmemoryPolicy::AllkeysRandom).await;
+    let mut client = connect(addr).await;
+    for i in 0..8 {
+        set(&mut client, &format!("key:{}", i), None).await;
+    }
+
+    let reply = call(&mut client, &["CONFIG", "SET", "maxmemory", "5000"]).await;
+    assert!(reply == "OK");
+    assert!(info_field(&mut client, "used_memory").await <= 5000);
+    assert!(info_field(&mut client, "evicted_keys").await >= 4);
+}
@@ -0,0 +1,96 @@
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use tokio_stream::StreamExt;
+
+pub mod common;
+use common::{call, connect, start_server, Client};
+
+/// Runs `INFO` with the given sections and returns the reply text.
+async fn info(framed: &mut Client, sections: &[&str]) -> String {
+    let mut args = vec!["INFO"];
+    args.extend_from_slice(sections);
+    match call(framed, &args).await {
+        Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+        frame => panic!("expected a bulk string, got {:?}", frame),
+    }
+}
+
+/// Returns the value of `field` in an `INFO` reply.
+fn field<'a>(info: &'a str, field: &str) -> Option<&'a str> {
+    info.lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+}
+
+#[tokio::test]
+async fn filter_sections() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    let all = info(&mut client, &[]).await;
+    for section in [
+        "Server",
+        "Clients",
+        "Memory",
+        "Persistence",
+        "Stats",
+        "Keyspace",
+    ] {
+        assert!(all.contains(&format!("# {}\r\n", section)), "{}", all);
+    }
+
+    let reply = info(&mut client, &["clients", "STATS"]).await;
+    assert!(reply.starts_with("# Clients\r\n"));
+    assert!(reply.contains("# Stats\r\n"));
+    assert!(!reply.contains("# Server"));
+    assert_eq!(field(&reply, "connected_clients"), Some("1"));
+    assert_eq!(field(&reply, "maxclients"), Some("250"));
+
+    // Only the lsm backend writes anything to disk.
+    let reply = info(&mut client, &["persistence"]).await;
+    assert_eq!(field(&reply, "aof_enabled"), Some("0"));
+}
+
+#[tokio::test]
+async fn keyspace_stats() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    let keyspace = info(&mut client, &["keyspace"]).await;
+    assert_eq!(keyspace, "# Keyspace\r\n");
+
+    call(&mut client, &["SET", "hello", "world"]).await;
+    call(&mut client, &["SET", "foo", "bar", "EX", "100"]).await;
+    call(&mut client, &["GET", "hello"]).await;
+    call(&mut client, &["GET", "missing"]).await;
+
+    let reply = info(&mut client, &[]).await;
+    assert_eq!(field(&reply, "keyspace_hits"), Some("1"));
+    assert_eq!(field(&reply, "keyspace_misses"), Some("1"));
+    assert_eq!(field(&reply, "db0"), Some("keys=2,expires=1,avg_ttl=0"));
+    assert_eq!(field(&reply, "rdb_changes_since_last_save"), Some("2"));
+
+    assert!(call(&mut client, &["CONFIG", "RESETSTAT"]).await == "OK");
+    let reply = info(&mut client, &["stats"]).await;
+    assert_eq!(field(&reply, "keyspace_hits"), Some("0"));
+    // The INFO command itself has been processed.
+    assert_eq!(field(&reply, "total_commands_processed"), Some("1"));
+}
+
+#[tokio::test]
+async fn reject_connections_over_maxclients() {
+    let config = ServerConfig {
+        max_clients: 1,
+        ..ServerConfig::default()
+    };
+    let addr = start_server(config).await;
+    let mut client = connect(addr).await;
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+
+    let mut rejected = connect(addr).await;
+    let reply = rejected.next().await.unwrap().unwrap();
+    assert!(matches!(reply, Frame::Error(msg) if msg.contains("max number of clients")));
+
+    let reply = info(&mut client, &["stats"]).await;
+    assert_eq!(field(&reply, "total_connections_received"), Some("2"));
+    assert_eq!(field(&reply, "rejected_connections"), Some("1"));
+}
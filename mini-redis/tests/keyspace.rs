@@ -0,0 +1,78 @@
+use bytes::Bytes;
+use mini_redis::Db;
+use std::thread;
+use tokio::time::{self, Duration};
+
+#[tokio::test]
+async fn get_and_set_across_shards() {
+    let db = Db::with_shards(8);
+    let keys: Vec<String> = (0..100).map(|i| format!("key:{}", i)).collect();
+    for key in &keys {
+        db.set(key.clone(), Bytes::from(key.clone()), None);
+    }
+    for key in &keys {
+        assert_eq!(db.get(key), Some(Bytes::from(key.clone())));
+    }
+
+    let mut wanted: Vec<&str> = keys.iter().map(String::as_str).collect();
+    wanted.push("missing");
+    let values = db.mget(&wanted);
+    assert_eq!(values.len(), 101);
+    assert_eq!(values[42], Some(Bytes::from("key:42")));
+    assert_eq!(values[100], None);
+
+    // The same key twice locks its shard once.
+    db.mset(vec![
+        ("a".to_string(), Bytes::from("1")),
+        ("a".to_string(), Bytes::from("2")),
+    ]);
+    assert_eq!(db.mget(&["a", "a"]), vec![Some(Bytes::from("2")); 2]);
+}
+
+#[tokio::test]
+async fn expire_keys_in_every_shard() {
+    let db = Db::with_shards(8);
+    for i in 0..20 {
+        db.set(
+            format!("key:{}", i),
+            Bytes::from("value"),
+            Some(Duration::from_millis(50)),
+        );
+    }
+    db.set("persistent".to_string(), Bytes::from("value"), None);
+
+    time::sleep(Duration::from_millis(200)).await;
+    for i in 0..20 {
+        assert_eq!(db.get(&format!("key:{}", i)), None);
+    }
+    assert!(db.get("persistent").is_some());
+}
+
+#[tokio::test]
+async fn set_several_keys_atomically() {
+    let db = Db::with_shards(16);
+    let keys: Vec<String> = (0..8).map(|i| format!("key:{}", i)).collect();
+    let pairs = |n: usize| {
+        keys.iter()
+            .map(|key| (key.clone(), Bytes::from(n.to_string())))
+            .collect()
+    };
+    db.mset(pairs(0));
+
+    thread::scope(|scope| {
+        for _ in 0..2 {
+            let db = db.clone();
+            let keys = &keys;
+            scope.spawn(move || {
+                let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+                for _ in 0..1000 {
+                    let values = db.mget(&keys);
+                    assert!(values.iter().all(|value| *value == values[0]));
+                }
+            });
+        }
+        for n in 1..1000 {
+            db.mset(pairs(n));
+        }
+    });
+}
@@ -0,0 +1,122 @@
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use tokio::time::{self, Duration};
+
+pub mod common;
+use common::{call, connect, start_server, Client};
+
+/// Returns the value of `field` in the reply to `INFO`.
+async fn info_field(framed: &mut Client, field: &str) -> u64 {
+    let info = match call(framed, &["INFO"]).await {
+        Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+        frame => panic!("expected a bulk string, got {:?}", frame),
+    };
+    info.lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Waits for the background thread to free `count` values in total.
+async fn wait_freed(framed: &mut Client, count: u64) {
+    for _ in 0..100 {
+        if info_field(framed, "lazyfreed_objects").await >= count {
+            assert_eq!(info_field(framed, "lazyfree_pending_objects").await, 0);
+            return;
+        }
+        time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("values not freed");
+}
+
+#[tokio::test]
+async fn delete_and_unlink_keys() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    let large = "x".repeat(100 * 1024);
+    call(&mut client, &["SET", "a", &large]).await;
+    call(&mut client, &["SET", "b", &large]).await;
+    call(&mut client, &["SET", "c", "small"]).await;
+
+    let reply = call(&mut client, &["DEL", "a", "missing"]).await;
+    assert!(matches!(reply, Frame::Integer(1)));
+    assert_eq!(info_field(&mut client, "lazyfreed_objects").await, 0);
+
+    // Small values are freed right away, even by `UNLINK`.
+    let reply = call(&mut client, &["UNLINK", "b", "c"]).await;
+    assert!(matches!(reply, Frame::Integer(2)));
+    wait_freed(&mut client, 1).await;
+    assert!(matches!(
+        call(&mut client, &["GET", "b"]).await,
+        Frame::Null
+    ));
+    assert!(matches!(
+        call(&mut client, &["GET", "c"]).await,
+        Frame::Null
+    ));
+    assert_eq!(info_field(&mut client, "lazyfreed_objects").await, 1);
+}
+
+#[tokio::test]
+async fn free_removed_values_as_configured() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    let large = "x".repeat(100 * 1024);
+    for param in [
+        "lazyfree-lazy-user-del",
+        "lazyfree-lazy-server-del",
+        "lazyfree-lazy-expire",
+    ] {
+        let reply = call(&mut client, &["CONFIG", "SET", param, "yes"]).await;
+        assert!(reply == "OK");
+    }
+
+    call(&mut client, &["SET", "key", &large]).await;
+    call(&mut client, &["SET", "key", "small"]).await;
+    wait_freed(&mut client, 1).await;
+    assert!(call(&mut client, &["GET", "key"]).await == "small");
+
+    call(&mut client, &["SET", "deleted", &large]).await;
+    let reply = call(&mut client, &["DEL", "deleted"]).await;
+    assert!(matches!(reply, Frame::Integer(1)));
+    wait_freed(&mut client, 2).await;
+
+    call(&mut client, &["SET", "expiring", &large, "PX", "1"]).await;
+    time::sleep(Duration::from_millis(10)).await;
+    assert!(matches!(
+        call(&mut client, &["GET", "expiring"]).await,
+        Frame::Null
+    ));
+    wait_freed(&mut client, 3).await;
+}
+
+#[tokio::test]
+async fn flush_keyspace() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    for i in 0..10 {
+        call(&mut client, &["SET", &format!("key:{}", i), "value"]).await;
+    }
+
+    assert!(call(&mut client, &["FLUSHALL", "ASYNC"]).await == "OK");
+    assert!(matches!(
+        call(&mut client, &["GET", "key:0"]).await,
+        Frame::Null
+    ));
+    assert_eq!(info_field(&mut client, "used_memory").await, 0);
+    wait_freed(&mut client, 1).await;
+
+    call(&mut client, &["SET", "key", "value"]).await;
+    assert!(call(&mut client, &["FLUSHDB", "SYNC"]).await == "OK");
+    assert!(matches!(
+        call(&mut client, &["GET", "key"]).await,
+        Frame::Null
+    ));
+    assert_eq!(info_field(&mut client, "lazyfreed_objects").await, 1);
+
+    let reply = call(&mut client, &["FLUSHALL", "LATER"]).await;
+    assert!(matches!(reply, Frame::Error(msg) if msg == "ERR syntax error"));
+    // The connection is still open.
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+}
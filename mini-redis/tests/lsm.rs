@@ -0,0 +1,292 @@
+use bytes::Bytes;
+use mini_redis::config::{Backend, MaxmemoryPolicy};
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::storage::{Entry, LsmOptions, LsmStorage, Storage};
+use mini_redis::Frame;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+
+pub mod common;
+use common::{call, connect};
+
+/// An empty directory for the test `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mini-redis-lsm-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Small buffers, so that a few hundred keys fill several segments.
+fn options() -> LsmOptions {
+    LsmOptions {
+        cache_size: 4 * 1024,
+        memtable_size: 4 * 1024,
+    }
+}
+
+fn set(storage: &mut LsmStorage, key: &str, value: &str) {
+    storage.remove(key);
+    storage.insert(
+        key.to_string(),
+        Entry::new(Bytes::from(value.to_string()), None),
+    );
+}
+
+fn get(storage: &mut LsmStorage, key: &str) -> Option<Bytes> {
+    storage.get(key).map(|entry| entry.data().clone())
+}
+
+fn files(dir: &Path, extension: &str) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter(|file| file.as_ref().unwrap().path().extension() == Some(extension.as_ref()))
+        .count()
+}
+
+#[test]
+fn recover_keys_from_segments_and_log() {
+    let dir = temp_dir("recover");
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    for i in 0..500 {
+        set(&mut storage, &format!("key:{}", i), &format!("value:{}", i));
+    }
+    for i in 0..100 {
+        set(&mut storage, &format!("key:{}", i), "overwritten");
+    }
+    for i in 100..200 {
+        storage.remove(&format!("key:{}", i));
+    }
+    // Dropping the storage waits for the flush in progress.
+    drop(storage);
+    assert!(files(&dir, "seg") > 0);
+
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    assert_eq!(storage.len(), 400);
+    assert_eq!(get(&mut storage, "key:0").unwrap(), "overwritten");
+    assert_eq!(get(&mut storage, "key:150"), None);
+    for i in 200..500 {
+        assert_eq!(
+            get(&mut storage, &format!("key:{}", i)).unwrap(),
+            format!("value:{}", i)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compact_segments_in_the_background() {
+    let dir = temp_dir("compact");
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    for round in 0..10 {
+        for i in 0..100 {
+            set(
+                &mut storage,
+                &format!("key:{}", i),
+                &format!("{}:{}", round, i),
+            );
+        }
+    }
+
+    // Compactions are installed by the next operations once done.
+    let start = Instant::now();
+    while files(&dir, "seg") >= 4 {
+        assert!(start.elapsed() < Duration::from_secs(10), "not compacted");
+        std::thread::sleep(Duration::from_millis(10));
+        get(&mut storage, "key:0");
+    }
+    assert_eq!(storage.len(), 100);
+    for i in 0..100 {
+        assert_eq!(
+            get(&mut storage, &format!("key:{}", i)).unwrap(),
+            format!("9:{}", i)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn drop_torn_log_records() {
+    let dir = temp_dir("torn");
+    let mut storage = LsmStorage::open(&dir, LsmOptions::default()).unwrap();
+    set(&mut storage, "first", "1");
+    set(&mut storage, "second", "2");
+    drop(storage);
+
+    // A crash in the middle of a write leaves part of a record.
+    let wal = fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .find(|path| path.extension() == Some("wal".as_ref()))
+        .unwrap();
+    let mut file = OpenOptions::new().append(true).open(&wal).unwrap();
+    file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+    drop(file);
+
+    let mut storage = LsmStorage::open(&dir, LsmOptions::default()).unwrap();
+    assert_eq!(storage.len(), 2);
+    assert_eq!(get(&mut storage, "second").unwrap(), "2");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keep_expirations_and_flushes() {
+    let dir = temp_dir("expire");
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    for i in 0..200 {
+        set(&mut storage, &format!("old:{}", i), "value");
+    }
+    storage.clear();
+    let expires_at = Instant::now() + Duration::from_secs(100);
+    storage.insert(
+        "volatile".to_string(),
+        Entry::new(Bytes::from("value"), Some(expires_at)),
+    );
+    drop(storage);
+
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    assert_eq!(storage.len(), 1);
+    assert_eq!(storage.expires(), 1);
+    let (when, key) = storage.next_expiration().unwrap();
+    assert_eq!(key, "volatile");
+    // Stored with a millisecond precision.
+    assert!(when.max(expires_at) - when.min(expires_at) <= Duration::from_millis(2));
+    assert_eq!(get(&mut storage, "old:0"), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn load_values_ahead_of_commands() {
+    let dir = temp_dir("fetch");
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    for i in 0..100 {
+        set(&mut storage, &format!("key:{}", i), &format!("value:{}", i));
+    }
+    drop(storage);
+
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    assert!(storage.peek("key:1").is_none());
+    let fetch = storage.fetch(&["key:1", "key:2", "missing"]).unwrap();
+    storage.fetched(fetch());
+    assert_eq!(storage.peek("key:1").unwrap().data(), "value:1");
+    assert_eq!(storage.peek("key:2").unwrap().data(), "value:2");
+    // Nothing left to read.
+    assert!(storage.fetch(&["key:1", "missing"]).is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn shrink_the_cache_and_keep_the_keys() {
+    let dir = temp_dir("shrink");
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    for i in 0..10 {
+        set(&mut storage, &format!("key:{}", i), "value");
+    }
+    let resident = storage.resident_memory().unwrap();
+    assert!(resident > 0);
+
+    assert!(storage.shrink(usize::MAX) > 0);
+    assert!(storage.resident_memory().unwrap() < resident);
+    assert!(storage.peek("key:0").is_none());
+    assert_eq!(storage.len(), 10);
+    assert_eq!(get(&mut storage, "key:0").unwrap(), "value");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn read_without_caching() {
+    let dir = temp_dir("read");
+    let mut storage = LsmStorage::open(&dir, options()).unwrap();
+    for i in 0..500 {
+        set(&mut storage, &format!("key:{}", i), &format!("value:{}", i));
+    }
+    storage.shrink(usize::MAX);
+    let resident = storage.resident_memory().unwrap();
+    assert!(storage.peek("key:1").is_none());
+
+    assert_eq!(storage.read("key:1").unwrap().data(), "value:1");
+    assert!(storage.read("missing").is_none());
+    assert!(storage.peek("key:1").is_none());
+    assert_eq!(storage.resident_memory().unwrap(), resident);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Starts a server on the lsm backend in `dir`.
+async fn start_server(dir: &Path, config: ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        backend: Backend::Lsm,
+        lsm_dir: dir.to_path_buf(),
+        lsm_memtable_size: 16 * 1024,
+        ..config
+    };
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, std::future::pending::<()>()).await;
+    });
+    addr
+}
+
+#[tokio::test]
+async fn evict_values_but_keep_keys() {
+    let dir = temp_dir("evict");
+    let config = ServerConfig {
+        maxmemory: 1024 * 1024,
+        maxmemory_policy: MaxmemoryPolicy::AllkeysLru,
+        ..ServerConfig::default()
+    };
+    let addr = start_server(&dir, config).await;
+    let mut client = connect(addr).await;
+
+    let value = "x".repeat(1024);
+    for i in 0..2000 {
+        let reply = call(&mut client, &["SET", &format!("key:{}", i), &value]).await;
+        assert!(reply == "OK", "{:?}", reply);
+    }
+    match call(&mut client, &["INFO", "keyspace"]).await {
+        Frame::Bulk(info) => {
+            let info = std::str::from_utf8(&info).unwrap();
+            assert!(info.contains("db0:keys=2000,"), "{}", info);
+        }
+        frame => panic!("expected the keyspace, got {:?}", frame),
+    }
+    assert!(call(&mut client, &["GET", "key:0"]).await == value.as_str());
+    assert!(matches!(
+        call(&mut client, &["MGET", "key:1", "key:1999"]).await,
+        Frame::Array(values) if values.len() == 2 && values.iter().all(|v| *v == value.as_str())
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn refuse_writes_after_io_error() {
+    let dir = temp_dir("misconf");
+    let addr = start_server(&dir, ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    assert!(call(&mut client, &["SET", "before", "value"]).await == "OK");
+
+    // Flushing a memtable now fails.
+    fs::remove_dir_all(&dir).unwrap();
+    let value = "x".repeat(1024);
+    let start = Instant::now();
+    let err = loop {
+        match call(&mut client, &["SET", "key", &value]).await {
+            Frame::Error(err) => break err,
+            reply => assert!(reply == "OK", "{:?}", reply),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "no error");
+    };
+    assert!(err.starts_with("MISCONF"), "{}", err);
+    assert!(call(&mut client, &["GET", "before"]).await == "value");
+    match call(&mut client, &["INFO", "persistence"]).await {
+        Frame::Bulk(info) => {
+            let info = std::str::from_utf8(&info).unwrap();
+            assert!(info.contains("aof_enabled:1\r\n"), "{}", info);
+            assert!(info.contains("aof_last_write_status:err\r\n"), "{}", info);
+        }
+        frame => panic!("expected the persistence section, got {:?}", frame),
+    }
+}
@@ -0,0 +1,106 @@
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+
+pub mod common;
+use common::{call, connect, start_server, Client};
+
+fn integer(frame: Frame) -> u64 {
+    match frame {
+        Frame::Integer(value) => value,
+        frame => panic!("expected an integer, got {:?}", frame),
+    }
+}
+
+/// Returns the value of `field` in the reply to `MEMORY STATS`.
+async fn memory_stat(framed: &mut Client, field: &str) -> u64 {
+    let Frame::Array(items) = call(framed, &["MEMORY", "STATS"]).await else {
+        panic!("expected an array");
+    };
+    let position = items.iter().position(|item| *item == field).unwrap();
+    match items[position + 1] {
+        Frame::Integer(value) => value,
+        ref frame => panic!("expected an integer, got {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn inspect_objects() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    call(&mut client, &["SET", "int", "12345"]).await;
+    call(&mut client, &["SET", "short", "hello"]).await;
+    call(&mut client, &["SET", "long", &"x".repeat(100)]).await;
+
+    assert!(call(&mut client, &["OBJECT", "ENCODING", "int"]).await == "int");
+    assert!(call(&mut client, &["OBJECT", "ENCODING", "short"]).await == "embstr");
+    assert!(call(&mut client, &["OBJECT", "ENCODING", "long"]).await == "raw");
+    assert!(matches!(
+        call(&mut client, &["OBJECT", "ENCODING", "missing"]).await,
+        Frame::Null
+    ));
+    assert_eq!(
+        integer(call(&mut client, &["OBJECT", "REFCOUNT", "int"]).await),
+        1
+    );
+    assert_eq!(
+        integer(call(&mut client, &["OBJECT", "IDLETIME", "int"]).await),
+        0
+    );
+    // New keys start with a non-zero counter.
+    assert!(integer(call(&mut client, &["OBJECT", "FREQ", "int"]).await) > 0);
+}
+
+#[tokio::test]
+async fn report_memory_usage() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+    call(&mut client, &["SET", "small", "x"]).await;
+    call(&mut client, &["SET", "large", &"x".repeat(1000)]).await;
+
+    let small = integer(call(&mut client, &["MEMORY", "USAGE", "small"]).await);
+    let large = integer(call(&mut client, &["MEMORY", "USAGE", "large", "SAMPLES", "5"]).await);
+    assert_eq!(large - small, 999);
+    assert!(matches!(
+        call(&mut client, &["MEMORY", "USAGE", "missing"]).await,
+        Frame::Null
+    ));
+
+    assert_eq!(memory_stat(&mut client, "keys.count").await, 2);
+    assert_eq!(memory_stat(&mut client, "dataset.bytes").await, 1001);
+    assert_eq!(memory_stat(&mut client, "overhead.expirations").await, 0);
+    assert_eq!(memory_stat(&mut client, "overhead.pubsub").await, 0);
+
+    call(&mut client, &["SET", "volatile", "x", "EX", "100"]).await;
+    assert!(memory_stat(&mut client, "overhead.expirations").await > 0);
+
+    let mut subscriber = connect(addr).await;
+    call(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+    assert!(memory_stat(&mut client, "overhead.pubsub").await > 0);
+
+    let total = memory_stat(&mut client, "total.allocated").await;
+    let overhead = memory_stat(&mut client, "overhead.total").await;
+    assert_eq!(total - overhead, 1002);
+}
+
+#[tokio::test]
+async fn refuse_malformed_subcommands() {
+    let addr = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    for args in [
+        &["OBJECT", "FOO", "key"][..],
+        &["OBJECT", "ENCODING"],
+        &["MEMORY", "FOO"],
+        &["MEMORY", "USAGE", "key", "SAMPLES", "x"],
+        &["MEMORY", "USAGE", "key", "COUNT", "5"],
+    ] {
+        let reply = call(&mut client, args).await;
+        assert!(
+            matches!(&reply, Frame::Error(msg) if msg.starts_with("ERR")),
+            "{:?}",
+            reply
+        );
+    }
+    // The connection is still open.
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+}
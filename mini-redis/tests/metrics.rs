@@ -0,0 +1,112 @@
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::Client;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration};
+
+/// Starts a server exporting metrics, returning the addresses of the server
+/// and of the exporter.
+async fn start_server() -> (SocketAddr, SocketAddr) {
+    // Find a free port for the exporter, which binds its own listener.
+    let metrics_addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = ServerConfig {
+        metrics_addr: Some(metrics_addr.to_string()),
+        ..ServerConfig::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+    });
+    (addr, metrics_addr)
+}
+
+/// Sends a GET request, returning the response head and body.
+async fn http_get(addr: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => break stream,
+            // The exporter may not listen yet.
+            Err(_) => time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), body.to_string())
+}
+
+/// Returns the value of the sample `name` in a scrape.
+fn sample<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+}
+
+#[tokio::test]
+async fn export_metrics() {
+    let (addr, metrics_addr) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    client.get("hello").await.unwrap();
+    client.get("missing").await.unwrap();
+    client
+        .publish("news", "nobody listens".into())
+        .await
+        .unwrap();
+
+    let (head, body) = http_get(metrics_addr, "/metrics").await;
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+    assert_eq!(sample(&body, "miniredis_connected_clients"), Some("1"));
+    assert_eq!(sample(&body, "miniredis_keys"), Some("1"));
+    assert_eq!(sample(&body, "miniredis_keyspace_hits_total"), Some("1"));
+    assert_eq!(sample(&body, "miniredis_keyspace_misses_total"), Some("1"));
+    assert_eq!(sample(&body, "miniredis_pubsub_messages_total"), Some("1"));
+    assert_eq!(
+        sample(&body, "miniredis_pubsub_deliveries_total"),
+        Some("0")
+    );
+    assert_eq!(
+        sample(&body, "miniredis_command_calls_total{cmd=\"get\"}"),
+        Some("2")
+    );
+    assert_eq!(
+        sample(
+            &body,
+            "miniredis_command_duration_seconds_bucket{cmd=\"get\",le=\"+Inf\"}"
+        ),
+        Some("2")
+    );
+    assert_eq!(
+        sample(
+            &body,
+            "miniredis_command_duration_seconds_count{cmd=\"set\"}"
+        ),
+        Some("1")
+    );
+}
+
+#[tokio::test]
+async fn unknown_path() {
+    let (_, metrics_addr) = start_server().await;
+    let (head, _) = http_get(metrics_addr, "/").await;
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+}
+
+#[tokio::test]
+async fn close_idle_connections() {
+    let (_, metrics_addr) = start_server().await;
+    // Wait for the exporter to listen.
+    http_get(metrics_addr, "/").await;
+
+    // A scraper that never sends its request doesn't hold the connection.
+    let mut stream = TcpStream::connect(metrics_addr).await.unwrap();
+    let mut buf = vec![];
+    let read = time::timeout(Duration::from_secs(10), stream.read_to_end(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0))), "{:?}", read);
+}
@@ -0,0 +1,436 @@
+use mini_redis::config::{Backend, MaxmemoryPolicy};
+use mini_redis::server::ServerConfig;
+use mini_redis::Frame;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{self, Duration, Instant};
+
+pub mod common;
+use common::{call, connect, start_server, Client};
+
+
+/// Starts a master and a replica of it, returning a client of each once the
+/// replica is synced.
+async fn start_pair() -> (Client, Client) {
+    let master = start_server(ServerConfig::default()).await;
+    let config = ServerConfig {
+        replicaof: Some(("127.0.0.1".to_string(), master.port())),
+        ..ServerConfig::default()
+    };
+    let replica = start_server(config).await;
+    let mut replica = connect(replica).await;
+    wait_for_link(&mut replica).await;
+    (connect(master).await, replica)
+}
+
+/// Returns the value of `field` in the `INFO` section `section`.
+async fn info_field(client: &mut Client, section: &str, field: &str) -> String {
+    let info = match call(client, &["INFO", section]).await {
+        Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+        frame => panic!("expected a bulk string, got {:?}", frame),
+    };
+    info.lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .unwrap_or_else(|| panic!("no {} in {}", field, info))
+        .to_string()
+}
+
+/// Waits until `check` passes, for up to 5 seconds.
+async fn eventually<F>(client: &mut Client, args: &[&str], check: F)
+where
+    F: Fn(&Frame) -> bool,
+{
+    let start = Instant::now();
+    loop {
+        let reply = call(client, args).await;
+        if check(&reply) {
+            return;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "{:?}", reply);
+        time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+async fn wait_for_link(replica: &mut Client) {
+    eventually(replica, &["ROLE"], |reply| match reply {
+        Frame::Array(fields) => fields.len() == 5 && fields[3] == "connected",
+        _ => false,
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn sync_then_stream_writes() {
+    let master = start_server(ServerConfig::default()).await;
+    let mut client = connect(master).await;
+    call(&mut client, &["SET", "before", "1"]).await;
+    call(&mut client, &["SET", "volatile", "1", "PX", "200"]).await;
+
+    let replica = start_server(ServerConfig::default()).await;
+    let mut replica = connect(replica).await;
+    let port = master.port().to_string();
+    assert!(call(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await == "OK");
+    assert!(
+        call(&mut replica, &["REPLICAOF", "127.0.0.1", &port]).await
+            == "OK Already connected to specified master"
+    );
+    wait_for_link(&mut replica).await;
+
+    // Loaded from the snapshot, with the time left before it expires.
+    assert!(call(&mut replica, &["GET", "before"]).await == "1");
+    assert!(call(&mut replica, &["GET", "volatile"]).await == "1");
+
+    call(&mut client, &["MSET", "a", "1", "b", "2"]).await;
+    call(&mut client, &["DEL", "before"]).await;
+    eventually(&mut replica, &["GET", "before"], |reply| {
+        matches!(reply, Frame::Null)
+    })
+    .await;
+    // Applied in the order of the master.
+    assert!(call(&mut replica, &["GET", "b"]).await == "2");
+    eventually(&mut replica, &["GET", "volatile"], |reply| {
+        matches!(reply, Frame::Null)
+    })
+    .await;
+
+    call(&mut client, &["FLUSHALL"]).await;
+    eventually(&mut replica, &["GET", "a"], |reply| {
+        matches!(reply, Frame::Null)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn replica_refuses_writes() {
+    let (_, mut replica) = start_pair().await;
+    match call(&mut replica, &["SET", "key", "value"]).await {
+        Frame::Error(msg) => assert!(msg.starts_with("READONLY"), "{}", msg),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+
+    call(&mut replica, &["CONFIG", "SET", "replica-read-only", "no"]).await;
+    assert!(call(&mut replica, &["SET", "key", "value"]).await == "OK");
+}
+
+#[tokio::test]
+async fn report_role_and_offsets() {
+    let (mut master, mut replica) = start_pair().await;
+    call(&mut master, &["SET", "key", "value"]).await;
+    eventually(&mut replica, &["GET", "key"], |reply| *reply == "value").await;
+
+    assert_eq!(
+        info_field(&mut master, "replication", "role").await,
+        "master"
+    );
+    assert_eq!(
+        info_field(&mut master, "replication", "connected_slaves").await,
+        "1"
+    );
+    let offset = info_field(&mut master, "replication", "master_repl_offset").await;
+    assert_ne!(offset, "0");
+    // Acknowledged every second.
+    eventually(&mut master, &["ROLE"], |reply| match reply {
+        Frame::Array(fields) => match &fields[2] {
+            Frame::Array(replicas) => match &replicas[..] {
+                [Frame::Array(replica)] => replica[2] == offset.as_str(),
+                _ => false,
+            },
+            _ => false,
+        },
+        _ => false,
+    })
+    .await;
+
+    assert_eq!(
+        info_field(&mut replica, "replication", "role").await,
+        "slave"
+    );
+    assert_eq!(
+        info_field(&mut replica, "replication", "master_link_status").await,
+        "up"
+    );
+    assert_eq!(
+        info_field(&mut replica, "replication", "slave_repl_offset").await,
+        offset
+    );
+    assert_eq!(
+        info_field(&mut replica, "replication", "master_replid").await,
+        info_field(&mut master, "replication", "master_replid").await
+    );
+}
+
+#[tokio::test]
+async fn continue_after_disconnection() {
+    let (mut master, mut replica) = start_pair().await;
+    assert_eq!(info_field(&mut master, "stats", "sync_full").await, "1");
+
+    // Drops the link, the replica connects again after a second.
+    call(&mut master, &["CLIENT", "KILL", "USER", "default"]).await;
+    call(&mut master, &["SET", "missed", "value"]).await;
+    eventually(&mut replica, &["GET", "missed"], |reply| *reply == "value").await;
+
+    assert_eq!(info_field(&mut master, "stats", "sync_full").await, "1");
+    assert_eq!(
+        info_field(&mut master, "stats", "sync_partial_ok").await,
+        "1"
+    );
+}
+
+#[tokio::test]
+async fn promote_replica() {
+    let (mut master, mut replica) = start_pair().await;
+    call(&mut master, &["SET", "key", "value"]).await;
+    eventually(&mut replica, &["GET", "key"], |reply| *reply == "value").await;
+    let replid = info_field(&mut master, "replication", "master_replid").await;
+
+    assert!(call(&mut replica, &["REPLICAOF", "NO", "ONE"]).await == "OK");
+    assert!(call(&mut replica, &["SET", "key", "new"]).await == "OK");
+    assert_eq!(
+        info_field(&mut replica, "replication", "role").await,
+        "master"
+    );
+    // Replicas of the former master may continue with the promoted one.
+    assert_eq!(
+        info_field(&mut replica, "replication", "master_replid2").await,
+        replid
+    );
+    assert!(call(&mut master, &["GET", "key"]).await == "value");
+}
+
+#[tokio::test]
+async fn wait_for_acknowledgments() {
+    let (mut master, mut replica) = start_pair().await;
+    call(&mut master, &["SET", "key", "value"]).await;
+
+    // Asked for right away rather than after the periodic acknowledgment.
+    let start = Instant::now();
+    assert!(matches!(
+        call(&mut master, &["WAIT", "1", "0"]).await,
+        Frame::Integer(1)
+    ));
+    assert!(start.elapsed() < Duration::from_millis(900));
+    assert!(call(&mut replica, &["GET", "key"]).await == "value");
+
+    // Times out with the replicas that acknowledged.
+    let start = Instant::now();
+    assert!(matches!(
+        call(&mut master, &["WAIT", "2", "100"]).await,
+        Frame::Integer(1)
+    ));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    match call(&mut replica, &["WAIT", "1", "0"]).await {
+        Frame::Error(msg) => assert!(msg.contains("replica"), "{}", msg),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn wait_for_no_replicas() {
+    let master = start_server(ServerConfig::default()).await;
+    let mut client = connect(master).await;
+    call(&mut client, &["SET", "key", "value"]).await;
+
+    assert!(matches!(
+        call(&mut client, &["WAIT", "0", "0"]).await,
+        Frame::Integer(0)
+    ));
+    match call(&mut client, &["WAITAOF", "1", "0", "0"]).await {
+        Frame::Error(msg) => assert!(msg.contains("appendonly"), "{}", msg),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+    match call(&mut client, &["WAITAOF", "0", "1", "50"]).await {
+        Frame::Array(counts) => {
+            assert!(matches!(counts[..], [Frame::Integer(0), Frame::Integer(0)]))
+        }
+        frame => panic!("expected an array, got {:?}", frame),
+    }
+}
+
+/// Starts a server on the lsm backend, in a directory named after `name`.
+async fn start_lsm_server(name: &str, replicaof: Option<u16>) -> SocketAddr {
+    let dir = std::env::temp_dir().join(format!(
+        "mini-redis-replication-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    start_server(ServerConfig {
+        backend: Backend::Lsm,
+        lsm_dir: dir,
+        replicaof: replicaof.map(|port| ("127.0.0.1".to_string(), port)),
+        ..ServerConfig::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn wait_for_durable_writes() {
+    let master = start_lsm_server("master", None).await;
+    let durable = start_lsm_server("replica", Some(master.port())).await;
+    let memory = start_server(ServerConfig {
+        replicaof: Some(("127.0.0.1".to_string(), master.port())),
+        ..ServerConfig::default()
+    })
+    .await;
+    wait_for_link(&mut connect(durable).await).await;
+    wait_for_link(&mut connect(memory).await).await;
+
+    let mut client = connect(master).await;
+    call(&mut client, &["SET", "key", "value"]).await;
+    // Only the replica on the lsm backend makes the write durable.
+    match call(&mut client, &["WAITAOF", "1", "2", "200"]).await {
+        Frame::Array(counts) => {
+            assert!(matches!(counts[..], [Frame::Integer(1), Frame::Integer(1)]))
+        }
+        frame => panic!("expected an array, got {:?}", frame),
+    }
+}
+
+/// Sets 20 keys of 1000 bytes.
+async fn fill(client: &mut Client) {
+    let value = "x".repeat(1000);
+    for i in 0..20 {
+        call(client, &["SET", &format!("key:{}", i), &value]).await;
+    }
+}
+
+#[tokio::test]
+async fn replicate_evictions() {
+    let master = start_server(ServerConfig {
+        maxmemory: 12_000,
+        maxmemory_policy: MaxmemoryPolicy::AllkeysLru,
+        ..ServerConfig::default()
+    })
+    .await;
+    let replica = start_server(ServerConfig {
+        replicaof: Some(("127.0.0.1".to_string(), master.port())),
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut replica = connect(replica).await;
+    wait_for_link(&mut replica).await;
+    let mut master = connect(master).await;
+
+    fill(&mut master).await;
+    assert!(matches!(
+        call(&mut master, &["WAIT", "1", "0"]).await,
+        Frame::Integer(1)
+    ));
+    let keyspace = info_field(&mut master, "keyspace", "db0").await;
+    assert!(!keyspace.starts_with("keys=20,"), "{}", keyspace);
+    assert_eq!(info_field(&mut replica, "keyspace", "db0").await, keyspace);
+}
+
+#[tokio::test]
+async fn replicate_expirations() {
+    let (mut master, mut replica) = start_pair().await;
+    call(&mut master, &["SET", "volatile", "1", "PX", "50"]).await;
+    eventually(&mut replica, &["GET", "volatile"], |reply| *reply == "1").await;
+    time::sleep(Duration::from_millis(100)).await;
+
+    // Removed on the master when read, and on the replica with it.
+    assert!(matches!(
+        call(&mut master, &["GET", "volatile"]).await,
+        Frame::Null
+    ));
+    let expired = info_field(&mut master, "stats", "expired_keys").await;
+    assert_eq!(expired, "1");
+    assert!(matches!(
+        call(&mut master, &["WAIT", "1", "0"]).await,
+        Frame::Integer(1)
+    ));
+    assert!(matches!(
+        call(&mut replica, &["GET", "volatile"]).await,
+        Frame::Null
+    ));
+}
+
+#[tokio::test]
+async fn load_snapshot_without_evicting() {
+    let master = start_server(ServerConfig::default()).await;
+    let mut client = connect(master).await;
+    fill(&mut client).await;
+
+    let replica = start_server(ServerConfig {
+        replicaof: Some(("127.0.0.1".to_string(), master.port())),
+        maxmemory: 12_000,
+        maxmemory_policy: MaxmemoryPolicy::AllkeysLru,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut replica = connect(replica).await;
+    wait_for_link(&mut replica).await;
+    // Neither the snapshot nor the stream evict keys on the replica.
+    call(&mut client, &["SET", "after", "1"]).await;
+    eventually(&mut replica, &["GET", "after"], |reply| *reply == "1").await;
+    let keyspace = info_field(&mut replica, "keyspace", "db0").await;
+    assert!(keyspace.starts_with("keys=21,"), "{}", keyspace);
+}
+
+#[tokio::test]
+async fn sync_while_writing() {
+    let master = start_server(ServerConfig::default()).await;
+    let mut client = connect(master).await;
+    let keys: Vec<String> = (0..2000).map(|i| format!("key:{}", i)).collect();
+    for chunk in keys.chunks(100) {
+        let mut args = vec!["MSET"];
+        for key in chunk {
+            args.extend([key.as_str(), "before"]);
+        }
+        call(&mut client, &args).await;
+    }
+
+    // Writes made while the snapshot is sent reach the replica as well,
+    // whether its values were read before or after them.
+    let writer = tokio::spawn(async move {
+        let mut client = connect(master).await;
+        for i in (0..2000).step_by(7) {
+            let key = format!("key:{}", i);
+            if i % 2 == 0 {
+                call(&mut client, &["SET", &key, "after"]).await;
+            } else {
+                call(&mut client, &["DEL", &key]).await;
+            }
+        }
+    });
+    let replica = start_server(ServerConfig {
+        replicaof: Some(("127.0.0.1".to_string(), master.port())),
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut replica = connect(replica).await;
+    writer.await.unwrap();
+    wait_for_link(&mut replica).await;
+    // `WAIT` waits for the writes of its own connection.
+    call(&mut client, &["SET", "done", "1"]).await;
+    assert!(matches!(
+        call(&mut client, &["WAIT", "1", "0"]).await,
+        Frame::Integer(1)
+    ));
+
+    let mut args = vec!["MGET"];
+    args.extend(keys.iter().map(String::as_str));
+    let expected = format!("{:?}", call(&mut client, &args).await);
+    assert_eq!(format!("{:?}", call(&mut replica, &args).await), expected);
+}
+
+#[tokio::test]
+async fn set_absolute_expirations() {
+    let master = start_server(ServerConfig::default()).await;
+    let mut client = connect(master).await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    let past = (now - 1000).to_string();
+    assert!(call(&mut client, &["SET", "past", "1", "PXAT", &past]).await == "OK");
+    assert!(matches!(
+        call(&mut client, &["GET", "past"]).await,
+        Frame::Null
+    ));
+    let future = (now / 1000 + 100).to_string();
+    assert!(call(&mut client, &["SET", "future", "1", "EXAT", &future]).await == "OK");
+    assert!(call(&mut client, &["GET", "future"]).await == "1");
+}
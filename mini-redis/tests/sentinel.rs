@@ -0,0 +1,314 @@
+use mini_redis::clients::SentinelOptions;
+use mini_redis::sentinel::{self, MasterConfig, SentinelConfig};
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::Frame;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::{self, Duration, Instant};
+
+pub mod common;
+use common::{call, connect, Client};
+
+
+/// Starts a server, replicating `master` if given. The server stops when the
+/// returned sender is dropped.
+async fn start_server(master: Option<SocketAddr>) -> (SocketAddr, oneshot::Sender<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Replicas announce their port to the master, which reports it.
+    let config = ServerConfig {
+        port: addr.port(),
+        replicaof: master.map(|master| ("127.0.0.1".to_string(), master.port())),
+        ..ServerConfig::default()
+    };
+    let (stop, stopped) = oneshot::channel();
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, stopped).await;
+    });
+    (addr, stop)
+}
+
+/// Starts a sentinel monitoring `master` as `mymaster`.
+async fn start_sentinel(master: SocketAddr, quorum: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = SentinelConfig {
+        port: addr.port(),
+        masters: vec![MasterConfig {
+            down_after: Duration::from_millis(500),
+            failover_timeout: Duration::from_secs(2),
+            ..MasterConfig::new("mymaster", "127.0.0.1", master.port(), quorum)
+        }],
+        ..SentinelConfig::default()
+    };
+    tokio::spawn(async move {
+        sentinel::run(listener, config, tokio::signal::ctrl_c()).await;
+    });
+    addr
+}
+
+/// Waits until `check` passes, for up to `timeout`.
+async fn eventually<F>(client: &mut Client, args: &[&str], timeout: Duration, check: F)
+where
+    F: Fn(&Frame) -> bool,
+{
+    let start = Instant::now();
+    loop {
+        let reply = call(client, args).await;
+        if check(&reply) {
+            return;
+        }
+        assert!(start.elapsed() < timeout, "{:?}", reply);
+        time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// The value of `name` in a flat list of fields, as `SENTINEL MASTER`
+/// replies.
+fn field<'a>(fields: &'a Frame, name: &str) -> Option<&'a Frame> {
+    match fields {
+        Frame::Array(fields) => fields
+            .chunks(2)
+            .find(|pair| pair[0] == name)
+            .map(|pair| &pair[1]),
+        _ => None,
+    }
+}
+
+/// The port `SENTINEL GET-MASTER-ADDR-BY-NAME mymaster` replies.
+async fn master_port(sentinel: &mut Client) -> u16 {
+    match call(
+        sentinel,
+        &["SENTINEL", "get-master-addr-by-name", "mymaster"],
+    )
+    .await
+    {
+        Frame::Array(addr) => match &addr[..] {
+            [ip, Frame::Bulk(port)] if *ip == "127.0.0.1" => {
+                std::str::from_utf8(port).unwrap().parse().unwrap()
+            }
+            _ => panic!("unexpected address {:?}", addr),
+        },
+        frame => panic!("expected an address, got {:?}", frame),
+    }
+}
+
+/// Waits until the replica is linked to the master at `port`.
+async fn wait_for_master(replica: &mut Client, port: u16, timeout: Duration) {
+    eventually(replica, &["ROLE"], timeout, |reply| match reply {
+        Frame::Array(fields) => {
+            fields.len() == 5
+                && matches!(fields[2], Frame::Integer(linked) if linked == port as u64)
+                && fields[3] == "connected"
+        }
+        _ => false,
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn answer_queries() {
+    let (master, _stop) = start_server(None).await;
+    let sentinel = start_sentinel(master, 1).await;
+    let mut client = connect(sentinel).await;
+
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+    assert_eq!(master_port(&mut client).await, master.port());
+    let reply = call(
+        &mut client,
+        &["SENTINEL", "get-master-addr-by-name", "other"],
+    )
+    .await;
+    assert!(matches!(reply, Frame::Null), "{:?}", reply);
+    match call(&mut client, &["SENTINEL", "master", "other"]).await {
+        Frame::Error(err) => assert_eq!(err, "ERR No such master with that name"),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+    let master_fields = call(&mut client, &["SENTINEL", "master", "mymaster"]).await;
+    assert!(*field(&master_fields, "name").unwrap() == "mymaster");
+    assert!(*field(&master_fields, "quorum").unwrap() == "1");
+    match call(&mut client, &["SENTINEL", "myid"]).await {
+        Frame::Bulk(id) => assert_eq!(id.len(), 40),
+        frame => panic!("expected an ID, got {:?}", frame),
+    }
+    match call(&mut client, &["GET", "key"]).await {
+        Frame::Error(err) => assert_eq!(err, "ERR unknown command get"),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+    // Malformed commands are refused without closing the connection.
+    for args in [
+        &["SENTINEL", "foo"][..],
+        &["SENTINEL", "master"],
+        &["SENTINEL", "is-master-down-by-addr", "127.0.0.1", "port"],
+    ] {
+        let reply = call(&mut client, args).await;
+        assert!(matches!(reply, Frame::Error(_)), "{:?}", reply);
+    }
+    assert!(call(&mut client, &["PING"]).await == "PONG");
+
+    // Servers don't run the sentinel commands.
+    let mut master = connect(master).await;
+    match call(&mut master, &["SENTINEL", "masters"]).await {
+        Frame::Error(err) => assert_eq!(err, "ERR unknown command sentinel"),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn fail_over_when_master_is_down() {
+    let (master, stop) = start_server(None).await;
+    let (first, _stop_first) = start_server(Some(master)).await;
+    let (second, _stop_second) = start_server(Some(master)).await;
+    let mut client = connect(master).await;
+    call(&mut client, &["SET", "key", "value"]).await;
+    let (mut first_client, mut second_client) = (connect(first).await, connect(second).await);
+    wait_for_master(&mut first_client, master.port(), Duration::from_secs(5)).await;
+    wait_for_master(&mut second_client, master.port(), Duration::from_secs(5)).await;
+
+    let mut sentinels = vec![];
+    for _ in 0..3 {
+        sentinels.push(start_sentinel(master, 2).await);
+    }
+    let mut clients = vec![];
+    for sentinel in &sentinels {
+        let mut client = connect(*sentinel).await;
+        // The sentinels find each other through the hello messages.
+        let args = ["SENTINEL", "master", "mymaster"];
+        eventually(&mut client, &args, Duration::from_secs(10), |reply| {
+            field(reply, "num-slaves").is_some_and(|count| *count == "2")
+                && field(reply, "num-other-sentinels").is_some_and(|count| *count == "2")
+        })
+        .await;
+        clients.push(client);
+    }
+
+    drop(stop);
+    let start = Instant::now();
+    let promoted = loop {
+        let ports = [
+            master_port(&mut clients[0]).await,
+            master_port(&mut clients[1]).await,
+            master_port(&mut clients[2]).await,
+        ];
+        if ports[0] != master.port() && ports.iter().all(|port| *port == ports[0]) {
+            break ports[0];
+        }
+        assert!(start.elapsed() < Duration::from_secs(30), "{:?}", ports);
+        time::sleep(Duration::from_millis(100)).await;
+    };
+    let (mut new_master, mut other) = if promoted == first.port() {
+        (first_client, second_client)
+    } else {
+        assert_eq!(promoted, second.port());
+        (second_client, first_client)
+    };
+    match call(&mut new_master, &["ROLE"]).await {
+        Frame::Array(fields) => assert!(fields[0] == "master"),
+        frame => panic!("expected a role, got {:?}", frame),
+    }
+    wait_for_master(&mut other, promoted, Duration::from_secs(5)).await;
+    assert!(call(&mut new_master, &["GET", "key"]).await == "value");
+
+    // Clients find the new master through the sentinels.
+    let mut client = mini_redis::Client::connect_sentinel(&sentinels, "mymaster")
+        .await
+        .unwrap();
+    client.set("after", "failover".into()).await.unwrap();
+    eventually(
+        &mut other,
+        &["GET", "after"],
+        Duration::from_secs(5),
+        |reply| *reply == "failover",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn forced_failover() {
+    let (master, _stop) = start_server(None).await;
+    let (replica, _stop_replica) = start_server(Some(master)).await;
+    let mut replica_client = connect(replica).await;
+    wait_for_master(&mut replica_client, master.port(), Duration::from_secs(5)).await;
+    let sentinel = start_sentinel(master, 1).await;
+    let mut client = connect(sentinel).await;
+
+    let args = ["SENTINEL", "replicas", "mymaster"];
+    eventually(
+        &mut client,
+        &args,
+        Duration::from_secs(5),
+        |reply| match reply {
+            Frame::Array(replicas) => replicas
+                .first()
+                .and_then(|replica| field(replica, "master-link-status"))
+                .is_some_and(|status| *status == "ok"),
+            _ => false,
+        },
+    )
+    .await;
+    assert!(call(&mut client, &["SENTINEL", "failover", "mymaster"]).await == "OK");
+    match call(&mut client, &["SENTINEL", "failover", "mymaster"]).await {
+        Frame::Error(err) => assert!(err.starts_with("INPROG"), "{}", err),
+        frame => panic!("expected an error, got {:?}", frame),
+    }
+
+    let start = Instant::now();
+    while master_port(&mut client).await != replica.port() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        time::sleep(Duration::from_millis(50)).await;
+    }
+    match call(&mut replica_client, &["ROLE"]).await {
+        Frame::Array(fields) => assert!(fields[0] == "master"),
+        frame => panic!("expected a role, got {:?}", frame),
+    }
+    // The former master, still up, is made a replica once the new
+    // configuration had time to spread.
+    let mut master = connect(master).await;
+    wait_for_master(&mut master, replica.port(), Duration::from_secs(15)).await;
+}
+
+#[tokio::test]
+async fn discover_master_requiring_a_password() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let master = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        port: master.port(),
+        requirepass: Some("secret".to_string()),
+        ..ServerConfig::default()
+    };
+    tokio::spawn(server::run_with_config(
+        listener,
+        config,
+        std::future::pending::<()>(),
+    ));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sentinel = listener.local_addr().unwrap();
+    let config = SentinelConfig {
+        port: sentinel.port(),
+        masters: vec![MasterConfig {
+            auth_pass: Some("secret".to_string()),
+            ..MasterConfig::new("mymaster", "127.0.0.1", master.port(), 1)
+        }],
+        ..SentinelConfig::default()
+    };
+    tokio::spawn(sentinel::run(
+        listener,
+        config,
+        std::future::pending::<()>(),
+    ));
+
+    // The master refuses ROLE until the client authenticates.
+    let sentinels = [sentinel];
+    assert!(mini_redis::Client::connect_sentinel(&sentinels, "mymaster")
+        .await
+        .is_err());
+    let options = SentinelOptions {
+        password: Some("secret".to_string()),
+        ..SentinelOptions::default()
+    };
+    let mut client = mini_redis::Client::connect_sentinel_with(&sentinels, "mymaster", &options)
+        .await
+        .unwrap();
+    client.set("key", "value".into()).await.unwrap();
+}
@@ -1,8 +1,14 @@
-use mini_redis::{clients::Client, server};
+use bytes::Bytes;
+use futures::SinkExt;
+use mini_redis::frame::Limits;
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::{Frame, Listener, RespCodec};
 use std::net::SocketAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
 
 async fn start_server() -> SocketAddr {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -50,6 +56,57 @@ async fn send_error_unknown_command() {
     assert_eq!(b"-ERR unknown command foo\r\n", &response[0..len]);
 }
 
+#[tokio::test]
+async fn key_value_get_set_framed() {
+    let addr = start_server().await;
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(stream, RespCodec::new());
+
+    let mut set = Frame::array();
+    set.push_bulk(Bytes::from_static(b"SET"));
+    set.push_bulk(Bytes::from_static(b"hello"));
+    set.push_bulk(Bytes::from_static(b"world"));
+    framed.send(set).await.unwrap();
+    let response = framed.next().await.unwrap().unwrap();
+    assert!(response == "OK");
+
+    let mut get = Frame::array();
+    get.push_bulk(Bytes::from_static(b"GET"));
+    get.push_bulk(Bytes::from_static(b"hello"));
+    framed.send(get).await.unwrap();
+    let response = framed.next().await.unwrap().unwrap();
+    assert!(response == "world");
+}
+
+#[tokio::test]
+async fn reply_to_malformed_hello() {
+    let addr = start_server().await;
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(stream, RespCodec::new());
+
+    let mut call = async |args: &[&str]| {
+        let mut frame = Frame::array();
+        for arg in args {
+            frame.push_bulk(Bytes::from(arg.to_string()));
+        }
+        framed.send(frame).await.unwrap();
+        framed.next().await.unwrap().unwrap()
+    };
+    let response = call(&["HELLO", "two"]).await;
+    assert!(
+        matches!(response, Frame::Error(err) if err == "ERR Protocol version is not an integer or out of range")
+    );
+    let response = call(&["HELLO", "3"]).await;
+    assert!(matches!(response, Frame::Error(err) if err == "NOPROTO unsupported protocol version"));
+    let response = call(&["HELLO", "2", "AUTH", "default"]).await;
+    assert!(
+        matches!(response, Frame::Error(err) if err == "ERR Syntax error in HELLO option 'AUTH'")
+    );
+    // The connection is still open.
+    let response = call(&["PING"]).await;
+    assert!(response == "PONG");
+}
+
 async fn get_hello_raw(stream: &mut TcpStream, size: usize) -> Vec<u8> {
     stream
         .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
@@ -62,3 +119,148 @@ async fn get_hello_raw(stream: &mut TcpStream, size: usize) -> Vec<u8> {
     };
     response
 }
+
+#[tokio::test]
+async fn reject_oversized_bulk_length() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$4294967296\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        b"-ERR Protocol error: invalid bulk length\r\n",
+        &response[..]
+    );
+}
+
+#[tokio::test]
+async fn reject_oversized_multibulk_length() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"*4294967296\r\n").await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        b"-ERR Protocol error: invalid multibulk length\r\n",
+        &response[..]
+    );
+}
+
+#[tokio::test]
+async fn reject_deeply_nested_frames() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&b"*1\r\n".repeat(200)).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        b"-ERR Protocol error: invalid nesting depth\r\n",
+        &response[..]
+    );
+}
+
+#[tokio::test]
+async fn reject_query_buffer_over_limit() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        limits: Limits {
+            max_query_buffer_len: 1024,
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    };
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+    });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    // Send a partial frame slightly larger than the limit in one write, so the
+    // server has consumed everything before it closes the socket.
+    let mut request = b"*2\r\n$3\r\nGET\r\n$4096\r\n".to_vec();
+    request.extend_from_slice(&[b'a'; 1100]);
+    stream.write_all(&request).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        b"-ERR Protocol error: max query buffer length reached\r\n",
+        &response[..]
+    );
+}
+
+#[tokio::test]
+async fn close_idle_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        timeout: Some(Duration::from_millis(100)),
+        ..ServerConfig::default()
+    };
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+    });
+
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    let mut subscriber = TcpStream::connect(addr).await.unwrap();
+    subscriber
+        .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+    let mut response = [0; 34];
+    subscriber.read_exact(&mut response).await.unwrap();
+
+    let mut response = Vec::new();
+    let closed = time::timeout(Duration::from_secs(5), idle.read_to_end(&mut response)).await;
+    assert_eq!(closed.unwrap().unwrap(), 0);
+
+    // Subscribers are left alone.
+    let mut buf = [0; 1];
+    let read = time::timeout(Duration::from_millis(300), subscriber.read(&mut buf)).await;
+    assert!(read.is_err());
+}
+
+#[tokio::test]
+async fn set_tcp_keepalive() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stream = TcpStream::connect(addr).await.unwrap();
+
+    TcpListener::set_keepalive(&stream, Some(Duration::from_secs(60))).unwrap();
+    assert!(socket2::SockRef::from(&stream).keepalive().unwrap());
+    TcpListener::set_keepalive(&stream, None).unwrap();
+    assert!(!socket2::SockRef::from(&stream).keepalive().unwrap());
+}
+
+/// Hands out in-memory streams, which have no socket options.
+#[derive(Debug)]
+struct DuplexListener(tokio::sync::mpsc::Receiver<tokio::io::DuplexStream>);
+
+#[async_trait::async_trait]
+impl Listener for DuplexListener {
+    type Stream = tokio::io::DuplexStream;
+
+    async fn accept(&mut self) -> std::io::Result<(Self::Stream, String)> {
+        match self.0.recv().await {
+            Some(stream) => Ok((stream, "duplex:0".to_string())),
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn serve_custom_listener() {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+        server::run(DuplexListener(rx), tokio::signal::ctrl_c()).await;
+    });
+    let (mut client, server) = tokio::io::duplex(1024);
+    tx.send(server).await.unwrap();
+
+    client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut response = [0; 10];
+    client.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$4\r\nPONG\r\n", &response);
+}
@@ -0,0 +1,151 @@
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::storage::{Entry, Storage};
+use mini_redis::Frame;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::time::{self, Duration, Instant};
+
+pub mod common;
+use common::{call, connect};
+
+/// A storage keeping the entries sorted, and reporting the keys it holds
+/// to the tests.
+#[derive(Debug)]
+struct SortedStorage {
+    entries: BTreeMap<String, Entry>,
+    /// Keys of every shard.
+    keys: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl Storage for SortedStorage {
+    fn get(&mut self, key: &str) -> Option<&mut Entry> {
+        self.entries.get_mut(key)
+    }
+
+    fn peek(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        self.keys.lock().unwrap().insert(key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        self.keys.lock().unwrap().remove(key);
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn expires(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|entry| entry.expires_at().is_some())
+            .count()
+    }
+
+    fn next_expiration(&self) -> Option<(Instant, &str)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, entry)| Some((entry.expires_at()?, key.as_str())))
+            .min()
+    }
+
+    fn sample(&mut self, volatile: bool) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|(_, entry)| !volatile || entry.expires_at().is_some())
+            .map(|(key, _)| key.clone())
+    }
+
+    fn clear(&mut self) -> Box<dyn Send> {
+        let mut keys = self.keys.lock().unwrap();
+        for key in self.entries.keys() {
+            keys.remove(key);
+        }
+        Box::new(std::mem::take(&mut self.entries))
+    }
+
+    fn for_each_key(&self, f: &mut dyn FnMut(&str, usize, Option<Instant>)) {
+        for (key, entry) in &self.entries {
+            f(key, entry.data().len(), entry.expires_at());
+        }
+    }
+}
+
+/// Starts a server on `SortedStorage`, returning its address and the keys
+/// stored.
+async fn start_server(config: ServerConfig) -> (SocketAddr, Arc<Mutex<BTreeSet<String>>>) {
+    let keys = Arc::new(Mutex::new(BTreeSet::new()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shared = keys.clone();
+    let open = move |_| {
+        Ok(Box::new(SortedStorage {
+            entries: BTreeMap::new(),
+            keys: shared.clone(),
+        }) as Box<dyn Storage>)
+    };
+    tokio::spawn(async move {
+        server::run_with_storage(listener, config, open, tokio::signal::ctrl_c()).await;
+    });
+    (addr, keys)
+}
+
+#[tokio::test]
+async fn run_commands_on_another_storage() {
+    let (addr, keys) = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    assert!(call(&mut client, &["MSET", "a", "1", "b", "2", "c", "3"]).await == "OK");
+    assert!(call(&mut client, &["GET", "b"]).await == "2");
+    assert_eq!(
+        *keys.lock().unwrap(),
+        BTreeSet::from(["a".to_string(), "b".to_string(), "c".to_string()])
+    );
+
+    assert!(matches!(
+        call(&mut client, &["DEL", "a", "missing"]).await,
+        Frame::Integer(1)
+    ));
+    assert!(matches!(
+        call(&mut client, &["GET", "a"]).await,
+        Frame::Null
+    ));
+    assert!(call(&mut client, &["FLUSHDB"]).await == "OK");
+    assert!(keys.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn expire_keys_of_another_storage() {
+    let (addr, keys) = start_server(ServerConfig::default()).await;
+    let mut client = connect(addr).await;
+
+    call(&mut client, &["SET", "soon", "value", "PX", "20"]).await;
+    call(&mut client, &["SET", "persistent", "value"]).await;
+    time::sleep(Duration::from_millis(100)).await;
+
+    // Removed by the background task, without being accessed.
+    assert_eq!(
+        *keys.lock().unwrap(),
+        BTreeSet::from(["persistent".to_string()])
+    );
+}
+
+#[tokio::test]
+async fn refuse_to_start_without_storage() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let open = |_| Err("no storage".into());
+    let run = server::run_with_storage(
+        listener,
+        ServerConfig::default(),
+        open,
+        std::future::pending::<()>(),
+    );
+    time::timeout(Duration::from_secs(1), run).await.unwrap();
+}
@@ -0,0 +1,147 @@
+use mini_redis::tls::{self, TlsConfig, TlsListener};
+use mini_redis::{clients::Client, server};
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration};
+
+/// Self-signed CA plus a server and a client certificate issued by it, written
+/// as PEM files to a fresh temporary directory.
+struct Pki {
+    dir: PathBuf,
+}
+
+impl Pki {
+    fn generate(name: &str) -> Pki {
+        let dir = std::env::temp_dir().join(format!("mini-redis-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        std::fs::write(dir.join("ca.crt"), ca_cert.pem()).unwrap();
+
+        let issue = |file: &str, purpose: ExtendedKeyUsagePurpose| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let cert = params.signed_by(&key, &ca_cert, &ca_key).unwrap();
+            std::fs::write(dir.join(format!("{}.crt", file)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}.key", file)), key.serialize_pem()).unwrap();
+        };
+        issue("server", ExtendedKeyUsagePurpose::ServerAuth);
+        issue("client", ExtendedKeyUsagePurpose::ClientAuth);
+
+        Pki { dir }
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    fn server_config(&self) -> TlsConfig {
+        TlsConfig::new(self.path("server.crt"), self.path("server.key"))
+    }
+}
+
+impl Drop for Pki {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn start_tls_server(config: &TlsConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let listener = TlsListener::new(listener, config).unwrap();
+    tokio::spawn(async move {
+        server::run(listener, tokio::signal::ctrl_c()).await;
+    });
+    addr
+}
+
+fn roots(path: &Path) -> tls::rustls::RootCertStore {
+    tls::load_root_store(path).unwrap()
+}
+
+#[tokio::test]
+async fn key_value_get_set_over_tls() {
+    let pki = Pki::generate("plain");
+    let addr = start_tls_server(&pki.server_config()).await;
+
+    let mut client = Client::connect_tls(addr, "localhost", roots(&pki.path("ca.crt")))
+        .await
+        .unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    let got = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &got[..]);
+}
+
+#[tokio::test]
+async fn reject_untrusted_server_certificate() {
+    let pki = Pki::generate("untrusted");
+    let other = Pki::generate("untrusted-other");
+    let addr = start_tls_server(&pki.server_config()).await;
+
+    let res = Client::connect_tls(addr, "localhost", roots(&other.path("ca.crt"))).await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_client_certificate() {
+    let pki = Pki::generate("mutual");
+    let config = TlsConfig {
+        ca_cert_file: Some(pki.path("ca.crt")),
+        auth_clients: true,
+        ..pki.server_config()
+    };
+    let addr = start_tls_server(&config).await;
+
+    // Without a certificate the server aborts the handshake. With TLS 1.3 the
+    // client only notices on its first read.
+    let res = match Client::connect_tls(addr, "localhost", roots(&pki.path("ca.crt"))).await {
+        Ok(mut client) => client.ping(None).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    assert!(res.is_err());
+
+    let client_config = tls::client_config_with_auth(
+        roots(&pki.path("ca.crt")),
+        tls::load_certs(pki.path("client.crt")).unwrap(),
+        tls::load_private_key(pki.path("client.key")).unwrap(),
+    )
+    .unwrap();
+    let mut client = Client::connect_tls_with_config(addr, "localhost", client_config)
+        .await
+        .unwrap();
+    let pong = client.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+}
+
+#[tokio::test]
+async fn limit_pending_handshakes() {
+    let pki = Pki::generate("pending");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut listener = TlsListener::new(listener, &pki.server_config()).unwrap();
+    listener.set_max_handshakes(1);
+    tokio::spawn(async move {
+        server::run(listener, tokio::signal::ctrl_c()).await;
+    });
+
+    // A client never starting its handshake holds the only slot.
+    let idle = TcpStream::connect(addr).await.unwrap();
+    let connect = Client::connect_tls(addr, "localhost", roots(&pki.path("ca.crt")));
+    assert!(time::timeout(Duration::from_millis(300), connect)
+        .await
+        .is_err());
+
+    drop(idle);
+    let mut client = Client::connect_tls(addr, "localhost", roots(&pki.path("ca.crt")))
+        .await
+        .unwrap();
+    let pong = client.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+}
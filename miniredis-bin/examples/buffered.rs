@@ -1,4 +1,3 @@
-use bytes::Bytes;
 use mini_redis::Result;
 use mini_redis::{BufferedClient, Client};
 use miniredis_bin::SERVER_ADDR;
@@ -1,4 +1,3 @@
-use bytes::Bytes;
 use mini_redis::Client;
 use mini_redis::Result;
 use miniredis_bin::SERVER_ADDR;
@@ -1,52 +1,324 @@
-use bytes::Bytes;
-use mini_redis::run;
-use mini_redis::Db;
-use mini_redis::Shutdown;
-use mini_redis::{Connection, Frame, Result};
-use miniredis_bin::SERVER_ADDR;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::{thread, time};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::select;
+use clap::Parser;
+use mini_redis::config::{Backend, Engine, LogLevel};
+use mini_redis::sentinel::{self, SentinelConfig};
+use mini_redis::server::{self, ServerConfig};
+use mini_redis::tls::{TlsConfig, TlsListener};
+use mini_redis::{MultiListener, Result};
+use std::path::PathBuf;
+use tokio::net::TcpListener;
 use tokio::signal;
-use tokio::sync::broadcast;
-use tracing::{debug, error, info, instrument};
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::{info, Level};
+
+/// Options given on the command line override the configuration file.
+#[derive(Parser, Debug)]
+#[command(name = "miniredis-server", version, about = "A mini Redis server")]
+struct Cli {
+    /// Configuration file in redis.conf format.
+    config: Option<PathBuf>,
+
+    /// Addresses to listen on for TCP connections [default: 127.0.0.1]
+    #[arg(long, num_args = 1..)]
+    bind: Option<Vec<String>>,
+
+    /// TCP port to listen on. 0 disables TCP [default: 6379, or 26379 with
+    /// --sentinel]
+    #[arg(long, short)]
+    port: Option<u16>,
+
+    /// Path of a Unix socket to listen on as well.
+    #[cfg(unix)]
+    #[arg(long)]
+    unixsocket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, in octal [default: 700]
+    #[cfg(unix)]
+    #[arg(long, value_parser = parse_octal)]
+    unixsocketperm: Option<u32>,
+
+    /// TCP port to accept TLS connections on. 0 disables TLS [default: 0]
+    #[arg(long)]
+    tls_port: Option<u16>,
+
+    /// PEM file holding the certificate chain served on --tls-port.
+    #[arg(long)]
+    tls_cert_file: Option<PathBuf>,
+
+    /// PEM file holding the private key of --tls-cert-file.
+    #[arg(long)]
+    tls_key_file: Option<PathBuf>,
+
+    /// PEM file holding the CA certificates client certificates are
+    /// verified against.
+    #[arg(long)]
+    tls_ca_cert_file: Option<PathBuf>,
+
+    /// Require TLS clients to present a certificate: yes or no [default:
+    /// no]
+    #[arg(long, value_parser = parse_yes_no)]
+    tls_auth_clients: Option<bool>,
+
+    /// Maximum number of clients served at the same time [default: 250]
+    #[arg(long, value_parser = parse_maxclients)]
+    maxclients: Option<usize>,
+
+    /// Verbosity of the log: debug, verbose, notice or warning [default:
+    /// notice]
+    #[arg(long)]
+    loglevel: Option<LogLevel>,
+
+    /// Working directory. Relative persistence paths are resolved against
+    /// it.
+    #[arg(long)]
+    dir: Option<PathBuf>,
+
+    /// File to load users from, see `ACL LOAD` and `ACL SAVE`.
+    #[arg(long)]
+    aclfile: Option<PathBuf>,
+
+    /// Password clients must `AUTH` with. Ignored when `--aclfile` is given.
+    #[arg(long)]
+    requirepass: Option<String>,
+
+    /// Address to serve Prometheus metrics on, e.g. 127.0.0.1:9121
+    #[arg(long)]
+    metrics_addr: Option<String>,
+
+    /// How commands are executed: shared or thread-per-core [default:
+    /// shared]
+    #[arg(long)]
+    engine: Option<Engine>,
+
+    /// Threads run by the thread-per-core engine, 0 for one per CPU
+    /// [default: 0]
+    #[arg(long)]
+    cores: Option<usize>,
+
+    /// Where the keys are stored: memory or lsm [default: memory]
+    #[arg(long)]
+    backend: Option<Backend>,
+
+    /// Directory of the lsm backend [default: lsm]
+    #[arg(long)]
+    lsm_dir: Option<PathBuf>,
+
+    /// Master to replicate, as "host port"
+    #[arg(long, value_parser = parse_replicaof)]
+    replicaof: Option<(String, u16)>,
+
+    /// Run as a sentinel monitoring the masters of the configuration file,
+    /// given in sentinel.conf format.
+    #[arg(long)]
+    sentinel: bool,
+}
+
+impl Cli {
+    /// Loads the sentinel configuration file and applies the options on top.
+    fn into_sentinel_config(self) -> Result<SentinelConfig> {
+        let path = self
+            .config
+            .ok_or("sentinel mode requires a configuration file")?;
+        let mut config = SentinelConfig::from_file(path)?;
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(loglevel) = self.loglevel {
+            config.loglevel = loglevel;
+        }
+        Ok(config)
+    }
+
+    /// Loads the configuration file, if any, and applies the options on top.
+    fn into_config(self) -> Result<ServerConfig> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::from_file(path)?,
+            None => ServerConfig::default(),
+        };
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        #[cfg(unix)]
+        {
+            if self.unixsocket.is_some() {
+                config.unixsocket = self.unixsocket;
+            }
+            if let Some(mode) = self.unixsocketperm {
+                config.unixsocketperm = mode;
+            }
+        }
+        if let Some(port) = self.tls_port {
+            config.tls_port = port;
+        }
+        if self.tls_cert_file.is_some() {
+            config.tls_cert_file = self.tls_cert_file;
+        }
+        if self.tls_key_file.is_some() {
+            config.tls_key_file = self.tls_key_file;
+        }
+        if self.tls_ca_cert_file.is_some() {
+            config.tls_ca_cert_file = self.tls_ca_cert_file;
+        }
+        if let Some(auth_clients) = self.tls_auth_clients {
+            config.tls_auth_clients = auth_clients;
+        }
+        if let Some(max_clients) = self.maxclients {
+            config.max_clients = max_clients;
+        }
+        if let Some(loglevel) = self.loglevel {
+            config.loglevel = loglevel;
+        }
+        if self.dir.is_some() {
+            config.dir = self.dir;
+        }
+        if self.aclfile.is_some() {
+            config.aclfile = self.aclfile;
+        }
+        if self.requirepass.is_some() {
+            config.requirepass = self.requirepass;
+        }
+        if self.metrics_addr.is_some() {
+            config.metrics_addr = self.metrics_addr;
+        }
+        if let Some(engine) = self.engine {
+            config.engine = engine;
+        }
+        if let Some(cores) = self.cores {
+            config.cores = cores;
+        }
+        if let Some(backend) = self.backend {
+            config.backend = backend;
+        }
+        if let Some(dir) = self.lsm_dir {
+            config.lsm_dir = dir;
+        }
+        if self.replicaof.is_some() {
+            config.replicaof = self.replicaof;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(unix)]
+fn parse_octal(s: &str) -> std::result::Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|err| err.to_string())
+}
+
+fn parse_maxclients(s: &str) -> std::result::Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("must be greater than 0".to_string()),
+        Ok(max_clients) => Ok(max_clients),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+fn parse_yes_no(s: &str) -> std::result::Result<bool, String> {
+    match s {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("expected yes or no".to_string()),
+    }
+}
+
+fn parse_replicaof(s: &str) -> std::result::Result<(String, u16), String> {
+    match s.split_whitespace().collect::<Vec<_>>()[..] {
+        [host, port] => Ok((host.to_string(), port.parse().map_err(|_| "invalid port")?)),
+        _ => Err("expected a host and a port".to_string()),
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // must use RUST_LOG=debug cargo run
-    tracing_subscriber::fmt::init();
-    // or set hard-code
-    tracing_subscriber::registry().with(fmt::layer()).init();
-
-    let listener = TcpListener::bind(SERVER_ADDR).await.unwrap();
-    println!("listening");
-    run(listener, signal::ctrl_c()).await;
+    let cli = Cli::parse();
+    if cli.sentinel {
+        return run_sentinel(cli.into_sentinel_config()?).await;
+    }
+    let mut config = cli.into_config()?;
+    tracing_subscriber::fmt()
+        .with_max_level(Level::from(config.loglevel))
+        .init();
+
+    if let Some(dir) = &config.dir {
+        // The configuration file is rewritten by `CONFIG REWRITE`, so keep
+        // its path valid after changing directory.
+        if let Some(file) = &mut config.config_file {
+            *file = std::fs::canonicalize(&*file)?;
+        }
+        std::env::set_current_dir(dir)
+            .map_err(|err| format!("can't chdir to '{}': {}", dir.display(), err))?;
+    }
+
+    let mut listener = MultiListener::new();
+    let mut listening = bind_tcp(&mut listener, &config.bind, config.port).await?;
+    if let Some(tls) = config.tls()? {
+        listening += bind_tls(&mut listener, &config.bind, config.tls_port, &tls).await?;
+    }
+    #[cfg(unix)]
+    if let Some(path) = &config.unixsocket {
+        let unix = mini_redis::listener::bind_unix(path, config.unixsocketperm)
+            .map_err(|err| format!("could not bind {}: {}", path.display(), err))?;
+        info!(path = %path.display(), "listening");
+        listener.add(unix);
+        listening += 1;
+    }
+    if listening == 0 {
+        return Err("no TCP port, TLS port or Unix socket to listen on".into());
+    }
+
+    server::run_with_config(listener, config, signal::ctrl_c()).await;
     Ok(())
 }
 
-async fn process(
-    connection: &mut Connection,
-    db: &Db,
-    shutdown: &mut Shutdown,
-) -> crate::Result<()> {
-    use mini_redis::Command::{self, Get, Ping, Publish, Set, Subscribe};
-    while !shutdown.is_shutdown() {
-        let maybe_frame = tokio::select! {
-            res = connection.read_frame() => res?,
-            _ = shutdown.recv() => None,
-        };
-
-        let frame = match maybe_frame {
-            Some(frame) => frame,
-            None => return Ok(()),
-        };
+async fn run_sentinel(config: SentinelConfig) -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(Level::from(config.loglevel))
+        .init();
 
-        let command = Command::from_frame(frame)?;
-        debug!(?command);
-        command.apply(db, connection, shutdown).await?;
+    let mut listener = MultiListener::new();
+    if bind_tcp(&mut listener, &config.bind, config.port).await? == 0 {
+        return Err("no TCP port to listen on".into());
     }
+    sentinel::run(listener, config, signal::ctrl_c()).await;
     Ok(())
 }
+
+/// Listens on `port` of every address of `bind`, unless the port is 0.
+/// Returns the number of addresses listened on.
+async fn bind_tcp(listener: &mut MultiListener, bind: &[String], port: u16) -> Result<usize> {
+    if port == 0 {
+        return Ok(0);
+    }
+    for host in bind {
+        // IPv6 addresses are given without brackets, as in redis.conf.
+        let addr = if host.contains(':') {
+            format!("[{}]:{}", host, port)
+        } else {
+            format!("{}:{}", host, port)
+        };
+        let tcp = TcpListener::bind(&addr)
+            .await
+            .map_err(|err| format!("could not bind {}: {}", addr, err))?;
+        info!(%addr, "listening");
+        listener.add(tcp);
+    }
+    Ok(bind.len())
+}
+
+/// Listens for TLS connections on `port` of every address of `bind`, served
+/// as described by `tls`. Returns the number of addresses listened on.
+async fn bind_tls(
+    listener: &mut MultiListener,
+    bind: &[String],
+    port: u16,
+    tls: &TlsConfig,
+) -> Result<usize> {
+    let config = tls.server_config()?;
+    let mut tcp = MultiListener::new();
+    let count = bind_tcp(&mut tcp, bind, port).await?;
+    listener.add(TlsListener::with_server_config(tcp, config));
+    Ok(count)
+}
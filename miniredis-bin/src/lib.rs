@@ -1,6 +1 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use bytes::Bytes;
-
 pub const SERVER_ADDR: &str = "127.0.0.1:6379";
-
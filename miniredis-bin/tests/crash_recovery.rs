@@ -0,0 +1,125 @@
+//! Kills the server in the middle of its work, without letting it shut down,
+//! and checks that the `lsm` backend recovers the acknowledged writes when
+//! restarted.
+
+use bytes::Bytes;
+use mini_redis::Client;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use tokio::time::{self, Duration};
+
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Server {
+    /// Starts the server on the data in `dir`.
+    async fn start(dir: &Path) -> Server {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .arg(dir.join("redis.conf"))
+            .args(["--port", &port.to_string(), "--loglevel", "warning"])
+            .spawn()
+            .unwrap();
+        Server { child, port }
+    }
+
+    async fn connect(&self) -> Client {
+        for _ in 0..100 {
+            if let Ok(client) = Client::connect(("127.0.0.1", self.port)).await {
+                return client;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("server not started");
+    }
+
+    /// Kills the server with `SIGKILL`.
+    fn kill(mut self) {
+        self.child.kill().unwrap();
+        self.child.wait().unwrap();
+    }
+}
+
+/// A data directory for the test `name`, with a small memtable so that the
+/// writes fill several segments.
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("miniredis-crash-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = format!(
+        "backend lsm\nlsm-dir {}\nlsm-memtable-size 16kb\nlsm-cache-size 16kb\n",
+        dir.join("lsm").display()
+    );
+    fs::write(dir.join("redis.conf"), config).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn recover_after_kill() {
+    let dir = data_dir("kill");
+    let server = Server::start(&dir).await;
+    let mut client = server.connect().await;
+    for i in 0..2000 {
+        let value = Bytes::from(format!("value:{}", i));
+        client.set(&format!("key:{}", i), value).await.unwrap();
+    }
+    for i in 0..100 {
+        let value = Bytes::from("overwritten");
+        client.set(&format!("key:{}", i), value).await.unwrap();
+    }
+    client
+        .set_expires("volatile", Bytes::from("value"), Duration::from_secs(100))
+        .await
+        .unwrap();
+    server.kill();
+
+    let server = Server::start(&dir).await;
+    let mut client = server.connect().await;
+    assert_eq!(client.get("key:0").await.unwrap().unwrap(), "overwritten");
+    for i in 100..2000 {
+        let value = client.get(&format!("key:{}", i)).await.unwrap();
+        assert_eq!(value.unwrap(), format!("value:{}", i));
+    }
+    assert_eq!(client.get("volatile").await.unwrap().unwrap(), "value");
+    server.kill();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn recover_writes_acknowledged_before_kill() {
+    let dir = data_dir("writes");
+    let server = Server::start(&dir).await;
+    let mut client = server.connect().await;
+    let writer = tokio::spawn(async move {
+        let mut acknowledged = 0;
+        loop {
+            let value = Bytes::from(format!("value:{}", acknowledged));
+            let key = format!("key:{}", acknowledged);
+            if client.set(&key, value).await.is_err() {
+                return acknowledged;
+            }
+            acknowledged += 1;
+        }
+    });
+    time::sleep(Duration::from_millis(500)).await;
+    server.kill();
+    let acknowledged = writer.await.unwrap();
+    assert!(acknowledged > 0);
+
+    let server = Server::start(&dir).await;
+    let mut client = server.connect().await;
+    for i in 0..acknowledged {
+        let value = client.get(&format!("key:{}", i)).await.unwrap();
+        assert_eq!(value.unwrap(), format!("value:{}", i));
+    }
+    server.kill();
+    fs::remove_dir_all(&dir).unwrap();
+}